@@ -0,0 +1,390 @@
+use std::io;
+use std::collections::TreeMap;
+
+use super::{Bson,Object,String,Boolean,Int32,Int64,ParserError,MAX_DOCUMENT_SIZE};
+use super::{encode,from_slice};
+
+/// The first four bytes of every archive, little-endian.
+pub static MAGIC: u32 = 0x8199e26d;
+/// Written where a length prefix would go to end the prelude or a block.
+static TERMINATOR: i32 = -1;
+
+/// The first document of the prelude.
+#[deriving(PartialEq, Clone, Show)]
+pub struct ArchiveHeader {
+    pub concurrent_collections: i32,
+    pub version: String,
+    pub server_version: String,
+    pub tool_version: String,
+}
+
+/// What mongodump recorded about one collection. `metadata` is the
+/// collection's options and indexes as Extended JSON.
+#[deriving(PartialEq, Clone, Show)]
+pub struct CollectionMetadata {
+    pub db: String,
+    pub collection: String,
+    pub metadata: String,
+    pub size: i64,
+}
+
+#[deriving(PartialEq, Clone, Show)]
+pub struct Prelude {
+    pub header: ArchiveHeader,
+    pub collections: Vec<CollectionMetadata>,
+}
+
+#[deriving(Show)]
+pub enum ArchiveError {
+    /// The stream doesn't start with `MAGIC`; this is what it starts with.
+    BadMagic(u32),
+    /// Something other than what the format allows at this offset.
+    Corrupt(uint, String),
+    /// A document at this offset that couldn't be parsed.
+    BadDocument(uint, ParserError),
+    /// The documents of this namespace don't add up to the checksum at its end.
+    ChecksumMismatch(String),
+    ArchiveIoError(io::IoError),
+}
+
+pub type ArchiveResult<T> = Result<T, ArchiveError>;
+
+/// What comes out of the body of an archive, in the order it was written.
+/// Namespaces are `db.collection`.
+#[deriving(PartialEq, Clone, Show)]
+pub enum ArchiveEntry {
+    Document(String, Bson),
+    /// Every document of the namespace has been read.
+    EndOfCollection(String),
+}
+
+/// CRC-64 with the ECMA polynomial, as Go's hash/crc64 computes it.
+struct Crc64 {
+    table: Vec<u64>,
+}
+impl Crc64 {
+    fn new() -> Crc64 {
+        let table = range(0u64, 256).map(|i| {
+            let mut crc = i;
+            for _ in range(0u, 8) {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0xC96C5795D7870F42 } else { crc >> 1 };
+            }
+            crc
+        }).collect();
+        Crc64 { table: table }
+    }
+
+    fn update(&self, crc: u64, bytes: &[u8]) -> u64 {
+        let mut crc = !crc;
+        for &b in bytes.iter() {
+            crc = self.table[((crc as u8) ^ b) as uint] ^ (crc >> 8);
+        }
+        !crc
+    }
+}
+
+fn namespace(db: &str, collection: &str) -> String {
+    format!("{}.{}", db, collection)
+}
+
+fn field<'a>(obj: &'a Object, key: &str, offset: uint) -> ArchiveResult<&'a Bson> {
+    match obj.find(&key.to_string()) {
+        Some(v) => Ok(v),
+        None => Err(Corrupt(offset, format!("missing `{}`", key))),
+    }
+}
+
+fn text(obj: &Object, key: &str, offset: uint) -> ArchiveResult<String> {
+    match *try!(field(obj, key, offset)) {
+        String(ref s) => Ok(s.clone()),
+        _ => Err(Corrupt(offset, format!("`{}` should be a string", key))),
+    }
+}
+
+fn number(obj: &Object, key: &str, offset: uint) -> ArchiveResult<i64> {
+    match *try!(field(obj, key, offset)) {
+        Int32(v) => Ok(v as i64),
+        Int64(v) => Ok(v),
+        _ => Err(Corrupt(offset, format!("`{}` should be an integer", key))),
+    }
+}
+
+fn parse_document(bytes: &[u8], offset: uint) -> ArchiveResult<Object> {
+    match from_slice(bytes) {
+        Ok(super::Object(obj)) => Ok(obj),
+        Ok(_) => Err(Corrupt(offset, "expected a document".to_string())),
+        Err(e) => Err(BadDocument(offset, e)),
+    }
+}
+
+/// Reads an archive, prelude first and then the body one entry at a time.
+pub struct ArchiveReader<R> {
+    reader: R,
+    pos: uint,
+    prelude: Prelude,
+    crc: Crc64,
+    crcs: TreeMap<String, u64>,
+    // The namespace of the block being read, if inside one.
+    current: Option<String>,
+    done: bool,
+}
+
+impl<R: Reader> ArchiveReader<R> {
+    /// Checks the magic number and reads the prelude.
+    pub fn new(reader: R) -> ArchiveResult<ArchiveReader<R>> {
+        let header = ArchiveHeader {
+            concurrent_collections: 0,
+            version: String::new(),
+            server_version: String::new(),
+            tool_version: String::new(),
+        };
+        let mut archive = ArchiveReader {
+            reader: reader,
+            pos: 0,
+            prelude: Prelude { header: header, collections: Vec::new() },
+            crc: Crc64::new(),
+            crcs: TreeMap::new(),
+            current: None,
+            done: false,
+        };
+        let magic = try!(archive.io(|r| r.read_le_u32()));
+        archive.pos += 4;
+        if magic != MAGIC {
+            return Err(BadMagic(magic));
+        }
+
+        let (offset, header) = match try!(archive.read_document()) {
+            Some(d) => d,
+            None => return Err(Corrupt(0, "no archive header".to_string())),
+        };
+        archive.prelude.header = ArchiveHeader {
+            concurrent_collections: try!(number(&header, "concurrent_collections", offset)) as i32,
+            version: try!(text(&header, "version", offset)),
+            server_version: try!(text(&header, "server_version", offset)),
+            tool_version: try!(text(&header, "tool_version", offset)),
+        };
+        loop {
+            let (offset, meta) = match try!(archive.read_document()) {
+                Some(d) => d,
+                None => break,
+            };
+            archive.prelude.collections.push(CollectionMetadata {
+                db: try!(text(&meta, "db", offset)),
+                collection: try!(text(&meta, "collection", offset)),
+                metadata: try!(text(&meta, "metadata", offset)),
+                size: try!(number(&meta, "size", offset)),
+            });
+        }
+        Ok(archive)
+    }
+
+    pub fn prelude(&self) -> &Prelude {
+        &self.prelude
+    }
+
+    fn io<T>(&mut self, f: |&mut R| -> io::IoResult<T>) -> ArchiveResult<T> {
+        let pos = self.pos;
+        f(&mut self.reader).map_err(|e| match e.kind {
+            io::EndOfFile => Corrupt(pos, "unexpected end of archive".to_string()),
+            _ => ArchiveIoError(e),
+        })
+    }
+
+    /// Reads the bytes of one document, or `None` for a terminator.
+    fn read_block(&mut self) -> ArchiveResult<Option<Vec<u8>>> {
+        let start = self.pos;
+        let len = try!(self.io(|r| r.read_le_i32()));
+        self.pos += 4;
+        if len == TERMINATOR {
+            return Ok(None);
+        }
+        if len < 5 || len as uint > MAX_DOCUMENT_SIZE {
+            return Err(Corrupt(start, format!("bad document length {}", len)));
+        }
+        let mut bytes = Vec::with_capacity(len as uint);
+        bytes.push_all(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+        let rest = try!(self.io(|r| r.read_exact(len as uint - 4)));
+        bytes.push_all(rest.as_slice());
+        self.pos += len as uint - 4;
+        Ok(Some(bytes))
+    }
+
+    fn read_document(&mut self) -> ArchiveResult<Option<(uint, Object)>> {
+        let offset = self.pos;
+        match try!(self.read_block()) {
+            None => Ok(None),
+            Some(bytes) => Ok(Some((offset, try!(parse_document(bytes.as_slice(), offset))))),
+        }
+    }
+
+    fn next_entry(&mut self) -> ArchiveResult<Option<ArchiveEntry>> {
+        loop {
+            let ns = match self.current.clone() {
+                Some(ns) => ns,
+                None => {
+                    // A namespace header, or the end of the archive.
+                    let offset = self.pos;
+                    let mut first = [0u8];
+                    match self.reader.read(&mut first) {
+                        Err(ref e) if e.kind == io::EndOfFile => return Ok(None),
+                        Err(e) => return Err(ArchiveIoError(e)),
+                        Ok(_) => (),
+                    }
+                    let rest = try!(self.io(|r| r.read_exact(3)));
+                    self.pos += 4;
+                    let len = (first[0] as u32 | rest[0] as u32 << 8 | rest[1] as u32 << 16 | rest[2] as u32 << 24) as i32;
+                    if len < 5 || len as uint > MAX_DOCUMENT_SIZE {
+                        return Err(Corrupt(offset, format!("expected a namespace header, found length {}", len)));
+                    }
+                    let mut bytes = vec!(first[0], rest[0], rest[1], rest[2]);
+                    bytes.push_all(try!(self.io(|r| r.read_exact(len as uint - 4))).as_slice());
+                    self.pos += len as uint - 4;
+                    let header = try!(parse_document(bytes.as_slice(), offset));
+                    let ns = namespace(try!(text(&header, "db", offset)).as_slice(),
+                                       try!(text(&header, "collection", offset)).as_slice());
+                    let eof = match header.find(&"EOF".to_string()) {
+                        Some(&Boolean(b)) => b,
+                        _ => false,
+                    };
+                    if !eof {
+                        self.current = Some(ns);
+                        continue;
+                    }
+                    let crc = try!(number(&header, "CRC", offset)) as u64;
+                    let seen = self.crcs.find(&ns).map_or(0, |c| *c);
+                    if crc != 0 && crc != seen {
+                        return Err(ChecksumMismatch(ns));
+                    }
+                    let end = self.pos;
+                    if try!(self.read_block()).is_some() {
+                        return Err(Corrupt(end, "expected a terminator".to_string()));
+                    }
+                    return Ok(Some(EndOfCollection(ns)));
+                },
+            };
+            let offset = self.pos;
+            match try!(self.read_block()) {
+                None => self.current = None,
+                Some(bytes) => {
+                    let crc = self.crcs.find(&ns).map_or(0, |c| *c);
+                    let crc = self.crc.update(crc, bytes.as_slice());
+                    self.crcs.insert(ns.clone(), crc);
+                    let doc = match from_slice(bytes.as_slice()) {
+                        Ok(doc) => doc,
+                        Err(e) => return Err(BadDocument(offset, e)),
+                    };
+                    return Ok(Some(Document(ns, doc)));
+                },
+            }
+        }
+    }
+}
+
+impl<R: Reader> Iterator<ArchiveResult<ArchiveEntry>> for ArchiveReader<R> {
+    /// Errors end the iteration, since nothing after them can be trusted.
+    fn next(&mut self) -> Option<ArchiveResult<ArchiveEntry>> {
+        if self.done {
+            return None;
+        }
+        match self.next_entry() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+/// Reads a whole archive, gathering each namespace's documents together.
+pub fn read_archive<R: Reader>(reader: R) -> ArchiveResult<(Prelude, TreeMap<String, Vec<Bson>>)> {
+    let mut archive = try!(ArchiveReader::new(reader));
+    let mut collections = TreeMap::new();
+    for entry in archive.by_ref() {
+        match try!(entry) {
+            Document(ns, doc) => {
+                if !collections.contains_key(&ns) {
+                    collections.insert(ns.clone(), Vec::new());
+                }
+                collections.find_mut(&ns).unwrap().push(doc);
+            },
+            EndOfCollection(_) => (),
+        }
+    }
+    Ok((archive.prelude.clone(), collections))
+}
+
+/// Writes an archive: the prelude when created, then blocks of documents in
+/// any order, with each collection closed by `end_collection`.
+pub struct ArchiveWriter<W> {
+    writer: W,
+    crc: Crc64,
+    crcs: TreeMap<String, u64>,
+}
+
+fn write_document<W: Writer>(w: &mut W, doc: Object) -> io::IoResult<Vec<u8>> {
+    let bytes = encode(&super::Object(doc));
+    try!(w.write(bytes.as_slice()));
+    Ok(bytes)
+}
+
+fn namespace_header(db: &str, collection: &str, eof: bool, crc: u64) -> Object {
+    let mut header = TreeMap::new();
+    header.insert("db".to_string(), String(db.to_string()));
+    header.insert("collection".to_string(), String(collection.to_string()));
+    header.insert("EOF".to_string(), Boolean(eof));
+    header.insert("CRC".to_string(), Int64(crc as i64));
+    header
+}
+
+impl<W: Writer> ArchiveWriter<W> {
+    pub fn new(mut writer: W, prelude: &Prelude) -> io::IoResult<ArchiveWriter<W>> {
+        try!(writer.write_le_u32(MAGIC));
+        let mut header = TreeMap::new();
+        let h = &prelude.header;
+        header.insert("concurrent_collections".to_string(), Int32(h.concurrent_collections));
+        header.insert("version".to_string(), String(h.version.clone()));
+        header.insert("server_version".to_string(), String(h.server_version.clone()));
+        header.insert("tool_version".to_string(), String(h.tool_version.clone()));
+        try!(write_document(&mut writer, header));
+        for c in prelude.collections.iter() {
+            let mut meta = TreeMap::new();
+            meta.insert("db".to_string(), String(c.db.clone()));
+            meta.insert("collection".to_string(), String(c.collection.clone()));
+            meta.insert("metadata".to_string(), String(c.metadata.clone()));
+            meta.insert("size".to_string(), Int64(c.size));
+            try!(write_document(&mut writer, meta));
+        }
+        try!(writer.write_le_i32(TERMINATOR));
+        Ok(ArchiveWriter { writer: writer, crc: Crc64::new(), crcs: TreeMap::new() })
+    }
+
+    /// Writes one block of documents for a collection. Blocks of different
+    /// collections can be interleaved.
+    pub fn write_documents<I: Iterator<Object>>(&mut self, db: &str, collection: &str, docs: I) -> io::IoResult<()> {
+        try!(write_document(&mut self.writer, namespace_header(db, collection, false, 0)));
+        let ns = namespace(db, collection);
+        let mut crc = self.crcs.find(&ns).map_or(0, |c| *c);
+        for doc in docs {
+            let bytes = try!(write_document(&mut self.writer, doc));
+            crc = self.crc.update(crc, bytes.as_slice());
+        }
+        self.crcs.insert(ns, crc);
+        self.writer.write_le_i32(TERMINATOR)
+    }
+
+    /// Marks a collection as complete, with the checksum of its documents.
+    pub fn end_collection(&mut self, db: &str, collection: &str) -> io::IoResult<()> {
+        let crc = self.crcs.find(&namespace(db, collection)).map_or(0, |c| *c);
+        try!(write_document(&mut self.writer, namespace_header(db, collection, true, crc)));
+        self.writer.write_le_i32(TERMINATOR)
+    }
+
+    pub fn unwrap(self) -> W {
+        self.writer
+    }
+}
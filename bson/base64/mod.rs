@@ -0,0 +1,54 @@
+// Standard base64 with padding (RFC 4648), as used by `$binary`.
+static ALPHABET: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], if chunk.len() > 1 { chunk[1] } else { 0 }, if chunk.len() > 2 { chunk[2] } else { 0 }];
+        let n = (b[0] as uint << 16) | (b[1] as uint << 8) | b[2] as uint;
+        for i in range(0u, 4) {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) & 0x3F] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn value(c: u8) -> Option<uint> {
+    ALPHABET.iter().position(|&a| a == c)
+}
+
+/// Reads padded base64, refusing anything that isn't exactly that.
+pub fn decode(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    if bytes.len() % 4 != 0 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(bytes.len() / 4 * 3);
+    for (i, chunk) in bytes.chunks(4).enumerate() {
+        let last = i == bytes.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == '=' as u8).count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut n = 0u;
+        for &c in chunk.slice_to(4 - padding).iter() {
+            n = (n << 6) | match value(c) {
+                Some(v) => v,
+                None => return None,
+            };
+        }
+        n <<= 6 * padding;
+        out.push((n >> 16) as u8);
+        if padding < 2 {
+            out.push((n >> 8) as u8);
+        }
+        if padding < 1 {
+            out.push(n as u8);
+        }
+    }
+    Some(out)
+}
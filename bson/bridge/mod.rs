@@ -0,0 +1,711 @@
+//! Encoding and decoding through the standard library's `Encodable` and
+//! `Decodable`, the traits `serialize::json` uses, so types that already
+//! derive them for JSON can be stored as BSON without a second set of
+//! impls for `bson::serialize`.
+//!
+//! Those traits have no notion of ObjectIds, dates or decimals, so fields
+//! of those types use the `ObjectId`, `DateTime` and `Decimal128` newtypes
+//! here. Each encodes as a struct with a marker name, which this encoder
+//! turns into the BSON type and this decoder turns back. Any other encoder
+//! sees an ordinary struct, so JSON gets `{"$oid": ...}`, `{"$date": ...}`
+//! and `{"$numberDecimal": ...}`.
+//!
+//! Enums are externally tagged: `"Variant"` for unit variants,
+//! `{ "Variant": [args] }` and `{ "Variant": { fields } }` otherwise.
+//!
+//! The `serde` module does the same for serde's traits, and also writes
+//! and reads raw BSON bytes directly.
+
+use std::{io,mem};
+use std::num::Float;
+use std::collections::TreeMap;
+use serialize::{Encodable,Decodable};
+
+use super::{Bson,Object,String,Boolean,Int32,Int64,Null,UtcDatetime};
+use super::{DecoderError,DecodeResult,ParseError,ExpectedType,MissingField,UnknownVariant,OutOfRange,Custom};
+use super::{PathElt,Field,Index,format_path,to_hex,from_hex,decimal};
+
+pub mod serde;
+
+/// Struct names that mark the special types.
+pub static OBJECT_ID: &'static str = "$__bson_ObjectId";
+pub static DATE_TIME: &'static str = "$__bson_DateTime";
+pub static DECIMAL128: &'static str = "$__bson_Decimal128";
+
+/// An ObjectId's twelve bytes.
+#[deriving(PartialEq, Clone, Show)]
+pub struct ObjectId(pub Vec<u8>);
+
+/// Milliseconds since the Unix epoch.
+#[deriving(PartialEq, Clone, Show)]
+pub struct DateTime(pub i64);
+
+/// The sixteen bytes of an IEEE 754-2008 decimal128.
+#[deriving(PartialEq, Clone, Show)]
+pub struct Decimal128(pub Vec<u8>);
+
+impl<E, S: ::serialize::Encoder<E>> Encodable<S, E> for ObjectId {
+    fn encode(&self, s: &mut S) -> Result<(), E> {
+        let ObjectId(ref id) = *self;
+        s.emit_struct(OBJECT_ID, 1, |s| s.emit_struct_field("$oid", 0, |s| to_hex(id.as_slice()).encode(s)))
+    }
+}
+
+impl<E, D: ::serialize::Decoder<E>> Decodable<D, E> for ObjectId {
+    fn decode(d: &mut D) -> Result<ObjectId, E> {
+        d.read_struct(OBJECT_ID, 1, |d| {
+            let hex: String = try!(d.read_struct_field("$oid", 0, |d| Decodable::decode(d)));
+            match from_hex(hex.as_slice()) {
+                Some(id) if id.len() == 12 => Ok(ObjectId(id)),
+                _ => Err(d.error(format!("`{}` is not an ObjectId", hex).as_slice())),
+            }
+        })
+    }
+}
+
+impl<E, S: ::serialize::Encoder<E>> Encodable<S, E> for DateTime {
+    fn encode(&self, s: &mut S) -> Result<(), E> {
+        let DateTime(ms) = *self;
+        s.emit_struct(DATE_TIME, 1, |s| s.emit_struct_field("$date", 0, |s| s.emit_i64(ms)))
+    }
+}
+
+impl<E, D: ::serialize::Decoder<E>> Decodable<D, E> for DateTime {
+    fn decode(d: &mut D) -> Result<DateTime, E> {
+        d.read_struct(DATE_TIME, 1, |d| {
+            let ms = try!(d.read_struct_field("$date", 0, |d| d.read_i64()));
+            Ok(DateTime(ms))
+        })
+    }
+}
+
+impl<E, S: ::serialize::Encoder<E>> Encodable<S, E> for Decimal128 {
+    fn encode(&self, s: &mut S) -> Result<(), E> {
+        let Decimal128(ref bytes) = *self;
+        s.emit_struct(DECIMAL128, 1, |s| {
+            s.emit_struct_field("$numberDecimal", 0, |s| decimal::to_string(bytes.as_slice()).encode(s))
+        })
+    }
+}
+
+impl<E, D: ::serialize::Decoder<E>> Decodable<D, E> for Decimal128 {
+    fn decode(d: &mut D) -> Result<Decimal128, E> {
+        d.read_struct(DECIMAL128, 1, |d| {
+            let text: String = try!(d.read_struct_field("$numberDecimal", 0, |d| Decodable::decode(d)));
+            match decimal::parse(text.as_slice()) {
+                Some(bytes) => Ok(Decimal128(bytes)),
+                None => Err(d.error(format!("`{}` is not a decimal128", text).as_slice())),
+            }
+        })
+    }
+}
+
+fn invalid(desc: &'static str, detail: String) -> io::IoError {
+    io::IoError { kind: io::InvalidInput, desc: desc, detail: Some(detail) }
+}
+
+/// Turns a marked struct, as encoded, into the BSON value it stands for.
+fn unmark(name: &str, obj: Object) -> io::IoResult<Bson> {
+    let mut obj = obj;
+    if name == OBJECT_ID {
+        match obj.pop(&"$oid".to_string()) {
+            Some(String(ref hex)) => match from_hex(hex.as_slice()) {
+                Some(id) if id.len() == 12 => return Ok(super::ObjectId(id)),
+                _ => (),
+            },
+            _ => (),
+        }
+        Err(invalid("bad ObjectId", "expected 24 hex digits".to_string()))
+    } else if name == DATE_TIME {
+        match obj.pop(&"$date".to_string()) {
+            Some(Int64(ms)) => Ok(UtcDatetime(ms)),
+            _ => Err(invalid("bad DateTime", "expected an i64".to_string())),
+        }
+    } else if name == DECIMAL128 {
+        match obj.pop(&"$numberDecimal".to_string()).and_then(|v| match v {
+            String(s) => decimal::parse(s.as_slice()),
+            _ => None,
+        }) {
+            Some(bytes) => Ok(super::Decimal128(bytes)),
+            None => Err(invalid("bad Decimal128", "expected a decimal string".to_string())),
+        }
+    } else {
+        Ok(super::Object(obj))
+    }
+}
+
+/// The other way: a BSON value as the struct a marked type decodes from.
+fn mark(name: &str, v: Bson) -> Bson {
+    let (key, inner) = match v {
+        super::ObjectId(ref id) if name == OBJECT_ID => ("$oid", String(to_hex(id.as_slice()))),
+        UtcDatetime(ms) if name == DATE_TIME => ("$date", Int64(ms)),
+        super::Decimal128(ref bytes) if name == DECIMAL128 => {
+            ("$numberDecimal", String(decimal::to_string(bytes.as_slice())))
+        },
+        other => return other,
+    };
+    let mut obj = TreeMap::new();
+    obj.insert(key.to_string(), inner);
+    super::Object(obj)
+}
+
+pub type EncodeResult = io::IoResult<()>;
+
+/// Builds a `Bson` value out of whatever is encoded into it.
+pub struct Encoder {
+    // Documents and arrays still being filled, innermost last.
+    containers: Vec<Bson>,
+    // Keys of map entries whose values are still to come.
+    keys: Vec<String>,
+    // The value last encoded, waiting to be put in its container.
+    value: Option<Bson>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder { containers: Vec::new(), keys: Vec::new(), value: None }
+    }
+
+    /// The value encoded, or null if nothing has been.
+    pub fn unwrap(self) -> Bson {
+        self.value.unwrap_or(Null)
+    }
+
+    fn put(&mut self, v: Bson) -> EncodeResult {
+        self.value = Some(v);
+        Ok(())
+    }
+
+    /// Runs `f` to fill `start`, returning it once it's done.
+    fn fill(&mut self, start: Bson, f: |&mut Encoder| -> EncodeResult) -> io::IoResult<Bson> {
+        self.containers.push(start);
+        let res = f(self);
+        let filled = self.containers.pop().unwrap();
+        try!(res);
+        Ok(filled)
+    }
+
+    /// Runs `f` and adds what it encodes to the innermost container, under
+    /// `key` if that's a document.
+    fn add(&mut self, key: &str, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        try!(f(self));
+        let v = self.value.take().unwrap_or(Null);
+        match *self.containers.last_mut().unwrap() {
+            super::Object(ref mut obj) => {
+                obj.insert(key.to_string(), v);
+            },
+            super::List(ref mut list) => list.push(v),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// `{ name: content }`, the layout of a variant with data.
+    fn variant(&mut self, name: &str, content: Bson) -> EncodeResult {
+        let mut obj = TreeMap::new();
+        obj.insert(name.to_string(), content);
+        self.put(super::Object(obj))
+    }
+}
+
+impl ::serialize::Encoder<io::IoError> for Encoder {
+    fn emit_nil(&mut self) -> EncodeResult {
+        self.put(Null)
+    }
+
+    fn emit_uint(&mut self, v: uint) -> EncodeResult {
+        self.emit_u64(v as u64)
+    }
+    fn emit_u64(&mut self, v: u64) -> EncodeResult {
+        if v > ::std::i64::MAX as u64 {
+            return Err(invalid("integer out of range", format!("{} doesn't fit in a long", v)));
+        }
+        self.put(Int64(v as i64))
+    }
+    fn emit_u32(&mut self, v: u32) -> EncodeResult {
+        self.put(Int64(v as i64))
+    }
+    fn emit_u16(&mut self, v: u16) -> EncodeResult {
+        self.put(Int32(v as i32))
+    }
+    fn emit_u8(&mut self, v: u8) -> EncodeResult {
+        self.put(Int32(v as i32))
+    }
+    fn emit_int(&mut self, v: int) -> EncodeResult {
+        self.put(Int64(v as i64))
+    }
+    fn emit_i64(&mut self, v: i64) -> EncodeResult {
+        self.put(Int64(v))
+    }
+    fn emit_i32(&mut self, v: i32) -> EncodeResult {
+        self.put(Int32(v))
+    }
+    fn emit_i16(&mut self, v: i16) -> EncodeResult {
+        self.put(Int32(v as i32))
+    }
+    fn emit_i8(&mut self, v: i8) -> EncodeResult {
+        self.put(Int32(v as i32))
+    }
+    fn emit_bool(&mut self, v: bool) -> EncodeResult {
+        self.put(Boolean(v))
+    }
+    fn emit_f64(&mut self, v: f64) -> EncodeResult {
+        self.put(super::Float(v))
+    }
+    fn emit_f32(&mut self, v: f32) -> EncodeResult {
+        self.put(super::Float(v as f64))
+    }
+    fn emit_char(&mut self, v: char) -> EncodeResult {
+        self.put(String(String::from_char(1, v)))
+    }
+    fn emit_str(&mut self, v: &str) -> EncodeResult {
+        self.put(String(v.to_string()))
+    }
+
+    fn emit_enum(&mut self, _name: &str, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        f(self)
+    }
+    fn emit_enum_variant(&mut self, v_name: &str, _v_id: uint, len: uint, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        if len == 0 {
+            return self.put(String(v_name.to_string()));
+        }
+        let args = try!(self.fill(super::List(Vec::new()), f));
+        self.variant(v_name, args)
+    }
+    fn emit_enum_variant_arg(&mut self, _a_idx: uint, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        self.add("", f)
+    }
+    fn emit_enum_struct_variant(&mut self, v_name: &str, _v_id: uint, _len: uint, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        let fields = try!(self.fill(super::Object(TreeMap::new()), f));
+        self.variant(v_name, fields)
+    }
+    fn emit_enum_struct_variant_field(&mut self, f_name: &str, _f_idx: uint, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        self.add(f_name, f)
+    }
+
+    fn emit_struct(&mut self, name: &str, _len: uint, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        let obj = match try!(self.fill(super::Object(TreeMap::new()), f)) {
+            super::Object(obj) => obj,
+            _ => unreachable!(),
+        };
+        let v = try!(unmark(name, obj));
+        self.put(v)
+    }
+    fn emit_struct_field(&mut self, f_name: &str, _f_idx: uint, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        self.add(f_name, f)
+    }
+
+    fn emit_tuple(&mut self, _len: uint, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        let list = try!(self.fill(super::List(Vec::new()), f));
+        self.put(list)
+    }
+    fn emit_tuple_arg(&mut self, _idx: uint, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        self.add("", f)
+    }
+    fn emit_tuple_struct(&mut self, _name: &str, len: uint, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        self.emit_tuple(len, f)
+    }
+    fn emit_tuple_struct_arg(&mut self, f_idx: uint, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        self.emit_tuple_arg(f_idx, f)
+    }
+
+    fn emit_option(&mut self, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        f(self)
+    }
+    fn emit_option_none(&mut self) -> EncodeResult {
+        self.put(Null)
+    }
+    fn emit_option_some(&mut self, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        f(self)
+    }
+
+    fn emit_seq(&mut self, len: uint, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        self.emit_tuple(len, f)
+    }
+    fn emit_seq_elt(&mut self, idx: uint, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        self.emit_tuple_arg(idx, f)
+    }
+
+    fn emit_map(&mut self, _len: uint, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        let obj = try!(self.fill(super::Object(TreeMap::new()), f));
+        self.put(obj)
+    }
+    /// Keys have to be strings, or integers, which are written as strings.
+    fn emit_map_elt_key(&mut self, _idx: uint, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        try!(f(self));
+        let key = match self.value.take() {
+            Some(String(s)) => s,
+            Some(Int32(n)) => n.to_string(),
+            Some(Int64(n)) => n.to_string(),
+            other => {
+                let found = other.map_or("nothing", |v| v.type_name());
+                return Err(invalid("map keys must be strings", format!("found {}", found)));
+            },
+        };
+        self.keys.push(key);
+        Ok(())
+    }
+    fn emit_map_elt_val(&mut self, _idx: uint, f: |&mut Encoder| -> EncodeResult) -> EncodeResult {
+        let key = self.keys.pop().expect("map value encoded without a key");
+        self.add(key.as_slice(), f)
+    }
+}
+
+/// Reads a `Bson` value out into whatever decodes from it. Errors carry
+/// the path of the value that failed, as `bson::Decoder`'s do.
+pub struct Decoder {
+    stack: Vec<Bson>,
+    path: Vec<PathElt>,
+    // The keys of the maps being read, innermost last.
+    keys: Vec<Vec<String>>,
+}
+
+impl Decoder {
+    pub fn new(v: Bson) -> Decoder {
+        Decoder { stack: vec![v], path: Vec::new(), keys: Vec::new() }
+    }
+
+    fn pop(&mut self) -> Bson {
+        self.stack.pop().unwrap()
+    }
+
+    fn path(&self) -> String {
+        format_path(self.path.as_slice())
+    }
+
+    fn path_with(&mut self, elt: PathElt) -> String {
+        self.path.push(elt);
+        let path = self.path();
+        self.path.pop();
+        path
+    }
+
+    fn expected<T>(&self, expected: &str, found: Bson) -> DecodeResult<T> {
+        Err(ExpectedType {
+            path: self.path(),
+            expected: expected.to_string(),
+            found: found.type_name().to_string(),
+        })
+    }
+
+    fn at<T>(&mut self, elt: PathElt, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.path.push(elt);
+        let res = f(self);
+        self.path.pop();
+        res
+    }
+
+    /// The length of the array on top of the stack, if that's what it is.
+    fn peek_len(&self) -> Option<uint> {
+        match self.stack.last() {
+            Some(&super::List(ref list)) => Some(list.len()),
+            _ => None,
+        }
+    }
+
+    fn list_len(&mut self) -> DecodeResult<uint> {
+        match self.peek_len() {
+            Some(len) => Ok(len),
+            None => {
+                let v = self.pop();
+                self.expected("array", v)
+            },
+        }
+    }
+
+    /// Runs `f` on the `pos`th element of the array on top of the stack,
+    /// which stays put for the elements after it.
+    fn element<T>(&mut self, pos: uint, elt: PathElt, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        match self.peek_len() {
+            None => {
+                let v = self.pop();
+                return self.expected("array", v);
+            },
+            Some(len) if pos >= len => return Err(MissingField { path: self.path_with(elt) }),
+            Some(_) => (),
+        }
+        let v = match *self.stack.last_mut().unwrap() {
+            super::List(ref mut list) => mem::replace(list.get_mut(pos), Null),
+            _ => unreachable!(),
+        };
+        self.stack.push(v);
+        self.at(elt, f)
+    }
+
+    /// Runs `f` on a field of the document on top of the stack. A missing
+    /// field decodes from null, so that options come out as `None`.
+    fn field<T>(&mut self, name: &str, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        let mut obj = match self.pop() {
+            super::Object(obj) => obj,
+            other => return self.expected("object", other),
+        };
+        let v = obj.pop(&name.to_string());
+        let missing = v.is_none();
+        self.stack.push(v.unwrap_or(Null));
+        let res = self.at(Field(name.to_string()), f);
+        self.stack.push(super::Object(obj));
+        match res {
+            Err(_) if missing => Err(MissingField { path: self.path_with(Field(name.to_string())) }),
+            res => res,
+        }
+    }
+
+    fn read_integer(&mut self, target: &str, min: i64, max: i64) -> DecodeResult<i64> {
+        let v = match self.pop() {
+            Int32(v) => v as i64,
+            Int64(v) => v,
+            other => return self.expected("int or long", other),
+        };
+        if v < min || v > max {
+            return Err(OutOfRange { path: self.path(), value: v.to_string(), target: target.to_string() });
+        }
+        Ok(v)
+    }
+
+    fn read_variant<T>(&mut self, names: &[&str], f: |&mut Decoder, uint| -> DecodeResult<T>) -> DecodeResult<T> {
+        let (name, content) = match self.pop() {
+            String(name) => (name, Null),
+            super::Object(obj) => {
+                if obj.len() != 1 {
+                    return Err(ExpectedType {
+                        path: self.path(),
+                        expected: "object with a single variant key".to_string(),
+                        found: format!("object with {} keys", obj.len()),
+                    });
+                }
+                obj.into_iter().next().unwrap()
+            },
+            other => return self.expected("string or object", other),
+        };
+        let idx = match names.iter().position(|n| *n == name.as_slice()) {
+            Some(idx) => idx,
+            None => return Err(UnknownVariant {
+                path: self.path(),
+                variant: name,
+                expected: names.iter().map(|n| n.to_string()).collect(),
+            }),
+        };
+        self.stack.push(content);
+        self.path.push(Field(name));
+        let res = f(self, idx);
+        self.path.pop();
+        self.stack.pop();
+        res
+    }
+}
+
+impl ::serialize::Decoder<DecoderError> for Decoder {
+    fn read_nil(&mut self) -> DecodeResult<()> {
+        match self.pop() {
+            Null => Ok(()),
+            other => self.expected("null", other),
+        }
+    }
+
+    fn read_uint(&mut self) -> DecodeResult<uint> {
+        Ok(try!(self.read_integer("uint", 0, ::std::i64::MAX)) as uint)
+    }
+    fn read_u64(&mut self) -> DecodeResult<u64> {
+        Ok(try!(self.read_integer("u64", 0, ::std::i64::MAX)) as u64)
+    }
+    fn read_u32(&mut self) -> DecodeResult<u32> {
+        Ok(try!(self.read_integer("u32", 0, ::std::u32::MAX as i64)) as u32)
+    }
+    fn read_u16(&mut self) -> DecodeResult<u16> {
+        Ok(try!(self.read_integer("u16", 0, ::std::u16::MAX as i64)) as u16)
+    }
+    fn read_u8(&mut self) -> DecodeResult<u8> {
+        Ok(try!(self.read_integer("u8", 0, ::std::u8::MAX as i64)) as u8)
+    }
+    fn read_int(&mut self) -> DecodeResult<int> {
+        Ok(try!(self.read_integer("int", ::std::int::MIN as i64, ::std::int::MAX as i64)) as int)
+    }
+    fn read_i64(&mut self) -> DecodeResult<i64> {
+        self.read_integer("i64", ::std::i64::MIN, ::std::i64::MAX)
+    }
+    fn read_i32(&mut self) -> DecodeResult<i32> {
+        Ok(try!(self.read_integer("i32", ::std::i32::MIN as i64, ::std::i32::MAX as i64)) as i32)
+    }
+    fn read_i16(&mut self) -> DecodeResult<i16> {
+        Ok(try!(self.read_integer("i16", ::std::i16::MIN as i64, ::std::i16::MAX as i64)) as i16)
+    }
+    fn read_i8(&mut self) -> DecodeResult<i8> {
+        Ok(try!(self.read_integer("i8", ::std::i8::MIN as i64, ::std::i8::MAX as i64)) as i8)
+    }
+    fn read_bool(&mut self) -> DecodeResult<bool> {
+        match self.pop() {
+            Boolean(v) => Ok(v),
+            other => self.expected("bool", other),
+        }
+    }
+    /// Only doubles, as with `bson::Decoder` when it isn't coercing.
+    fn read_f64(&mut self) -> DecodeResult<f64> {
+        match self.pop() {
+            super::Float(v) => Ok(v),
+            other => self.expected("double", other),
+        }
+    }
+    fn read_f32(&mut self) -> DecodeResult<f32> {
+        let v = try!(self.read_f64());
+        // Infinities and NaN carry over; finite values have to stay finite.
+        if v.is_finite() && v.abs() > ::std::f32::MAX as f64 {
+            return Err(OutOfRange { path: self.path(), value: v.to_string(), target: "f32".to_string() });
+        }
+        Ok(v as f32)
+    }
+    fn read_char(&mut self) -> DecodeResult<char> {
+        match self.pop() {
+            String(ref v) if v.as_slice().char_len() == 1 => Ok(v.as_slice().char_at(0)),
+            String(v) => Err(OutOfRange { path: self.path(), value: format!("\"{}\"", v), target: "char".to_string() }),
+            other => self.expected("string", other),
+        }
+    }
+    fn read_str(&mut self) -> DecodeResult<String> {
+        match self.pop() {
+            String(v) => Ok(v),
+            other => self.expected("string", other),
+        }
+    }
+
+    fn read_enum<T>(&mut self, _name: &str, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        f(self)
+    }
+    fn read_enum_variant<T>(&mut self, names: &[&str], f: |&mut Decoder, uint| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.read_variant(names, f)
+    }
+    fn read_enum_variant_arg<T>(&mut self, a_idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.element(a_idx, Index(a_idx), f)
+    }
+    fn read_enum_struct_variant<T>(&mut self, names: &[&str], f: |&mut Decoder, uint| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.read_variant(names, f)
+    }
+    fn read_enum_struct_variant_field<T>(&mut self, f_name: &str, _f_idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.field(f_name, f)
+    }
+
+    fn read_struct<T>(&mut self, s_name: &str, _len: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        let v = mark(s_name, self.pop());
+        let is_object = match v {
+            super::Object(_) => true,
+            _ => false,
+        };
+        if !is_object {
+            return self.expected("object", v);
+        }
+        self.stack.push(v);
+        let res = f(self);
+        self.stack.pop();
+        res
+    }
+    fn read_struct_field<T>(&mut self, f_name: &str, _f_idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.field(f_name, f)
+    }
+
+    fn read_tuple<T>(&mut self, f: |&mut Decoder, uint| -> DecodeResult<T>) -> DecodeResult<T> {
+        let len = try!(self.list_len());
+        let res = f(self, len);
+        self.stack.pop();
+        res
+    }
+    fn read_tuple_arg<T>(&mut self, a_idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.element(a_idx, Index(a_idx), f)
+    }
+    fn read_tuple_struct<T>(&mut self, _s_name: &str, f: |&mut Decoder, uint| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.read_tuple(f)
+    }
+    fn read_tuple_struct_arg<T>(&mut self, a_idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.read_tuple_arg(a_idx, f)
+    }
+
+    fn read_option<T>(&mut self, f: |&mut Decoder, bool| -> DecodeResult<T>) -> DecodeResult<T> {
+        let none = match self.stack.last() {
+            Some(&Null) | Some(&super::Undefined) => true,
+            _ => false,
+        };
+        if none {
+            self.pop();
+        }
+        f(self, !none)
+    }
+
+    fn read_seq<T>(&mut self, f: |&mut Decoder, uint| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.read_tuple(f)
+    }
+    fn read_seq_elt<T>(&mut self, idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.element(idx, Index(idx), f)
+    }
+
+    /// Maps are read from documents, with string keys.
+    fn read_map<T>(&mut self, f: |&mut Decoder, uint| -> DecodeResult<T>) -> DecodeResult<T> {
+        let obj = match self.pop() {
+            super::Object(obj) => obj,
+            other => return self.expected("object", other),
+        };
+        let len = obj.len();
+        // Laid out as [key, value, key, value, ...] so entries can be taken
+        // by index like array elements.
+        let mut keys = Vec::with_capacity(len);
+        let mut entries = Vec::with_capacity(len * 2);
+        for (k, v) in obj.into_iter() {
+            keys.push(k.clone());
+            entries.push(String(k));
+            entries.push(v);
+        }
+        self.keys.push(keys);
+        self.stack.push(super::List(entries));
+        let res = f(self, len);
+        self.stack.pop();
+        self.keys.pop();
+        res
+    }
+    fn read_map_elt_key<T>(&mut self, idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        let key = self.keys.last().unwrap().as_slice()[idx].clone();
+        self.element(idx * 2, Field(key), f)
+    }
+    fn read_map_elt_val<T>(&mut self, idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        let key = self.keys.last().unwrap().as_slice()[idx].clone();
+        self.element(idx * 2 + 1, Field(key), f)
+    }
+
+    fn error(&mut self, err: &str) -> DecoderError {
+        Custom { path: self.path(), msg: err.to_string() }
+    }
+}
+
+/// Encodes `value` as a `Bson` value.
+pub fn to_bson<T: Encodable<Encoder, io::IoError>>(value: &T) -> io::IoResult<Bson> {
+    let mut encoder = Encoder::new();
+    try!(value.encode(&mut encoder));
+    Ok(encoder.unwrap())
+}
+
+/// Encodes `value` as a document; it has to be a struct or a map.
+pub fn to_document<T: Encodable<Encoder, io::IoError>>(value: &T) -> io::IoResult<Object> {
+    match try!(to_bson(value)) {
+        super::Object(obj) => Ok(obj),
+        other => Err(invalid("only structs and maps encode as documents", format!("found {}", other.type_name()))),
+    }
+}
+
+/// Encodes `value` as the bytes of a BSON document.
+pub fn to_vec<T: Encodable<Encoder, io::IoError>>(value: &T) -> io::IoResult<Vec<u8>> {
+    let doc = try!(to_document(value));
+    Ok(super::encode(&super::Object(doc)))
+}
+
+pub fn from_bson<T: Decodable<Decoder, DecoderError>>(value: Bson) -> DecodeResult<T> {
+    let mut decoder = Decoder::new(value);
+    Decodable::decode(&mut decoder)
+}
+
+pub fn from_document<T: Decodable<Decoder, DecoderError>>(doc: Object) -> DecodeResult<T> {
+    from_bson(super::Object(doc))
+}
+
+/// Decodes a value from the bytes of a BSON document.
+pub fn from_slice<T: Decodable<Decoder, DecoderError>>(bytes: &[u8]) -> DecodeResult<T> {
+    match super::from_slice(bytes) {
+        Ok(v) => from_bson(v),
+        Err(e) => Err(ParseError(e)),
+    }
+}
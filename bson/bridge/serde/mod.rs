@@ -0,0 +1,1018 @@
+//! The same bridge for serde's `Serializable` and `Deserializable`, so
+//! types deriving those for JSON can be stored as BSON too. There are two
+//! targets each way: a `Bson` value, and raw BSON bytes, which are written
+//! and read directly without a `Bson` value in between.
+//!
+//! The `ObjectId`, `DateTime` and `Decimal128` newtypes serialize as structs
+//! with the same marker names as before. The serializers here turn them
+//! into the BSON types, and the deserializers give those types back in the
+//! `{ "$oid": ... }` shape the newtypes read, so any other format sees
+//! Extended JSON.
+//!
+//! Enums are externally tagged: `"Variant"` for unit variants and
+//! `{ "Variant": [args] }` otherwise. Binary data reads as an array of
+//! bytes; the other BSON types serde has no tokens for are errors.
+
+use std::{io,mem};
+use std::collections::{TreeMap,TreeSet};
+use serde::{ser,de};
+
+use super::super::{Bson,Object,String,Boolean,Int32,Int64,Null,UtcDatetime,Binary};
+use super::super::{DecoderError,DecodeResult,ParseError,ExpectedType,MissingField,UnknownField,UnknownVariant,Custom};
+use super::super::{PathElt,Field,Index,format_path,to_hex,from_hex,decimal};
+use super::super::{Parser,ParseResult,SyntaxError,DecoderOptions};
+use super::super::{BadLength,DepthExceeded,DocumentTooLarge,DuplicateKey,MissingTerminator,UnexpectedEndOfInput};
+use super::{ObjectId,DateTime,Decimal128,OBJECT_ID,DATE_TIME,DECIMAL128};
+use super::{invalid,mark,unmark};
+
+static END: &'static [de::TokenKind] = &[de::EndKind];
+static KEY: &'static [de::TokenKind] = &[de::StringKind];
+static MAP_START: &'static [de::TokenKind] = &[de::MapStartKind];
+static SEQ_START: &'static [de::TokenKind] = &[de::SeqStartKind];
+static VARIANT: &'static [de::TokenKind] = &[de::StringKind, de::MapStartKind];
+
+impl<S: ser::Serializer<E>, E> ser::Serializable<S, E> for ObjectId {
+    fn serialize(&self, s: &mut S) -> Result<(), E> {
+        let ObjectId(ref id) = *self;
+        try!(s.serialize_struct_start(OBJECT_ID, 1));
+        try!(s.serialize_struct_elt("$oid", &to_hex(id.as_slice())));
+        s.serialize_struct_end()
+    }
+}
+
+impl<S: ser::Serializer<E>, E> ser::Serializable<S, E> for DateTime {
+    fn serialize(&self, s: &mut S) -> Result<(), E> {
+        let DateTime(ms) = *self;
+        try!(s.serialize_struct_start(DATE_TIME, 1));
+        try!(s.serialize_struct_elt("$date", &ms));
+        s.serialize_struct_end()
+    }
+}
+
+impl<S: ser::Serializer<E>, E> ser::Serializable<S, E> for Decimal128 {
+    fn serialize(&self, s: &mut S) -> Result<(), E> {
+        let Decimal128(ref bytes) = *self;
+        try!(s.serialize_struct_start(DECIMAL128, 1));
+        try!(s.serialize_struct_elt("$numberDecimal", &decimal::to_string(bytes.as_slice())));
+        s.serialize_struct_end()
+    }
+}
+
+/// Reads `{ key: value }`, the struct a marked type is written as.
+fn marked<D: de::Deserializer<E>, E, T: de::Deserializable<D, E>>(d: &mut D, token: de::Token, name: &str, key: &str) -> Result<T, E> {
+    try!(d.expect_struct_start(token, name));
+    match try!(d.expect_token()) {
+        de::String(ref k) if k.as_slice() == key => (),
+        token => return Err(d.unexpected_name_error(token)),
+    }
+    let value = try!(de::Deserializable::deserialize(d));
+    match try!(d.expect_token()) {
+        de::End => Ok(value),
+        token => Err(d.syntax_error(token, END)),
+    }
+}
+
+impl<D: de::Deserializer<E>, E> de::Deserializable<D, E> for ObjectId {
+    fn deserialize_token(d: &mut D, token: de::Token) -> Result<ObjectId, E> {
+        let hex: String = try!(marked(d, token, OBJECT_ID, "$oid"));
+        match from_hex(hex.as_slice()) {
+            Some(id) if id.len() == 12 => Ok(ObjectId(id)),
+            _ => Err(d.conversion_error(de::String(hex))),
+        }
+    }
+}
+
+impl<D: de::Deserializer<E>, E> de::Deserializable<D, E> for DateTime {
+    fn deserialize_token(d: &mut D, token: de::Token) -> Result<DateTime, E> {
+        let ms = try!(marked(d, token, DATE_TIME, "$date"));
+        Ok(DateTime(ms))
+    }
+}
+
+impl<D: de::Deserializer<E>, E> de::Deserializable<D, E> for Decimal128 {
+    fn deserialize_token(d: &mut D, token: de::Token) -> Result<Decimal128, E> {
+        let text: String = try!(marked(d, token, DECIMAL128, "$numberDecimal"));
+        match decimal::parse(text.as_slice()) {
+            Some(bytes) => Ok(Decimal128(bytes)),
+            None => Err(d.conversion_error(de::String(text))),
+        }
+    }
+}
+
+pub type SerializeResult = io::IoResult<()>;
+
+/// A map key as a document key: strings as they are, integers written out.
+fn map_key(key: Option<Bson>) -> io::IoResult<String> {
+    match key {
+        Some(String(s)) => Ok(s),
+        Some(Int32(n)) => Ok(n.to_string()),
+        Some(Int64(n)) => Ok(n.to_string()),
+        other => {
+            let found = other.map_or("nothing", |v| v.type_name());
+            Err(invalid("map keys must be strings", format!("found {}", found)))
+        },
+    }
+}
+
+/// Builds a `Bson` value out of whatever is serialized into it.
+pub struct Serializer {
+    // Documents and arrays still being filled, innermost last, with the
+    // name of the struct or the variant each was started for.
+    containers: Vec<(Bson, String)>,
+    // The value last serialized, waiting to be put in its container.
+    value: Option<Bson>,
+}
+
+impl Serializer {
+    pub fn new() -> Serializer {
+        Serializer { containers: Vec::new(), value: None }
+    }
+
+    /// The value serialized, or null if nothing has been.
+    pub fn unwrap(self) -> Bson {
+        self.value.unwrap_or(Null)
+    }
+
+    fn put(&mut self, v: Bson) -> SerializeResult {
+        self.value = Some(v);
+        Ok(())
+    }
+
+    fn open(&mut self, start: Bson, name: &str) -> SerializeResult {
+        self.containers.push((start, name.to_string()));
+        Ok(())
+    }
+
+    fn close(&mut self) -> (Bson, String) {
+        self.containers.pop().expect("container ended without being started")
+    }
+
+    /// Serializes `v` into the innermost container, under `key` if that's
+    /// a document.
+    fn add<T: ser::Serializable<Serializer, io::IoError>>(&mut self, key: &str, v: &T) -> SerializeResult {
+        try!(v.serialize(self));
+        let v = self.value.take().unwrap_or(Null);
+        match self.containers.last_mut() {
+            Some(&(super::super::Object(ref mut obj), _)) => {
+                obj.insert(key.to_string(), v);
+            },
+            Some(&(super::super::List(ref mut list), _)) => list.push(v),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+impl ser::Serializer<io::IoError> for Serializer {
+    fn serialize_null(&mut self) -> SerializeResult {
+        self.put(Null)
+    }
+    fn serialize_bool(&mut self, v: bool) -> SerializeResult {
+        self.put(Boolean(v))
+    }
+    fn serialize_i64(&mut self, v: i64) -> SerializeResult {
+        self.put(Int64(v))
+    }
+    fn serialize_i32(&mut self, v: i32) -> SerializeResult {
+        self.put(Int32(v))
+    }
+    fn serialize_i16(&mut self, v: i16) -> SerializeResult {
+        self.put(Int32(v as i32))
+    }
+    fn serialize_i8(&mut self, v: i8) -> SerializeResult {
+        self.put(Int32(v as i32))
+    }
+    fn serialize_u64(&mut self, v: u64) -> SerializeResult {
+        if v > ::std::i64::MAX as u64 {
+            return Err(invalid("integer out of range", format!("{} doesn't fit in a long", v)));
+        }
+        self.put(Int64(v as i64))
+    }
+    fn serialize_u32(&mut self, v: u32) -> SerializeResult {
+        self.put(Int64(v as i64))
+    }
+    fn serialize_u16(&mut self, v: u16) -> SerializeResult {
+        self.put(Int32(v as i32))
+    }
+    fn serialize_u8(&mut self, v: u8) -> SerializeResult {
+        self.put(Int32(v as i32))
+    }
+    fn serialize_f64(&mut self, v: f64) -> SerializeResult {
+        self.put(super::super::Float(v))
+    }
+    fn serialize_char(&mut self, v: char) -> SerializeResult {
+        self.put(String(String::from_char(1, v)))
+    }
+    fn serialize_str(&mut self, v: &str) -> SerializeResult {
+        self.put(String(v.to_string()))
+    }
+
+    fn serialize_tuple_start(&mut self, _len: uint) -> SerializeResult {
+        self.open(super::super::List(Vec::new()), "")
+    }
+    fn serialize_tuple_elt<T: ser::Serializable<Serializer, io::IoError>>(&mut self, v: &T) -> SerializeResult {
+        self.add("", v)
+    }
+    fn serialize_tuple_end(&mut self) -> SerializeResult {
+        let (list, _) = self.close();
+        self.put(list)
+    }
+
+    fn serialize_struct_start(&mut self, name: &str, _len: uint) -> SerializeResult {
+        self.open(super::super::Object(TreeMap::new()), name)
+    }
+    fn serialize_struct_elt<T: ser::Serializable<Serializer, io::IoError>>(&mut self, name: &str, v: &T) -> SerializeResult {
+        self.add(name, v)
+    }
+    fn serialize_struct_end(&mut self) -> SerializeResult {
+        let (obj, name) = match self.close() {
+            (super::super::Object(obj), name) => (obj, name),
+            _ => unreachable!(),
+        };
+        let v = try!(unmark(name.as_slice(), obj));
+        self.put(v)
+    }
+
+    fn serialize_enum_start(&mut self, _name: &str, variant: &str, _len: uint) -> SerializeResult {
+        self.open(super::super::List(Vec::new()), variant)
+    }
+    fn serialize_enum_elt<T: ser::Serializable<Serializer, io::IoError>>(&mut self, v: &T) -> SerializeResult {
+        self.add("", v)
+    }
+    fn serialize_enum_end(&mut self) -> SerializeResult {
+        let (args, variant) = self.close();
+        let unit = match args {
+            super::super::List(ref args) => args.is_empty(),
+            _ => unreachable!(),
+        };
+        if unit {
+            return self.put(String(variant));
+        }
+        let mut obj = TreeMap::new();
+        obj.insert(variant, args);
+        self.put(super::super::Object(obj))
+    }
+
+    fn serialize_option<T: ser::Serializable<Serializer, io::IoError>>(&mut self, v: &Option<T>) -> SerializeResult {
+        match *v {
+            Some(ref v) => v.serialize(self),
+            None => self.put(Null),
+        }
+    }
+    fn serialize_seq<T: ser::Serializable<Serializer, io::IoError>, Iter: Iterator<T>>(&mut self, iter: Iter) -> SerializeResult {
+        try!(self.open(super::super::List(Vec::new()), ""));
+        for v in iter {
+            try!(self.add("", &v));
+        }
+        let (list, _) = self.close();
+        self.put(list)
+    }
+    fn serialize_map<K: ser::Serializable<Serializer, io::IoError>,
+                     V: ser::Serializable<Serializer, io::IoError>,
+                     Iter: Iterator<(K, V)>>(&mut self, iter: Iter) -> SerializeResult {
+        try!(self.open(super::super::Object(TreeMap::new()), ""));
+        for (k, v) in iter {
+            try!(k.serialize(self));
+            let key = try!(map_key(self.value.take()));
+            try!(self.add(key.as_slice(), &v));
+        }
+        let (obj, _) = self.close();
+        self.put(obj)
+    }
+}
+
+/// What a document being written stands for.
+enum Written {
+    Document,
+    Array,
+    /// A variant with arguments: a document holding the array of them.
+    Variant,
+    /// A marked struct, whose fields are kept to be turned into its value.
+    Marked(String, Object),
+    /// A variant with nothing to write after its name.
+    UnitVariant,
+}
+
+struct WriteFrame {
+    // Where the length goes, for the frames that write one.
+    start: uint,
+    // Elements written so far, which are the keys of an array's.
+    len: uint,
+    written: Written,
+}
+
+/// Writes BSON bytes as values are serialized, without building a `Bson`
+/// value first. The value has to be a struct or a map. Fields are written
+/// in the order they're serialized, so struct fields keep theirs.
+pub struct RawSerializer {
+    buf: Vec<u8>,
+    // Documents and arrays being written, innermost last.
+    frames: Vec<WriteFrame>,
+    // Where the type byte of the next value goes, once there's a key.
+    type_pos: Option<uint>,
+    // Map keys and the fields of marked structs are kept here instead of
+    // being written.
+    capture: bool,
+    captured: Option<Bson>,
+}
+
+impl RawSerializer {
+    pub fn new() -> RawSerializer {
+        RawSerializer { buf: Vec::new(), frames: Vec::new(), type_pos: None, capture: false, captured: None }
+    }
+
+    pub fn unwrap(self) -> Vec<u8> {
+        self.buf
+    }
+
+    fn push_le(&mut self, v: u64, bytes: uint) {
+        for i in range(0, bytes) {
+            self.buf.push((v >> (8 * i)) as u8);
+        }
+    }
+
+    fn push_cstring(&mut self, s: &str) {
+        self.buf.push_all(s.as_bytes());
+        self.buf.push(0x00);
+    }
+
+    /// Sets the type byte of the value about to be written.
+    fn value_type(&mut self, t: u8) -> SerializeResult {
+        match self.type_pos.take() {
+            Some(pos) => {
+                *self.buf.get_mut(pos) = t;
+                Ok(())
+            },
+            None if self.frames.is_empty() && self.buf.is_empty() && t == 0x03 => Ok(()),
+            None => Err(invalid("only structs and maps encode as documents", format!("found type {:#04x}", t))),
+        }
+    }
+
+    /// Starts an element of the innermost document or array.
+    fn key(&mut self, key: Option<&str>) {
+        let index = {
+            let frame = self.frames.last_mut().expect("element written outside a document");
+            frame.len += 1;
+            frame.len - 1
+        };
+        self.type_pos = Some(self.buf.len());
+        self.buf.push(0x00);
+        match key {
+            Some(key) => self.push_cstring(key),
+            None => self.push_cstring(index.to_string().as_slice()),
+        }
+    }
+
+    fn open(&mut self, t: u8, written: Written) -> SerializeResult {
+        if self.capture {
+            return Err(invalid("expected a single value", "found a document or array".to_string()));
+        }
+        try!(self.value_type(t));
+        let start = self.buf.len();
+        self.push_le(0, 4);
+        self.frames.push(WriteFrame { start: start, len: 0, written: written });
+        Ok(())
+    }
+
+    /// Ends the innermost document or array, filling in its length.
+    fn close(&mut self) -> SerializeResult {
+        let frame = self.frames.pop().expect("document ended without being started");
+        self.buf.push(0x00);
+        let len = self.buf.len() - frame.start;
+        for i in range(0u, 4) {
+            *self.buf.get_mut(frame.start + i) = (len >> (8 * i)) as u8;
+        }
+        Ok(())
+    }
+
+    /// Writes a value with no parts of its own to serialize.
+    fn scalar(&mut self, v: Bson) -> SerializeResult {
+        if self.capture {
+            self.captured = Some(v);
+            return Ok(());
+        }
+        match v {
+            super::super::Float(v) => {
+                try!(self.value_type(0x01));
+                self.push_le(unsafe { mem::transmute::<f64, u64>(v) }, 8);
+            },
+            String(s) => {
+                try!(self.value_type(0x02));
+                self.push_le(s.len() as u64 + 1, 4);
+                self.push_cstring(s.as_slice());
+            },
+            super::super::ObjectId(id) => {
+                try!(self.value_type(0x07));
+                self.buf.push_all(id.as_slice());
+            },
+            Boolean(b) => {
+                try!(self.value_type(0x08));
+                self.buf.push(b as u8);
+            },
+            UtcDatetime(ms) => {
+                try!(self.value_type(0x09));
+                self.push_le(ms as u64, 8);
+            },
+            Null => try!(self.value_type(0x0A)),
+            Int32(n) => {
+                try!(self.value_type(0x10));
+                self.push_le(n as u32 as u64, 4);
+            },
+            Int64(n) => {
+                try!(self.value_type(0x12));
+                self.push_le(n as u64, 8);
+            },
+            super::super::Decimal128(bytes) => {
+                try!(self.value_type(0x13));
+                self.buf.push_all(bytes.as_slice());
+            },
+            other => unreachable!("{} isn't written by the serializer", other.type_name()),
+        }
+        Ok(())
+    }
+}
+
+impl ser::Serializer<io::IoError> for RawSerializer {
+    fn serialize_null(&mut self) -> SerializeResult {
+        self.scalar(Null)
+    }
+    fn serialize_bool(&mut self, v: bool) -> SerializeResult {
+        self.scalar(Boolean(v))
+    }
+    fn serialize_i64(&mut self, v: i64) -> SerializeResult {
+        self.scalar(Int64(v))
+    }
+    fn serialize_i32(&mut self, v: i32) -> SerializeResult {
+        self.scalar(Int32(v))
+    }
+    fn serialize_i16(&mut self, v: i16) -> SerializeResult {
+        self.scalar(Int32(v as i32))
+    }
+    fn serialize_i8(&mut self, v: i8) -> SerializeResult {
+        self.scalar(Int32(v as i32))
+    }
+    fn serialize_u64(&mut self, v: u64) -> SerializeResult {
+        if v > ::std::i64::MAX as u64 {
+            return Err(invalid("integer out of range", format!("{} doesn't fit in a long", v)));
+        }
+        self.scalar(Int64(v as i64))
+    }
+    fn serialize_u32(&mut self, v: u32) -> SerializeResult {
+        self.scalar(Int64(v as i64))
+    }
+    fn serialize_u16(&mut self, v: u16) -> SerializeResult {
+        self.scalar(Int32(v as i32))
+    }
+    fn serialize_u8(&mut self, v: u8) -> SerializeResult {
+        self.scalar(Int32(v as i32))
+    }
+    fn serialize_f64(&mut self, v: f64) -> SerializeResult {
+        self.scalar(super::super::Float(v))
+    }
+    fn serialize_char(&mut self, v: char) -> SerializeResult {
+        self.scalar(String(String::from_char(1, v)))
+    }
+    fn serialize_str(&mut self, v: &str) -> SerializeResult {
+        self.scalar(String(v.to_string()))
+    }
+
+    fn serialize_tuple_start(&mut self, _len: uint) -> SerializeResult {
+        self.open(0x04, Array)
+    }
+    fn serialize_tuple_elt<T: ser::Serializable<RawSerializer, io::IoError>>(&mut self, v: &T) -> SerializeResult {
+        self.key(None);
+        v.serialize(self)
+    }
+    fn serialize_tuple_end(&mut self) -> SerializeResult {
+        self.close()
+    }
+
+    fn serialize_struct_start(&mut self, name: &str, _len: uint) -> SerializeResult {
+        if name != OBJECT_ID && name != DATE_TIME && name != DECIMAL128 {
+            return self.open(0x03, Document);
+        }
+        if self.capture {
+            return Err(invalid("expected a single value", format!("found {}", name)));
+        }
+        self.frames.push(WriteFrame { start: 0, len: 0, written: Marked(name.to_string(), TreeMap::new()) });
+        self.capture = true;
+        Ok(())
+    }
+    fn serialize_struct_elt<T: ser::Serializable<RawSerializer, io::IoError>>(&mut self, name: &str, v: &T) -> SerializeResult {
+        let marked = match self.frames.last() {
+            Some(&WriteFrame { written: Marked(..), .. }) => true,
+            _ => false,
+        };
+        if !marked {
+            self.key(Some(name));
+            return v.serialize(self);
+        }
+        try!(v.serialize(self));
+        let v = self.captured.take().unwrap_or(Null);
+        match self.frames.last_mut() {
+            Some(&WriteFrame { written: Marked(_, ref mut fields), .. }) => {
+                fields.insert(name.to_string(), v);
+            },
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+    fn serialize_struct_end(&mut self) -> SerializeResult {
+        match self.frames.last() {
+            Some(&WriteFrame { written: Marked(..), .. }) => (),
+            _ => return self.close(),
+        }
+        let (name, fields) = match self.frames.pop() {
+            Some(WriteFrame { written: Marked(name, fields), .. }) => (name, fields),
+            _ => unreachable!(),
+        };
+        self.capture = false;
+        let v = try!(unmark(name.as_slice(), fields));
+        self.scalar(v)
+    }
+
+    fn serialize_enum_start(&mut self, _name: &str, variant: &str, len: uint) -> SerializeResult {
+        if len == 0 {
+            try!(self.scalar(String(variant.to_string())));
+            self.frames.push(WriteFrame { start: 0, len: 0, written: UnitVariant });
+            return Ok(());
+        }
+        try!(self.open(0x03, Variant));
+        self.key(Some(variant));
+        self.open(0x04, Array)
+    }
+    fn serialize_enum_elt<T: ser::Serializable<RawSerializer, io::IoError>>(&mut self, v: &T) -> SerializeResult {
+        self.key(None);
+        v.serialize(self)
+    }
+    fn serialize_enum_end(&mut self) -> SerializeResult {
+        match self.frames.last() {
+            Some(&WriteFrame { written: UnitVariant, .. }) => {
+                self.frames.pop();
+                return Ok(());
+            },
+            _ => (),
+        }
+        // The array of arguments, then the document around it.
+        try!(self.close());
+        self.close()
+    }
+
+    fn serialize_option<T: ser::Serializable<RawSerializer, io::IoError>>(&mut self, v: &Option<T>) -> SerializeResult {
+        match *v {
+            Some(ref v) => v.serialize(self),
+            None => self.scalar(Null),
+        }
+    }
+    fn serialize_seq<T: ser::Serializable<RawSerializer, io::IoError>, Iter: Iterator<T>>(&mut self, iter: Iter) -> SerializeResult {
+        try!(self.open(0x04, Array));
+        for v in iter {
+            self.key(None);
+            try!(v.serialize(self));
+        }
+        self.close()
+    }
+    fn serialize_map<K: ser::Serializable<RawSerializer, io::IoError>,
+                     V: ser::Serializable<RawSerializer, io::IoError>,
+                     Iter: Iterator<(K, V)>>(&mut self, iter: Iter) -> SerializeResult {
+        try!(self.open(0x03, Document));
+        for (k, v) in iter {
+            self.capture = true;
+            let res = k.serialize(self);
+            self.capture = false;
+            try!(res);
+            let key = try!(map_key(self.captured.take()));
+            self.key(Some(key.as_slice()));
+            try!(v.serialize(self));
+        }
+        self.close()
+    }
+}
+
+/// A token still to be given out, with the path of the value it's part of.
+type Pending = (DecodeResult<de::Token>, String);
+
+/// Lays `v` out as tokens, in order, onto `out`.
+fn bson_tokens(v: Bson, path: &mut Vec<PathElt>, out: &mut Vec<Pending>) {
+    let here = format_path(path.as_slice());
+    let token = match v {
+        super::super::Float(v) => Ok(de::F64(v)),
+        String(s) => Ok(de::String(s)),
+        Boolean(b) => Ok(de::Bool(b)),
+        Null => Ok(de::Null),
+        Int32(n) => Ok(de::I32(n)),
+        Int64(n) => Ok(de::I64(n)),
+        super::super::List(items) => {
+            out.push((Ok(de::SeqStart(items.len())), here.clone()));
+            for (i, item) in items.into_iter().enumerate() {
+                path.push(Index(i));
+                bson_tokens(item, path, out);
+                path.pop();
+            }
+            Ok(de::End)
+        },
+        super::super::Object(obj) => {
+            out.push((Ok(de::MapStart(obj.len())), here.clone()));
+            for (key, value) in obj.into_iter() {
+                path.push(Field(key.clone()));
+                out.push((Ok(de::String(key)), format_path(path.as_slice())));
+                bson_tokens(value, path, out);
+                path.pop();
+            }
+            Ok(de::End)
+        },
+        Binary(_, bytes) => {
+            out.push((Ok(de::SeqStart(bytes.len())), here.clone()));
+            for b in bytes.into_iter() {
+                out.push((Ok(de::U8(b)), here.clone()));
+            }
+            Ok(de::End)
+        },
+        // Given in the shape the marked newtypes read.
+        super::super::ObjectId(id) => return bson_tokens(mark(OBJECT_ID, super::super::ObjectId(id)), path, out),
+        UtcDatetime(ms) => return bson_tokens(mark(DATE_TIME, UtcDatetime(ms)), path, out),
+        super::super::Decimal128(bytes) => {
+            return bson_tokens(mark(DECIMAL128, super::super::Decimal128(bytes)), path, out)
+        },
+        other => Err(Custom { path: here.clone(), msg: format!("{} has no serde equivalent", other.type_name()) }),
+    };
+    out.push((token, here));
+}
+
+fn token_name(token: &de::Token) -> &'static str {
+    match *token {
+        de::Null => "null",
+        de::Bool(_) => "bool",
+        de::I32(_) => "int",
+        de::I64(_) => "long",
+        de::F64(_) => "double",
+        de::Char(_) | de::Str(_) | de::String(_) => "string",
+        de::Option(_) => "option",
+        de::TupleStart(_) | de::SeqStart(_) => "array",
+        de::StructStart(..) | de::EnumStart(..) | de::MapStart(_) => "object",
+        de::End => "end of document",
+        _ => "number",
+    }
+}
+
+fn kind_names(kinds: &[de::TokenKind]) -> String {
+    let mut names: Vec<&str> = kinds.iter().map(|kind| match *kind {
+        de::NullKind => "null",
+        de::BoolKind => "bool",
+        de::CharKind | de::StrKind | de::StringKind => "string",
+        de::OptionKind => "option",
+        de::TupleStartKind | de::SeqStartKind => "array",
+        de::StructStartKind | de::EnumStartKind | de::MapStartKind => "object",
+        de::EndKind => "end of document",
+        _ => "number",
+    }).collect();
+    names.dedup();
+    names.connect(" or ")
+}
+
+fn field_path(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+/// A document or array being read from raw bytes.
+struct ReadFrame {
+    start: uint,
+    end: uint,
+    array: bool,
+    // Elements read so far, and a document's keys, which can't repeat.
+    len: uint,
+    keys: TreeSet<String>,
+}
+
+/// The state of a read from raw bytes. The parser is made afresh for each
+/// element, since it borrows the options kept here.
+struct RawInput<'a> {
+    buf: &'a [u8],
+    pos: uint,
+    // The keys leading to the element being read, for parse errors.
+    keys: Vec<String>,
+    options: DecoderOptions,
+    frames: Vec<ReadFrame>,
+}
+
+/// Starts reading a document or array whose length is next in the buffer,
+/// checking the length the way `Parser::read_elements` does.
+fn open_raw(parser: &mut Parser, frames: &mut Vec<ReadFrame>, array: bool) -> ParseResult<()> {
+    let start = parser.pos;
+    if frames.len() >= parser.options.max_depth {
+        return parser.error_at(DepthExceeded, start);
+    }
+    let len = try!(parser.read_i32());
+    if len < 5 {
+        return parser.error_at(BadLength, start);
+    }
+    let end = start + len as uint;
+    if end > parser.buf.len() {
+        return parser.error_at(UnexpectedEndOfInput, start);
+    }
+    if parser.buf[end - 1] != 0x00 {
+        return parser.error_at(MissingTerminator, end - 1);
+    }
+    frames.push(ReadFrame { start: start, end: end, array: array, len: 0, keys: TreeSet::new() });
+    Ok(())
+}
+
+/// Reads the next element from the bytes, or the end of the document or
+/// array it would be in, adding its tokens to `out` in order.
+fn read_raw(parser: &mut Parser, frames: &mut Vec<ReadFrame>, path: &mut Vec<PathElt>, out: &mut Vec<Pending>) -> ParseResult<()> {
+    let (start, end, array, index) = match frames.last() {
+        Some(frame) => (frame.start, frame.end, frame.array, frame.len),
+        None if parser.pos == 0 => {
+            if parser.buf.len() > parser.options.max_document_size {
+                return Err(SyntaxError(DocumentTooLarge, 0, String::new()));
+            }
+            try!(open_raw(parser, frames, false));
+            out.push((Ok(de::MapStart(0)), String::new()));
+            return Ok(());
+        },
+        // The document has been read.
+        None => return Ok(()),
+    };
+    if parser.pos >= end {
+        return parser.error_at(BadLength, start);
+    }
+    let type_pos = parser.pos;
+    let t = try!(parser.read_u8());
+    if t == 0x00 {
+        if parser.pos != end {
+            return parser.error_at(BadLength, start);
+        }
+        frames.pop();
+        out.push((Ok(de::End), format_path(path.as_slice())));
+        if frames.is_empty() {
+            if parser.pos != parser.buf.len() {
+                // The declared length stopped short of the end of the buffer.
+                return parser.error_at(BadLength, 0);
+            }
+        } else {
+            path.pop();
+            parser.path.pop();
+        }
+        return Ok(());
+    }
+    let key = try!(parser.read_cstring());
+    {
+        let frame = frames.last_mut().unwrap();
+        frame.len += 1;
+        if !array && !frame.keys.insert(key.clone()) {
+            parser.path.push(key);
+            return parser.error_at(DuplicateKey, type_pos);
+        }
+    }
+    parser.path.push(key.clone());
+    path.push(if array { Index(index) } else { Field(key.clone()) });
+    if !array {
+        out.push((Ok(de::String(key)), format_path(path.as_slice())));
+    }
+    match t {
+        // Left open until their ends are read.
+        0x03 => {
+            try!(open_raw(parser, frames, false));
+            out.push((Ok(de::MapStart(0)), format_path(path.as_slice())));
+        },
+        0x04 => {
+            try!(open_raw(parser, frames, true));
+            out.push((Ok(de::SeqStart(0)), format_path(path.as_slice())));
+        },
+        _ => {
+            let v = try!(parser.read_element(t, type_pos, None));
+            bson_tokens(v, path, out);
+            path.pop();
+            parser.path.pop();
+        },
+    }
+    Ok(())
+}
+
+/// Gives out the tokens of a `Bson` value, or of a document read straight
+/// from its bytes. Errors carry the path of the value that failed, as
+/// `bson::Decoder`'s do.
+pub struct Deserializer<'a> {
+    // Tokens still to give out, last first.
+    pending: Vec<Pending>,
+    // The path of the token given out last.
+    path: String,
+    // The path of the element being read from raw bytes.
+    raw_path: Vec<PathElt>,
+    raw: Option<RawInput<'a>>,
+    // For each enum being read, whether its variant came wrapped in a
+    // document and array that still have to end.
+    enums: Vec<bool>,
+}
+
+impl<'a> Deserializer<'a> {
+    pub fn new(v: Bson) -> Deserializer<'a> {
+        let mut tokens = Vec::new();
+        bson_tokens(v, &mut Vec::new(), &mut tokens);
+        tokens.reverse();
+        Deserializer { pending: tokens, path: String::new(), raw_path: Vec::new(), raw: None, enums: Vec::new() }
+    }
+
+    /// Reads a document from `bytes`, checked as `bson::from_slice` checks
+    /// them as it goes. A projection in `options` is ignored, and a
+    /// repeated key is always an error, since nothing is kept to replace.
+    pub fn from_slice(bytes: &'a [u8], options: DecoderOptions) -> Deserializer<'a> {
+        let raw = RawInput { buf: bytes, pos: 0, keys: Vec::new(), options: options, frames: Vec::new() };
+        Deserializer { pending: Vec::new(), path: String::new(), raw_path: Vec::new(), raw: Some(raw), enums: Vec::new() }
+    }
+
+    /// Reads on through the raw bytes, if that's what there is, until
+    /// there's a token to give out.
+    fn fill(&mut self) -> ParseResult<()> {
+        let raw = match self.raw {
+            Some(ref mut raw) => raw,
+            None => return Ok(()),
+        };
+        let mut parser = Parser {
+            buf: raw.buf,
+            pos: raw.pos,
+            path: mem::replace(&mut raw.keys, Vec::new()),
+            options: &raw.options,
+        };
+        let mut tokens = Vec::new();
+        let res = read_raw(&mut parser, &mut raw.frames, &mut self.raw_path, &mut tokens);
+        raw.pos = parser.pos;
+        raw.keys = parser.path;
+        self.pending.extend(tokens.into_iter().rev());
+        res
+    }
+
+    fn token(&mut self) -> DecodeResult<de::Token> {
+        match self.next() {
+            Some(token) => token,
+            None => Err(self.end_of_input()),
+        }
+    }
+
+    fn end_of_input(&self) -> DecoderError {
+        Custom { path: self.path.clone(), msg: "unexpected end of input".to_string() }
+    }
+
+    fn expected(&self, token: &de::Token, expected: &[de::TokenKind]) -> DecoderError {
+        ExpectedType { path: self.path.clone(), expected: kind_names(expected), found: token_name(token).to_string() }
+    }
+}
+
+impl<'a> Iterator<DecodeResult<de::Token>> for Deserializer<'a> {
+    fn next(&mut self) -> Option<DecodeResult<de::Token>> {
+        if self.pending.is_empty() {
+            match self.fill() {
+                Ok(()) => (),
+                Err(e) => {
+                    self.raw = None;
+                    return Some(Err(ParseError(e)));
+                },
+            }
+        }
+        match self.pending.pop() {
+            Some((token, path)) => {
+                self.path = path;
+                Some(token)
+            },
+            None => None,
+        }
+    }
+}
+
+impl<'a> de::Deserializer<DecoderError> for Deserializer<'a> {
+    fn end_of_stream_error(&mut self) -> DecoderError {
+        self.end_of_input()
+    }
+
+    fn syntax_error(&mut self, token: de::Token, expected: &'static [de::TokenKind]) -> DecoderError {
+        self.expected(&token, expected)
+    }
+
+    /// Names come with the path of the value they name.
+    fn unexpected_name_error(&mut self, _token: de::Token) -> DecoderError {
+        UnknownField { path: self.path.clone() }
+    }
+
+    fn conversion_error(&mut self, token: de::Token) -> DecoderError {
+        Custom { path: self.path.clone(), msg: format!("can't convert {}", token_name(&token)) }
+    }
+
+    /// A missing field reads as null, so that options come out as `None`.
+    fn missing_field<T: de::Deserializable<Deserializer<'a>, DecoderError>>(&mut self, field: &'static str) -> DecodeResult<T> {
+        match de::Deserializable::deserialize_token(self, de::Null) {
+            Ok(v) => Ok(v),
+            Err(_) => Err(MissingField { path: field_path(self.path.as_slice(), field) }),
+        }
+    }
+
+    fn expect_option<T: de::Deserializable<Deserializer<'a>, DecoderError>>(&mut self, token: de::Token) -> DecodeResult<Option<T>> {
+        match token {
+            de::Null => Ok(None),
+            token => Ok(Some(try!(de::Deserializable::deserialize_token(self, token)))),
+        }
+    }
+
+    fn expect_struct_start(&mut self, token: de::Token, _name: &str) -> DecodeResult<()> {
+        match token {
+            de::MapStart(_) => Ok(()),
+            token => Err(self.expected(&token, MAP_START)),
+        }
+    }
+
+    fn expect_enum_start(&mut self, token: de::Token, _name: &str, variants: &[&str]) -> DecodeResult<uint> {
+        let (variant, wrapped) = match token {
+            de::String(variant) => (variant, false),
+            de::MapStart(_) => {
+                let variant = match try!(self.token()) {
+                    de::String(variant) => variant,
+                    token => return Err(self.expected(&token, KEY)),
+                };
+                match try!(self.token()) {
+                    de::SeqStart(_) => (),
+                    token => return Err(self.expected(&token, SEQ_START)),
+                }
+                (variant, true)
+            },
+            token => return Err(self.expected(&token, VARIANT)),
+        };
+        match variants.iter().position(|v| *v == variant.as_slice()) {
+            Some(idx) => {
+                self.enums.push(wrapped);
+                Ok(idx)
+            },
+            None => Err(UnknownVariant {
+                path: self.path.clone(),
+                variant: variant,
+                expected: variants.iter().map(|v| v.to_string()).collect(),
+            }),
+        }
+    }
+
+    fn expect_enum_end(&mut self) -> DecodeResult<()> {
+        if self.enums.pop() == Some(true) {
+            // The end of the arguments, then of the document around them.
+            for _ in range(0u, 2) {
+                match try!(self.token()) {
+                    de::End => (),
+                    token => return Err(self.expected(&token, END)),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Serializes `value` as a `Bson` value.
+pub fn to_bson<T: ser::Serializable<Serializer, io::IoError>>(value: &T) -> io::IoResult<Bson> {
+    let mut serializer = Serializer::new();
+    try!(value.serialize(&mut serializer));
+    Ok(serializer.unwrap())
+}
+
+/// Serializes `value` as a document; it has to be a struct or a map.
+pub fn to_document<T: ser::Serializable<Serializer, io::IoError>>(value: &T) -> io::IoResult<Object> {
+    match try!(to_bson(value)) {
+        super::super::Object(obj) => Ok(obj),
+        other => Err(invalid("only structs and maps encode as documents", format!("found {}", other.type_name()))),
+    }
+}
+
+/// Serializes `value` straight to the bytes of a BSON document.
+pub fn to_vec<T: ser::Serializable<RawSerializer, io::IoError>>(value: &T) -> io::IoResult<Vec<u8>> {
+    let mut serializer = RawSerializer::new();
+    try!(value.serialize(&mut serializer));
+    if serializer.buf.is_empty() {
+        return Err(invalid("only structs and maps encode as documents", "found nothing".to_string()));
+    }
+    Ok(serializer.unwrap())
+}
+
+/// Everything has to be read: a token after the value is an error.
+fn finish<'a, T>(d: &mut Deserializer<'a>, value: T) -> DecodeResult<T> {
+    match d.next() {
+        None => Ok(value),
+        Some(Err(e)) => Err(e),
+        Some(Ok(token)) => Err(d.expected(&token, END)),
+    }
+}
+
+pub fn from_bson<T: de::Deserializable<Deserializer<'static>, DecoderError>>(value: Bson) -> DecodeResult<T> {
+    let mut d = Deserializer::new(value);
+    let value = try!(de::Deserializable::deserialize(&mut d));
+    finish(&mut d, value)
+}
+
+pub fn from_document<T: de::Deserializable<Deserializer<'static>, DecoderError>>(doc: Object) -> DecodeResult<T> {
+    from_bson(super::super::Object(doc))
+}
+
+/// Deserializes a value straight from the bytes of a BSON document.
+pub fn from_slice<'a, T: de::Deserializable<Deserializer<'a>, DecoderError>>(bytes: &'a [u8]) -> DecodeResult<T> {
+    let mut d = Deserializer::from_slice(bytes, DecoderOptions::new());
+    let value = try!(de::Deserializable::deserialize(&mut d));
+    finish(&mut d, value)
+}
@@ -0,0 +1,684 @@
+use std::{fmt,mem,str,f64,i32,i64};
+use std::num::Float;
+use std::collections::TreeMap;
+
+use super::{Bson,Object,String,Boolean,Null,Undefined,Binary,UtcDatetime,RegExp,DbPointer,JavaScript};
+use super::{Symbol,JavaScriptWithScope,Int32,Timestamp,Int64,Decimal128,MinKey,MaxKey,RawString};
+use super::{PathElt,Field,Index,format_path,MAX_DEPTH};
+use super::{datetime,decimal};
+use super::extjson::to_relaxed_extjson;
+
+/// IANA-registered tags with a BSON counterpart.
+pub static TAG_DATE_STRING: u64 = 0;
+pub static TAG_EPOCH_DATE: u64 = 1;
+pub static TAG_POSITIVE_BIGNUM: u64 = 2;
+pub static TAG_NEGATIVE_BIGNUM: u64 = 3;
+pub static TAG_DECIMAL_FRACTION: u64 = 4;
+pub static TAG_REGEX: u64 = 35;
+pub static TAG_UUID: u64 = 37;
+pub static TAG_SELF_DESCRIBED: u64 = 55799;
+
+/// Types with no registered tag get one of these: `0x42534f4e` ("BSON")
+/// followed by the BSON element type byte, in the first-come-first-served
+/// range. They're this crate's own, not IANA's.
+pub static TAG_BSON: u64 = 0x42534f4e00;
+pub static TAG_BINARY: u64 = TAG_BSON | 0x05;
+pub static TAG_OBJECT_ID: u64 = TAG_BSON | 0x07;
+pub static TAG_DB_POINTER: u64 = TAG_BSON | 0x0c;
+pub static TAG_JAVASCRIPT: u64 = TAG_BSON | 0x0d;
+pub static TAG_SYMBOL: u64 = TAG_BSON | 0x0e;
+pub static TAG_JAVASCRIPT_WITH_SCOPE: u64 = TAG_BSON | 0x0f;
+pub static TAG_TIMESTAMP: u64 = TAG_BSON | 0x11;
+pub static TAG_DECIMAL128: u64 = TAG_BSON | 0x13;
+pub static TAG_MAX_KEY: u64 = TAG_BSON | 0x7f;
+pub static TAG_MIN_KEY: u64 = TAG_BSON | 0xff;
+
+static MAJOR_UNSIGNED: u8 = 0;
+static MAJOR_NEGATIVE: u8 = 1;
+static MAJOR_BYTES: u8 = 2;
+static MAJOR_TEXT: u8 = 3;
+static MAJOR_ARRAY: u8 = 4;
+static MAJOR_MAP: u8 = 5;
+static MAJOR_TAG: u8 = 6;
+static MAJOR_SIMPLE: u8 = 7;
+static BREAK: u8 = 0xff;
+
+/// A conversion that couldn't keep everything: where, and what was lost.
+#[deriving(PartialEq, Clone)]
+pub struct Loss {
+    pub path: String,
+    pub msg: String,
+}
+
+impl fmt::Show for Loss {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.msg)
+        } else {
+            write!(f, "{}: {}", self.path, self.msg)
+        }
+    }
+}
+
+#[deriving(PartialEq, Clone)]
+pub struct CborError {
+    pub offset: uint,
+    pub msg: String,
+}
+
+impl fmt::Show for CborError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.msg, self.offset)
+    }
+}
+
+pub type CborResult<T> = Result<T, CborError>;
+
+struct Writer {
+    out: Vec<u8>,
+    path: Vec<PathElt>,
+    losses: Vec<Loss>,
+}
+
+impl Writer {
+    fn lossy(&mut self, msg: &str) {
+        self.losses.push(Loss { path: format_path(self.path.as_slice()), msg: msg.to_string() });
+    }
+
+    fn big_endian(&mut self, n: u64, width: uint) {
+        for i in range(0, width).rev() {
+            self.out.push((n >> (8 * i)) as u8);
+        }
+    }
+
+    /// The initial byte and argument, in as few bytes as will hold it.
+    fn head(&mut self, major: u8, n: u64) {
+        let major = major << 5;
+        if n < 24 {
+            self.out.push(major | n as u8);
+        } else if n <= 0xff {
+            self.out.push(major | 24);
+            self.big_endian(n, 1);
+        } else if n <= 0xffff {
+            self.out.push(major | 25);
+            self.big_endian(n, 2);
+        } else if n <= 0xffff_ffff {
+            self.out.push(major | 26);
+            self.big_endian(n, 4);
+        } else {
+            self.out.push(major | 27);
+            self.big_endian(n, 8);
+        }
+    }
+
+    /// Int64s always take an eight-byte argument, which is still valid
+    /// CBOR, so they read back as Int64s whatever their value.
+    fn integer(&mut self, n: i64, wide: bool) {
+        let (major, arg) = if n >= 0 { (MAJOR_UNSIGNED, n as u64) } else { (MAJOR_NEGATIVE, (-1 - n) as u64) };
+        if wide {
+            self.out.push((major << 5) | 27);
+            self.big_endian(arg, 8);
+        } else {
+            self.head(major, arg);
+        }
+    }
+
+    fn double(&mut self, f: f64) {
+        self.out.push((MAJOR_SIMPLE << 5) | 27);
+        self.big_endian(unsafe { mem::transmute::<f64, u64>(f) }, 8);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.head(MAJOR_BYTES, bytes.len() as u64);
+        self.out.push_all(bytes);
+    }
+
+    fn text(&mut self, s: &str) {
+        self.head(MAJOR_TEXT, s.len() as u64);
+        self.out.push_all(s.as_bytes());
+    }
+
+    fn object(&mut self, obj: &Object) {
+        self.head(MAJOR_MAP, obj.len() as u64);
+        for (k, v) in obj.iter() {
+            self.text(k.as_slice());
+            self.path.push(Field(k.clone()));
+            self.value(v);
+            self.path.pop();
+        }
+    }
+
+    fn value(&mut self, v: &Bson) {
+        match *v {
+            super::ObjectId(ref id) => {
+                self.head(MAJOR_TAG, TAG_OBJECT_ID);
+                self.bytes(id.as_slice());
+            },
+            super::Float(f) => self.double(f),
+            String(ref s) => self.text(s.as_slice()),
+            Boolean(b) => self.out.push(if b { 0xf5 } else { 0xf4 }),
+            super::List(ref items) => {
+                self.head(MAJOR_ARRAY, items.len() as u64);
+                for (i, item) in items.iter().enumerate() {
+                    self.path.push(Index(i));
+                    self.value(item);
+                    self.path.pop();
+                }
+            },
+            super::Object(ref obj) => self.object(obj),
+            Null => self.out.push(0xf6),
+            Undefined => self.out.push(0xf7),
+            Binary(0, ref data) => self.bytes(data.as_slice()),
+            Binary(4, ref data) if data.len() == 16 => {
+                self.head(MAJOR_TAG, TAG_UUID);
+                self.bytes(data.as_slice());
+            },
+            Binary(subtype, ref data) => {
+                self.head(MAJOR_TAG, TAG_BINARY);
+                self.head(MAJOR_ARRAY, 2);
+                self.head(MAJOR_UNSIGNED, subtype as u64);
+                self.bytes(data.as_slice());
+            },
+            UtcDatetime(ms) => {
+                self.head(MAJOR_TAG, TAG_EPOCH_DATE);
+                if ms % 1000 == 0 {
+                    self.integer(ms / 1000, false);
+                } else {
+                    let secs = ms as f64 / 1000.0;
+                    // What a reader gets back, kept in range for the cast.
+                    let back = (secs * 1000.0).round();
+                    if !(back >= i64::MIN as f64 && back < i64::MAX as f64) || back as i64 != ms {
+                        self.lossy("date is too far out for a double of seconds to keep its milliseconds");
+                    }
+                    self.double(secs);
+                }
+            },
+            // Tag 35 has no room for options, so they go inline, the way
+            // PCRE writes them.
+            RegExp(ref pattern, ref options) => {
+                self.head(MAJOR_TAG, TAG_REGEX);
+                let text = if options.is_empty() {
+                    pattern.clone()
+                } else {
+                    format!("(?{}){}", options, pattern)
+                };
+                // A pattern that starts with its own `(?flags)` can't be
+                // told apart from one that had options.
+                if regex(text.as_slice()) != *v {
+                    self.lossy("pattern starts with inline flags, which read back as options");
+                }
+                self.text(text.as_slice());
+            },
+            DbPointer(ref ns, ref id) => {
+                self.head(MAJOR_TAG, TAG_DB_POINTER);
+                self.head(MAJOR_ARRAY, 2);
+                self.text(ns.as_slice());
+                self.bytes(id.as_slice());
+            },
+            JavaScript(ref code) => {
+                self.head(MAJOR_TAG, TAG_JAVASCRIPT);
+                self.text(code.as_slice());
+            },
+            Symbol(ref s) => {
+                self.head(MAJOR_TAG, TAG_SYMBOL);
+                self.text(s.as_slice());
+            },
+            JavaScriptWithScope(ref code, ref scope) => {
+                self.head(MAJOR_TAG, TAG_JAVASCRIPT_WITH_SCOPE);
+                self.head(MAJOR_ARRAY, 2);
+                self.text(code.as_slice());
+                self.object(scope);
+            },
+            Int32(n) => self.integer(n as i64, false),
+            Timestamp(time, inc) => {
+                self.head(MAJOR_TAG, TAG_TIMESTAMP);
+                self.head(MAJOR_ARRAY, 2);
+                self.head(MAJOR_UNSIGNED, time as u64);
+                self.head(MAJOR_UNSIGNED, inc as u64);
+            },
+            Int64(n) => self.integer(n, true),
+            Decimal128(ref bytes) => {
+                self.head(MAJOR_TAG, TAG_DECIMAL128);
+                self.bytes(bytes.as_slice());
+            },
+            MinKey => {
+                self.head(MAJOR_TAG, TAG_MIN_KEY);
+                self.out.push(0xf6);
+            },
+            MaxKey => {
+                self.head(MAJOR_TAG, TAG_MAX_KEY);
+                self.out.push(0xf6);
+            },
+            RawString(ref bytes) => {
+                self.lossy("string isn't valid UTF-8, so it was written as a byte string");
+                self.bytes(bytes.as_slice());
+            },
+        }
+    }
+}
+
+/// Writes `v` as CBOR. Everything BSON holds has a CBOR form, so the only
+/// losses are strings that weren't valid UTF-8, patterns that start with
+/// inline flags, and dates too far out to keep their milliseconds.
+pub fn to_cbor(v: &Bson) -> (Vec<u8>, Vec<Loss>) {
+    let mut writer = Writer { out: Vec::new(), path: Vec::new(), losses: Vec::new() };
+    writer.value(v);
+    (writer.out, writer.losses)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: uint,
+    depth: uint,
+    path: Vec<PathElt>,
+    losses: Vec<Loss>,
+}
+
+/// What an initial byte says: the major type, and the argument with the
+/// number of bytes it took, or `None` for an indefinite length.
+struct Head {
+    major: u8,
+    arg: Option<u64>,
+    width: uint,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, offset: uint, msg: &str) -> CborResult<T> {
+        Err(CborError { offset: offset, msg: msg.to_string() })
+    }
+
+    fn lossy(&mut self, msg: String) {
+        self.losses.push(Loss { path: format_path(self.path.as_slice()), msg: msg });
+    }
+
+    fn take(&mut self, n: uint) -> CborResult<&'a [u8]> {
+        if n > self.bytes.len() - self.pos {
+            return self.error(self.bytes.len(), "unexpected end of input");
+        }
+        let taken = self.bytes.slice(self.pos, self.pos + n);
+        self.pos += n;
+        Ok(taken)
+    }
+
+    fn head(&mut self) -> CborResult<Head> {
+        let start = self.pos;
+        let initial = try!(self.take(1))[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        let width = match info {
+            n if n < 24 => return Ok(Head { major: major, arg: Some(info as u64), width: 0 }),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            31 if major >= MAJOR_BYTES && major <= MAJOR_MAP => return Ok(Head { major: major, arg: None, width: 0 }),
+            31 if major == MAJOR_SIMPLE => return self.error(start, "unexpected break"),
+            _ => return self.error(start, "reserved additional information"),
+        };
+        let arg = try!(self.take(width)).iter().fold(0u64, |n, b| (n << 8) | *b as u64);
+        Ok(Head { major: major, arg: Some(arg), width: width })
+    }
+
+    /// A definite length, which has to fit in what's left of the input.
+    fn length(&self, start: uint, arg: u64) -> CborResult<uint> {
+        if arg > (self.bytes.len() - self.pos) as u64 {
+            return self.error(start, "length runs past the end of the input");
+        }
+        Ok(arg as uint)
+    }
+
+    fn at_break(&mut self) -> CborResult<bool> {
+        if self.pos >= self.bytes.len() {
+            return self.error(self.pos, "unexpected end of input");
+        }
+        if self.bytes[self.pos] == BREAK {
+            self.pos += 1;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// The bytes of a byte or text string, joining the chunks of an
+    /// indefinite one.
+    fn string_bytes(&mut self, start: uint, head: &Head) -> CborResult<Vec<u8>> {
+        match head.arg {
+            Some(n) => {
+                let len = try!(self.length(start, n));
+                Ok(try!(self.take(len)).to_vec())
+            },
+            None => {
+                let mut joined = Vec::new();
+                while !try!(self.at_break()) {
+                    let chunk_start = self.pos;
+                    let chunk = try!(self.head());
+                    match chunk.arg {
+                        Some(n) if chunk.major == head.major => {
+                            let len = try!(self.length(chunk_start, n));
+                            joined.push_all(try!(self.take(len)));
+                        },
+                        _ => return self.error(chunk_start, "bad chunk in an indefinite-length string"),
+                    }
+                }
+                Ok(joined)
+            },
+        }
+    }
+
+    fn text(&mut self, start: uint, head: &Head) -> CborResult<String> {
+        let bytes = try!(self.string_bytes(start, head));
+        match str::from_utf8(bytes.as_slice()) {
+            Some(s) => Ok(s.to_string()),
+            None => self.error(start, "text string isn't valid UTF-8"),
+        }
+    }
+
+    /// Calls `f` for each item of an array or each pair of a map, definite
+    /// or not, with the item's index.
+    fn items(&mut self, start: uint, head: &Head, f: |&mut Parser<'a>, uint| -> CborResult<()>) -> CborResult<()> {
+        let mut i = 0;
+        loop {
+            let done = match head.arg {
+                Some(n) => i as u64 == n,
+                None => try!(self.at_break()),
+            };
+            if done {
+                return Ok(());
+            }
+            if self.pos >= self.bytes.len() {
+                return self.error(start, "unexpected end of input");
+            }
+            try!(f(self, i));
+            i += 1;
+        }
+    }
+
+    fn integer(&mut self, negative: bool, n: u64, width: uint) -> Bson {
+        if n > i64::MAX as u64 {
+            let f = n as f64;
+            self.lossy(format!("integer {}{} is out of range for a long, so it was read as a double",
+                               if negative { "-1 - " } else { "" }, n));
+            return super::Float(if negative { -1.0 - f } else { f });
+        }
+        let v = if negative { -1 - n as i64 } else { n as i64 };
+        if width < 8 && v >= i32::MIN as i64 && v <= i32::MAX as i64 {
+            Int32(v as i32)
+        } else {
+            Int64(v)
+        }
+    }
+
+    fn value(&mut self) -> CborResult<Bson> {
+        let start = self.pos;
+        let head = try!(self.head());
+        match head.major {
+            0 | 1 => Ok(self.integer(head.major == MAJOR_NEGATIVE, head.arg.unwrap(), head.width)),
+            2 => Ok(Binary(0, try!(self.string_bytes(start, &head)))),
+            3 => Ok(String(try!(self.text(start, &head)))),
+            4 => {
+                if self.depth >= MAX_DEPTH {
+                    return self.error(start, "nested too deeply");
+                }
+                self.depth += 1;
+                let mut items = Vec::new();
+                try!(self.items(start, &head, |p, i| {
+                    p.path.push(Index(i));
+                    let item = try!(p.value());
+                    p.path.pop();
+                    items.push(item);
+                    Ok(())
+                }));
+                self.depth -= 1;
+                Ok(super::List(items))
+            },
+            5 => {
+                if self.depth >= MAX_DEPTH {
+                    return self.error(start, "nested too deeply");
+                }
+                self.depth += 1;
+                let obj = try!(self.map(start, &head));
+                self.depth -= 1;
+                Ok(super::Object(obj))
+            },
+            6 => {
+                // Tags nest like arrays do, so they count against the limit.
+                if self.depth >= MAX_DEPTH {
+                    return self.error(start, "nested too deeply");
+                }
+                self.depth += 1;
+                let v = try!(self.tagged(start, head.arg.unwrap()));
+                self.depth -= 1;
+                Ok(v)
+            },
+            _ => self.simple(start, &head),
+        }
+    }
+
+    fn map(&mut self, start: uint, head: &Head) -> CborResult<Object> {
+        let mut obj = TreeMap::new();
+        try!(self.items(start, head, |p, _| {
+            let key = match try!(p.value()) {
+                String(s) => s,
+                other => {
+                    let key = match other {
+                        Int32(n) => n.to_string(),
+                        Int64(n) => n.to_string(),
+                        ref other => to_relaxed_extjson(other),
+                    };
+                    p.lossy(format!("map key {} isn't text, so it became a string", key));
+                    key
+                },
+            };
+            p.path.push(Field(key.clone()));
+            let value = try!(p.value());
+            if obj.contains_key(&key) {
+                p.lossy("duplicate key; the last value was kept".to_string());
+            }
+            p.path.pop();
+            obj.insert(key, value);
+            Ok(())
+        }));
+        Ok(obj)
+    }
+
+    fn simple(&mut self, start: uint, head: &Head) -> CborResult<Bson> {
+        let arg = head.arg.unwrap();
+        match (head.width, arg) {
+            (0, 20) => Ok(Boolean(false)),
+            (0, 21) => Ok(Boolean(true)),
+            (0, 22) => Ok(Null),
+            (0, 23) => Ok(Undefined),
+            (2, bits) => Ok(super::Float(half(bits as u16))),
+            (4, bits) => Ok(super::Float(unsafe { mem::transmute::<u32, f32>(bits as u32) } as f64)),
+            (8, bits) => Ok(super::Float(unsafe { mem::transmute::<u64, f64>(bits) })),
+            (1, n) if n < 32 => self.error(start, "badly encoded simple value"),
+            (_, n) => {
+                self.lossy(format!("simple value {} has no BSON counterpart, so it was read as null", n));
+                Ok(Null)
+            },
+        }
+    }
+
+    /// A tag's content, which has to be an array of two items.
+    fn pair(&mut self, start: uint, what: &str) -> CborResult<(Bson, Bson)> {
+        match try!(self.value()) {
+            super::List(mut items) => if items.len() == 2 {
+                let second = items.pop().unwrap();
+                return Ok((items.pop().unwrap(), second));
+            },
+            _ => (),
+        }
+        self.error(start, format!("malformed {}", what).as_slice())
+    }
+
+    fn tagged(&mut self, start: uint, tag: u64) -> CborResult<Bson> {
+        let content = self.pos;
+        let malformed = |p: &Parser, what: &str| p.error(start, format!("malformed {}", what).as_slice());
+        if tag == TAG_OBJECT_ID {
+            return match try!(self.value()) {
+                Binary(0, ref id) if id.len() == 12 => Ok(super::ObjectId(id.clone())),
+                _ => malformed(self, "ObjectId"),
+            };
+        }
+        if tag == TAG_DECIMAL128 {
+            return match try!(self.value()) {
+                Binary(0, ref bytes) if bytes.len() == 16 => Ok(Decimal128(bytes.clone())),
+                _ => malformed(self, "Decimal128"),
+            };
+        }
+        if tag == TAG_BINARY {
+            return match try!(self.pair(start, "binary")) {
+                (Int32(subtype), Binary(0, ref data)) if subtype >= 0 && subtype <= 0xff => {
+                    Ok(Binary(subtype as u8, data.clone()))
+                },
+                _ => malformed(self, "binary"),
+            };
+        }
+        if tag == TAG_DB_POINTER {
+            return match try!(self.pair(start, "DBPointer")) {
+                (String(ref ns), Binary(0, ref id)) if id.len() == 12 => Ok(DbPointer(ns.clone(), id.clone())),
+                _ => malformed(self, "DBPointer"),
+            };
+        }
+        if tag == TAG_JAVASCRIPT || tag == TAG_SYMBOL {
+            return match try!(self.value()) {
+                String(s) => Ok(if tag == TAG_SYMBOL { Symbol(s) } else { JavaScript(s) }),
+                _ => malformed(self, "code"),
+            };
+        }
+        if tag == TAG_JAVASCRIPT_WITH_SCOPE {
+            return match try!(self.pair(start, "code with scope")) {
+                (String(code), super::Object(scope)) => Ok(JavaScriptWithScope(code, scope)),
+                _ => malformed(self, "code with scope"),
+            };
+        }
+        if tag == TAG_TIMESTAMP {
+            let part = |v: &Bson| match *v {
+                Int32(n) if n >= 0 => Some(n as u32),
+                Int64(n) if n >= 0 && n <= 0xffff_ffff => Some(n as u32),
+                _ => None,
+            };
+            let (time, inc) = try!(self.pair(start, "timestamp"));
+            return match (part(&time), part(&inc)) {
+                (Some(t), Some(i)) => Ok(Timestamp(t, i)),
+                _ => malformed(self, "timestamp"),
+            };
+        }
+        if tag == TAG_MIN_KEY || tag == TAG_MAX_KEY {
+            return match try!(self.value()) {
+                Null => Ok(if tag == TAG_MIN_KEY { MinKey } else { MaxKey }),
+                _ => malformed(self, "MinKey or MaxKey"),
+            };
+        }
+
+        let value = try!(self.value());
+        let bignum = tag == TAG_POSITIVE_BIGNUM || tag == TAG_NEGATIVE_BIGNUM;
+        match value {
+            Binary(0, ref data) if tag == TAG_UUID && data.len() == 16 => return Ok(Binary(4, data.clone())),
+            String(ref s) if tag == TAG_REGEX => return Ok(regex(s.as_slice())),
+            String(ref s) if tag == TAG_DATE_STRING => match datetime::from_iso8601(s.as_slice()) {
+                Some(ms) => return Ok(UtcDatetime(ms)),
+                None => (),
+            },
+            Int32(secs) if tag == TAG_EPOCH_DATE => return Ok(UtcDatetime(secs as i64 * 1000)),
+            Int64(secs) if tag == TAG_EPOCH_DATE => {
+                if secs < i64::MIN / 1000 || secs > i64::MAX / 1000 {
+                    return self.error(content, "date out of range");
+                }
+                return Ok(UtcDatetime(secs * 1000));
+            },
+            super::Float(secs) if tag == TAG_EPOCH_DATE => {
+                let ms = (secs * 1000.0).round();
+                if !(ms >= i64::MIN as f64 && ms <= i64::MAX as f64) {
+                    return self.error(content, "date out of range");
+                }
+                if ms / 1000.0 != secs {
+                    self.lossy("date had more precision than milliseconds".to_string());
+                }
+                return Ok(UtcDatetime(ms as i64));
+            },
+            Binary(0, ref digits) if bignum => return Ok(self.bignum(tag == TAG_NEGATIVE_BIGNUM, digits.as_slice())),
+            super::List(ref parts) if tag == TAG_DECIMAL_FRACTION && parts.len() == 2 => {
+                return self.decimal_fraction(content, &parts[0], &parts[1]);
+            },
+            _ => (),
+        }
+        if tag == TAG_SELF_DESCRIBED {
+            return Ok(value);
+        }
+        let known = bignum || [TAG_DATE_STRING, TAG_EPOCH_DATE, TAG_DECIMAL_FRACTION, TAG_REGEX, TAG_UUID].contains(&tag);
+        if known {
+            self.lossy(format!("tag {} didn't hold what it should, so it was dropped", tag));
+        } else {
+            self.lossy(format!("tag {} has no BSON counterpart, so it was dropped", tag));
+        }
+        Ok(value)
+    }
+
+    fn bignum(&mut self, negative: bool, digits: &[u8]) -> Bson {
+        let significant = digits.iter().skip_while(|b| **b == 0).count();
+        if significant <= 8 {
+            let n = digits.iter().fold(0u64, |n, b| (n << 8) | *b as u64);
+            // Bignums are always read as longs, whatever their size.
+            return match self.integer(negative, n, 8) {
+                Int32(v) => Int64(v as i64),
+                v => v,
+            };
+        }
+        let f = digits.iter().fold(0f64, |f, b| f * 256.0 + *b as f64);
+        self.lossy("bignum is out of range for a long, so it was read as a double".to_string());
+        super::Float(if negative { -1.0 - f } else { f })
+    }
+
+    fn decimal_fraction(&mut self, start: uint, exponent: &Bson, mantissa: &Bson) -> CborResult<Bson> {
+        let whole = |v: &Bson| match *v {
+            Int32(n) => Some(n as i64),
+            Int64(n) => Some(n),
+            _ => None,
+        };
+        match (whole(exponent), whole(mantissa)) {
+            (Some(e), Some(m)) => match decimal::parse(format!("{}E{}", m, e).as_slice()) {
+                Some(bytes) => Ok(Decimal128(bytes)),
+                None => self.error(start, "decimal fraction out of range for a Decimal128"),
+            },
+            _ => self.error(start, "malformed decimal fraction"),
+        }
+    }
+}
+
+/// A regex with any inline `(?flags)` taken back out as BSON options.
+fn regex(s: &str) -> Bson {
+    if s.starts_with("(?") {
+        match s.find(')') {
+            Some(end) if end > 2 && s.slice(2, end).chars().all(|c| c.is_lowercase()) => {
+                return RegExp(s.slice_from(end + 1).to_string(), s.slice(2, end).to_string());
+            },
+            _ => (),
+        }
+    }
+    RegExp(s.to_string(), String::new())
+}
+
+/// An IEEE 754 half-precision float, widened.
+fn half(bits: u16) -> f64 {
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f64;
+    let magnitude = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        e => (mantissa + 1024.0) * 2f64.powi(e - 25),
+    };
+    if bits & 0x8000 != 0 { -magnitude } else { magnitude }
+}
+
+/// Reads one CBOR data item, which has to take up all of `bytes`.
+/// Registered tags are understood where BSON has a match, this crate's
+/// tags come back as the types they stand for, and anything without a
+/// BSON counterpart is converted with a `Loss` saying how.
+pub fn from_cbor(bytes: &[u8]) -> CborResult<(Bson, Vec<Loss>)> {
+    let mut parser = Parser { bytes: bytes, pos: 0, depth: 0, path: Vec::new(), losses: Vec::new() };
+    let value = try!(parser.value());
+    if parser.pos != bytes.len() {
+        return parser.error(parser.pos, "trailing bytes after the data item");
+    }
+    Ok((value, parser.losses))
+}
@@ -0,0 +1,669 @@
+use std::{fmt,uint};
+use std::ascii::StrAsciiExt;
+use std::collections::TreeMap;
+
+use super::{Bson,Object,String,Int32,Int64,Boolean,Null,ObjectId,UtcDatetime,Decimal128,Binary};
+use super::{format_double,to_hex,from_hex};
+use super::{base64,datetime,decimal};
+use super::extjson::to_relaxed_extjson;
+
+/// How arrays are laid out on export.
+#[deriving(PartialEq, Clone, Show)]
+pub enum ArrayStyle {
+    /// A column per element: `tags.0`, `tags.1`, ...
+    IndexedColumns,
+    /// The whole array as Extended JSON in one cell, as mongoexport does.
+    JsonCells,
+}
+
+#[deriving(PartialEq, Clone, Show)]
+pub struct CsvOptions {
+    /// `,` for CSV, `\t` for TSV.
+    pub delimiter: char,
+    pub arrays: ArrayStyle,
+    /// On import, leave fields out of the document when their cell is empty.
+    pub ignore_blanks: bool,
+}
+impl CsvOptions {
+    pub fn new() -> CsvOptions {
+        CsvOptions { delimiter: ',', arrays: JsonCells, ignore_blanks: false }
+    }
+
+    pub fn tsv() -> CsvOptions {
+        CsvOptions { delimiter: '\t', ..CsvOptions::new() }
+    }
+}
+
+/// Where an import went wrong. `column` counts fields from 1.
+#[deriving(PartialEq, Clone)]
+pub struct CsvError {
+    pub line: uint,
+    pub column: uint,
+    pub msg: String,
+}
+
+impl fmt::Show for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.msg, self.line, self.column)
+    }
+}
+
+pub type CsvResult<T> = Result<T, CsvError>;
+
+fn fail<T>(line: uint, column: uint, msg: String) -> CsvResult<T> {
+    Err(CsvError { line: line, column: column, msg: msg })
+}
+
+/// The value at a dotted path, stepping into arrays by index.
+fn lookup<'a>(v: &'a Bson, path: &str) -> Option<&'a Bson> {
+    let mut v = v;
+    for part in path.split('.') {
+        v = match *v {
+            super::Object(ref obj) => match obj.find(&part.to_string()) {
+                Some(child) => child,
+                None => return None,
+            },
+            super::List(ref list) => match from_str::<uint>(part) {
+                Some(i) if i < list.len() => &list[i],
+                _ => return None,
+            },
+            _ => return None,
+        };
+    }
+    Some(v)
+}
+
+/// Adds the columns `v` needs under `prefix`, in the order first seen.
+fn flatten(v: &Bson, prefix: String, options: &CsvOptions, columns: &mut Vec<String>) {
+    match *v {
+        super::Object(ref obj) if !obj.is_empty() => {
+            for (k, child) in obj.iter() {
+                flatten(child, format!("{}.{}", prefix, k), options, columns);
+            }
+        },
+        super::List(ref list) if !list.is_empty() && options.arrays == IndexedColumns => {
+            for (i, child) in list.iter().enumerate() {
+                flatten(child, format!("{}.{}", prefix, i), options, columns);
+            }
+        },
+        _ => if !columns.contains(&prefix) {
+            columns.push(prefix);
+        },
+    }
+}
+
+fn cell(v: &Bson) -> String {
+    match *v {
+        String(ref s) => s.clone(),
+        Int32(n) => n.to_string(),
+        Int64(n) => n.to_string(),
+        super::Float(f) => format_double(f),
+        Boolean(b) => b.to_string(),
+        Null => String::new(),
+        UtcDatetime(ms) => datetime::to_iso8601(ms),
+        ObjectId(ref id) => format!("ObjectId({})", to_hex(id.as_slice())),
+        Decimal128(ref bytes) => decimal::to_string(bytes.as_slice()),
+        _ => to_relaxed_extjson(v),
+    }
+}
+
+fn escape(s: &str, delimiter: char) -> String {
+    if s.chars().any(|c| c == delimiter || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", s.replace("\"", "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Writes `docs` as CSV or TSV with a header row. Each of `fields` becomes
+/// one column, or one per leaf when it holds a document (and, with
+/// `IndexedColumns`, an array), named by dotted path. Missing values are
+/// empty cells; dates come out as ISO-8601 and ObjectIds as `ObjectId(hex)`.
+pub fn export(docs: &[Bson], fields: &[&str], options: &CsvOptions) -> String {
+    let mut columns = Vec::new();
+    for &field in fields.iter() {
+        let before = columns.len();
+        for doc in docs.iter() {
+            match lookup(doc, field) {
+                Some(v) => flatten(v, field.to_string(), options, &mut columns),
+                None => (),
+            }
+        }
+        if columns.len() == before {
+            columns.push(field.to_string());
+        }
+    }
+
+    let sep = String::from_char(1, options.delimiter);
+    let mut out = String::new();
+    let header: Vec<String> = columns.iter().map(|c| escape(c.as_slice(), options.delimiter)).collect();
+    out.push_str(header.connect(sep.as_slice()).as_slice());
+    out.push('\n');
+    for doc in docs.iter() {
+        let row: Vec<String> = columns.iter().map(|c| match lookup(doc, c.as_slice()) {
+            Some(v) => escape(cell(v).as_slice(), options.delimiter),
+            None => String::new(),
+        }).collect();
+        out.push_str(row.connect(sep.as_slice()).as_slice());
+        out.push('\n');
+    }
+    out
+}
+
+/// Splits `text` into records of fields, each with the line it starts on.
+/// Fields may be quoted, with `""` for a quote, and quoted fields may span
+/// lines. Blank lines are skipped.
+fn records(text: &str, delimiter: char) -> CsvResult<Vec<(uint, Vec<String>)>> {
+    let chars: Vec<char> = text.chars().collect();
+    let len = chars.len();
+    let ends_field = |c: char| c == delimiter || c == '\n' || c == '\r';
+    let mut out = Vec::new();
+    let mut pos = 0;
+    let mut line = 1;
+    while pos < len {
+        let start = line;
+        let mut fields = Vec::new();
+        loop {
+            let mut field = String::new();
+            if pos < len && chars[pos] == '"' {
+                pos += 1;
+                loop {
+                    if pos >= len {
+                        return fail(start, fields.len() + 1, "unterminated quoted field".to_string());
+                    }
+                    let c = chars[pos];
+                    pos += 1;
+                    if c != '"' {
+                        if c == '\n' {
+                            line += 1;
+                        }
+                        field.push(c);
+                    } else if pos < len && chars[pos] == '"' {
+                        field.push('"');
+                        pos += 1;
+                    } else {
+                        break;
+                    }
+                }
+                if pos < len && !ends_field(chars[pos]) {
+                    return fail(line, fields.len() + 1, "unexpected character after a quoted field".to_string());
+                }
+            } else {
+                while pos < len && !ends_field(chars[pos]) {
+                    field.push(chars[pos]);
+                    pos += 1;
+                }
+            }
+            fields.push(field);
+            if pos < len && chars[pos] == delimiter {
+                pos += 1;
+                continue;
+            }
+            if pos < len && chars[pos] == '\r' {
+                pos += 1;
+            }
+            if pos < len && chars[pos] == '\n' {
+                pos += 1;
+            }
+            line += 1;
+            break;
+        }
+        if fields.len() > 1 || !fields[0].is_empty() {
+            out.push((start, fields));
+        }
+    }
+    Ok(out)
+}
+
+/// One piece of a date layout.
+#[deriving(PartialEq, Clone, Show)]
+enum Piece {
+    Literal(char),
+    LongYear,
+    ShortYear,
+    /// The bool is whether it's zero-padded to two digits.
+    Month(bool),
+    ShortMonthName,
+    LongMonthName,
+    Day(bool),
+    ShortWeekday,
+    LongWeekday,
+    Hour24(bool),
+    Hour12(bool),
+    Minute(bool),
+    Second(bool),
+    /// Digits of a fractional second after an optional separator. When not
+    /// exact, the fraction may be left out altogether.
+    Fraction(Option<char>, uint, bool),
+    Meridiem,
+    /// `±hh:mm` if the first bool, `±hhmm` otherwise; the second allows `Z`.
+    Zone(bool, bool),
+}
+
+static MONTHS: &'static [&'static str] = &["January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December"];
+static WEEKDAYS: &'static [&'static str] = &["Sunday", "Monday", "Tuesday", "Wednesday",
+    "Thursday", "Friday", "Saturday"];
+
+/// Reads a Go reference-time layout, like `2006-01-02T15:04:05Z07:00`.
+fn go_layout(layout: &str) -> Vec<Piece> {
+    let chars: Vec<char> = layout.chars().collect();
+    let mut pieces = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let rest = String::from_chars(chars.slice_from(i));
+        let rest = rest.as_slice();
+        let named = [("January", LongMonthName), ("Jan", ShortMonthName),
+                     ("Monday", LongWeekday), ("Mon", ShortWeekday),
+                     ("2006", LongYear), ("01", Month(true)), ("02", Day(true)), ("03", Hour12(true)),
+                     ("04", Minute(true)), ("05", Second(true)), ("06", ShortYear), ("15", Hour24(false)),
+                     ("1", Month(false)), ("2", Day(false)), ("_2", Day(false)), ("3", Hour12(false)),
+                     ("4", Minute(false)), ("5", Second(false)), ("PM", Meridiem), ("pm", Meridiem),
+                     ("Z07:00", Zone(true, true)), ("Z0700", Zone(false, true)),
+                     ("-07:00", Zone(true, false)), ("-0700", Zone(false, false))];
+        match named.iter().find(|&&(name, _)| rest.starts_with(name)) {
+            Some(&(name, ref piece)) => {
+                pieces.push(piece.clone());
+                i += name.len();
+                continue;
+            },
+            None => (),
+        }
+        // `.000` needs exactly that many digits, `.999` up to that many.
+        let c = chars[i];
+        if (c == '.' || c == ',') && i + 1 < chars.len() && (chars[i + 1] == '0' || chars[i + 1] == '9') {
+            let digit = chars[i + 1];
+            let mut end = i + 1;
+            while end < chars.len() && chars[end] == digit {
+                end += 1;
+            }
+            if end == chars.len() || chars[end].to_digit(10).is_none() {
+                pieces.push(Fraction(Some(c), end - i - 1, digit == '0'));
+                i = end;
+                continue;
+            }
+        }
+        pieces.push(Literal(c));
+        i += 1;
+    }
+    pieces
+}
+
+/// Reads a Microsoft-style layout, like `yyyy-MM-dd HH:mm:ss.fff`.
+fn ms_layout(layout: &str) -> Vec<Piece> {
+    let chars: Vec<char> = layout.chars().collect();
+    let mut pieces = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let mut run = 1;
+        while i + run < chars.len() && chars[i + run] == c {
+            run += 1;
+        }
+        let piece = match (c, run) {
+            ('y', 4) => LongYear,
+            ('y', 2) => ShortYear,
+            ('M', 4) => LongMonthName,
+            ('M', 3) => ShortMonthName,
+            ('M', n) if n <= 2 => Month(n == 2),
+            ('d', 4) => LongWeekday,
+            ('d', 3) => ShortWeekday,
+            ('d', n) if n <= 2 => Day(n == 2),
+            ('H', n) if n <= 2 => Hour24(n == 2),
+            ('h', n) if n <= 2 => Hour12(n == 2),
+            ('m', n) if n <= 2 => Minute(n == 2),
+            ('s', n) if n <= 2 => Second(n == 2),
+            ('f', n) if n <= 7 => Fraction(None, n, true),
+            ('t', 2) => Meridiem,
+            ('z', 3) => Zone(true, false),
+            ('K', 1) => Zone(true, true),
+            _ => {
+                for _ in range(0, run) {
+                    pieces.push(Literal(c));
+                }
+                i += run;
+                continue;
+            },
+        };
+        pieces.push(piece);
+        i += run;
+    }
+    pieces
+}
+
+fn number(s: &[char], pos: &mut uint, min: uint, max: uint) -> Option<i64> {
+    let start = *pos;
+    while *pos < s.len() && *pos - start < max && s[*pos].to_digit(10).is_some() {
+        *pos += 1;
+    }
+    if *pos - start < min {
+        return None;
+    }
+    Some(s.slice(start, *pos).iter().fold(0i64, |n, c| n * 10 + c.to_digit(10).unwrap() as i64))
+}
+
+/// Which of `names` is at `pos`, ignoring case, taking the first `len`
+/// characters of each if given.
+fn name(s: &[char], pos: &mut uint, names: &[&str], len: Option<uint>) -> Option<uint> {
+    for (i, n) in names.iter().enumerate() {
+        let n: Vec<char> = n.chars().take(len.unwrap_or(n.len())).collect();
+        if *pos + n.len() <= s.len()
+            && s.slice(*pos, *pos + n.len()).iter().zip(n.iter()).all(|(a, b)| a.to_lowercase() == b.to_lowercase()) {
+            *pos += n.len();
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Reads a date with the given layout. Fields the layout leaves out take
+/// their value from 0000-01-01T00:00:00Z, as in Go.
+fn parse_date(pieces: &[Piece], text: &str) -> Option<i64> {
+    let s: Vec<char> = text.chars().collect();
+    let s = s.as_slice();
+    let mut pos = 0u;
+    let (mut year, mut month, mut day) = (0i64, 1i64, 1i64);
+    let (mut hour, mut minute, mut second, mut millis) = (0i64, 0i64, 0i64, 0i64);
+    let mut pm = None;
+    let mut offset = 0i64;
+    for (i, piece) in pieces.iter().enumerate() {
+        let digits = |padded: bool| if padded { 2 } else { 1 };
+        let read = match *piece {
+            Literal(c) => if pos < s.len() && s[pos] == c {
+                pos += 1;
+                Some(())
+            } else {
+                None
+            },
+            LongYear => number(s, &mut pos, 4, 4).map(|n| year = n),
+            ShortYear => number(s, &mut pos, 2, 2).map(|n| year = if n >= 69 { 1900 + n } else { 2000 + n }),
+            Month(p) => number(s, &mut pos, digits(p), 2).map(|n| month = n),
+            ShortMonthName => name(s, &mut pos, MONTHS, Some(3)).map(|n| month = n as i64 + 1),
+            LongMonthName => name(s, &mut pos, MONTHS, None).map(|n| month = n as i64 + 1),
+            Day(p) => {
+                while !p && pos < s.len() && s[pos] == ' ' {
+                    pos += 1;
+                }
+                number(s, &mut pos, digits(p), 2).map(|n| day = n)
+            },
+            ShortWeekday => name(s, &mut pos, WEEKDAYS, Some(3)).map(|_| ()),
+            LongWeekday => name(s, &mut pos, WEEKDAYS, None).map(|_| ()),
+            Hour24(p) => number(s, &mut pos, digits(p), 2).map(|n| hour = n),
+            Hour12(p) => number(s, &mut pos, digits(p), 2).and_then(|n| if n >= 1 && n <= 12 {
+                hour = n;
+                Some(())
+            } else {
+                None
+            }),
+            Minute(p) => number(s, &mut pos, digits(p), 2).map(|n| minute = n),
+            Second(p) => {
+                let read = number(s, &mut pos, digits(p), 2).map(|n| second = n);
+                // Go takes a fraction after the seconds even when the layout
+                // doesn't ask for one.
+                let fraction_next = match pieces.get(i + 1) {
+                    Some(&Fraction(..)) => true,
+                    _ => false,
+                };
+                if read.is_some() && !fraction_next && pos + 1 < s.len()
+                    && (s[pos] == '.' || s[pos] == ',') && s[pos + 1].to_digit(10).is_some() {
+                    pos += 1;
+                    millis = fraction(s, &mut pos, 1, uint::MAX).unwrap();
+                }
+                read
+            },
+            Fraction(sep, n, exact) => {
+                let has_sep = match sep {
+                    Some(c) => pos < s.len() && s[pos] == c,
+                    None => true,
+                };
+                let mut end = pos + if sep.is_some() && has_sep { 1 } else { 0 };
+                let found = if !has_sep {
+                    None
+                } else if exact {
+                    fraction(s, &mut end, n, n)
+                } else {
+                    fraction(s, &mut end, 1, uint::MAX)
+                };
+                match found {
+                    Some(f) => {
+                        millis = f;
+                        pos = end;
+                        Some(())
+                    },
+                    None if !exact => Some(()),
+                    None => None,
+                }
+            },
+            Meridiem => if pos + 2 <= s.len() {
+                let m: String = s.slice(pos, pos + 2).iter().map(|c| c.to_uppercase()).collect();
+                pos += 2;
+                pm = match m.as_slice() {
+                    "AM" => Some(false),
+                    "PM" => Some(true),
+                    _ => return None,
+                };
+                Some(())
+            } else {
+                None
+            },
+            Zone(colon, z) => if z && pos < s.len() && s[pos] == 'Z' {
+                pos += 1;
+                Some(())
+            } else if pos < s.len() && (s[pos] == '+' || s[pos] == '-') {
+                let sign = if s[pos] == '-' { -1 } else { 1 };
+                pos += 1;
+                let h = number(s, &mut pos, 2, 2);
+                if colon && pos < s.len() && s[pos] == ':' {
+                    pos += 1;
+                }
+                match (h, number(s, &mut pos, 2, 2)) {
+                    (Some(h), Some(m)) if h < 24 && m < 60 => {
+                        offset = sign * (h * 60 + m);
+                        Some(())
+                    },
+                    _ => None,
+                }
+            } else {
+                None
+            },
+        };
+        if read.is_none() {
+            return None;
+        }
+    }
+    if pos != s.len() {
+        return None;
+    }
+    match pm {
+        Some(true) if hour < 12 => hour += 12,
+        Some(false) if hour == 12 => hour = 0,
+        _ => (),
+    }
+    datetime::from_civil(year, month, day, hour, minute, second, millis).map(|ms| ms - offset * 60_000)
+}
+
+/// Reads the digits of a fraction of a second as milliseconds, dropping
+/// any past the third.
+fn fraction(s: &[char], pos: &mut uint, min: uint, max: uint) -> Option<i64> {
+    let start = *pos;
+    while *pos < s.len() && *pos - start < max && s[*pos].to_digit(10).is_some() {
+        *pos += 1;
+    }
+    if *pos - start < min {
+        return None;
+    }
+    let mut millis = 0;
+    for i in range(start, start + 3) {
+        millis = millis * 10 + if i < *pos { s[i].to_digit(10).unwrap() as i64 } else { 0 };
+    }
+    Some(millis)
+}
+
+/// The types a column header can ask for, as mongoimport's
+/// `--columnsHaveTypes` spells them.
+enum Kind {
+    AutoKind,
+    StringKind,
+    Int32Kind,
+    Int64Kind,
+    DoubleKind,
+    DecimalKind,
+    BooleanKind,
+    DateKind(Vec<Piece>),
+    Base64Kind,
+    HexKind,
+}
+
+struct Column {
+    name: String,
+    path: Vec<String>,
+    kind: Kind,
+    /// As written in the header, for error messages.
+    type_name: String,
+}
+
+/// Splits `name.type(arg)` into its parts.
+fn split_type(header: &str) -> Option<(&str, &str, &str)> {
+    if !header.ends_with(")") {
+        return None;
+    }
+    let open = match header.find('(') {
+        Some(i) => i,
+        None => return None,
+    };
+    let dot = match header.slice_to(open).rfind('.') {
+        Some(i) => i,
+        None => return None,
+    };
+    Some((header.slice_to(dot), header.slice(dot + 1, open), header.slice(open + 1, header.len() - 1)))
+}
+
+fn column(header: &str, line: uint, index: uint) -> CsvResult<Column> {
+    let (name, type_name, arg) = split_type(header).unwrap_or((header, "auto", ""));
+    let kind = match (type_name, arg) {
+        ("auto", "") => AutoKind,
+        ("string", "") => StringKind,
+        ("int32", "") => Int32Kind,
+        ("int64", "") => Int64Kind,
+        ("double", "") => DoubleKind,
+        ("decimal", "") => DecimalKind,
+        ("boolean", "") => BooleanKind,
+        ("date", layout) | ("date_go", layout) if !layout.is_empty() => DateKind(go_layout(layout)),
+        ("date_ms", layout) if !layout.is_empty() => DateKind(ms_layout(layout)),
+        ("binary", "base64") => Base64Kind,
+        ("binary", "hex") => HexKind,
+        _ => return fail(line, index, format!("unknown column type `{}({})`", type_name, arg)),
+    };
+    if name.split('.').any(|part| part.is_empty()) {
+        return fail(line, index, format!("bad field name `{}`", name));
+    }
+    Ok(Column {
+        name: name.to_string(),
+        path: name.split('.').map(|part| part.to_string()).collect(),
+        kind: kind,
+        type_name: format!("{}({})", type_name, arg),
+    })
+}
+
+/// Numbers as the narrowest of int32, int64 and double that holds them,
+/// `ObjectId(hex)` as an ObjectId, and anything else as a string.
+fn auto(text: &str) -> Bson {
+    if text.starts_with("ObjectId(") && text.ends_with(")") {
+        match from_hex(text.slice(9, text.len() - 1)) {
+            Some(id) if id.len() == 12 => return ObjectId(id),
+            _ => (),
+        }
+    }
+    match from_str::<i64>(text) {
+        Some(n) if n as i32 as i64 == n => return Int32(n as i32),
+        Some(n) => return Int64(n),
+        None => (),
+    }
+    let numeric = text.chars().any(|c| c.to_digit(10).is_some())
+        && text.chars().all(|c| c.to_digit(10).is_some() || "+-.eE".contains_char(c));
+    match from_str::<f64>(text) {
+        Some(f) if numeric => super::Float(f),
+        _ => String(text.to_string()),
+    }
+}
+
+fn convert(text: &str, kind: &Kind) -> Option<Bson> {
+    match *kind {
+        AutoKind => Some(auto(text)),
+        StringKind => Some(String(text.to_string())),
+        Int32Kind => from_str::<i32>(text).map(|n| Int32(n)),
+        Int64Kind => from_str::<i64>(text).map(|n| Int64(n)),
+        DoubleKind => from_str::<f64>(text).map(|f| super::Float(f)),
+        DecimalKind => decimal::parse(text).map(|d| Decimal128(d)),
+        BooleanKind => match text.to_ascii_lower().as_slice() {
+            "true" | "t" | "1" => Some(Boolean(true)),
+            "false" | "f" | "0" => Some(Boolean(false)),
+            _ => None,
+        },
+        DateKind(ref pieces) => parse_date(pieces.as_slice(), text).map(|ms| UtcDatetime(ms)),
+        Base64Kind => base64::decode(text).map(|b| Binary(0, b)),
+        HexKind => from_hex(text).map(|b| Binary(0, b)),
+    }
+}
+
+fn insert(obj: &mut Object, path: &[String], v: Bson) -> Result<(), String> {
+    if path.len() == 1 {
+        obj.insert(path[0].clone(), v);
+        return Ok(());
+    }
+    if !obj.contains_key(&path[0]) {
+        obj.insert(path[0].clone(), super::Object(TreeMap::new()));
+    }
+    match *obj.find_mut(&path[0]).unwrap() {
+        super::Object(ref mut child) => insert(child, path.slice_from(1), v),
+        _ => Err(format!("`{}` is both a value and a document", path[0])),
+    }
+}
+
+/// Reads CSV or TSV with a header row into documents. Headers are dotted
+/// paths, optionally typed as `name.type(arg)`: `auto()`, `string()`,
+/// `int32()`, `int64()`, `double()`, `decimal()`, `boolean()`,
+/// `date(layout)` or `date_go(layout)` with a Go layout, `date_ms(layout)`
+/// with a Microsoft one, and `binary(base64)` or `binary(hex)`. Untyped
+/// columns are `auto()`, and cells past the end of the header go in
+/// `field<n>`.
+pub fn import(text: &str, options: &CsvOptions) -> CsvResult<Vec<Bson>> {
+    let mut rows = try!(records(text, options.delimiter)).into_iter();
+    let (line, header) = match rows.next() {
+        Some(row) => row,
+        None => return Ok(Vec::new()),
+    };
+    let mut columns: Vec<Column> = Vec::new();
+    for (i, h) in header.iter().enumerate() {
+        let c = try!(column(h.as_slice(), line, i + 1));
+        if columns.iter().any(|other| other.name == c.name) {
+            return fail(line, i + 1, format!("`{}` appears twice in the header", c.name));
+        }
+        columns.push(c);
+    }
+
+    let mut docs = Vec::new();
+    for (line, fields) in rows {
+        while columns.len() < fields.len() {
+            let n = columns.len();
+            columns.push(column(format!("field{}", n).as_slice(), line, n + 1).unwrap());
+        }
+        let mut obj = TreeMap::new();
+        for (i, text) in fields.iter().enumerate() {
+            if text.is_empty() && options.ignore_blanks {
+                continue;
+            }
+            let c = &columns[i];
+            let v = match convert(text.as_slice(), &c.kind) {
+                Some(v) => v,
+                None => return fail(line, i + 1, format!("`{}` isn't a valid {}", text, c.type_name)),
+            };
+            match insert(&mut obj, c.path.as_slice(), v) {
+                Ok(()) => (),
+                Err(msg) => return fail(line, i + 1, msg),
+            }
+        }
+        docs.push(super::Object(obj));
+    }
+    Ok(docs)
+}
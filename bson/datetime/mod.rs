@@ -0,0 +1,131 @@
+// Dates are milliseconds since the Unix epoch, UTC, on the proleptic
+// Gregorian calendar.
+static MS_PER_DAY: i64 = 86_400_000;
+
+/// The last millisecond of 9999-12-31, the latest date ISO-8601 can hold
+/// with a four digit year.
+pub static MAX_ISO_DATE: i64 = 253_402_300_799_999;
+
+/// The (year, month, day) that falls `days` days after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = (if z >= 0 { z } else { z - 146_096 }) / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Formats a date as `YYYY-MM-DDTHH:MM:SS.mmmZ`, leaving the milliseconds
+/// off when there aren't any. Only dates from year 0 to 9999 come out as
+/// valid ISO-8601.
+pub fn to_iso8601(ms: i64) -> String {
+    let days = if ms >= 0 { ms / MS_PER_DAY } else { (ms - MS_PER_DAY + 1) / MS_PER_DAY };
+    let rem = ms - days * MS_PER_DAY;
+    let (year, month, day) = civil_from_days(days);
+    let secs = rem / 1000;
+    let millis = rem % 1000;
+    let mut s = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                        year, month, day, secs / 3600, secs / 60 % 60, secs % 60);
+    if millis != 0 {
+        s.push_str(format!(".{:03}", millis).as_slice());
+    }
+    s.push('Z');
+    s
+}
+
+/// Days from 1970-01-01 to the given date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Milliseconds since the epoch for a UTC date and time, or `None` if the
+/// fields don't name one.
+pub fn from_civil(year: i64, month: i64, day: i64, hour: i64, minute: i64, second: i64, millis: i64) -> Option<i64> {
+    if month < 1 || month > 12 || hour < 0 || hour > 23 || minute < 0 || minute > 59
+        || second < 0 || second > 59 || millis < 0 || millis > 999 {
+        return None;
+    }
+    let month_days = days_from_civil(year + month / 12, month % 12 + 1, 1) - days_from_civil(year, month, 1);
+    if day < 1 || day > month_days {
+        return None;
+    }
+    let seconds = (hour * 60 + minute) * 60 + second;
+    Some(days_from_civil(year, month, day) * MS_PER_DAY + seconds * 1000 + millis)
+}
+
+fn digits(b: &[u8], from: uint, len: uint) -> Option<i64> {
+    if b.len() < from + len {
+        return None;
+    }
+    let mut n = 0i64;
+    for &c in b.slice(from, from + len).iter() {
+        if c < '0' as u8 || c > '9' as u8 {
+            return None;
+        }
+        n = n * 10 + (c - '0' as u8) as i64;
+    }
+    Some(n)
+}
+
+/// Reads `YYYY-MM-DDTHH:MM:SS[.fff](Z|+HH:MM|+HHMM)`. Fractions finer than
+/// a millisecond are dropped.
+pub fn from_iso8601(s: &str) -> Option<i64> {
+    let b = s.as_bytes();
+    if b.len() < 20 || b[4] != '-' as u8 || b[7] != '-' as u8 || b[10] != 'T' as u8
+        || b[13] != ':' as u8 || b[16] != ':' as u8 {
+        return None;
+    }
+    let fields: Vec<Option<i64>> = [(0u, 4u), (5, 2), (8, 2), (11, 2), (14, 2), (17, 2)].iter()
+        .map(|&(from, len)| digits(b, from, len)).collect();
+    if fields.iter().any(|f| f.is_none()) {
+        return None;
+    }
+    let fields: Vec<i64> = fields.into_iter().map(|f| f.unwrap()).collect();
+    let (year, month, day) = (fields[0], fields[1], fields[2]);
+    let (hour, minute, second) = (fields[3], fields[4], fields[5]);
+    let mut pos = 19;
+    let mut millis = 0;
+    if b[pos] == '.' as u8 {
+        pos += 1;
+        let start = pos;
+        while pos < b.len() && b[pos] >= '0' as u8 && b[pos] <= '9' as u8 {
+            pos += 1;
+        }
+        let len = if pos - start > 3 { 3 } else { pos - start };
+        millis = match digits(b, start, len) {
+            Some(m) if len > 0 => m,
+            _ => return None,
+        };
+        for _ in range(len, 3) {
+            millis *= 10;
+        }
+    }
+
+    let zone = b.slice_from(pos);
+    let offset = if zone == b"Z" {
+        0
+    } else {
+        // +HH:MM or +HHMM
+        let minutes_at = if zone.len() == 6 && zone[3] == ':' as u8 { 4 } else { 3 };
+        if zone.len() != minutes_at + 2 || (zone[0] != '+' as u8 && zone[0] != '-' as u8) {
+            return None;
+        }
+        let offset = match (digits(zone, 1, 2), digits(zone, minutes_at, 2)) {
+            (Some(h), Some(m)) if h < 24 && m < 60 => h * 60 + m,
+            _ => return None,
+        };
+        if zone[0] == '-' as u8 { -offset } else { offset }
+    };
+    from_civil(year, month, day, hour, minute, second, millis).map(|ms| ms - offset * 60_000)
+}
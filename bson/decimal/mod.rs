@@ -0,0 +1,215 @@
+use std::f64;
+use std::ascii::StrAsciiExt;
+use std::num::Float;
+
+use super::le_u64;
+
+// The exponent is stored with this bias.
+static EXPONENT_BIAS: i32 = 6176;
+// Coefficients with more digits than this are non-canonical and read as zero.
+static MAX_DIGITS: uint = 34;
+static MIN_EXPONENT: i64 = -6176;
+static MAX_EXPONENT: i64 = 6111;
+
+enum Kind {
+    NaN,
+    Infinity,
+    /// exponent, coefficient digits
+    Finite(i32, String),
+}
+
+/// Pulls the sign, exponent and coefficient out of the 16 little-endian
+/// bytes of a Decimal128.
+fn classify(bytes: &[u8]) -> (bool, Kind) {
+    let low = le_u64(bytes.slice_to(8));
+    let high = le_u64(bytes.slice(8, 16));
+    let negative = high >> 63 == 1;
+    let combination = (high >> 58) & 0x1F;
+    if combination == 0x1F {
+        return (negative, NaN);
+    }
+    if combination == 0x1E {
+        return (negative, Infinity);
+    }
+    let (biased, digits) = if (high >> 61) & 0x3 == 0x3 {
+        // The implied leading bits put the coefficient past 34 digits.
+        ((high >> 47) & 0x3FFF, "0".to_string())
+    } else {
+        ((high >> 49) & 0x3FFF, coefficient_digits(high & 0x1FFFFFFFFFFFF, low))
+    };
+    let digits = if digits.len() > MAX_DIGITS { "0".to_string() } else { digits };
+    (negative, Finite(biased as i32 - EXPONENT_BIAS, digits))
+}
+
+/// Turns the 113-bit coefficient into decimal digits, nine at a time.
+fn coefficient_digits(high: u64, low: u64) -> String {
+    let mut words = [high >> 32, high & 0xFFFFFFFF, low >> 32, low & 0xFFFFFFFF];
+    let mut chunks = Vec::new();
+    while words.iter().any(|&w| w != 0) {
+        let mut rem = 0u64;
+        for i in range(0u, 4) {
+            let cur = (rem << 32) | words[i];
+            words[i] = cur / 1_000_000_000;
+            rem = cur % 1_000_000_000;
+        }
+        chunks.push(rem);
+    }
+    match chunks.pop() {
+        None => "0".to_string(),
+        Some(first) => {
+            let mut s = first.to_string();
+            for chunk in chunks.iter().rev() {
+                s.push_str(format!("{:09}", *chunk).as_slice());
+            }
+            s
+        }
+    }
+}
+
+/// The string form from the Decimal128 spec, as used by `$numberDecimal`.
+pub fn to_string(bytes: &[u8]) -> String {
+    let (negative, kind) = classify(bytes);
+    let sign = if negative { "-" } else { "" };
+    match kind {
+        NaN => "NaN".to_string(),
+        Infinity => format!("{}Infinity", sign),
+        Finite(exponent, digits) => {
+            let adjusted = exponent + digits.len() as i32 - 1;
+            if exponent <= 0 && adjusted >= -6 {
+                // Plain notation, with the point moved `-exponent` places in.
+                let point = digits.len() as i32 + exponent;
+                if exponent == 0 {
+                    format!("{}{}", sign, digits)
+                } else if point > 0 {
+                    let point = point as uint;
+                    format!("{}{}.{}", sign, digits.as_slice().slice_to(point), digits.as_slice().slice_from(point))
+                } else {
+                    format!("{}0.{}{}", sign, "0".repeat((-point) as uint), digits)
+                }
+            } else {
+                let mut s = format!("{}{}", sign, digits.as_slice().slice_to(1));
+                if digits.len() > 1 {
+                    s.push('.');
+                    s.push_str(digits.as_slice().slice_from(1));
+                }
+                s.push_str(format!("E{}{}", if adjusted >= 0 { "+" } else { "" }, adjusted).as_slice());
+                s
+            }
+        }
+    }
+}
+
+/// The nearest double, more or less. Anything past 17 significant digits or
+/// outside the double's exponent range is lost.
+pub fn to_f64(bytes: &[u8]) -> f64 {
+    let (negative, kind) = classify(bytes);
+    let v = match kind {
+        NaN => return f64::NAN,
+        Infinity => f64::INFINITY,
+        Finite(exponent, digits) => {
+            let c: f64 = from_str(digits.as_slice()).unwrap();
+            if exponent >= 0 {
+                c * 10f64.powi(exponent)
+            } else {
+                c / 10f64.powi(-exponent)
+            }
+        }
+    };
+    if negative { -v } else { v }
+}
+
+fn pack(high: u64, low: u64) -> Vec<u8> {
+    range(0u, 16).map(|i| if i < 8 { (low >> (8 * i)) as u8 } else { (high >> (8 * (i - 8))) as u8 }).collect()
+}
+
+/// Reads the string form back into the 16 little-endian bytes. Values that
+/// would have to be rounded to fit are refused.
+pub fn parse(s: &str) -> Option<Vec<u8>> {
+    let (negative, body) = if s.starts_with("-") {
+        (true, s.slice_from(1))
+    } else if s.starts_with("+") {
+        (false, s.slice_from(1))
+    } else {
+        (false, s)
+    };
+    let sign = if negative { 1u64 << 63 } else { 0 };
+    if body.eq_ignore_ascii_case("nan") {
+        return Some(pack(0x7C00000000000000, 0));
+    }
+    if body.eq_ignore_ascii_case("inf") || body.eq_ignore_ascii_case("infinity") {
+        return Some(pack(sign | 0x7800000000000000, 0));
+    }
+
+    let (mantissa, mut exponent) = match body.find(|c: char| c == 'e' || c == 'E') {
+        None => (body, 0i64),
+        Some(i) => {
+            let e = body.slice_from(i + 1);
+            let (minus, e) = if e.starts_with("-") {
+                (true, e.slice_from(1))
+            } else if e.starts_with("+") {
+                (false, e.slice_from(1))
+            } else {
+                (false, e)
+            };
+            // One sign at most, then digits. Anything longer than eight is
+            // far outside the range anyway.
+            if e.is_empty() || e.len() > 8 || !e.chars().all(|c| c >= '0' && c <= '9') {
+                return None;
+            }
+            let e: i64 = from_str(e).unwrap();
+            (body.slice_to(i), if minus { -e } else { e })
+        }
+    };
+    let mut digits = String::new();
+    let mut seen_point = false;
+    for c in mantissa.chars() {
+        if c >= '0' && c <= '9' {
+            digits.push(c);
+            if seen_point {
+                exponent -= 1;
+            }
+        } else if c == '.' && !seen_point {
+            seen_point = true;
+        } else {
+            return None;
+        }
+    }
+    if digits.is_empty() {
+        return None;
+    }
+
+    let mut coefficient = match digits.as_slice().trim_left_chars('0') {
+        "" => "0".to_string(),
+        d => d.to_string(),
+    };
+    // Trailing zeros can move into the exponent, and zeros can be added to
+    // bring a large exponent into range.
+    while (coefficient.len() > MAX_DIGITS || exponent < MIN_EXPONENT)
+        && coefficient.len() > 1 && coefficient.as_slice().ends_with("0") {
+        coefficient.pop();
+        exponent += 1;
+    }
+    if coefficient.as_slice() == "0" {
+        exponent = if exponent < MIN_EXPONENT { MIN_EXPONENT } else if exponent > MAX_EXPONENT { MAX_EXPONENT } else { exponent };
+    }
+    while exponent > MAX_EXPONENT && coefficient.len() < MAX_DIGITS {
+        coefficient.push('0');
+        exponent -= 1;
+    }
+    if coefficient.len() > MAX_DIGITS || exponent < MIN_EXPONENT || exponent > MAX_EXPONENT {
+        return None;
+    }
+
+    let mut words = [0u64, ..4];
+    for c in coefficient.chars() {
+        let mut carry = (c as u64) - ('0' as u64);
+        for i in range(0u, 4).rev() {
+            let cur = words[i] * 10 + carry;
+            words[i] = cur & 0xFFFFFFFF;
+            carry = cur >> 32;
+        }
+    }
+    let biased = (exponent + EXPONENT_BIAS as i64) as u64;
+    let high = sign | (biased << 49) | (words[0] << 32) | words[1];
+    Some(pack(high, (words[2] << 32) | words[3]))
+}
@@ -0,0 +1,245 @@
+use std::{cmp,uint};
+
+use super::{Parser,ParseResult,ParserError,SyntaxError,IoError,DecoderOptions,Utf8Lossy};
+use super::{BadLength,DepthExceeded,MAX_DEPTH,le_u64};
+use super::extjson::quote;
+use super::shell::{to_shell,ShellOptions};
+
+// Bytes of hex on each row of a listing.
+static ROW: uint = 16;
+// Width of each side of a diff.
+static DIFF_COLUMN: uint = 64;
+
+/// One annotated run of bytes.
+struct Line {
+    offset: uint,
+    len: uint,
+    depth: uint,
+    text: String,
+    /// The dotted path of the element the bytes belong to.
+    path: String,
+}
+
+fn type_name(t: u8) -> &'static str {
+    match t {
+        0x01 => "double",
+        0x02 => "string",
+        0x03 => "object",
+        0x04 => "array",
+        0x05 => "binData",
+        0x06 => "undefined",
+        0x07 => "objectId",
+        0x08 => "bool",
+        0x09 => "date",
+        0x0A => "null",
+        0x0B => "regex",
+        0x0C => "dbPointer",
+        0x0D => "javascript",
+        0x0E => "symbol",
+        0x0F => "javascriptWithScope",
+        0x10 => "int",
+        0x11 => "timestamp",
+        0x12 => "long",
+        0x13 => "decimal",
+        0xFF => "minKey",
+        0x7F => "maxKey",
+        _ => "unknown",
+    }
+}
+
+struct Walker<'a> {
+    parser: Parser<'a>,
+    lines: Vec<Line>,
+}
+
+impl<'a> Walker<'a> {
+    /// Annotates the bytes from `offset` up to where the parser is now.
+    fn line(&mut self, offset: uint, depth: uint, text: String) {
+        let line = Line {
+            offset: offset,
+            len: self.parser.pos - offset,
+            depth: depth,
+            text: text,
+            path: self.parser.path.as_slice().connect("."),
+        };
+        self.lines.push(line);
+    }
+
+    fn document(&mut self, depth: uint, what: &str) -> ParseResult<()> {
+        let start = self.parser.pos;
+        if depth >= MAX_DEPTH {
+            return self.parser.error_at(DepthExceeded, start);
+        }
+        let len = try!(self.parser.read_i32());
+        self.line(start, depth, format!("{} length {}", what, len));
+        if len < 5 {
+            return self.parser.error_at(BadLength, start);
+        }
+        loop {
+            let type_pos = self.parser.pos;
+            let t = try!(self.parser.read_u8());
+            if t == 0x00 {
+                self.line(type_pos, depth, format!("end of {}", what));
+                if self.parser.pos != start + len as uint {
+                    return self.parser.error_at(BadLength, start);
+                }
+                return Ok(());
+            }
+            let key = try!(self.parser.read_cstring());
+            self.parser.path.push(key);
+            let header = format!("{} {}", type_name(t), quote(self.parser.path.last().unwrap().as_slice()));
+            self.line(type_pos, depth + 1, header);
+            try!(self.value(t, type_pos, depth + 2));
+            self.parser.path.pop();
+        }
+    }
+
+    fn value(&mut self, t: u8, type_pos: uint, depth: uint) -> ParseResult<()> {
+        let start = self.parser.pos;
+        match t {
+            0x03 => self.document(depth, "document"),
+            0x04 => self.document(depth, "array"),
+            0x0F => {
+                let len = try!(self.parser.read_i32());
+                self.line(start, depth, format!("code with scope length {}", len));
+                let code_pos = self.parser.pos;
+                let (code, _) = try!(self.parser.read_string_bytes());
+                let code = String::from_utf8_lossy(code).into_string();
+                self.line(code_pos, depth, format!("length {} code {}", code.len() + 1, quote(code.as_slice())));
+                self.document(depth, "scope")
+            },
+            _ => {
+                let v = try!(self.parser.read_element(t, type_pos, None));
+                let shown = to_shell(&v, &ShellOptions { indent: 0, width: uint::MAX });
+                let text = match t {
+                    // These carry a length prefix of their own.
+                    0x02 | 0x05 | 0x0C | 0x0D | 0x0E => {
+                        let len = le_u64(self.parser.buf.slice(start, start + 4)) as u32 as i32;
+                        format!("length {} {}", len, shown)
+                    },
+                    _ => shown,
+                };
+                self.line(start, depth, text);
+                Ok(())
+            },
+        }
+    }
+}
+
+/// Walks every document in `bytes`, stopping at the first thing that
+/// can't be parsed, recording it as the last line and giving it back.
+fn walk(bytes: &[u8]) -> (Vec<Line>, Option<ParserError>) {
+    let mut options = DecoderOptions::new();
+    options.utf8 = Utf8Lossy;
+    let mut walker = Walker { parser: Parser::new(bytes, &options), lines: Vec::new() };
+    while walker.parser.pos < bytes.len() {
+        let e = match walker.document(0, "document") {
+            Ok(()) => continue,
+            Err(e) => e,
+        };
+        let offset = match e {
+            SyntaxError(_, offset, _) => offset,
+            IoError(..) => walker.parser.pos,
+        };
+        let offset = cmp::min(offset, bytes.len());
+        let path = walker.parser.path.as_slice().connect(".");
+        walker.lines.push(Line {
+            offset: offset,
+            len: cmp::min(ROW, bytes.len() - offset),
+            depth: 0,
+            text: format!("error: {}", e),
+            path: path,
+        });
+        return (walker.lines, Some(e));
+    }
+    (walker.lines, None)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", *b)).collect::<Vec<String>>().connect(" ")
+}
+
+/// An indented listing of the documents in `bytes`, one line per length,
+/// key and value, each with its offset and its bytes in hex. Anything that
+/// can't be parsed ends the listing with an `error:` line at its offset,
+/// and the error comes back alongside it.
+pub fn annotate(bytes: &[u8]) -> (String, Option<ParserError>) {
+    let (lines, error) = walk(bytes);
+    let mut out = String::new();
+    for line in lines.iter() {
+        let end = line.offset + line.len;
+        let mut row = line.offset;
+        loop {
+            let row_end = cmp::min(row + ROW, end);
+            let text = if row == line.offset {
+                format!("{}{}", "  ".repeat(line.depth), line.text)
+            } else {
+                String::new()
+            };
+            let row_hex = hex(bytes.slice(row, row_end));
+            out.push_str(format!("{:08x}  {:<47}  {}", row, row_hex, text).as_slice().trim_right());
+            out.push('\n');
+            row = row_end;
+            if row >= end {
+                break;
+            }
+        }
+    }
+    (out, error)
+}
+
+/// One side of a diff row, cut or padded to `DIFF_COLUMN` characters.
+fn cell(bytes: &[u8], line: Option<&Line>) -> String {
+    let text = match line {
+        None => String::new(),
+        Some(line) => {
+            // Long values only show their first few bytes.
+            let shown = cmp::min(line.len, 6);
+            let mut h = hex(bytes.slice(line.offset, line.offset + shown));
+            if shown < line.len {
+                h.push_str(" ..");
+            }
+            format!("{:06x} {:<20} {}{}", line.offset, h, "  ".repeat(line.depth), line.text)
+        },
+    };
+    let chars: Vec<char> = text.as_slice().chars().collect();
+    if chars.len() > DIFF_COLUMN {
+        let mut cut = String::from_chars(chars.slice_to(DIFF_COLUMN - 1));
+        cut.push('…');
+        cut
+    } else {
+        format!("{}{}", text, " ".repeat(DIFF_COLUMN - chars.len()))
+    }
+}
+
+fn same(left: &[u8], l: &Line, right: &[u8], r: &Line) -> bool {
+    l.text == r.text && l.path == r.path
+        && left.slice(l.offset, l.offset + l.len) == right.slice(r.offset, r.offset + r.len)
+}
+
+/// Two listings side by side. The first line where they part ways is marked
+/// with `>>` and named, by path and offsets, at the top.
+pub fn diff(left: &[u8], right: &[u8]) -> String {
+    let ((l, _), (r, _)) = (walk(left), walk(right));
+    let rows = cmp::max(l.len(), r.len());
+    let first = range(0, rows).find(|&i| match (l.as_slice().get(i), r.as_slice().get(i)) {
+        (Some(a), Some(b)) => !same(left, a, right, b),
+        _ => true,
+    });
+    let mut out = match first {
+        None => "identical\n".to_string(),
+        Some(i) => {
+            let (a, b) = (l.as_slice().get(i), r.as_slice().get(i));
+            let path = a.or(b).map_or(String::new(), |line| line.path.clone());
+            let offset = |line: Option<&Line>| line.map_or("end".to_string(), |line| format!("{:#x}", line.offset));
+            format!("first difference at `{}`: left {}, right {}\n", path, offset(a), offset(b))
+        },
+    };
+    for i in range(0, rows) {
+        let marker = if first == Some(i) { ">>" } else { "  " };
+        let row = format!("{} {} | {}", marker, cell(left, l.as_slice().get(i)), cell(right, r.as_slice().get(i)));
+        out.push_str(row.as_slice().trim_right());
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,709 @@
+use std::{fmt,char,f64,i32,i64,u32};
+use std::collections::TreeMap;
+use std::num::Float;
+
+use super::{Bson,Object,ObjectId,String,Boolean,List,Null,Binary,Undefined,UtcDatetime,RegExp};
+use super::{DbPointer,JavaScript,Symbol,JavaScriptWithScope,Int32,Timestamp,Int64,Decimal128};
+use super::{MinKey,MaxKey,RawString};
+use super::{decimal,datetime,base64,to_hex,from_hex,format_double,MAX_DEPTH};
+
+/// Extended JSON v2 in canonical mode: every value is written with its type,
+/// so it reads back as exactly the same BSON.
+pub fn to_canonical_extjson(v: &Bson) -> String {
+    let mut out = String::new();
+    write_value(&mut out, v, false);
+    out
+}
+
+/// Extended JSON v2 in relaxed mode: numbers are plain JSON numbers and
+/// dates in the usual range are ISO-8601 strings. Easier to read, but the
+/// exact numeric types are lost.
+pub fn to_relaxed_extjson(v: &Bson) -> String {
+    let mut out = String::new();
+    write_value(&mut out, v, true);
+    out
+}
+
+/// A JSON string literal.
+pub fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\x08' => out.push_str("\\b"),
+            '\x0c' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => out.push_str(format!("\\u{:04x}", c as u32).as_slice()),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `{"key":value}`, where `value` is already JSON.
+fn wrap(out: &mut String, key: &str, value: &str) {
+    out.push_str(format!("{{{}:{}}}", quote(key), value).as_slice());
+}
+
+fn double_string(v: f64) -> String {
+    if v.is_nan() {
+        "NaN".to_string()
+    } else if v.is_infinite() {
+        if v > 0f64 { "Infinity".to_string() } else { "-Infinity".to_string() }
+    } else {
+        format_double(v)
+    }
+}
+
+fn write_document(out: &mut String, obj: &Object, relaxed: bool) {
+    out.push('{');
+    for (i, (key, value)) in obj.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str(quote(key.as_slice()).as_slice());
+        out.push(':');
+        write_value(out, value, relaxed);
+    }
+    out.push('}');
+}
+
+fn write_value(out: &mut String, v: &Bson, relaxed: bool) {
+    match *v {
+        ObjectId(ref id) => wrap(out, "$oid", quote(to_hex(id.as_slice()).as_slice()).as_slice()),
+        // The variant is spelled out since the Float trait is imported.
+        super::Float(v) if relaxed && v.is_finite() => out.push_str(format_double(v).as_slice()),
+        super::Float(v) => wrap(out, "$numberDouble", quote(double_string(v).as_slice()).as_slice()),
+        String(ref s) => out.push_str(quote(s.as_slice()).as_slice()),
+        Boolean(b) => out.push_str(if b { "true" } else { "false" }),
+        List(ref items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(out, item, relaxed);
+            }
+            out.push(']');
+        },
+        Object(ref obj) => write_document(out, obj, relaxed),
+        Null => out.push_str("null"),
+        Binary(subtype, ref data) => {
+            let binary = format!("{{\"base64\":{},\"subType\":\"{:02x}\"}}",
+                                 quote(base64::encode(data.as_slice()).as_slice()), subtype);
+            wrap(out, "$binary", binary.as_slice());
+        },
+        Undefined => wrap(out, "$undefined", "true"),
+        UtcDatetime(ms) if relaxed && ms >= 0 && ms <= datetime::MAX_ISO_DATE => {
+            wrap(out, "$date", quote(datetime::to_iso8601(ms).as_slice()).as_slice());
+        },
+        UtcDatetime(ms) => wrap(out, "$date", format!("{{\"$numberLong\":\"{}\"}}", ms).as_slice()),
+        RegExp(ref pattern, ref options) => {
+            let regex = format!("{{\"pattern\":{},\"options\":{}}}",
+                                quote(pattern.as_slice()), quote(options.as_slice()));
+            wrap(out, "$regularExpression", regex.as_slice());
+        },
+        DbPointer(ref ns, ref id) => {
+            let pointer = format!("{{\"$ref\":{},\"$id\":{{\"$oid\":{}}}}}",
+                                  quote(ns.as_slice()), quote(to_hex(id.as_slice()).as_slice()));
+            wrap(out, "$dbPointer", pointer.as_slice());
+        },
+        JavaScript(ref code) => wrap(out, "$code", quote(code.as_slice()).as_slice()),
+        Symbol(ref s) => wrap(out, "$symbol", quote(s.as_slice()).as_slice()),
+        JavaScriptWithScope(ref code, ref scope) => {
+            out.push_str(format!("{{\"$code\":{},\"$scope\":", quote(code.as_slice())).as_slice());
+            write_document(out, scope, relaxed);
+            out.push('}');
+        },
+        Int32(v) if relaxed => out.push_str(v.to_string().as_slice()),
+        Int32(v) => wrap(out, "$numberInt", quote(v.to_string().as_slice()).as_slice()),
+        Timestamp(time, increment) => {
+            wrap(out, "$timestamp", format!("{{\"t\":{},\"i\":{}}}", time, increment).as_slice());
+        },
+        Int64(v) if relaxed => out.push_str(v.to_string().as_slice()),
+        Int64(v) => wrap(out, "$numberLong", quote(v.to_string().as_slice()).as_slice()),
+        Decimal128(ref bytes) => wrap(out, "$numberDecimal", quote(decimal::to_string(bytes.as_slice()).as_slice()).as_slice()),
+        MinKey => wrap(out, "$minKey", "1"),
+        MaxKey => wrap(out, "$maxKey", "1"),
+        // There's no way to write bytes that aren't UTF-8 in JSON.
+        RawString(ref bytes) => out.push_str(quote(String::from_utf8_lossy(bytes.as_slice()).as_slice()).as_slice()),
+    }
+}
+
+/// Where in the text a value went wrong. Lines and columns count from one,
+/// columns in characters.
+#[deriving(PartialEq, Clone)]
+pub struct ExtJsonError {
+    pub line: uint,
+    pub column: uint,
+    pub msg: String,
+}
+
+impl fmt::Show for ExtJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.msg, self.line, self.column)
+    }
+}
+
+pub type ExtJsonResult<T> = Result<T, ExtJsonError>;
+
+fn fail<T>(line: uint, column: uint, msg: String) -> ExtJsonResult<T> {
+    Err(ExtJsonError { line: line, column: column, msg: msg })
+}
+
+/// Plain JSON, before any `$` keys have been looked at. Numbers keep their
+/// text so integers and doubles can be told apart.
+enum Json {
+    JsonNull,
+    JsonBool(bool),
+    JsonNumber(String),
+    JsonString(String),
+    JsonArray(Vec<Node>),
+    JsonObject(Vec<Member>),
+}
+
+struct Node {
+    value: Json,
+    line: uint,
+    column: uint,
+}
+
+struct Member {
+    key: String,
+    line: uint,
+    column: uint,
+    value: Node,
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    pos: uint,
+    line: uint,
+    column: uint,
+    depth: uint,
+}
+
+impl JsonParser {
+    fn error<T>(&self, msg: &str) -> ExtJsonResult<T> {
+        fail(self.line, self.column, msg.to_string())
+    }
+
+    fn peek(&self) -> Option<char> {
+        if self.pos < self.chars.len() { Some(self.chars[self.pos]) } else { None }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        match c {
+            Some('\n') => {
+                self.line += 1;
+                self.column = 1;
+            },
+            Some(_) => self.column += 1,
+            None => (),
+        }
+        self.pos += 1;
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') | Some('\n') | Some('\r') => { self.bump(); },
+                _ => return,
+            }
+        }
+    }
+
+    fn expect(&mut self, c: char) -> ExtJsonResult<()> {
+        if self.peek() == Some(c) {
+            self.bump();
+            Ok(())
+        } else {
+            self.error(format!("expected `{}`", c).as_slice())
+        }
+    }
+
+    fn parse_literal(&mut self, word: &str, value: Json) -> ExtJsonResult<Json> {
+        for c in word.chars() {
+            if self.peek() != Some(c) {
+                return self.error(format!("expected `{}`", word).as_slice());
+            }
+            self.bump();
+        }
+        Ok(value)
+    }
+
+    fn parse_value(&mut self) -> ExtJsonResult<Node> {
+        self.skip_whitespace();
+        let (line, column) = (self.line, self.column);
+        let value = match self.peek() {
+            Some('{') => try!(self.parse_object()),
+            Some('[') => try!(self.parse_array()),
+            Some('"') => JsonString(try!(self.parse_string())),
+            Some('t') => try!(self.parse_literal("true", JsonBool(true))),
+            Some('f') => try!(self.parse_literal("false", JsonBool(false))),
+            Some('n') => try!(self.parse_literal("null", JsonNull)),
+            Some(c) if c == '-' || (c >= '0' && c <= '9') => JsonNumber(try!(self.parse_number())),
+            Some(_) => return self.error("expected a value"),
+            None => return self.error("unexpected end of input"),
+        };
+        Ok(Node { value: value, line: line, column: column })
+    }
+
+    fn enter(&mut self) -> ExtJsonResult<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return self.error("nested too deeply");
+        }
+        self.bump();
+        self.skip_whitespace();
+        Ok(())
+    }
+
+    fn parse_array(&mut self) -> ExtJsonResult<Json> {
+        try!(self.enter());
+        let mut items = Vec::new();
+        if self.peek() != Some(']') {
+            loop {
+                items.push(try!(self.parse_value()));
+                self.skip_whitespace();
+                if self.peek() != Some(',') {
+                    break;
+                }
+                self.bump();
+            }
+        }
+        try!(self.expect(']'));
+        self.depth -= 1;
+        Ok(JsonArray(items))
+    }
+
+    fn parse_object(&mut self) -> ExtJsonResult<Json> {
+        try!(self.enter());
+        let mut members = Vec::new();
+        if self.peek() != Some('}') {
+            loop {
+                self.skip_whitespace();
+                let (line, column) = (self.line, self.column);
+                if self.peek() != Some('"') {
+                    return self.error("expected a string key");
+                }
+                let key = try!(self.parse_string());
+                self.skip_whitespace();
+                try!(self.expect(':'));
+                let value = try!(self.parse_value());
+                members.push(Member { key: key, line: line, column: column, value: value });
+                self.skip_whitespace();
+                if self.peek() != Some(',') {
+                    break;
+                }
+                self.bump();
+            }
+        }
+        try!(self.expect('}'));
+        self.depth -= 1;
+        Ok(JsonObject(members))
+    }
+
+    fn parse_hex4(&mut self) -> ExtJsonResult<u32> {
+        let mut n = 0u32;
+        for _ in range(0u, 4) {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(d) => n = n * 16 + d as u32,
+                None => return self.error("expected four hex digits"),
+            }
+            self.bump();
+        }
+        Ok(n)
+    }
+
+    fn parse_string(&mut self) -> ExtJsonResult<String> {
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                None => return self.error("unterminated string"),
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.bump() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\x08',
+                        Some('f') => '\x0c',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let mut n = try!(self.parse_hex4());
+                            if n >= 0xD800 && n < 0xDC00 {
+                                // The first half of a surrogate pair.
+                                if self.bump() != Some('\\') || self.bump() != Some('u') {
+                                    return self.error("expected the second half of a surrogate pair");
+                                }
+                                let low = try!(self.parse_hex4());
+                                if low < 0xDC00 || low >= 0xE000 {
+                                    return self.error("invalid surrogate pair");
+                                }
+                                n = 0x10000 + ((n - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            match char::from_u32(n) {
+                                Some(c) => c,
+                                None => return self.error("invalid unicode escape"),
+                            }
+                        },
+                        _ => return self.error("invalid escape"),
+                    };
+                    s.push(c);
+                },
+                Some(c) if (c as u32) < 0x20 => return self.error("control character in string"),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> ExtJsonResult<String> {
+        let start = self.pos;
+        if self.peek() == Some('-') {
+            self.bump();
+        }
+        let int_start = self.pos;
+        try!(self.parse_digits());
+        if self.chars[int_start] == '0' && self.pos - int_start > 1 {
+            return self.error("leading zero in a number");
+        }
+        if self.peek() == Some('.') {
+            self.bump();
+            try!(self.parse_digits());
+        }
+        if self.peek() == Some('e') || self.peek() == Some('E') {
+            self.bump();
+            if self.peek() == Some('+') || self.peek() == Some('-') {
+                self.bump();
+            }
+            try!(self.parse_digits());
+        }
+        Ok(String::from_chars(self.chars.slice(start, self.pos)))
+    }
+
+    fn parse_digits(&mut self) -> ExtJsonResult<()> {
+        let start = self.pos;
+        while self.peek().map_or(false, |c| c >= '0' && c <= '9') {
+            self.bump();
+        }
+        if self.pos == start {
+            return self.error("expected a digit");
+        }
+        Ok(())
+    }
+}
+
+/// Parses canonical or relaxed Extended JSON v2, the `$uuid` shorthand for
+/// binary subtype 4, and the legacy v1 forms of `$date`, `$binary` (with
+/// `$type`) and `$regex` (with `$options`).
+/// An object with a wrapper's key has to be exactly that wrapper; other `$`
+/// keys, like query operators, are left as ordinary fields.
+pub fn from_extjson(s: &str) -> ExtJsonResult<Bson> {
+    let mut parser = JsonParser { chars: s.chars().collect(), pos: 0, line: 1, column: 1, depth: 0 };
+    let node = try!(parser.parse_value());
+    parser.skip_whitespace();
+    if parser.peek().is_some() {
+        return parser.error("trailing characters");
+    }
+    to_bson(&node)
+}
+
+static WRAPPERS: &'static [&'static str] = &[
+    "$oid", "$symbol", "$numberInt", "$numberLong", "$numberDouble", "$numberDecimal",
+    "$binary", "$code", "$timestamp", "$regularExpression", "$dbPointer", "$date",
+    "$minKey", "$maxKey", "$undefined", "$uuid",
+];
+
+fn to_bson(node: &Node) -> ExtJsonResult<Bson> {
+    match node.value {
+        JsonNull => Ok(Null),
+        JsonBool(b) => Ok(Boolean(b)),
+        JsonString(ref s) => Ok(String(s.clone())),
+        JsonNumber(ref text) => Ok(number(text.as_slice())),
+        JsonArray(ref items) => {
+            let mut list = Vec::with_capacity(items.len());
+            for item in items.iter() {
+                list.push(try!(to_bson(item)));
+            }
+            Ok(List(list))
+        },
+        JsonObject(ref members) => {
+            match try!(wrapper(node, members.as_slice())) {
+                Some(v) => Ok(v),
+                None => Ok(Object(try!(to_document(members.as_slice())))),
+            }
+        },
+    }
+}
+
+fn to_document(members: &[Member]) -> ExtJsonResult<Object> {
+    let mut obj = TreeMap::new();
+    for m in members.iter() {
+        if obj.contains_key(&m.key) {
+            return fail(m.line, m.column, format!("duplicate key `{}`", m.key));
+        }
+        if m.key.as_slice().contains_char('\0') {
+            return fail(m.line, m.column, "keys can't contain a null character".to_string());
+        }
+        obj.insert(m.key.clone(), try!(to_bson(&m.value)));
+    }
+    Ok(obj)
+}
+
+/// Integers become int32 or int64, whichever is the smallest to hold them;
+/// anything else is a double.
+fn number(text: &str) -> Bson {
+    if !text.contains_char('.') && !text.contains_char('e') && !text.contains_char('E') {
+        match from_str::<i64>(text) {
+            Some(v) if v >= i32::MIN as i64 && v <= i32::MAX as i64 => return Int32(v as i32),
+            Some(v) => return Int64(v),
+            None => (),
+        }
+    }
+    super::Float(double(text).unwrap())
+}
+
+fn double(text: &str) -> Option<f64> {
+    from_str::<f64>(text.replace("e+", "e").replace("E+", "E").as_slice())
+}
+
+fn is_string(node: &Node) -> bool {
+    match node.value {
+        JsonString(_) => true,
+        _ => false,
+    }
+}
+
+/// `$regex` is also a query operator, so it's only the v1 regular
+/// expression when it and `$options` are both strings.
+fn is_legacy_regex(m: &Member, members: &[Member]) -> bool {
+    m.key.as_slice() == "$regex" && is_string(&m.value)
+        && members.iter().any(|o| o.key.as_slice() == "$options" && is_string(&o.value))
+}
+
+/// Checks that a wrapper has exactly the keys it should.
+fn only(node: &Node, members: &[Member], wrapper: &str, keys: &[&str]) -> ExtJsonResult<()> {
+    for m in members.iter() {
+        if !keys.iter().any(|k| *k == m.key.as_slice()) {
+            return fail(m.line, m.column, format!("unexpected key `{}` in `{}`", m.key, wrapper));
+        }
+    }
+    for k in keys.iter() {
+        if members.iter().filter(|m| m.key.as_slice() == *k).count() != 1 {
+            return fail(node.line, node.column, format!("`{}` needs exactly one `{}`", wrapper, *k));
+        }
+    }
+    Ok(())
+}
+
+fn member<'a>(members: &'a [Member], key: &str) -> &'a Node {
+    &members.iter().find(|m| m.key.as_slice() == key).unwrap().value
+}
+
+fn string<'a>(node: &'a Node, what: &str) -> ExtJsonResult<&'a str> {
+    match node.value {
+        JsonString(ref s) => Ok(s.as_slice()),
+        _ => fail(node.line, node.column, format!("`{}` must be a string", what)),
+    }
+}
+
+/// A string that has to fit in a BSON C string.
+fn cstring<'a>(node: &'a Node, what: &str) -> ExtJsonResult<&'a str> {
+    let s = try!(string(node, what));
+    if s.contains_char('\0') {
+        return fail(node.line, node.column, format!("`{}` can't contain a null character", what));
+    }
+    Ok(s)
+}
+
+fn object_members<'a>(node: &'a Node, what: &str) -> ExtJsonResult<&'a [Member]> {
+    match node.value {
+        JsonObject(ref members) => Ok(members.as_slice()),
+        _ => fail(node.line, node.column, format!("`{}` must be an object", what)),
+    }
+}
+
+/// Parses a string with `parse`, failing at `node` if it can't be read.
+fn parsed<T>(node: &Node, what: &str, parse: |&str| -> Option<T>) -> ExtJsonResult<T> {
+    let s = try!(string(node, what));
+    match parse(s) {
+        Some(v) => Ok(v),
+        None => fail(node.line, node.column, format!("invalid `{}`: {}", what, quote(s))),
+    }
+}
+
+fn integer(node: &Node, what: &str, min: i64, max: i64) -> ExtJsonResult<i64> {
+    match node.value {
+        JsonNumber(ref text) => match from_str::<i64>(text.as_slice()) {
+            Some(v) if v >= min && v <= max => return Ok(v),
+            _ => (),
+        },
+        _ => (),
+    }
+    fail(node.line, node.column, format!("`{}` must be an integer from {} to {}", what, min, max))
+}
+
+fn object_id(node: &Node, what: &str) -> ExtJsonResult<Vec<u8>> {
+    parsed(node, what, |s| if s.len() == 24 { from_hex(s) } else { None })
+}
+
+/// The 16 bytes of a UUID in its usual hyphenated form.
+fn uuid(s: &str) -> Option<Vec<u8>> {
+    let hyphens = [8u, 13, 18, 23];
+    if s.len() != 36 || s.char_indices().any(|(i, c)| (c == '-') != hyphens.contains(&i)) {
+        return None;
+    }
+    from_hex(s.replace("-", "").as_slice())
+}
+
+/// Options are kept in alphabetical order, as BSON stores them.
+fn regex(pattern: &Node, options: &Node, pattern_what: &str, options_what: &str) -> ExtJsonResult<Bson> {
+    let pattern = try!(cstring(pattern, pattern_what)).to_string();
+    let mut options: Vec<char> = try!(cstring(options, options_what)).chars().collect();
+    options.sort();
+    Ok(RegExp(pattern, options.into_iter().collect()))
+}
+
+fn subtype(s: &str) -> Option<u8> {
+    if s.len() == 0 || s.len() > 2 {
+        return None;
+    }
+    from_hex(if s.len() == 1 { format!("0{}", s) } else { s.to_string() }.as_slice()).map(|b| b[0])
+}
+
+/// Reads the value of a `$`-keyed wrapper, or returns `None` if the object
+/// is an ordinary document.
+fn wrapper(node: &Node, members: &[Member]) -> ExtJsonResult<Option<Bson>> {
+    let key = match members.iter().find(|m| WRAPPERS.iter().any(|w| *w == m.key.as_slice())
+                                             || is_legacy_regex(*m, members)) {
+        Some(m) => m.key.clone(),
+        None => return Ok(None),
+    };
+    let key = key.as_slice();
+    let value = member(members, key);
+    let v = match key {
+        "$oid" => {
+            try!(only(node, members, key, &["$oid"]));
+            ObjectId(try!(object_id(value, key)))
+        },
+        "$symbol" => {
+            try!(only(node, members, key, &["$symbol"]));
+            Symbol(try!(string(value, key)).to_string())
+        },
+        "$numberInt" => {
+            try!(only(node, members, key, &["$numberInt"]));
+            Int32(try!(parsed(value, key, |s| from_str::<i32>(s))))
+        },
+        "$numberLong" => {
+            try!(only(node, members, key, &["$numberLong"]));
+            Int64(try!(parsed(value, key, |s| from_str::<i64>(s))))
+        },
+        "$numberDouble" => {
+            try!(only(node, members, key, &["$numberDouble"]));
+            super::Float(try!(parsed(value, key, |s| match s {
+                "Infinity" => Some(f64::INFINITY),
+                "-Infinity" => Some(f64::NEG_INFINITY),
+                "NaN" => Some(f64::NAN),
+                s => double(s),
+            })))
+        },
+        "$numberDecimal" => {
+            try!(only(node, members, key, &["$numberDecimal"]));
+            Decimal128(try!(parsed(value, key, |s| decimal::parse(s))))
+        },
+        "$binary" => match value.value {
+            JsonObject(ref inner) => {
+                try!(only(node, members, key, &["$binary"]));
+                try!(only(value, inner.as_slice(), key, &["base64", "subType"]));
+                let data = try!(parsed(member(inner.as_slice(), "base64"), "base64", |s| base64::decode(s)));
+                Binary(try!(parsed(member(inner.as_slice(), "subType"), "subType", |s| subtype(s))), data)
+            },
+            _ => {
+                // Version 1 put the subtype alongside, as `$type`.
+                try!(only(node, members, key, &["$binary", "$type"]));
+                let data = try!(parsed(value, key, |s| base64::decode(s)));
+                Binary(try!(parsed(member(members, "$type"), "$type", |s| subtype(s))), data)
+            },
+        },
+        "$uuid" => {
+            try!(only(node, members, key, &["$uuid"]));
+            Binary(0x04, try!(parsed(value, key, |s| uuid(s))))
+        },
+        "$code" => {
+            let code = try!(string(value, key)).to_string();
+            if members.iter().any(|m| m.key.as_slice() == "$scope") {
+                try!(only(node, members, key, &["$code", "$scope"]));
+                let scope = try!(object_members(member(members, "$scope"), "$scope"));
+                JavaScriptWithScope(code, try!(to_document(scope)))
+            } else {
+                try!(only(node, members, key, &["$code"]));
+                JavaScript(code)
+            }
+        },
+        "$timestamp" => {
+            try!(only(node, members, key, &["$timestamp"]));
+            let inner = try!(object_members(value, key));
+            try!(only(value, inner, key, &["t", "i"]));
+            let time = try!(integer(member(inner, "t"), "t", 0, u32::MAX as i64));
+            let increment = try!(integer(member(inner, "i"), "i", 0, u32::MAX as i64));
+            Timestamp(time as u32, increment as u32)
+        },
+        "$regularExpression" => {
+            try!(only(node, members, key, &["$regularExpression"]));
+            let inner = try!(object_members(value, key));
+            try!(only(value, inner, key, &["pattern", "options"]));
+            try!(regex(member(inner, "pattern"), member(inner, "options"), "pattern", "options"))
+        },
+        "$regex" => {
+            try!(only(node, members, key, &["$regex", "$options"]));
+            try!(regex(value, member(members, "$options"), key, "$options"))
+        },
+        "$dbPointer" => {
+            try!(only(node, members, key, &["$dbPointer"]));
+            let inner = try!(object_members(value, key));
+            try!(only(value, inner, key, &["$ref", "$id"]));
+            let id = member(inner, "$id");
+            let oid = try!(object_members(id, "$id"));
+            try!(only(id, oid, "$id", &["$oid"]));
+            DbPointer(try!(string(member(inner, "$ref"), "$ref")).to_string(),
+                      try!(object_id(member(oid, "$oid"), "$oid")))
+        },
+        "$date" => {
+            try!(only(node, members, key, &["$date"]));
+            UtcDatetime(match value.value {
+                JsonString(_) => try!(parsed(value, key, |s| datetime::from_iso8601(s))),
+                JsonObject(ref inner) => {
+                    try!(only(value, inner.as_slice(), key, &["$numberLong"]));
+                    try!(parsed(member(inner.as_slice(), "$numberLong"), "$numberLong", |s| from_str::<i64>(s)))
+                },
+                // Version 1 allowed plain milliseconds.
+                _ => try!(integer(value, key, i64::MIN, i64::MAX)),
+            })
+        },
+        "$minKey" | "$maxKey" => {
+            try!(only(node, members, key, &[key]));
+            try!(integer(value, key, 1, 1));
+            if key == "$minKey" { MinKey } else { MaxKey }
+        },
+        "$undefined" => {
+            try!(only(node, members, key, &["$undefined"]));
+            match value.value {
+                JsonBool(true) => Undefined,
+                _ => return fail(value.line, value.column, "`$undefined` must be true".to_string()),
+            }
+        },
+        _ => unreachable!(),
+    };
+    Ok(Some(v))
+}
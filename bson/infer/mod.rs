@@ -0,0 +1,250 @@
+use std::cmp;
+use std::collections::TreeMap;
+
+use super::{Bson,Object,String,Int32,decimal};
+
+/// Appended to an array's path for its elements, as in `tags.$[]`, so
+/// `items.$[].price` is the price field of documents in `items`.
+pub static ELEMENTS: &'static str = "$[]";
+
+/// What turned up at one dotted path.
+#[deriving(PartialEq, Clone, Show)]
+pub struct FieldStats {
+    /// Documents the path turned up in at least once.
+    pub documents: uint,
+    /// Values seen, counting each element under an array.
+    pub values: uint,
+    /// How many of the values were of each type, by `Bson::type_name`.
+    pub types: TreeMap<String, uint>,
+    /// The least and greatest values other than documents and arrays, in
+    /// the server's sort order.
+    pub min: Option<Bson>,
+    pub max: Option<Bson>,
+    /// The shortest and longest strings, in characters.
+    pub string_lengths: Option<(uint, uint)>,
+    /// How many arrays there were of each length.
+    pub array_lengths: TreeMap<uint, uint>,
+}
+
+impl FieldStats {
+    fn new() -> FieldStats {
+        FieldStats {
+            documents: 0,
+            values: 0,
+            types: TreeMap::new(),
+            min: None,
+            max: None,
+            string_lengths: None,
+            array_lengths: TreeMap::new(),
+        }
+    }
+
+    fn add(&mut self, v: &Bson) {
+        self.values += 1;
+        bump(&mut self.types, v.type_name().to_string());
+        match *v {
+            super::Object(_) => return,
+            super::List(ref items) => {
+                bump(&mut self.array_lengths, items.len());
+                return;
+            },
+            String(ref s) => {
+                let len = s.as_slice().char_len();
+                self.string_lengths = Some(match self.string_lengths {
+                    Some((lo, hi)) => (cmp::min(lo, len), cmp::max(hi, len)),
+                    None => (len, len),
+                });
+            },
+            _ => (),
+        }
+        let lower = match self.min {
+            Some(ref min) => v.compare(min) == cmp::Less,
+            None => true,
+        };
+        if lower {
+            self.min = Some(v.clone());
+        }
+        let higher = match self.max {
+            Some(ref max) => v.compare(max) == cmp::Greater,
+            None => true,
+        };
+        if higher {
+            self.max = Some(v.clone());
+        }
+    }
+
+    /// How many values had type `name`.
+    pub fn count(&self, name: &str) -> uint {
+        self.types.find(&name.to_string()).map(|n| *n).unwrap_or(0)
+    }
+}
+
+fn bump<K: Ord>(counts: &mut TreeMap<K, uint>, key: K) {
+    let n = counts.find(&key).map(|n| *n).unwrap_or(0);
+    counts.insert(key, n + 1);
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// What a collection holds, path by path, built up a document at a time.
+#[deriving(PartialEq, Clone, Show)]
+pub struct Inference {
+    pub documents: uint,
+    pub fields: TreeMap<String, FieldStats>,
+}
+
+impl Inference {
+    pub fn new() -> Inference {
+        Inference { documents: 0, fields: TreeMap::new() }
+    }
+
+    pub fn add(&mut self, doc: &Bson) {
+        self.documents += 1;
+        let mut seen = TreeMap::new();
+        match *doc {
+            super::Object(ref obj) => for (k, v) in obj.iter() {
+                self.walk(k.clone(), v, &mut seen);
+            },
+            _ => (),
+        }
+        for path in seen.keys() {
+            self.fields.find_mut(path).unwrap().documents += 1;
+        }
+    }
+
+    fn walk(&mut self, path: String, v: &Bson, seen: &mut TreeMap<String, ()>) {
+        if !self.fields.contains_key(&path) {
+            self.fields.insert(path.clone(), FieldStats::new());
+        }
+        self.fields.find_mut(&path).unwrap().add(v);
+        seen.insert(path.clone(), ());
+        match *v {
+            super::Object(ref obj) => for (k, child) in obj.iter() {
+                self.walk(join(path.as_slice(), k.as_slice()), child, seen);
+            },
+            super::List(ref items) => for item in items.iter() {
+                self.walk(join(path.as_slice(), ELEMENTS), item, seen);
+            },
+            _ => (),
+        }
+    }
+
+    /// The share of documents `path` turned up in, from 0 to 1.
+    pub fn presence(&self, path: &str) -> f64 {
+        match self.fields.find(&path.to_string()) {
+            Some(stats) if self.documents > 0 => stats.documents as f64 / self.documents as f64,
+            _ => 0.0,
+        }
+    }
+
+    /// A draft `$jsonSchema` that everything seen so far satisfies: the
+    /// types at each path, fields found in every document that holds them
+    /// as `required`, and the observed ranges of numbers, string lengths
+    /// and array lengths. It's a starting point, and the bounds in
+    /// particular are only as wide as the sample.
+    pub fn to_schema(&self) -> Bson {
+        let mut schema = TreeMap::new();
+        schema.insert("bsonType".to_string(), String("object".to_string()));
+        self.add_properties(&mut schema, "", self.documents);
+        super::Object(schema)
+    }
+
+    /// `properties` and `required` for the fields directly under `prefix`,
+    /// which held a document `objects` times.
+    fn add_properties(&self, schema: &mut Object, prefix: &str, objects: uint) {
+        let mut properties = TreeMap::new();
+        let mut required = Vec::new();
+        for (path, stats) in self.fields.iter() {
+            let name = if prefix.is_empty() {
+                path.as_slice()
+            } else if path.len() > prefix.len() && path.as_slice().starts_with(prefix)
+                    && path.as_slice().char_at(prefix.len()) == '.' {
+                path.as_slice().slice_from(prefix.len() + 1)
+            } else {
+                continue;
+            };
+            if name.contains(".") || name == ELEMENTS {
+                continue;
+            }
+            properties.insert(name.to_string(), self.field_schema(path.as_slice(), stats));
+            if stats.values == objects {
+                required.push(String(name.to_string()));
+            }
+        }
+        if !required.is_empty() {
+            schema.insert("required".to_string(), super::List(required));
+        }
+        if !properties.is_empty() {
+            schema.insert("properties".to_string(), super::Object(properties));
+        }
+    }
+
+    fn field_schema(&self, path: &str, stats: &FieldStats) -> Bson {
+        let mut schema = TreeMap::new();
+        let mut types: Vec<Bson> = stats.types.keys().map(|t| String(t.clone())).collect();
+        let bson_type = if types.len() == 1 { types.pop().unwrap() } else { super::List(types) };
+        schema.insert("bsonType".to_string(), bson_type);
+
+        let objects = stats.count("object");
+        if objects > 0 {
+            self.add_properties(&mut schema, path, objects);
+        }
+        match (stats.array_lengths.iter().next(), stats.array_lengths.rev_iter().next()) {
+            (Some((&lo, _)), Some((&hi, _))) => {
+                schema.insert("minItems".to_string(), Int32(lo as i32));
+                schema.insert("maxItems".to_string(), Int32(hi as i32));
+            },
+            _ => (),
+        }
+        let elements = join(path, ELEMENTS);
+        match self.fields.find(&elements) {
+            Some(items) => {
+                schema.insert("items".to_string(), self.field_schema(elements.as_slice(), items));
+            },
+            None => (),
+        }
+        match stats.string_lengths {
+            Some((lo, hi)) => {
+                schema.insert("minLength".to_string(), Int32(lo as i32));
+                schema.insert("maxLength".to_string(), Int32(hi as i32));
+            },
+            None => (),
+        }
+        // Bounds only mean something when every value they cover is a
+        // number; otherwise min and max span types.
+        let numbers = ["int", "long", "double", "decimal"];
+        let numeric = stats.types.keys().all(|t| {
+            numbers.contains(&t.as_slice()) || t.as_slice() == "object" || t.as_slice() == "array"
+        });
+        // NaN sorts below every number, so any NaN seen is the minimum. It
+        // fails every bound, so then there are none.
+        let nan = match stats.min {
+            Some(super::Float(f)) => f.is_nan(),
+            Some(super::Decimal128(ref bytes)) => decimal::to_f64(bytes.as_slice()).is_nan(),
+            _ => false,
+        };
+        match (&stats.min, &stats.max) {
+            (&Some(ref min), &Some(ref max)) if numeric && !nan => {
+                schema.insert("minimum".to_string(), min.clone());
+                schema.insert("maximum".to_string(), max.clone());
+            },
+            _ => (),
+        }
+        super::Object(schema)
+    }
+}
+
+/// Runs an `Inference` over every document in `docs`.
+pub fn infer<I: Iterator<Bson>>(docs: I) -> Inference {
+    let mut inference = Inference::new();
+    for doc in docs {
+        inference.add(&doc);
+    }
+    inference
+}
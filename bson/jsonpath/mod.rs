@@ -0,0 +1,501 @@
+use std::{fmt,cmp,i32};
+
+use super::{Bson,String,Boolean,Null,Int32,Int64};
+
+/// Where an expression stopped making sense, as a byte offset into it.
+#[deriving(PartialEq, Clone)]
+pub struct JsonPathError {
+    pub pos: uint,
+    pub msg: String,
+}
+
+impl fmt::Show for JsonPathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.msg, self.pos)
+    }
+}
+
+pub type JsonPathResult<T> = Result<T, JsonPathError>;
+
+enum Selector {
+    Name(String),
+    Wildcard,
+    /// Negative indexes count back from the end.
+    Index(i64),
+    /// start, end, step, with Python's defaults for the missing ones.
+    Slice(Option<i64>, Option<i64>, i64),
+    Filter(Expr),
+}
+
+/// One `.name`, `[...]` or `..[...]`: selectors applied to each node so
+/// far, or with `descendant` set to each node and everything under it.
+struct Step {
+    descendant: bool,
+    selectors: Vec<Selector>,
+}
+
+enum Operand {
+    /// `@...`, from the node being filtered.
+    Relative(Vec<Step>),
+    /// `$...`, from the root.
+    Absolute(Vec<Step>),
+    Literal(Bson),
+}
+
+#[deriving(PartialEq, Clone)]
+enum Comparison {
+    EqualTo,
+    NotEqualTo,
+    LessThan,
+    LessOrEqual,
+    GreaterThan,
+    GreaterOrEqual,
+}
+
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, Comparison, Operand),
+    /// A bare path, true when it selects anything.
+    Exists(Operand),
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: uint,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, msg: &str) -> JsonPathResult<T> {
+        Err(JsonPathError { pos: self.pos, msg: msg.to_string() })
+    }
+
+    fn peek(&self) -> Option<char> {
+        if self.pos < self.src.len() {
+            Some(self.src.char_at(self.pos))
+        } else {
+            None
+        }
+    }
+
+    fn bump(&mut self) {
+        self.pos = self.src.char_range_at(self.pos).next;
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if self.src.slice_from(self.pos).starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> JsonPathResult<()> {
+        if self.eat(s) {
+            Ok(())
+        } else {
+            self.error(format!("expected `{}`", s).as_slice())
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().map_or(false, |c| c.is_whitespace()) {
+            self.bump();
+        }
+    }
+
+    fn steps(&mut self) -> JsonPathResult<Vec<Step>> {
+        let mut steps = Vec::new();
+        loop {
+            let descendant = self.eat("..");
+            let selectors = if self.peek() == Some('[') {
+                try!(self.bracket())
+            } else if descendant || self.eat(".") {
+                if self.eat("*") {
+                    vec!(Wildcard)
+                } else {
+                    vec!(Name(try!(self.name())))
+                }
+            } else {
+                return Ok(steps);
+            };
+            steps.push(Step { descendant: descendant, selectors: selectors });
+        }
+    }
+
+    fn name(&mut self) -> JsonPathResult<String> {
+        let start = self.pos;
+        while self.peek().map_or(false, |c| c.is_alphanumeric() || c == '_' || c == '$') {
+            self.bump();
+        }
+        if self.pos == start {
+            return self.error("expected a field name");
+        }
+        Ok(self.src.slice(start, self.pos).to_string())
+    }
+
+    fn bracket(&mut self) -> JsonPathResult<Vec<Selector>> {
+        try!(self.expect("["));
+        let mut selectors = Vec::new();
+        loop {
+            self.skip_spaces();
+            selectors.push(try!(self.selector()));
+            self.skip_spaces();
+            if self.eat("]") {
+                return Ok(selectors);
+            }
+            try!(self.expect(","));
+        }
+    }
+
+    fn selector(&mut self) -> JsonPathResult<Selector> {
+        match self.peek() {
+            Some('\'') | Some('"') => return Ok(Name(try!(self.string()))),
+            Some('*') => {
+                self.bump();
+                return Ok(Wildcard);
+            },
+            Some('?') => {
+                self.bump();
+                return Ok(Filter(try!(self.expr())));
+            },
+            _ => (),
+        }
+        let start = try!(self.integer());
+        self.skip_spaces();
+        if !self.eat(":") {
+            return match start {
+                Some(i) => Ok(Index(i)),
+                None => self.error("expected a name, index, slice, `*` or filter"),
+            };
+        }
+        self.skip_spaces();
+        let end = try!(self.integer());
+        self.skip_spaces();
+        let step = if self.eat(":") {
+            self.skip_spaces();
+            try!(self.integer())
+        } else {
+            None
+        };
+        Ok(Slice(start, end, step.unwrap_or(1)))
+    }
+
+    fn integer(&mut self) -> JsonPathResult<Option<i64>> {
+        let start = self.pos;
+        self.eat("-");
+        while self.peek().map_or(false, |c| c >= '0' && c <= '9') {
+            self.bump();
+        }
+        if self.pos == start {
+            return Ok(None);
+        }
+        match from_str::<i64>(self.src.slice(start, self.pos)) {
+            Some(n) => Ok(Some(n)),
+            None => {
+                self.pos = start;
+                self.error("bad integer")
+            },
+        }
+    }
+
+    /// A quoted string, in either kind of quotes.
+    fn string(&mut self) -> JsonPathResult<String> {
+        let quote = self.peek().unwrap();
+        self.bump();
+        let mut s = String::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return self.error("unterminated string"),
+            };
+            self.bump();
+            if c == quote {
+                return Ok(s);
+            }
+            if c != '\\' {
+                s.push(c);
+                continue;
+            }
+            match self.peek() {
+                Some('n') => s.push('\n'),
+                Some('t') => s.push('\t'),
+                Some(c) if c == '\\' || c == '\'' || c == '"' || c == '/' => s.push(c),
+                _ => return self.error("bad escape"),
+            }
+            self.bump();
+        }
+    }
+
+    fn expr(&mut self) -> JsonPathResult<Expr> {
+        let mut left = try!(self.and());
+        loop {
+            self.skip_spaces();
+            if !self.eat("||") {
+                return Ok(left);
+            }
+            let right = try!(self.and());
+            left = Or(box left, box right);
+        }
+    }
+
+    fn and(&mut self) -> JsonPathResult<Expr> {
+        let mut left = try!(self.unary());
+        loop {
+            self.skip_spaces();
+            if !self.eat("&&") {
+                return Ok(left);
+            }
+            let right = try!(self.unary());
+            left = And(box left, box right);
+        }
+    }
+
+    fn unary(&mut self) -> JsonPathResult<Expr> {
+        self.skip_spaces();
+        if self.eat("!") {
+            return Ok(Not(box try!(self.unary())));
+        }
+        if self.eat("(") {
+            let inner = try!(self.expr());
+            self.skip_spaces();
+            try!(self.expect(")"));
+            return Ok(inner);
+        }
+        let left = try!(self.operand());
+        self.skip_spaces();
+        let ops = [("==", EqualTo), ("!=", NotEqualTo), ("<=", LessOrEqual), (">=", GreaterOrEqual),
+                   ("<", LessThan), (">", GreaterThan)];
+        let mut op = None;
+        for &(text, comparison) in ops.iter() {
+            if self.eat(text) {
+                op = Some(comparison);
+                break;
+            }
+        }
+        let op = match op {
+            Some(op) => op,
+            None => return match left {
+                Literal(_) => self.error("expected a comparison"),
+                path => Ok(Exists(path)),
+            },
+        };
+        let right = try!(self.operand());
+        Ok(Compare(left, op, right))
+    }
+
+    fn operand(&mut self) -> JsonPathResult<Operand> {
+        self.skip_spaces();
+        match self.peek() {
+            Some('@') => {
+                self.bump();
+                return Ok(Relative(try!(self.steps())));
+            },
+            Some('$') => {
+                self.bump();
+                return Ok(Absolute(try!(self.steps())));
+            },
+            Some('\'') | Some('"') => return Ok(Literal(String(try!(self.string())))),
+            _ => (),
+        }
+        for &(word, ref value) in [("true", Boolean(true)), ("false", Boolean(false)), ("null", Null)].iter() {
+            if self.eat(word) {
+                return Ok(Literal(value.clone()));
+            }
+        }
+        let start = self.pos;
+        while self.peek().map_or(false, |c| (c >= '0' && c <= '9') || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E') {
+            self.bump();
+        }
+        let text = self.src.slice(start, self.pos);
+        let number = if text.contains(".") || text.contains("e") || text.contains("E") {
+            from_str::<f64>(text).map(|f| super::Float(f))
+        } else {
+            from_str::<i64>(text).map(|n| if n >= i32::MIN as i64 && n <= i32::MAX as i64 { Int32(n as i32) } else { Int64(n) })
+        };
+        match number {
+            Some(n) => Ok(Literal(n)),
+            None => {
+                self.pos = start;
+                self.error("expected a path, string, number, true, false or null")
+            },
+        }
+    }
+}
+
+/// `v` itself and everything under it, parents first.
+fn descendants<'a>(v: &'a Bson, out: &mut Vec<&'a Bson>) {
+    out.push(v);
+    match *v {
+        super::Object(ref obj) => for child in obj.values() {
+            descendants(child, out);
+        },
+        super::List(ref items) => for item in items.iter() {
+            descendants(item, out);
+        },
+        _ => (),
+    }
+}
+
+fn children<'a>(v: &'a Bson, out: &mut Vec<&'a Bson>) {
+    match *v {
+        super::Object(ref obj) => out.extend(obj.values()),
+        super::List(ref items) => out.extend(items.iter()),
+        _ => (),
+    }
+}
+
+/// The elements a slice picks out, the way RFC 9535 defines it.
+fn slice<'a>(items: &'a [Bson], start: Option<i64>, end: Option<i64>, step: i64, out: &mut Vec<&'a Bson>) {
+    let len = items.len() as i64;
+    let normalize = |i: i64| if i >= 0 { i } else { len + i };
+    if step > 0 {
+        let lower = cmp::min(cmp::max(normalize(start.unwrap_or(0)), 0), len);
+        let upper = cmp::min(cmp::max(normalize(end.unwrap_or(len)), 0), len);
+        let mut i = lower;
+        while i < upper {
+            out.push(&items[i as uint]);
+            // Checked against `upper` first, so a huge step can't wrap.
+            if upper - i <= step {
+                break;
+            }
+            i += step;
+        }
+    } else if step < 0 {
+        let upper = cmp::min(cmp::max(normalize(start.unwrap_or(len - 1)), -1), len - 1);
+        let lower = cmp::min(cmp::max(normalize(end.unwrap_or(-len - 1)), -1), len - 1);
+        // `i` is never negative in the loop, so adding even the most
+        // negative step can't overflow.
+        let mut i = upper;
+        while lower < i {
+            out.push(&items[i as uint]);
+            i += step;
+        }
+    }
+}
+
+fn select<'a>(selectors: &[Selector], v: &'a Bson, root: &'a Bson, out: &mut Vec<&'a Bson>) {
+    for selector in selectors.iter() {
+        match (selector, v) {
+            (&Name(ref name), &super::Object(ref obj)) => match obj.find(name) {
+                Some(child) => out.push(child),
+                None => (),
+            },
+            (&Wildcard, _) => children(v, out),
+            (&Index(i), &super::List(ref items)) => {
+                let i = if i < 0 { i + items.len() as i64 } else { i };
+                if i >= 0 && i < items.len() as i64 {
+                    out.push(&items[i as uint]);
+                }
+            },
+            (&Slice(start, end, step), &super::List(ref items)) => slice(items.as_slice(), start, end, step, out),
+            (&Filter(ref expr), _) => {
+                let mut candidates = Vec::new();
+                children(v, &mut candidates);
+                for candidate in candidates.into_iter() {
+                    if test(expr, candidate, root) {
+                        out.push(candidate);
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+fn evaluate<'a>(steps: &[Step], start: &'a Bson, root: &'a Bson) -> Vec<&'a Bson> {
+    let mut nodes = vec!(start);
+    for step in steps.iter() {
+        let mut next = Vec::new();
+        for node in nodes.into_iter() {
+            if step.descendant {
+                let mut under = Vec::new();
+                descendants(node, &mut under);
+                for d in under.into_iter() {
+                    select(step.selectors.as_slice(), d, root, &mut next);
+                }
+            } else {
+                select(step.selectors.as_slice(), node, root, &mut next);
+            }
+        }
+        nodes = next;
+    }
+    nodes
+}
+
+/// What an operand stands for: the first node a path selects, or `None`
+/// when it selects nothing.
+fn operand_value<'a>(operand: &'a Operand, current: &'a Bson, root: &'a Bson) -> Option<&'a Bson> {
+    match *operand {
+        Relative(ref steps) => evaluate(steps.as_slice(), current, root).into_iter().next(),
+        Absolute(ref steps) => evaluate(steps.as_slice(), root, root).into_iter().next(),
+        Literal(ref v) => Some(v),
+    }
+}
+
+/// Compares the way a query does: values of different types, other than
+/// numbers of different widths, are neither equal nor ordered. Something
+/// missing only equals something else missing.
+fn compare(left: Option<&Bson>, op: Comparison, right: Option<&Bson>) -> bool {
+    match (left, right) {
+        (None, None) => op == EqualTo || op == LessOrEqual || op == GreaterOrEqual,
+        (Some(a), Some(b)) if a.type_rank() == b.type_rank() => {
+            let ord = a.compare(b);
+            match op {
+                EqualTo => ord == cmp::Equal,
+                NotEqualTo => ord != cmp::Equal,
+                LessThan => ord == cmp::Less,
+                LessOrEqual => ord != cmp::Greater,
+                GreaterThan => ord == cmp::Greater,
+                GreaterOrEqual => ord != cmp::Less,
+            }
+        },
+        _ => op == NotEqualTo,
+    }
+}
+
+fn test(expr: &Expr, current: &Bson, root: &Bson) -> bool {
+    match *expr {
+        Or(ref a, ref b) => test(&**a, current, root) || test(&**b, current, root),
+        And(ref a, ref b) => test(&**a, current, root) && test(&**b, current, root),
+        Not(ref a) => !test(&**a, current, root),
+        Compare(ref left, op, ref right) => {
+            compare(operand_value(left, current, root), op, operand_value(right, current, root))
+        },
+        Exists(ref operand) => operand_value(operand, current, root).is_some(),
+    }
+}
+
+/// A parsed JSONPath expression: `$`, then any of `.name`, `['name']`,
+/// `.*`, `[*]`, `[1]`, `[-1]`, `[1:5:2]`, `[?(@.total > 100)]` and
+/// unions of them like `['a', 'b']`, each of which may follow `..` to
+/// apply at every depth. Filters take `==`, `!=`, `<`, `<=`, `>`, `>=`,
+/// `&&`, `||`, `!` and parentheses over `@` and `$` paths, strings,
+/// numbers, `true`, `false` and `null`, and compare with BSON's ordering.
+pub struct JsonPath {
+    steps: Vec<Step>,
+}
+
+impl JsonPath {
+    pub fn parse(expr: &str) -> JsonPathResult<JsonPath> {
+        let mut parser = Parser { src: expr, pos: 0 };
+        try!(parser.expect("$"));
+        let steps = try!(parser.steps());
+        if parser.pos != expr.len() {
+            return parser.error("unexpected character");
+        }
+        Ok(JsonPath { steps: steps })
+    }
+
+    /// Every value the path selects from `root`, in document order.
+    pub fn select<'a>(&self, root: &'a Bson) -> Vec<&'a Bson> {
+        evaluate(self.steps.as_slice(), root, root)
+    }
+}
+
+/// Parses `expr` and selects with it, for one-off queries.
+pub fn query<'a>(expr: &str, root: &'a Bson) -> JsonPathResult<Vec<&'a Bson>> {
+    Ok(try!(JsonPath::parse(expr)).select(root))
+}
@@ -1,10 +1,29 @@
-
-use std::{io,mem,fmt};
+use std::{io,mem,fmt,str,f64,cmp};
 use std::collections::TreeMap;
+use std::num::Float;
+
+pub use self::reader::DocumentReader;
+pub use self::extjson::{to_canonical_extjson,to_relaxed_extjson,from_extjson,ExtJsonError};
 
-mod serialize;
+pub mod serialize;
+pub mod reader;
+pub mod decimal;
+pub mod datetime;
+pub mod base64;
+pub mod extjson;
+pub mod shell;
+pub mod dump;
+pub mod archive;
+pub mod csv;
+pub mod bridge;
+pub mod schema;
+pub mod infer;
+pub mod redact;
+pub mod cbor;
+pub mod jsonpath;
+pub mod patch;
 
-#[deriving(PartialEq)]
+#[deriving(PartialEq, Clone, Show)]
 pub enum Bson {
     ObjectId(Id),
     Float(f64),
@@ -13,48 +32,416 @@ pub enum Bson {
     List(List),
     Object(Object),
     Null,
+    /// subtype, data
+    Binary(u8, Vec<u8>),
+    Undefined,
+    /// milliseconds since the epoch
+    UtcDatetime(i64),
+    /// pattern, options
+    RegExp(String, String),
+    /// namespace, id
+    DbPointer(String, Id),
+    JavaScript(String),
+    Symbol(String),
+    /// code, scope
+    JavaScriptWithScope(String, Object),
+    Int32(i32),
+    /// time, increment
+    Timestamp(u32, u32),
+    Int64(i64),
+    /// the 16 raw bytes, little-endian
+    Decimal128(Vec<u8>),
+    MinKey,
+    MaxKey,
+    /// A string whose bytes aren't valid UTF-8, kept exactly as they were.
+    /// Only produced when decoding with `Utf8Raw`.
+    RawString(Vec<u8>),
 }
 
 pub type List = Vec<Bson>;
 pub type Object = TreeMap<String, Bson>;
 pub type Id = Vec<u8>;
 
+impl Bson {
+    /// The name the server uses for this value's type, as in `$type`.
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            ObjectId(_) => "objectId",
+            Float(_)    => "double",
+            String(_)   => "string",
+            Boolean(_)  => "bool",
+            List(_)     => "array",
+            Object(_)   => "object",
+            Null        => "null",
+            Binary(..)  => "binData",
+            Undefined   => "undefined",
+            UtcDatetime(_) => "date",
+            RegExp(..)  => "regex",
+            DbPointer(..) => "dbPointer",
+            JavaScript(_) => "javascript",
+            Symbol(_)   => "symbol",
+            JavaScriptWithScope(..) => "javascriptWithScope",
+            Int32(_)    => "int",
+            Timestamp(..) => "timestamp",
+            Int64(_)    => "long",
+            Decimal128(_) => "decimal",
+            MinKey      => "minKey",
+            MaxKey      => "maxKey",
+            RawString(_) => "string",
+        }
+    }
+
+    /// Orders values the way the server sorts them: first by type, with
+    /// all numbers together and null alongside undefined, then by value.
+    pub fn compare(&self, other: &Bson) -> cmp::Ordering {
+        match self.type_rank().cmp(&other.type_rank()) {
+            cmp::Equal => (),
+            ord => return ord,
+        }
+        match (self, other) {
+            (&Int32(_), _) | (&Int64(_), _) | (&Float(_), _) | (&Decimal128(_), _) => compare_numbers(self, other),
+            (&ObjectId(ref a), &ObjectId(ref b)) => a.cmp(b),
+            (&Boolean(a), &Boolean(b)) => a.cmp(&b),
+            (&UtcDatetime(a), &UtcDatetime(b)) => a.cmp(&b),
+            (&Timestamp(at, ai), &Timestamp(bt, bi)) => (at, ai).cmp(&(bt, bi)),
+            (&List(ref a), &List(ref b)) => {
+                for (x, y) in a.iter().zip(b.iter()) {
+                    match x.compare(y) {
+                        cmp::Equal => (),
+                        ord => return ord,
+                    }
+                }
+                a.len().cmp(&b.len())
+            },
+            (&Object(ref a), &Object(ref b)) => compare_objects(a, b),
+            // Shorter binary sorts first, whatever the bytes.
+            (&Binary(at, ref a), &Binary(bt, ref b)) => (a.len(), at, a).cmp(&(b.len(), bt, b)),
+            (&RegExp(ref ap, ref ao), &RegExp(ref bp, ref bo)) => (ap, ao).cmp(&(bp, bo)),
+            (&DbPointer(ref an, ref ai), &DbPointer(ref bn, ref bi)) => (an, ai).cmp(&(bn, bi)),
+            (&JavaScript(ref a), &JavaScript(ref b)) => a.cmp(b),
+            (&JavaScriptWithScope(ref ac, ref a), &JavaScriptWithScope(ref bc, ref b)) => match ac.cmp(bc) {
+                cmp::Equal => compare_objects(a, b),
+                ord => ord,
+            },
+            // Strings and symbols, byte by byte.
+            _ => match (self.string_bytes(), other.string_bytes()) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                _ => cmp::Equal,
+            },
+        }
+    }
+
+    fn type_rank(&self) -> uint {
+        match *self {
+            MinKey => 0,
+            Null | Undefined => 5,
+            Int32(_) | Int64(_) | Float(_) | Decimal128(_) => 10,
+            String(_) | Symbol(_) | RawString(_) => 15,
+            Object(_) => 20,
+            List(_) => 25,
+            Binary(..) => 30,
+            ObjectId(_) => 35,
+            Boolean(_) => 40,
+            UtcDatetime(_) => 45,
+            Timestamp(..) => 47,
+            RegExp(..) => 50,
+            DbPointer(..) => 55,
+            JavaScript(_) => 60,
+            JavaScriptWithScope(..) => 65,
+            MaxKey => 127,
+        }
+    }
+
+    fn string_bytes<'a>(&'a self) -> Option<&'a [u8]> {
+        match *self {
+            String(ref s) | Symbol(ref s) => Some(s.as_bytes()),
+            RawString(ref bytes) => Some(bytes.as_slice()),
+            _ => None,
+        }
+    }
+}
+
+/// Integers exactly, anything else as the nearest double, with NaN below
+/// every other number.
+fn compare_numbers(a: &Bson, b: &Bson) -> cmp::Ordering {
+    let integer = |v: &Bson| match *v {
+        Int32(n) => Some(n as i64),
+        Int64(n) => Some(n),
+        _ => None,
+    };
+    match (integer(a), integer(b)) {
+        (Some(x), Some(y)) => return x.cmp(&y),
+        _ => (),
+    }
+    let double = |v: &Bson| match *v {
+        Int32(n) => n as f64,
+        Int64(n) => n as f64,
+        Float(f) => f,
+        Decimal128(ref bytes) => decimal::to_f64(bytes.as_slice()),
+        _ => unreachable!(),
+    };
+    let (x, y) = (double(a), double(b));
+    match (x.is_nan(), y.is_nan()) {
+        (true, true) => cmp::Equal,
+        (true, false) => cmp::Less,
+        (false, true) => cmp::Greater,
+        (false, false) => x.partial_cmp(&y).unwrap(),
+    }
+}
+
+/// Field by field: the value's type, then the name, then the value.
+fn compare_objects(a: &Object, b: &Object) -> cmp::Ordering {
+    for ((ak, av), (bk, bv)) in a.iter().zip(b.iter()) {
+        let ord = match av.type_rank().cmp(&bv.type_rank()) {
+            cmp::Equal => match ak.cmp(bk) {
+                cmp::Equal => av.compare(bv),
+                ord => ord,
+            },
+            ord => ord,
+        };
+        if ord != cmp::Equal {
+            return ord;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+/// Documents nested deeper than this are rejected rather than parsed,
+/// unless the options say otherwise.
+pub static MAX_DEPTH: uint = 100;
+/// The largest document the server will store, and the default limit.
+pub static MAX_DOCUMENT_SIZE: uint = 16 * 1024 * 1024;
+
+/// What to do with strings that aren't valid UTF-8.
+#[deriving(PartialEq, Clone, Show)]
+pub enum Utf8Handling {
+    /// Fail with `InvalidUtf8`.
+    Utf8Strict,
+    /// Swap the bad sequences for U+FFFD.
+    Utf8Lossy,
+    /// Keep string values as `RawString` so they re-encode byte for byte.
+    /// Keys, regexes and code can't hold raw bytes and are made lossy.
+    Utf8Raw,
+}
+
+/// What to do when a document has the same key more than once.
+#[deriving(PartialEq, Clone, Show)]
+pub enum DuplicateKeys {
+    /// Fail with `DuplicateKey`.
+    RejectDuplicates,
+    KeepFirst,
+    KeepLast,
+}
+
+/// Which values the `Decoder` may convert to a type other than their own.
+/// Null always decodes as `None`.
+#[deriving(PartialEq, Clone, Show)]
+pub struct Coercion {
+    /// int32, int64 and double can be read as any number type, provided the
+    /// value comes through the conversion exactly.
+    pub numeric: bool,
+    /// Decimal128 can be read as the nearest double.
+    pub decimal_to_f64: bool,
+    /// ObjectIds (as hex) and Decimal128s can be read as strings.
+    pub to_string: bool,
+    /// Undefined decodes as `None`, the same as null.
+    pub undefined_as_none: bool,
+}
+impl Coercion {
+    /// Every value has to be read as its own type.
+    pub fn strict() -> Coercion {
+        Coercion { numeric: false, decimal_to_f64: false, to_string: false, undefined_as_none: false }
+    }
+}
+
+/// Knobs for decoding data that doesn't play by the rules. The defaults
+/// from `new` are strict.
+#[deriving(PartialEq, Clone, Show)]
+pub struct DecoderOptions {
+    pub utf8: Utf8Handling,
+    pub duplicate_keys: DuplicateKeys,
+    pub max_depth: uint,
+    pub max_document_size: uint,
+    pub coercion: Coercion,
+    /// Only the fields under these paths are kept; everything else is
+    /// stepped over by its length without being parsed.
+    pub projection: Option<Projection>,
+}
+impl DecoderOptions {
+    pub fn new() -> DecoderOptions {
+        DecoderOptions {
+            utf8: Utf8Strict,
+            duplicate_keys: RejectDuplicates,
+            max_depth: MAX_DEPTH,
+            max_document_size: MAX_DOCUMENT_SIZE,
+            coercion: Coercion::strict(),
+            projection: None,
+        }
+    }
+}
+
+/// A set of dotted field paths to decode, as in a find projection. A path
+/// keeps everything below it, and arrays on the way are looked through, so
+/// `items.sku` keeps the `sku` of every document in `items`. Values on the
+/// way to a path that aren't documents or arrays are left out.
+#[deriving(PartialEq, Clone, Show)]
+pub struct Projection {
+    fields: TreeMap<String, Projection>,
+    whole: bool,
+}
+impl Projection {
+    /// An empty list keeps the whole document.
+    pub fn new(fields: &[&str]) -> Projection {
+        let mut root = Projection { fields: TreeMap::new(), whole: fields.is_empty() };
+        for field in fields.iter() {
+            let keys: Vec<&str> = field.split('.').collect();
+            root.insert(keys.as_slice());
+        }
+        root
+    }
+
+    fn insert(&mut self, keys: &[&str]) {
+        if self.whole {
+            return;
+        }
+        if keys.is_empty() {
+            self.whole = true;
+            self.fields = TreeMap::new();
+            return;
+        }
+        let key = keys[0].to_string();
+        if !self.fields.contains_key(&key) {
+            self.fields.insert(key.clone(), Projection { fields: TreeMap::new(), whole: false });
+        }
+        self.fields.find_mut(&key).unwrap().insert(keys.slice_from(1));
+    }
+
+    /// Cuts an already decoded value down to the projected fields.
+    pub fn apply(&self, bson: Bson) -> Bson {
+        match bson {
+            Object(obj) => Object(self.apply_object(obj)),
+            other => other,
+        }
+    }
+
+    fn apply_object(&self, obj: Object) -> Object {
+        if self.whole {
+            return obj;
+        }
+        let mut out = TreeMap::new();
+        for (key, value) in obj.into_iter() {
+            match self.fields.find(&key) {
+                Some(child) if child.whole => { out.insert(key, value); },
+                Some(child) => match child.descend(value) {
+                    Some(value) => { out.insert(key, value); },
+                    None => (),
+                },
+                None => (),
+            }
+        }
+        out
+    }
+
+    fn descend(&self, value: Bson) -> Option<Bson> {
+        match value {
+            Object(obj) => Some(Object(self.apply_object(obj))),
+            List(items) => Some(List(items.into_iter().filter_map(|v| self.descend(v)).collect())),
+            _ => None,
+        }
+    }
+
+    /// What to do with `key` inside the document this node applies to:
+    /// `None` skips it, `Some(None)` keeps all of it and `Some(Some(p))`
+    /// goes on projecting with `p`.
+    fn child<'a>(&'a self, key: &String) -> Option<Option<&'a Projection>> {
+        match self.fields.find(key) {
+            None => None,
+            Some(child) if child.whole => Some(None),
+            Some(child) => Some(Some(child)),
+        }
+    }
+}
+
+#[deriving(PartialEq, Clone)]
 pub enum ErrorCode {
     InvalidSyntax,
-    // TODO - add the rest
+    BadLength,
+    UnknownElementType,
+    InvalidUtf8,
+    MissingTerminator,
+    UnexpectedEndOfInput,
+    DuplicateKey,
+    DepthExceeded,
+    /// A stream of documents ended partway through the last one.
+    TruncatedDocument,
+    DocumentTooLarge,
 }
 impl fmt::Show for ErrorCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", match *self {
             InvalidSyntax => "Invalid syntax",
+            BadLength => "Length does not match contents",
+            UnknownElementType => "Unknown element type",
+            InvalidUtf8 => "Invalid UTF-8",
+            MissingTerminator => "Missing null terminator",
+            UnexpectedEndOfInput => "Unexpected end of input",
+            DuplicateKey => "Duplicate key",
+            DepthExceeded => "Maximum nesting depth exceeded",
+            TruncatedDocument => "Stream ended partway through a document",
+            DocumentTooLarge => "Document exceeds the maximum size",
         })
     }
 }
 
-#[deriving(Show)]
 pub enum ParserError {
-    /// msg, line, col
-    SyntaxError(ErrorCode, uint, uint),
+    /// code, byte offset, dotted path of the element (empty at the top level)
+    SyntaxError(ErrorCode, uint, String),
     IoError(io::IoErrorKind, &'static str)
 }
+impl fmt::Show for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SyntaxError(ref code, offset, ref path) if path.is_empty() => {
+                write!(f, "{} at byte {}", code, offset)
+            },
+            SyntaxError(ref code, offset, ref path) => {
+                write!(f, "{} at byte {} in `{}`", code, offset, path)
+            },
+            IoError(kind, msg) => write!(f, "{} ({})", msg, kind),
+        }
+    }
+}
 
 pub type BuilderError = ParserError;
 
+/// Everything but `ParseError` carries the path of the value that failed,
+/// in the form `orders[3].items[0].price`.
 #[deriving(Show)]
 pub enum DecoderError {
     ParseError(ParserError),
-    // TODO - add the rest
+    ExpectedType { path: String, expected: String, found: String },
+    MissingField { path: String },
+    UnknownField { path: String },
+    UnknownVariant { path: String, variant: String, expected: Vec<String> },
+    /// The value was the right type but doesn't fit in the target.
+    OutOfRange { path: String, value: String, target: String },
+    /// Raised by a `Decodable` implementation through `Decoder::error`.
+    Custom { path: String, msg: String },
 }
 
 pub type EncodeResult = io::IoResult<Vec<u8>>;
 pub type DecodeResult<T> = Result<T, DecoderError>;
 
 pub fn decode<T: serialize::Decodable<Decoder, DecoderError>>(v: Vec<u8>) -> DecodeResult<T> {
-    let bson = match from_vec(v) {
+    decode_with_options(v, DecoderOptions::new())
+}
+
+pub fn decode_with_options<T: serialize::Decodable<Decoder, DecoderError>>(v: Vec<u8>, options: DecoderOptions) -> DecodeResult<T> {
+    let bson = match from_slice_with_options(v.as_slice(), options.clone()) {
         Ok(b) => b,
-        Err(e) => unimplemented!(),
+        Err(e) => return Err(ParseError(e)),
     };
-    let mut decoder = Decoder::new(bson);
+    let mut decoder = Decoder::with_options(bson, options);
     serialize::Decodable::decode(&mut decoder)
 }
 
@@ -62,13 +449,58 @@ pub fn encode<'a, T: serialize::Encodable<Encoder<'a>, io::IoError>>(object: &T)
     Encoder::buffer_encode(object)
 }
 
+/// Writes a single element: the value's type byte, the key as a cstring,
+/// then the value itself. Strings get their length prefix here.
+fn element(key: &str, mut v: Vec<u8>) -> EncodeResult {
+    let mut w = io::MemWriter::new();
+    let t = v.shift().unwrap();
+    try!(w.write_u8(t));
+    try!(w.write_str(key));
+    try!(w.write_u8(0x00));
+    match t {
+        0x02 => try!(w.write_le_u32(v.len() as u32)),
+        _ => ()
+    }
+    try!(w.write(v.as_slice()));
+    Ok(w.unwrap())
+}
+
+/// Wraps a run of elements up as an embedded document (0x03) or array (0x04).
+fn document(t: u8, elements: &[u8]) -> EncodeResult {
+    let mut w = io::MemWriter::new();
+    try!(w.write_u8(t));
+    try!(w.write_le_u32(elements.len() as u32 + 5));
+    try!(w.write(elements));
+    try!(w.write_u8(0x00));
+    Ok(w.unwrap())
+}
+
+fn str_value(v: &str) -> EncodeResult {
+    let mut w = io::MemWriter::new();
+    try!(w.write_u8(0x02));
+    for c in v.chars() {
+        try!(w.write_char(c));
+    }
+    try!(w.write_u8(0x00));
+    Ok(w.unwrap())
+}
+
+fn enum_error(detail: String) -> io::IoError {
+    io::IoError {
+        kind: io::InvalidInput,
+        desc: "enum cannot be encoded with this representation",
+        detail: Some(detail),
+    }
+}
+
 pub struct Encoder<'a> {
     stack: Vec<Vec<u8>>,
+    enums: Vec<serialize::EnumRepr>,
     writer: &'a mut io::Writer,
 }
 impl<'a> Encoder<'a> {
     pub fn new(writer: &'a mut io::Writer) -> Encoder {
-        Encoder { writer: writer, stack: Vec::new() }
+        Encoder { writer: writer, stack: Vec::new(), enums: Vec::new() }
     }
     pub fn buffer_encode<T: serialize::Encodable<Encoder<'a>, io::IoError>>(object: &T) -> Vec<u8> {
         let mut m = io::MemWriter::new();
@@ -79,13 +511,49 @@ impl<'a> Encoder<'a> {
         }
         m.unwrap()
     }
+
+    fn enum_repr(&self) -> serialize::EnumRepr {
+        *self.enums.last().expect("enum variant encoded outside of build_enum")
+    }
+
+    /// Lays out a variant according to the current enum's representation.
+    /// `content` is the encoded arguments or fields, if the variant has any.
+    fn tag_variant(&mut self, name: &str, content: Option<Vec<u8>>) -> EncodeResult {
+        match (self.enum_repr(), content) {
+            (serialize::ExternallyTagged, None) | (serialize::UnitAsString, None) => {
+                str_value(name)
+            },
+            (serialize::ExternallyTagged, Some(c)) => {
+                document(0x03, try!(element(name, c)).as_slice())
+            },
+            (serialize::InternallyTagged(tag), None) => {
+                document(0x03, try!(element(tag, try!(str_value(name)))).as_slice())
+            },
+            (serialize::InternallyTagged(_), Some(_)) => {
+                Err(enum_error(format!("variant `{}` has arguments and cannot be internally tagged", name)))
+            },
+            (serialize::AdjacentlyTagged(tag, key), content) => {
+                let mut buf = try!(element(tag, try!(str_value(name))));
+                match content {
+                    Some(c) => buf.push_all(try!(element(key, c)).as_slice()),
+                    None => ()
+                }
+                document(0x03, buf.as_slice())
+            },
+            (serialize::Untagged, None) => Ok(vec![0x0A]),
+            (serialize::Untagged, Some(c)) => Ok(c),
+            (serialize::UnitAsString, Some(_)) => {
+                Err(enum_error(format!("variant `{}` has data and cannot be encoded as a string", name)))
+            },
+        }
+    }
 }
 impl<'a> serialize::Encoder<io::IoError> for Encoder<'a> {
     fn build_nil(&mut self) -> EncodeResult {
-        unimplemented!();
+        Ok(vec![0x0A])
     }
     fn build_bool(&mut self, v: bool) -> EncodeResult {
-        unimplemented!();
+        Ok(vec![0x08, v as u8])
     }
     fn build_f64(&mut self, v: f64) -> EncodeResult {
         let mut w = io::MemWriter::new();
@@ -94,45 +562,88 @@ impl<'a> serialize::Encoder<io::IoError> for Encoder<'a> {
         Ok(w.unwrap())
     }
     fn build_str(&mut self, v: &str) -> EncodeResult {
-        let mut w = io::MemWriter::new();
-        try!(w.write_u8(0x02));
-        for c in v.chars() {
-            try!(w.write_char(c));
-        }
-        try!(w.write_u8(0x00));
-        Ok(w.unwrap())
+        str_value(v)
     }
     fn build_seq(&mut self, len: uint, f: |&mut Encoder<'a>| -> EncodeResult) -> EncodeResult {
-        unimplemented!();
+        let buf = try!(f(self));
+        document(0x04, buf.as_slice())
     }
     fn build_seq_elt(&mut self, idx: uint, f: |&mut Encoder<'a>| -> EncodeResult) -> EncodeResult {
-        unimplemented!();
+        let v = try!(f(self));
+        element(idx.to_string().as_slice(), v)
     }
     fn build_map(&mut self, len: uint, f: |&mut Encoder<'a>| -> EncodeResult) -> EncodeResult {
-        let mut w = io::MemWriter::new();
-        try!(w.write_u8(0x03));
         let buf = try!(f(self));
-        try!(w.write_le_u32(buf.len() as u32 + 5));
-        try!(w.write(buf.as_slice()));
-        try!(w.write_u8(0x00));
-        Ok(w.unwrap())
+        document(0x03, buf.as_slice())
     }
     fn build_map_item(&mut self, idx: uint, key: |&mut Encoder<'a>| -> EncodeResult, val: |&mut Encoder<'a>| -> EncodeResult) -> EncodeResult {
-        let mut w = io::MemWriter::new();
         let mut k = try!(key(self));
-        let _ = k.shift();
-        let mut v = try!(val(self));
-        let t = v.shift().unwrap();
-        try!(w.write_u8(t));
-        try!(w.write(k.as_slice()));
-        match t {
-            0x02 => try!(w.write_le_u32(v.len() as u32)),
-            _ => ()
+        let _ = k.shift(); // the string type
+        let _ = k.pop();   // and its terminator, which element() puts back
+        let v = try!(val(self));
+        match String::from_utf8(k) {
+            Ok(k) => element(k.as_slice(), v),
+            Err(_) => Err(io::IoError {
+                kind: io::InvalidInput,
+                desc: "map key is not valid UTF-8",
+                detail: None,
+            }),
         }
-        try!(w.write(v.as_slice()));
-        println!("{} {} {}", k, v, t);
-        Ok(w.unwrap())
     }
+
+    fn build_enum(&mut self, name: &str, repr: serialize::EnumRepr, f: |&mut Encoder<'a>| -> EncodeResult) -> EncodeResult {
+        self.enums.push(repr);
+        let res = f(self);
+        self.enums.pop();
+        res
+    }
+    fn build_enum_variant(&mut self, v_name: &str, v_id: uint, len: uint, f: |&mut Encoder<'a>| -> EncodeResult) -> EncodeResult {
+        let content = if len == 0 {
+            None
+        } else {
+            let args = try!(f(self));
+            Some(try!(document(0x04, args.as_slice())))
+        };
+        self.tag_variant(v_name, content)
+    }
+    fn build_enum_variant_arg(&mut self, a_idx: uint, f: |&mut Encoder<'a>| -> EncodeResult) -> EncodeResult {
+        let v = try!(f(self));
+        element(a_idx.to_string().as_slice(), v)
+    }
+    fn build_enum_struct_variant(&mut self, v_name: &str, v_id: uint, len: uint, f: |&mut Encoder<'a>| -> EncodeResult) -> EncodeResult {
+        let fields = try!(f(self));
+        match self.enum_repr() {
+            // The tag sits alongside the fields rather than wrapping them.
+            serialize::InternallyTagged(tag) => {
+                let mut buf = try!(element(tag, try!(str_value(v_name))));
+                buf.push_all(fields.as_slice());
+                document(0x03, buf.as_slice())
+            },
+            _ => {
+                let content = try!(document(0x03, fields.as_slice()));
+                self.tag_variant(v_name, Some(content))
+            }
+        }
+    }
+    fn build_enum_struct_variant_field(&mut self, f_name: &str, f_idx: uint, f: |&mut Encoder<'a>| -> EncodeResult) -> EncodeResult {
+        let v = try!(f(self));
+        element(f_name, v)
+    }
+}
+
+/// Builds a value that the serialize traits have no method for: the type
+/// byte followed by whatever `f` writes.
+fn custom(t: u8, f: |&mut io::MemWriter| -> io::IoResult<()>) -> Vec<u8> {
+    let mut w = io::MemWriter::new();
+    w.write_u8(t).unwrap();
+    f(&mut w).unwrap();
+    w.unwrap()
+}
+
+fn write_string(w: &mut io::MemWriter, v: &str) -> io::IoResult<()> {
+    try!(w.write_le_i32(v.len() as i32 + 1));
+    try!(w.write_str(v));
+    w.write_u8(0x00)
 }
 
 impl<E: serialize::Encoder<S>, S> serialize::Encodable<E, S> for Bson {
@@ -151,39 +662,926 @@ impl<E: serialize::Encoder<S>, S> serialize::Encodable<E, S> for Bson {
             Boolean(v)    => v.encode(e),
             List(ref v)   => v.encode(e),
             Object(ref v) => v.encode(e),
-            Null => { e.build_nil() }
+            Null => { e.build_nil() },
+            Binary(subtype, ref v) => {
+                e.build_custom(|_| Ok(custom(0x05, |w| {
+                    // The old binary subtype repeats the length inside the payload.
+                    if subtype == 0x02 {
+                        try!(w.write_le_i32(v.len() as i32 + 4));
+                        try!(w.write_u8(subtype));
+                        try!(w.write_le_i32(v.len() as i32));
+                    } else {
+                        try!(w.write_le_i32(v.len() as i32));
+                        try!(w.write_u8(subtype));
+                    }
+                    w.write(v.as_slice())
+                })))
+            },
+            Undefined => e.build_custom(|_| Ok(vec![0x06])),
+            UtcDatetime(v) => e.build_custom(|_| Ok(custom(0x09, |w| w.write_le_i64(v)))),
+            RegExp(ref pattern, ref options) => {
+                e.build_custom(|_| Ok(custom(0x0B, |w| {
+                    try!(w.write_str(pattern.as_slice()));
+                    try!(w.write_u8(0x00));
+                    try!(w.write_str(options.as_slice()));
+                    w.write_u8(0x00)
+                })))
+            },
+            DbPointer(ref ns, ref id) => {
+                e.build_custom(|_| Ok(custom(0x0C, |w| {
+                    try!(write_string(w, ns.as_slice()));
+                    w.write(id.as_slice())
+                })))
+            },
+            JavaScript(ref v) => e.build_custom(|_| Ok(custom(0x0D, |w| write_string(w, v.as_slice())))),
+            Symbol(ref v) => e.build_custom(|_| Ok(custom(0x0E, |w| write_string(w, v.as_slice())))),
+            JavaScriptWithScope(ref code, ref scope) => {
+                e.build_custom(|e| {
+                    let mut scope = try!(scope.encode(e));
+                    let _ = scope.shift(); // the 'object' type
+                    Ok(custom(0x0F, |w| {
+                        try!(w.write_le_i32((4 + 4 + code.len() + 1 + scope.len()) as i32));
+                        try!(write_string(w, code.as_slice()));
+                        w.write(scope.as_slice())
+                    }))
+                })
+            },
+            Int32(v) => e.build_custom(|_| Ok(custom(0x10, |w| w.write_le_i32(v)))),
+            Timestamp(time, increment) => {
+                e.build_custom(|_| Ok(custom(0x11, |w| {
+                    try!(w.write_le_u32(increment));
+                    w.write_le_u32(time)
+                })))
+            },
+            Int64(v) => e.build_custom(|_| Ok(custom(0x12, |w| w.write_le_i64(v)))),
+            Decimal128(ref v) => e.build_custom(|_| Ok(custom(0x13, |w| w.write(v.as_slice())))),
+            MinKey => e.build_custom(|_| Ok(vec![0xFF])),
+            MaxKey => e.build_custom(|_| Ok(vec![0x7F])),
+            // Shaped like build_str's output so the element gets its length.
+            RawString(ref v) => {
+                e.build_custom(|_| Ok(custom(0x02, |w| {
+                    try!(w.write(v.as_slice()));
+                    w.write_u8(0x00)
+                })))
+            },
+        }
+    }
+}
+
+type ParseResult<T> = Result<T, ParserError>;
+
+fn le_u64(b: &[u8]) -> u64 {
+    b.iter().rev().fold(0u64, |acc, &x| (acc << 8) | x as u64)
+}
+
+/// Walks a buffer of BSON one element at a time, keeping track of the byte
+/// offset and the path of keys that led there so errors can point at the
+/// exact element that was bad.
+struct Parser<'a> {
+    buf: &'a [u8],
+    pos: uint,
+    path: Vec<String>,
+    options: &'a DecoderOptions,
+}
+impl<'a> Parser<'a> {
+    fn new(buf: &'a [u8], options: &'a DecoderOptions) -> Parser<'a> {
+        Parser { buf: buf, pos: 0, path: Vec::new(), options: options }
+    }
+
+    fn error_at<T>(&self, code: ErrorCode, pos: uint) -> ParseResult<T> {
+        Err(SyntaxError(code, pos, self.path.as_slice().connect(".")))
+    }
+
+    fn take(&mut self, n: uint) -> ParseResult<&'a [u8]> {
+        if self.buf.len() - self.pos < n {
+            return self.error_at(UnexpectedEndOfInput, self.pos);
+        }
+        let bytes = self.buf.slice(self.pos, self.pos + n);
+        self.pos += n;
+        Ok(bytes)
+    }
+    fn read_u8(&mut self) -> ParseResult<u8> {
+        Ok(try!(self.take(1))[0])
+    }
+    fn read_i32(&mut self) -> ParseResult<i32> {
+        Ok(le_u64(try!(self.take(4))) as u32 as i32)
+    }
+    fn read_u32(&mut self) -> ParseResult<u32> {
+        Ok(le_u64(try!(self.take(4))) as u32)
+    }
+    fn read_i64(&mut self) -> ParseResult<i64> {
+        Ok(le_u64(try!(self.take(8))) as i64)
+    }
+    fn read_f64(&mut self) -> ParseResult<f64> {
+        let bits = le_u64(try!(self.take(8)));
+        Ok(unsafe { mem::transmute::<u64, f64>(bits) })
+    }
+
+    fn utf8(&self, bytes: &[u8], pos: uint) -> ParseResult<String> {
+        match str::from_utf8(bytes) {
+            Some(s) => Ok(s.to_string()),
+            None if self.options.utf8 == Utf8Strict => self.error_at(InvalidUtf8, pos),
+            None => Ok(String::from_utf8_lossy(bytes).into_string()),
+        }
+    }
+    fn read_cstring(&mut self) -> ParseResult<String> {
+        let start = self.pos;
+        match self.buf.slice_from(start).iter().position(|&b| b == 0x00) {
+            Some(n) => {
+                self.pos = start + n + 1;
+                self.utf8(self.buf.slice(start, start + n), start)
+            },
+            None => self.error_at(MissingTerminator, self.buf.len()),
+        }
+    }
+    /// Reads a length-prefixed string, returning its bytes (without the
+    /// terminator) and where they start.
+    fn read_string_bytes(&mut self) -> ParseResult<(&'a [u8], uint)> {
+        let start = self.pos;
+        let len = try!(self.read_i32());
+        if len < 1 {
+            return self.error_at(BadLength, start);
+        }
+        let bytes = try!(self.take(len as uint));
+        if bytes[bytes.len() - 1] != 0x00 {
+            return self.error_at(MissingTerminator, self.pos - 1);
+        }
+        Ok((bytes.slice_to(bytes.len() - 1), start + 4))
+    }
+    fn read_string(&mut self) -> ParseResult<String> {
+        let (bytes, pos) = try!(self.read_string_bytes());
+        self.utf8(bytes, pos)
+    }
+
+    fn skip(&mut self, n: uint) -> ParseResult<()> {
+        try!(self.take(n));
+        Ok(())
+    }
+    fn skip_cstring(&mut self) -> ParseResult<()> {
+        match self.buf.slice_from(self.pos).iter().position(|&b| b == 0x00) {
+            Some(n) => { self.pos += n + 1; Ok(()) },
+            None => self.error_at(MissingTerminator, self.buf.len()),
+        }
+    }
+    /// Steps over a document (or code with scope) using its length, checking
+    /// only that it fits and ends with a terminator.
+    fn skip_document(&mut self, min: i32) -> ParseResult<()> {
+        let start = self.pos;
+        let len = try!(self.read_i32());
+        if len < min {
+            return self.error_at(BadLength, start);
+        }
+        let end = start + len as uint;
+        if end > self.buf.len() {
+            return self.error_at(UnexpectedEndOfInput, start);
+        }
+        if self.buf[end - 1] != 0x00 {
+            return self.error_at(MissingTerminator, end - 1);
+        }
+        self.pos = end;
+        Ok(())
+    }
+    /// Moves past an element's value without decoding it. Lengths are still
+    /// checked against the buffer, but strings aren't checked for UTF-8 and
+    /// skipped documents aren't looked inside.
+    fn skip_element(&mut self, t: u8, type_pos: uint) -> ParseResult<()> {
+        match t {
+            0x01 | 0x09 | 0x11 | 0x12 => self.skip(8),
+            0x02 | 0x0D | 0x0E => self.read_string_bytes().map(|_| ()),
+            0x03 | 0x04 => self.skip_document(5),
+            0x05 => {
+                let start = self.pos;
+                let len = try!(self.read_i32());
+                if len < 0 {
+                    return self.error_at(BadLength, start);
+                }
+                self.skip(len as uint + 1)
+            },
+            0x06 | 0x0A | 0xFF | 0x7F => Ok(()),
+            0x07 => self.skip(12),
+            0x08 => self.read_element(t, type_pos, None).map(|_| ()),
+            0x0B => {
+                try!(self.skip_cstring());
+                self.skip_cstring()
+            },
+            0x0C => {
+                try!(self.read_string_bytes());
+                self.skip(12)
+            },
+            0x0F => self.skip_document(4 + 5 + 5),
+            0x10 => self.skip(4),
+            0x13 => self.skip(16),
+            _ => self.error_at(UnknownElementType, type_pos),
+        }
+    }
+
+    /// Reads a length-prefixed list of elements, checking that the declared
+    /// length is exactly what the elements and terminator take up. Each
+    /// element comes back with the offset of its type byte. Elements that
+    /// fall outside `node` are skipped; array items are projected with the
+    /// same node as the array.
+    fn read_elements(&mut self, node: Option<&'a Projection>, array: bool) -> ParseResult<Vec<(uint, String, Bson)>> {
+        let start = self.pos;
+        if self.path.len() >= self.options.max_depth {
+            return self.error_at(DepthExceeded, start);
+        }
+        let len = try!(self.read_i32());
+        if len < 5 {
+            return self.error_at(BadLength, start);
+        }
+        let end = start + len as uint;
+        if end > self.buf.len() {
+            return self.error_at(UnexpectedEndOfInput, start);
+        }
+        if self.buf[end - 1] != 0x00 {
+            return self.error_at(MissingTerminator, end - 1);
+        }
+        let mut elements = Vec::new();
+        loop {
+            if self.pos >= end {
+                return self.error_at(BadLength, start);
+            }
+            let type_pos = self.pos;
+            let t = try!(self.read_u8());
+            if t == 0x00 {
+                if self.pos != end {
+                    return self.error_at(BadLength, start);
+                }
+                return Ok(elements);
+            }
+            let key = try!(self.read_cstring());
+            let child = match node {
+                Some(p) if !array => p.child(&key),
+                _ => Some(node),
+            };
+            // Only documents and arrays can hold the fields further down.
+            let child = match child {
+                Some(Some(_)) if t != 0x03 && t != 0x04 => None,
+                child => child,
+            };
+            self.path.push(key);
+            let value = match child {
+                Some(child) => try!(self.read_element(t, type_pos, child)),
+                None => {
+                    try!(self.skip_element(t, type_pos));
+                    self.path.pop();
+                    continue;
+                },
+            };
+            let key = self.path.pop().unwrap();
+            elements.push((type_pos, key, value));
+        }
+    }
+    fn read_document(&mut self, node: Option<&'a Projection>) -> ParseResult<Object> {
+        let mut obj = TreeMap::new();
+        for (pos, key, value) in try!(self.read_elements(node, false)).into_iter() {
+            if obj.contains_key(&key) {
+                match self.options.duplicate_keys {
+                    RejectDuplicates => {
+                        self.path.push(key);
+                        return self.error_at(DuplicateKey, pos);
+                    },
+                    KeepFirst => continue,
+                    KeepLast => (),
+                }
+            }
+            obj.insert(key, value);
+        }
+        Ok(obj)
+    }
+
+    fn read_element(&mut self, t: u8, type_pos: uint, node: Option<&'a Projection>) -> ParseResult<Bson> {
+        match t {
+            0x01 => Ok(Float(try!(self.read_f64()))),
+            0x02 => {
+                let (bytes, pos) = try!(self.read_string_bytes());
+                match str::from_utf8(bytes) {
+                    None if self.options.utf8 == Utf8Raw => Ok(RawString(bytes.to_vec())),
+                    _ => Ok(String(try!(self.utf8(bytes, pos)))),
+                }
+            },
+            0x03 => Ok(Object(try!(self.read_document(node)))),
+            0x04 => {
+                let elements = try!(self.read_elements(node, true));
+                Ok(List(elements.into_iter().map(|(_, _, v)| v).collect()))
+            },
+            0x05 => {
+                let start = self.pos;
+                let len = try!(self.read_i32());
+                if len < 0 {
+                    return self.error_at(BadLength, start);
+                }
+                let subtype = try!(self.read_u8());
+                let mut data = try!(self.take(len as uint));
+                if subtype == 0x02 {
+                    // The old binary subtype repeats the length inside the payload.
+                    if data.len() < 4 || le_u64(data.slice_to(4)) as u32 as i32 != len - 4 {
+                        return self.error_at(BadLength, start);
+                    }
+                    data = data.slice_from(4);
+                }
+                Ok(Binary(subtype, data.to_vec()))
+            },
+            0x06 => Ok(Undefined),
+            0x07 => Ok(ObjectId(try!(self.take(12)).to_vec())),
+            0x08 => match try!(self.read_u8()) {
+                0x00 => Ok(Boolean(false)),
+                0x01 => Ok(Boolean(true)),
+                _ => self.error_at(InvalidSyntax, self.pos - 1),
+            },
+            0x09 => Ok(UtcDatetime(try!(self.read_i64()))),
+            0x0A => Ok(Null),
+            0x0B => {
+                let pattern = try!(self.read_cstring());
+                // Options are stored in alphabetical order, whatever order
+                // they were written in.
+                let mut options: Vec<char> = try!(self.read_cstring()).chars().collect();
+                options.sort();
+                Ok(RegExp(pattern, options.into_iter().collect()))
+            },
+            0x0C => {
+                let ns = try!(self.read_string());
+                Ok(DbPointer(ns, try!(self.take(12)).to_vec()))
+            },
+            0x0D => Ok(JavaScript(try!(self.read_string()))),
+            0x0E => Ok(Symbol(try!(self.read_string()))),
+            0x0F => {
+                let start = self.pos;
+                let len = try!(self.read_i32());
+                // The length, the shortest string and the shortest document.
+                if len < 4 + 5 + 5 {
+                    return self.error_at(BadLength, start);
+                }
+                let end = start + len as uint;
+                if end > self.buf.len() {
+                    return self.error_at(UnexpectedEndOfInput, start);
+                }
+                let code = try!(self.read_string());
+                let scope = try!(self.read_document(None));
+                if self.pos != end {
+                    return self.error_at(BadLength, start);
+                }
+                Ok(JavaScriptWithScope(code, scope))
+            },
+            0x10 => Ok(Int32(try!(self.read_i32()))),
+            0x11 => {
+                let increment = try!(self.read_u32());
+                let time = try!(self.read_u32());
+                Ok(Timestamp(time, increment))
+            },
+            0x12 => Ok(Int64(try!(self.read_i64()))),
+            0x13 => Ok(Decimal128(try!(self.take(16)).to_vec())),
+            0xFF => Ok(MinKey),
+            0x7F => Ok(MaxKey),
+            _ => self.error_at(UnknownElementType, type_pos),
         }
     }
 }
 
 // line 1796
 pub fn from_vec(v: Vec<u8>) -> Result<Bson, BuilderError> {
-    unimplemented!();
+    from_slice(v.as_slice())
+}
+
+pub fn from_slice(v: &[u8]) -> Result<Bson, BuilderError> {
+    from_slice_with_options(v, DecoderOptions::new())
+}
+
+pub fn from_vec_with_options(v: Vec<u8>, options: DecoderOptions) -> Result<Bson, BuilderError> {
+    from_slice_with_options(v.as_slice(), options)
+}
+
+pub fn from_slice_with_options(v: &[u8], options: DecoderOptions) -> Result<Bson, BuilderError> {
+    if v.len() > options.max_document_size {
+        return Err(SyntaxError(DocumentTooLarge, 0, String::new()));
+    }
+    let mut parser = Parser::new(v, &options);
+    let obj = try!(parser.read_document(options.projection.as_ref()));
+    if parser.pos != v.len() {
+        // The declared length stopped short of the end of the buffer.
+        return parser.error_at(BadLength, 0);
+    }
+    Ok(Object(obj))
 }
 
 // line 1802
+/// One step on the way from the root of the value being decoded to the
+/// value currently on top of the stack.
+enum PathElt {
+    Field(String),
+    Index(uint),
+}
+
+fn format_path(path: &[PathElt]) -> String {
+    let mut s = String::new();
+    for elt in path.iter() {
+        match *elt {
+            Field(ref name) => {
+                if !s.is_empty() {
+                    s.push('.');
+                }
+                s.push_str(name.as_slice());
+            },
+            Index(idx) => s.push_str(format!("[{}]", idx).as_slice()),
+        }
+    }
+    s
+}
+
 pub struct Decoder {
-    stack: Vec<Bson>
+    stack: Vec<Bson>,
+    path: Vec<PathElt>,
+    enums: Vec<serialize::EnumRepr>,
+    // Bumped whenever variant data is read, so an untagged unit variant
+    // can't claim a value that actually carries data.
+    variant_reads: uint,
+    deny_unknown_fields: bool,
+    options: DecoderOptions,
 }
 impl Decoder {
     pub fn new(bson: Bson) -> Decoder {
-        Decoder { stack: vec![bson] }
+        Decoder::with_options(bson, DecoderOptions::new())
+    }
+
+    /// Only `utf8`, `max_depth`, `coercion` and `projection` matter here;
+    /// duplicate keys and document size are dealt with when the bytes are
+    /// parsed.
+    pub fn with_options(bson: Bson, options: DecoderOptions) -> Decoder {
+        let bson = match options.projection {
+            Some(ref projection) => projection.apply(bson),
+            None => bson,
+        };
+        Decoder {
+            stack: vec![bson],
+            path: Vec::new(),
+            enums: Vec::new(),
+            variant_reads: 0,
+            deny_unknown_fields: false,
+            options: options,
+        }
+    }
+
+    /// Makes fields that no struct asked for an `UnknownField` error rather
+    /// than being ignored.
+    pub fn set_deny_unknown_fields(&mut self, deny: bool) {
+        self.deny_unknown_fields = deny;
+    }
+
+    fn pop(&mut self) -> Bson {
+        self.stack.pop().unwrap()
+    }
+
+    fn enum_repr(&self) -> serialize::EnumRepr {
+        *self.enums.last().expect("enum variant decoded outside of read_enum")
+    }
+
+    /// The current position, as in `orders[3].items[0].price`.
+    fn path(&self) -> String {
+        format_path(self.path.as_slice())
+    }
+    fn path_to(&self, name: &str) -> String {
+        let mut s = self.path();
+        if !s.is_empty() {
+            s.push('.');
+        }
+        s.push_str(name);
+        s
+    }
+
+    /// Runs `f` one step further down the path. Errors capture the path
+    /// when they are made, so it is unwound whether or not `f` succeeds.
+    fn at<T>(&mut self, elt: PathElt, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        let len = self.path.len();
+        if len >= self.options.max_depth {
+            return Err(Custom { path: self.path(), msg: "maximum nesting depth exceeded".to_string() });
+        }
+        self.path.push(elt);
+        let res = f(self);
+        self.path.truncate(len);
+        res
+    }
+
+    fn expected<T>(&self, expected: &str, found: Bson) -> DecodeResult<T> {
+        Err(ExpectedType {
+            path: self.path(),
+            expected: expected.to_string(),
+            found: found.type_name().to_string(),
+        })
+    }
+
+    /// Pulls a single field out of the object on top of the stack and makes
+    /// it the current value for `f`. The rest of the object stays put for
+    /// the next field.
+    fn read_field<T>(&mut self, name: &str, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        let mut obj = match self.pop() {
+            Object(o) => o,
+            other => return self.expected("object", other),
+        };
+        let value = match obj.pop(&name.to_string()) {
+            None => {
+                // Let fields that can be absent (options) decode from null.
+                self.stack.push(Null);
+                match self.at(Field(name.to_string()), f) {
+                    Ok(v) => v,
+                    Err(_) => return Err(MissingField { path: self.path_to(name) }),
+                }
+            },
+            Some(v) => {
+                self.stack.push(v);
+                try!(self.at(Field(name.to_string()), f))
+            }
+        };
+        self.stack.push(Object(obj));
+        Ok(value)
+    }
+
+    fn tag_name(&self, tag: &str, v: Option<Bson>) -> DecodeResult<String> {
+        match v {
+            Some(String(name)) => Ok(name),
+            Some(other) => {
+                let path = self.path_to(tag);
+                Err(ExpectedType { path: path, expected: "string".to_string(), found: other.type_name().to_string() })
+            },
+            None => Err(MissingField { path: self.path_to(tag) }),
+        }
+    }
+
+    /// Splits a tagged enum value into the variant name and its content,
+    /// which is null for unit variants.
+    fn split_variant(&self, value: Bson) -> DecodeResult<(String, Bson)> {
+        match (self.enum_repr(), value) {
+            (serialize::ExternallyTagged, String(name)) | (serialize::UnitAsString, String(name)) => {
+                Ok((name, Null))
+            },
+            (serialize::ExternallyTagged, Object(o)) => {
+                if o.len() != 1 {
+                    return Err(ExpectedType {
+                        path: self.path(),
+                        expected: "object with a single variant key".to_string(),
+                        found: format!("object with {} keys", o.len()),
+                    });
+                }
+                let (name, content) = o.into_iter().next().unwrap();
+                Ok((name, content))
+            },
+            (serialize::InternallyTagged(tag), Object(mut o)) => {
+                let name = try!(self.tag_name(tag, o.pop(&tag.to_string())));
+                Ok((name, Object(o)))
+            },
+            (serialize::AdjacentlyTagged(tag, key), Object(mut o)) => {
+                let name = try!(self.tag_name(tag, o.pop(&tag.to_string())));
+                Ok((name, o.pop(&key.to_string()).unwrap_or(Null)))
+            },
+            (serialize::UnitAsString, other) | (serialize::ExternallyTagged, other) => {
+                self.expected("string or object", other)
+            },
+            (_, other) => self.expected("object", other),
+        }
     }
+
+    fn read_untagged<T>(&mut self, names: &[&str], f: |&mut Decoder, uint| -> DecodeResult<T>) -> DecodeResult<T> {
+        let value = self.pop();
+        let depth = self.stack.len();
+        for i in range(0u, names.len()) {
+            let reads = self.variant_reads;
+            self.stack.push(value.clone());
+            let res = f(self, i);
+            self.stack.truncate(depth);
+            match res {
+                Ok(v) => {
+                    let read_data = self.variant_reads != reads;
+                    if read_data == (value != Null) {
+                        return Ok(v);
+                    }
+                },
+                Err(_) => ()
+            }
+        }
+        self.expected(format!("one of {}", names).as_slice(), value)
+    }
+
+    fn out_of_range<T, V: fmt::Show>(&self, value: V, target: &str) -> DecodeResult<T> {
+        Err(OutOfRange { path: self.path(), value: value.to_string(), target: target.to_string() })
+    }
+
+    /// Pops an integer for one of the fixed-width reads. Narrow widths are
+    /// stored as int32 and wide ones as int64, though unsigned wide ones
+    /// take an int32 too; the value has to fit `target`. With numeric
+    /// coercion any number type will do.
+    fn read_integer(&mut self, target: &'static str, wide: bool, min: i64, max: i64) -> DecodeResult<i64> {
+        let numeric = self.options.coercion.numeric;
+        let value = match (wide, self.pop()) {
+            (false, Int32(v)) => v as i64,
+            (true, Int64(v)) => v,
+            // The range check turns away negatives.
+            (true, Int32(v)) if min == 0 => v as i64,
+            (_, Int32(v)) if numeric => v as i64,
+            (_, Int64(v)) if numeric => v,
+            (_, Float(v)) if numeric => match exact_integer(v) {
+                Some(v) => v,
+                None => return self.out_of_range(v, target),
+            },
+            (false, other) => return self.expected("int", other),
+            (true, other) => return self.expected("long", other),
+        };
+        if value < min || value > max {
+            return self.out_of_range(value, target);
+        }
+        Ok(value)
+    }
+}
+
+// 2^63, the first double past the end of i64.
+static I64_LIMIT: f64 = 9223372036854775808.0;
+
+/// The double as an i64, if it is one exactly.
+fn exact_integer(v: f64) -> Option<i64> {
+    if v.is_finite() && v == v.trunc() && v >= -I64_LIMIT && v < I64_LIMIT {
+        Some(v as i64)
+    } else {
+        None
+    }
+}
+
+/// The i64 as a double, if it survives the trip.
+fn exact_float(v: i64) -> Option<f64> {
+    let f = v as f64;
+    if f < I64_LIMIT && f as i64 == v {
+        Some(f)
+    } else {
+        None
+    }
+}
+
+/// The shortest decimal form of a finite double that reads back as the same
+/// double, always with a point or an exponent so it stays a double.
+fn format_double(v: f64) -> String {
+    let sign = if v.is_negative() { "-" } else { "" };
+    let v = v.abs();
+    let mut exp = String::new();
+    for digits in range(0u, 17) {
+        exp = f64::to_str_exp_digits(v, digits, false);
+        if from_str::<f64>(exp.as_slice()) == Some(v) {
+            break;
+        }
+    }
+    // Split "d.ddde-x" into its digits and the power of ten of the first.
+    let e = exp.as_slice().find('e').unwrap();
+    let mantissa: String = exp.as_slice().slice_to(e).chars().filter(|&c| c != '.').collect();
+    let power: int = from_str(exp.as_slice().slice_from(e + 1).trim_left_chars('+')).unwrap();
+    if mantissa.as_slice() == "0" {
+        return format!("{}0.0", sign);
+    }
+    let digits = mantissa.as_slice().trim_right_chars('0');
+    if power >= 0 && power < 16 {
+        let point = power as uint + 1;
+        if digits.len() <= point {
+            format!("{}{}{}.0", sign, digits, "0".repeat(point - digits.len()))
+        } else {
+            format!("{}{}.{}", sign, digits.slice_to(point), digits.slice_from(point))
+        }
+    } else if power < 0 && power > -7 {
+        format!("{}0.{}{}", sign, "0".repeat((-power - 1) as uint), digits)
+    } else {
+        let rest = if digits.len() > 1 { digits.slice_from(1) } else { "0" };
+        format!("{}{}.{}E{}{}", sign, digits.slice_to(1), rest, if power > 0 { "+" } else { "-" }, power.abs())
+    }
+}
+
+/// Lowercase hex, the way ObjectIds are written out.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", *b)).collect::<Vec<String>>().concat()
+}
+
+/// Reads hex in either case back into bytes.
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(s.len() / 2);
+    for pair in s.as_bytes().chunks(2) {
+        match ((pair[0] as char).to_digit(16), (pair[1] as char).to_digit(16)) {
+            (Some(hi), Some(lo)) => bytes.push((hi * 16 + lo) as u8),
+            _ => return None,
+        }
+    }
+    Some(bytes)
 }
+
 impl serialize::Decoder<DecoderError> for Decoder {
+    fn read_nil(&mut self) -> DecodeResult<()> {
+        match self.pop() {
+            Null => Ok(()),
+            other => self.expected("null", other),
+        }
+    }
     fn read_bool(&mut self) -> DecodeResult<bool> {
-        unimplemented!();
+        match self.pop() {
+            Boolean(v) => Ok(v),
+            other => self.expected("bool", other),
+        }
     }
     fn read_f64(&mut self) -> DecodeResult<f64> {
-        unimplemented!();
+        let coercion = self.options.coercion.clone();
+        match self.pop() {
+            Float(v) => Ok(v),
+            Int32(v) if coercion.numeric => Ok(v as f64),
+            Int64(v) if coercion.numeric => match exact_float(v) {
+                Some(f) => Ok(f),
+                None => self.out_of_range(v, "f64"),
+            },
+            Decimal128(ref v) if coercion.decimal_to_f64 => Ok(decimal::to_f64(v.as_slice())),
+            other => self.expected("double", other),
+        }
+    }
+    fn read_f32(&mut self) -> DecodeResult<f32> {
+        let v = try!(self.read_f64());
+        // Infinities and NaN carry over; finite values have to stay finite.
+        if v.is_finite() && v.abs() > ::std::f32::MAX as f64 {
+            return self.out_of_range(v, "f32");
+        }
+        Ok(v as f32)
     }
     fn read_str(&mut self) -> DecodeResult<String> {
-        unimplemented!();
+        match self.pop() {
+            String(v) => Ok(v),
+            RawString(ref v) if self.options.utf8 == Utf8Lossy => {
+                Ok(String::from_utf8_lossy(v.as_slice()).into_string())
+            },
+            RawString(_) => {
+                Err(ExpectedType { path: self.path(), expected: "string".to_string(), found: "invalid UTF-8".to_string() })
+            },
+            ObjectId(ref v) if self.options.coercion.to_string => Ok(to_hex(v.as_slice())),
+            Decimal128(ref v) if self.options.coercion.to_string => Ok(decimal::to_string(v.as_slice())),
+            other => self.expected("string", other),
+        }
+    }
+    fn read_char(&mut self) -> DecodeResult<char> {
+        match self.pop() {
+            String(ref v) if v.as_slice().char_len() == 1 => Ok(v.as_slice().char_at(0)),
+            String(v) => Err(OutOfRange { path: self.path(), value: format!("\"{}\"", v), target: "char".to_string() }),
+            other => self.expected("string", other),
+        }
+    }
+
+    fn read_i8(&mut self) -> DecodeResult<i8> {
+        Ok(try!(self.read_integer("i8", false, ::std::i8::MIN as i64, ::std::i8::MAX as i64)) as i8)
+    }
+    fn read_i16(&mut self) -> DecodeResult<i16> {
+        Ok(try!(self.read_integer("i16", false, ::std::i16::MIN as i64, ::std::i16::MAX as i64)) as i16)
+    }
+    fn read_i32(&mut self) -> DecodeResult<i32> {
+        Ok(try!(self.read_integer("i32", false, ::std::i32::MIN as i64, ::std::i32::MAX as i64)) as i32)
+    }
+    fn read_i64(&mut self) -> DecodeResult<i64> {
+        self.read_integer("i64", true, ::std::i64::MIN, ::std::i64::MAX)
+    }
+    fn read_int(&mut self) -> DecodeResult<int> {
+        Ok(try!(self.read_integer("int", true, ::std::int::MIN as i64, ::std::int::MAX as i64)) as int)
+    }
+    fn read_u8(&mut self) -> DecodeResult<u8> {
+        Ok(try!(self.read_integer("u8", false, 0, ::std::u8::MAX as i64)) as u8)
+    }
+    fn read_u16(&mut self) -> DecodeResult<u16> {
+        Ok(try!(self.read_integer("u16", false, 0, ::std::u16::MAX as i64)) as u16)
+    }
+    fn read_u32(&mut self) -> DecodeResult<u32> {
+        Ok(try!(self.read_integer("u32", true, 0, ::std::u32::MAX as i64)) as u32)
+    }
+    fn read_u64(&mut self) -> DecodeResult<u64> {
+        Ok(try!(self.read_integer("u64", true, 0, ::std::i64::MAX)) as u64)
+    }
+    fn read_uint(&mut self) -> DecodeResult<uint> {
+        Ok(try!(self.read_integer("uint", true, 0, ::std::i64::MAX)) as uint)
+    }
+
+    fn read_option<T>(&mut self, f: |&mut Decoder, bool| -> DecodeResult<T>) -> DecodeResult<T> {
+        match self.pop() {
+            Null => f(self, false),
+            Undefined if self.options.coercion.undefined_as_none => f(self, false),
+            value => {
+                self.stack.push(value);
+                f(self, true)
+            }
+        }
+    }
+
+    fn read_seq<T>(&mut self, f: |&mut Decoder, uint| -> DecodeResult<T>) -> DecodeResult<T> {
+        let list = match self.pop() {
+            List(l) => l,
+            other => return self.expected("array", other),
+        };
+        let len = list.len();
+        for v in list.into_iter().rev() {
+            self.stack.push(v);
+        }
+        f(self, len)
     }
+    fn read_seq_elt<T>(&mut self, idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.at(Index(idx), f)
+    }
+
     fn read_map<T>(&mut self, f: |&mut Decoder, uint| -> DecodeResult<T>) -> DecodeResult<T> {
-        unimplemented!();
+        let obj = match self.pop() {
+            Object(o) => o,
+            other => return self.expected("object", other),
+        };
+        let len = obj.len();
+        let entries: Vec<(String, Bson)> = obj.into_iter().collect();
+        for (key, value) in entries.into_iter().rev() {
+            self.stack.push(value);
+            self.stack.push(String(key));
+        }
+        f(self, len)
     }
     fn read_map_item<T1,T2>(&mut self, idx: uint, key: |&mut Decoder| -> DecodeResult<T1>, val: |&mut Decoder| -> DecodeResult<T2>) -> DecodeResult<(T1,T2)> {
-        unimplemented!();
+        let elt = match self.stack.last() {
+            Some(&String(ref k)) => Field(k.clone()),
+            _ => Index(idx),
+        };
+        let k = try!(key(self));
+        let v = try!(self.at(elt, val));
+        Ok((k, v))
+    }
+
+    fn read_struct<T>(&mut self, s_name: &str, len: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        let value = try!(f(self));
+        match self.pop() {
+            Object(ref rest) if self.deny_unknown_fields && rest.len() > 0 => {
+                let (name, _) = rest.iter().next().unwrap();
+                Err(UnknownField { path: self.path_to(name.as_slice()) })
+            },
+            _ => Ok(value),
+        }
+    }
+    fn read_struct_field<T>(&mut self, f_name: &str, f_idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.read_field(f_name, f)
+    }
+
+    fn read_enum<T>(&mut self, name: &str, repr: serialize::EnumRepr, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.enums.push(repr);
+        let res = f(self);
+        self.enums.pop();
+        res
+    }
+    fn read_enum_variant<T>(&mut self, names: &[&str], f: |&mut Decoder, uint| -> DecodeResult<T>) -> DecodeResult<T> {
+        let repr = self.enum_repr();
+        if repr == serialize::Untagged {
+            return self.read_untagged(names, f);
+        }
+        let value = self.pop();
+        let (name, content) = try!(self.split_variant(value));
+        let idx = match names.iter().position(|n| *n == name.as_slice()) {
+            Some(idx) => idx,
+            None => {
+                return Err(UnknownVariant {
+                    path: self.path(),
+                    variant: name,
+                    expected: names.iter().map(|n| n.to_string()).collect(),
+                });
+            }
+        };
+        // Point the path at wherever the variant's data actually lives.
+        let plen = self.path.len();
+        match repr {
+            serialize::ExternallyTagged => self.path.push(Field(name)),
+            serialize::AdjacentlyTagged(_, key) => self.path.push(Field(key.to_string())),
+            _ => ()
+        }
+        let depth = self.stack.len();
+        self.stack.push(content);
+        let res = f(self, idx);
+        self.stack.truncate(depth);
+        self.path.truncate(plen);
+        res
+    }
+    fn read_enum_variant_arg<T>(&mut self, a_idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        let mut args = match self.pop() {
+            List(l) => l,
+            other => return self.expected("array of variant arguments", other),
+        };
+        if a_idx >= args.len() {
+            return Err(ExpectedType {
+                path: self.path(),
+                expected: format!("at least {} variant arguments", a_idx + 1),
+                found: format!("{}", args.len()),
+            });
+        }
+        let arg = mem::replace(&mut args.as_mut_slice()[a_idx], Null);
+        self.stack.push(List(args));
+        self.stack.push(arg);
+        self.variant_reads += 1;
+        self.at(Index(a_idx), f)
+    }
+    fn read_enum_struct_variant<T>(&mut self, names: &[&str], f: |&mut Decoder, uint| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.read_enum_variant(names, f)
+    }
+    fn read_enum_struct_variant_field<T>(&mut self, f_name: &str, f_idx: uint, f: |&mut Decoder| -> DecodeResult<T>) -> DecodeResult<T> {
+        self.variant_reads += 1;
+        self.read_field(f_name, f)
+    }
+
+    fn error(&mut self, msg: &str) -> DecoderError {
+        Custom { path: self.path(), msg: msg.to_string() }
     }
 }
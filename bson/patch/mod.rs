@@ -0,0 +1,281 @@
+use std::{fmt,cmp};
+use std::collections::TreeMap;
+
+use super::{Bson,Object,String,Null,Int32,Int64,Decimal128};
+
+/// One JSON Patch operation, with paths as JSON Pointers.
+#[deriving(PartialEq, Clone, Show)]
+pub enum PatchOp {
+    Add { path: String, value: Bson },
+    Remove { path: String },
+    Replace { path: String, value: Bson },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    /// Fails the patch unless the value at `path` equals `value`.
+    Test { path: String, value: Bson },
+}
+
+/// Which operation of a patch failed, counting from zero, and why.
+#[deriving(PartialEq, Clone)]
+pub struct PatchError {
+    pub index: uint,
+    pub msg: String,
+}
+
+impl fmt::Show for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in operation {}", self.msg, self.index)
+    }
+}
+
+pub type PatchResult<T> = Result<T, PatchError>;
+
+fn member_string(obj: &Object, name: &str) -> Result<String, String> {
+    match obj.find(&name.to_string()) {
+        Some(&String(ref s)) => Ok(s.clone()),
+        Some(_) => Err(format!("`{}` must be a string", name)),
+        None => Err(format!("missing `{}`", name)),
+    }
+}
+
+fn member_value(obj: &Object) -> Result<Bson, String> {
+    match obj.find(&"value".to_string()) {
+        Some(v) => Ok(v.clone()),
+        None => Err("missing `value`".to_string()),
+    }
+}
+
+fn parse_op(op: &Bson) -> Result<PatchOp, String> {
+    let obj = match *op {
+        super::Object(ref obj) => obj,
+        _ => return Err("an operation must be a document".to_string()),
+    };
+    let path = || member_string(obj, "path");
+    let from = || member_string(obj, "from");
+    Ok(match try!(member_string(obj, "op")).as_slice() {
+        "add" => Add { path: try!(path()), value: try!(member_value(obj)) },
+        "remove" => Remove { path: try!(path()) },
+        "replace" => Replace { path: try!(path()), value: try!(member_value(obj)) },
+        "move" => Move { from: try!(from()), path: try!(path()) },
+        "copy" => Copy { from: try!(from()), path: try!(path()) },
+        "test" => Test { path: try!(path()), value: try!(member_value(obj)) },
+        other => return Err(format!("unknown operation `{}`", other)),
+    })
+}
+
+/// Reads a patch in its JSON form, an array of `{ op, path, ... }`
+/// documents. Members an operation doesn't use are ignored.
+pub fn parse_patch(patch: &Bson) -> PatchResult<Vec<PatchOp>> {
+    let items = match *patch {
+        super::List(ref items) => items,
+        _ => return Err(PatchError { index: 0, msg: "a patch must be an array".to_string() }),
+    };
+    let mut ops = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        match parse_op(item) {
+            Ok(op) => ops.push(op),
+            Err(msg) => return Err(PatchError { index: index, msg: msg }),
+        }
+    }
+    Ok(ops)
+}
+
+/// Splits a JSON Pointer into its unescaped reference tokens.
+fn tokens(pointer: &str) -> Result<Vec<String>, String> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !pointer.starts_with("/") {
+        return Err(format!("`{}` isn't a JSON Pointer", pointer));
+    }
+    Ok(pointer.slice_from(1).split('/').map(|t| t.replace("~1", "/").replace("~0", "~")).collect())
+}
+
+/// An array index token: digits, without leading zeros, below `len`.
+fn index(token: &str, len: uint) -> Result<uint, String> {
+    let digits = token.len() > 0 && token.chars().all(|c| c >= '0' && c <= '9');
+    if !digits || (token.len() > 1 && token.starts_with("0")) {
+        return Err(format!("`{}` isn't an array index", token));
+    }
+    match from_str::<uint>(token) {
+        Some(i) if i < len => Ok(i),
+        _ => Err(format!("index {} is out of bounds", token)),
+    }
+}
+
+fn resolve<'a>(v: &'a Bson, tokens: &[String]) -> Result<&'a Bson, String> {
+    if tokens.is_empty() {
+        return Ok(v);
+    }
+    let token = tokens[0].as_slice();
+    let next = match *v {
+        super::Object(ref obj) => match obj.find(&tokens[0]) {
+            Some(child) => child,
+            None => return Err(format!("no field `{}`", token)),
+        },
+        super::List(ref items) => &items[try!(index(token, items.len()))],
+        _ => return Err(format!("can't look up `{}` in a {}", token, v.type_name())),
+    };
+    resolve(next, tokens.slice_from(1))
+}
+
+fn resolve_mut<'a>(v: &'a mut Bson, tokens: &[String]) -> Result<&'a mut Bson, String> {
+    if tokens.is_empty() {
+        return Ok(v);
+    }
+    let token = tokens[0].as_slice();
+    let type_name = v.type_name();
+    let next = match *v {
+        super::Object(ref mut obj) => match obj.find_mut(&tokens[0]) {
+            Some(child) => child,
+            None => return Err(format!("no field `{}`", token)),
+        },
+        super::List(ref mut items) => {
+            let i = try!(index(token, items.len()));
+            items.get_mut(i)
+        },
+        _ => return Err(format!("can't look up `{}` in a {}", token, type_name)),
+    };
+    resolve_mut(next, tokens.slice_from(1))
+}
+
+fn add(doc: &mut Bson, path: &[String], value: Bson) -> Result<(), String> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+    let (parent, last) = (path.slice_to(path.len() - 1), &path[path.len() - 1]);
+    let parent = try!(resolve_mut(doc, parent));
+    let type_name = parent.type_name();
+    match *parent {
+        super::Object(ref mut obj) => {
+            obj.insert(last.clone(), value);
+        },
+        super::List(ref mut items) => {
+            if last.as_slice() == "-" {
+                items.push(value);
+            } else {
+                // One past the end is allowed, and appends.
+                let i = try!(index(last.as_slice(), items.len() + 1));
+                items.insert(i, value);
+            }
+        },
+        _ => return Err(format!("can't add `{}` to a {}", last, type_name)),
+    }
+    Ok(())
+}
+
+fn remove(doc: &mut Bson, path: &[String]) -> Result<Bson, String> {
+    if path.is_empty() {
+        return Err("can't remove the whole document".to_string());
+    }
+    let (parent, last) = (path.slice_to(path.len() - 1), &path[path.len() - 1]);
+    let parent = try!(resolve_mut(doc, parent));
+    let type_name = parent.type_name();
+    match *parent {
+        super::Object(ref mut obj) => match obj.pop(last) {
+            Some(v) => Ok(v),
+            None => Err(format!("no field `{}`", last)),
+        },
+        super::List(ref mut items) => {
+            let i = try!(index(last.as_slice(), items.len()));
+            Ok(items.remove(i).unwrap())
+        },
+        _ => Err(format!("can't remove `{}` from a {}", last, type_name)),
+    }
+}
+
+fn is_number(v: &Bson) -> bool {
+    match *v {
+        Int32(_) | Int64(_) | super::Float(_) | Decimal128(_) => true,
+        _ => false,
+    }
+}
+
+/// JSON equality: numbers by value whatever their type, documents by
+/// their fields, and everything else exactly.
+fn equal(a: &Bson, b: &Bson) -> bool {
+    match (a, b) {
+        (&super::List(ref x), &super::List(ref y)) => {
+            x.len() == y.len() && x.iter().zip(y.iter()).all(|(x, y)| equal(x, y))
+        },
+        (&super::Object(ref x), &super::Object(ref y)) => {
+            x.len() == y.len() && x.iter().zip(y.iter()).all(|((xk, xv), (yk, yv))| xk == yk && equal(xv, yv))
+        },
+        _ if is_number(a) && is_number(b) => a.compare(b) == cmp::Equal,
+        _ => a == b,
+    }
+}
+
+fn apply_op(doc: &mut Bson, op: &PatchOp) -> Result<(), String> {
+    match *op {
+        Add { ref path, ref value } => add(doc, try!(tokens(path.as_slice())).as_slice(), value.clone()),
+        Remove { ref path } => remove(doc, try!(tokens(path.as_slice())).as_slice()).map(|_| ()),
+        Replace { ref path, ref value } => {
+            let target = try!(resolve_mut(doc, try!(tokens(path.as_slice())).as_slice()));
+            *target = value.clone();
+            Ok(())
+        },
+        Move { ref from, ref path } => {
+            let (from, to) = (try!(tokens(from.as_slice())), try!(tokens(path.as_slice())));
+            if to.len() > from.len() && to.slice_to(from.len()) == from.as_slice() {
+                return Err("can't move a value inside itself".to_string());
+            }
+            let value = try!(remove(doc, from.as_slice()));
+            add(doc, to.as_slice(), value)
+        },
+        Copy { ref from, ref path } => {
+            let value = try!(resolve(doc, try!(tokens(from.as_slice())).as_slice())).clone();
+            add(doc, try!(tokens(path.as_slice())).as_slice(), value)
+        },
+        Test { ref path, ref value } => {
+            let current = try!(resolve(doc, try!(tokens(path.as_slice())).as_slice()));
+            if equal(current, value) {
+                Ok(())
+            } else {
+                Err(format!("test failed: found {}", super::to_relaxed_extjson(current)))
+            }
+        },
+    }
+}
+
+/// Applies `ops` in order to a copy of `doc` (RFC 6902). If any of them
+/// fails, so does the whole patch, and nothing is changed. Values are
+/// moved around as they are, so BSON-only types come through untouched.
+pub fn apply_patch(doc: &Bson, ops: &[PatchOp]) -> PatchResult<Bson> {
+    let mut patched = doc.clone();
+    for (index, op) in ops.iter().enumerate() {
+        match apply_op(&mut patched, op) {
+            Ok(()) => (),
+            Err(msg) => return Err(PatchError { index: index, msg: msg }),
+        }
+    }
+    Ok(patched)
+}
+
+/// Merges `patch` into `target` (RFC 7396): documents merge field by
+/// field, a null removes the field, and anything else replaces what was
+/// there, BSON-only types included.
+pub fn merge_patch(target: &Bson, patch: &Bson) -> Bson {
+    let fields = match *patch {
+        super::Object(ref fields) => fields,
+        _ => return patch.clone(),
+    };
+    let mut merged = match *target {
+        super::Object(ref obj) => obj.clone(),
+        _ => TreeMap::new(),
+    };
+    let missing = Null;
+    for (k, v) in fields.iter() {
+        match *v {
+            Null => {
+                merged.remove(k);
+            },
+            _ => {
+                let value = merge_patch(merged.find(k).unwrap_or(&missing), v);
+                merged.insert(k.clone(), value);
+            },
+        }
+    }
+    super::Object(merged)
+}
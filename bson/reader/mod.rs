@@ -0,0 +1,122 @@
+use std::{io,cmp};
+
+use super::{Bson,ParserError,SyntaxError,IoError,BadLength,TruncatedDocument,DocumentTooLarge};
+use super::DecoderOptions;
+
+// How much to ask the underlying reader for at once. Lengths come from the
+// data, so the buffer only grows as fast as bytes actually arrive.
+static READ_CHUNK: uint = 64 * 1024;
+
+/// Reads back-to-back documents, like a `.bson` file from mongodump, out of
+/// any `Reader`. One buffer is reused for every document.
+///
+/// Once the stream itself goes wrong (a bad length prefix, a read error or a
+/// truncated final document) that error is returned and iteration stops. A
+/// document that is framed properly but fails to parse is returned as an
+/// error and reading carries on with the next one.
+pub struct DocumentReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    start: uint,
+    pos: uint,
+    done: bool,
+    options: DecoderOptions,
+}
+
+impl<R: Reader> DocumentReader<R> {
+    pub fn new(reader: R) -> DocumentReader<R> {
+        DocumentReader::with_options(reader, DecoderOptions::new())
+    }
+
+    pub fn with_options(reader: R, options: DecoderOptions) -> DocumentReader<R> {
+        DocumentReader {
+            reader: reader,
+            buf: Vec::new(),
+            start: 0,
+            pos: 0,
+            done: false,
+            options: options,
+        }
+    }
+
+    /// The stream offset of the document most recently read.
+    pub fn offset(&self) -> uint {
+        self.start
+    }
+
+    /// Reads the next document without parsing it. The slice is only good
+    /// until the next call.
+    pub fn next_raw(&mut self) -> Option<Result<&[u8], ParserError>> {
+        if self.done {
+            return None;
+        }
+        self.start = self.pos;
+        self.buf.clear();
+        match self.fill(4) {
+            Ok(()) => (),
+            Err(ref e) if e.kind == io::EndOfFile && self.buf.len() == 0 => {
+                self.done = true;
+                return None;
+            },
+            Err(e) => {
+                let err = self.stream_error(e);
+                return self.fail(err);
+            }
+        }
+        let len = super::le_u64(self.buf.as_slice()) as u32 as i32;
+        if len < 5 {
+            let err = SyntaxError(BadLength, self.start, String::new());
+            return self.fail(err);
+        }
+        if len as uint > self.options.max_document_size {
+            let err = SyntaxError(DocumentTooLarge, self.start, String::new());
+            return self.fail(err);
+        }
+        match self.fill(len as uint) {
+            Ok(()) => Some(Ok(self.buf.as_slice())),
+            Err(e) => {
+                let err = self.stream_error(e);
+                self.fail(err)
+            }
+        }
+    }
+
+    /// Reads from the stream until the buffer holds `len` bytes.
+    fn fill(&mut self, len: uint) -> io::IoResult<()> {
+        while self.buf.len() < len {
+            let want = cmp::min(len - self.buf.len(), READ_CHUNK);
+            let n = try!(self.reader.push(want, &mut self.buf));
+            self.pos += n;
+        }
+        Ok(())
+    }
+
+    fn stream_error(&self, e: io::IoError) -> ParserError {
+        match e.kind {
+            io::EndOfFile => SyntaxError(TruncatedDocument, self.start, String::new()),
+            kind => IoError(kind, e.desc),
+        }
+    }
+
+    fn fail<T>(&mut self, err: ParserError) -> Option<Result<T, ParserError>> {
+        self.done = true;
+        Some(Err(err))
+    }
+}
+
+impl<R: Reader> Iterator<Result<Bson, ParserError>> for DocumentReader<R> {
+    fn next(&mut self) -> Option<Result<Bson, ParserError>> {
+        let options = self.options.clone();
+        let res = match self.next_raw() {
+            None => return None,
+            Some(Err(e)) => return Some(Err(e)),
+            Some(Ok(raw)) => super::from_slice_with_options(raw, options),
+        };
+        // Offsets from the parser are relative to the document.
+        let start = self.start;
+        Some(res.map_err(|e| match e {
+            SyntaxError(code, offset, path) => SyntaxError(code, start + offset, path),
+            e => e,
+        }))
+    }
+}
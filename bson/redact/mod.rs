@@ -0,0 +1,208 @@
+use std::hash::sip;
+use std::collections::TreeMap;
+
+use super::{Bson,Object,String};
+use super::extjson::to_canonical_extjson;
+
+/// The placeholder `RedactOptions::new` uses.
+pub static REDACTED: &'static str = "<redacted>";
+
+/// Commands that carry credentials somewhere in them, logged as nothing
+/// but their name.
+pub static SENSITIVE_COMMANDS: &'static [&'static str] = &["authenticate", "saslStart", "saslContinue",
+    "getnonce", "createUser", "updateUser", "copydbgetnonce", "copydbsaslstart", "copydb"];
+
+/// What a redacted value becomes.
+#[deriving(PartialEq, Clone, Show)]
+pub enum Mask {
+    /// The same string, whatever the value was.
+    Placeholder(String),
+    /// The value's SipHash under this key, in hex. Equal values still look
+    /// equal in the log, without the key giving them away.
+    KeyedHash(u64, u64),
+}
+
+#[deriving(PartialEq, Clone, Show)]
+pub struct RedactOptions {
+    /// Dotted paths to redact. In each segment `*` matches any run of
+    /// characters, and a `**` segment matches any number of segments, so
+    /// `*.password` is a password one level down and `**.password` is one
+    /// at any depth. Arrays are looked through, as in queries, but their
+    /// elements can be picked out by index too.
+    pub paths: Vec<String>,
+    pub mask: Mask,
+    /// Commands to redact whole, keeping only their name, along with their
+    /// replies. Hellos that carry `speculativeAuthenticate` are redacted
+    /// whole as well.
+    pub sensitive_commands: Vec<String>,
+}
+
+impl RedactOptions {
+    pub fn new(paths: &[&str]) -> RedactOptions {
+        RedactOptions {
+            paths: paths.iter().map(|p| (*p).to_string()).collect(),
+            mask: Placeholder(REDACTED.to_string()),
+            sensitive_commands: SENSITIVE_COMMANDS.iter().map(|c| (*c).to_string()).collect(),
+        }
+    }
+}
+
+/// Where each pattern has got to, as (pattern, segment) pairs.
+type States = Vec<(uint, uint)>;
+
+/// Whether `glob`, with `*` for any run of characters, matches all of `s`.
+fn glob_match(glob: &[u8], s: &[u8]) -> bool {
+    if glob.is_empty() {
+        return s.is_empty();
+    }
+    let rest = glob.slice_from(1);
+    if glob[0] == b'*' {
+        range(0, s.len() + 1).any(|i| glob_match(rest, s.slice_from(i)))
+    } else {
+        !s.is_empty() && s[0] == glob[0] && glob_match(rest, s.slice_from(1))
+    }
+}
+
+struct Redactor<'a> {
+    patterns: Vec<Vec<&'a str>>,
+    mask: &'a Mask,
+}
+
+impl<'a> Redactor<'a> {
+    /// Adds the states a `**` lets through without using up a segment.
+    fn close(&self, mut states: States) -> States {
+        let mut i = 0;
+        while i < states.len() {
+            let (p, pos) = states[i];
+            if pos < self.patterns[p].len() && self.patterns[p][pos] == "**" && !states.contains(&(p, pos + 1)) {
+                states.push((p, pos + 1));
+            }
+            i += 1;
+        }
+        states
+    }
+
+    fn advance(&self, states: &States, key: &str) -> States {
+        let mut next = Vec::new();
+        for &(p, pos) in states.iter() {
+            let pattern = &self.patterns[p];
+            if pos == pattern.len() {
+                continue;
+            }
+            let segment = pattern[pos];
+            let state = if segment == "**" {
+                (p, pos)
+            } else if glob_match(segment.as_bytes(), key.as_bytes()) {
+                (p, pos + 1)
+            } else {
+                continue;
+            };
+            if !next.contains(&state) {
+                next.push(state);
+            }
+        }
+        self.close(next)
+    }
+
+    fn matched(&self, states: &States) -> bool {
+        states.iter().any(|&(p, pos)| pos == self.patterns[p].len())
+    }
+
+    fn mask(&self, v: &Bson) -> Bson {
+        match *self.mask {
+            Placeholder(ref text) => String(text.clone()),
+            KeyedHash(k0, k1) => String(format!("{:016x}", sip::hash_with_keys(k0, k1, &to_canonical_extjson(v)))),
+        }
+    }
+
+    fn object(&self, obj: &Object, states: &States) -> Object {
+        obj.iter().map(|(k, v)| (k.clone(), self.value(v, &self.advance(states, k.as_slice())))).collect()
+    }
+
+    fn value(&self, v: &Bson, states: &States) -> Bson {
+        if self.matched(states) {
+            return self.mask(v);
+        }
+        if states.is_empty() {
+            return v.clone();
+        }
+        match *v {
+            super::Object(ref obj) => super::Object(self.object(obj, states)),
+            super::List(ref items) => super::List(items.iter().enumerate().map(|(i, item)| {
+                let mut through = self.advance(states, i.to_string().as_slice());
+                for state in states.iter() {
+                    if !through.contains(state) {
+                        through.push(*state);
+                    }
+                }
+                self.value(item, &through)
+            }).collect()),
+            _ => v.clone(),
+        }
+    }
+}
+
+static HELLOS: &'static [&'static str] = &["hello", "isMaster", "ismaster"];
+
+/// The name of the sensitive command `doc` runs, if it runs one.
+fn sensitive_command(obj: &Object, options: &RedactOptions) -> Option<String> {
+    if obj.contains_key(&"speculativeAuthenticate".to_string()) {
+        for name in HELLOS.iter() {
+            if obj.contains_key(&(*name).to_string()) {
+                return Some((*name).to_string());
+            }
+        }
+    }
+    options.sensitive_commands.iter().find(|c| obj.contains_key(*c)).map(|c| c.clone())
+}
+
+/// Masks whatever `options.paths` picks out of `doc`.
+fn redact_paths(doc: &Bson, options: &RedactOptions) -> Bson {
+    let redactor = Redactor {
+        patterns: options.paths.iter().map(|p| p.as_slice().split('.').collect()).collect(),
+        mask: &options.mask,
+    };
+    let states = redactor.close(range(0, redactor.patterns.len()).map(|p| (p, 0u)).collect());
+    redactor.value(doc, &states)
+}
+
+/// A copy of `doc` fit for a log: sensitive commands cut down to
+/// `{ <name>: <placeholder> }`, and anything at one of `options.paths`
+/// masked. The placeholder is fixed even under `KeyedHash`, since a hash
+/// of the whole command would fingerprint the credentials in it.
+pub fn redact(doc: &Bson, options: &RedactOptions) -> Bson {
+    match *doc {
+        super::Object(ref obj) => match sensitive_command(obj, options) {
+            Some(name) => {
+                let placeholder = match options.mask {
+                    Placeholder(ref text) => text.clone(),
+                    KeyedHash(..) => REDACTED.to_string(),
+                };
+                let mut redacted = TreeMap::new();
+                redacted.insert(name, String(placeholder));
+                return super::Object(redacted);
+            },
+            None => (),
+        },
+        _ => (),
+    }
+    redact_paths(doc, options)
+}
+
+/// A copy of the reply to `command` fit for a log. Replies don't say what
+/// they answer, so the caller names the command. Replies to sensitive
+/// commands, and hellos answering `speculativeAuthenticate`, come back as
+/// an empty document; anything else has `options.paths` masked.
+pub fn redact_reply(reply: &Bson, command: &str, options: &RedactOptions) -> Bson {
+    let sensitive = options.sensitive_commands.iter().any(|c| c.as_slice() == command) || match *reply {
+        super::Object(ref obj) => {
+            HELLOS.contains(&command) && obj.contains_key(&"speculativeAuthenticate".to_string())
+        },
+        _ => false,
+    };
+    if sensitive {
+        super::Object(TreeMap::new())
+    } else {
+        redact_paths(reply, options)
+    }
+}
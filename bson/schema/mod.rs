@@ -0,0 +1,699 @@
+use std::fmt;
+use std::num::Float;
+use std::collections::TreeMap;
+use regex::Regex;
+
+use super::{Bson,Object,String,Boolean,Int32,Int64,Decimal128,decimal};
+
+/// What's wrong with a schema, and where in it, as in
+/// `properties.age.minimum`.
+#[deriving(PartialEq, Clone)]
+pub struct SchemaError {
+    pub path: String,
+    pub msg: String,
+}
+
+impl fmt::Show for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at `{}`", self.msg, self.path)
+    }
+}
+
+pub type SchemaResult<T> = Result<T, SchemaError>;
+
+fn fail<T>(path: &str, msg: String) -> SchemaResult<T> {
+    Err(SchemaError { path: path.to_string(), msg: msg })
+}
+
+static BSON_TYPES: &'static [&'static str] = &["double", "string", "object", "array", "binData",
+    "undefined", "objectId", "bool", "date", "null", "regex", "dbPointer", "javascript", "symbol",
+    "javascriptWithScope", "int", "timestamp", "long", "decimal", "minKey", "maxKey"];
+/// What the `number` alias stands for.
+static NUMBER_TYPES: &'static [&'static str] = &["int", "long", "double", "decimal"];
+
+/// A compiled schema: the rules for one value.
+struct Node {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    keyword: String,
+    /// How the keyword was written, for `specifiedAs`. Keywords that only
+    /// hold subschemas report their details instead.
+    specified: Option<Object>,
+    kind: Kind,
+}
+
+enum Dependency {
+    PropertyDependency(Vec<String>),
+    SchemaDependency(Node),
+}
+
+enum Kind {
+    /// Type names as `Bson::type_name` gives them.
+    Types(Vec<&'static str>),
+    Required(Vec<String>),
+    Properties(Vec<(String, Node)>),
+    PatternProperties(Vec<(String, Regex, Node)>),
+    /// `None` for `false`. The rest are what `properties` and
+    /// `patternProperties` already cover.
+    AdditionalProperties(Option<Box<Node>>, Vec<String>, Vec<Regex>),
+    Items(Box<Node>),
+    TupleItems(Vec<Node>),
+    /// `None` for `false`, with the number of tuple items before them.
+    AdditionalItems(Option<Box<Node>>, uint),
+    /// The bound, and whether it's exclusive.
+    Minimum(f64, bool),
+    Maximum(f64, bool),
+    MultipleOf(f64),
+    MinLength(uint),
+    MaxLength(uint),
+    Pattern(Regex),
+    MinItems(uint),
+    MaxItems(uint),
+    UniqueItems,
+    MinProperties(uint),
+    MaxProperties(uint),
+    Enum(Vec<Bson>),
+    AllOf(Vec<Node>),
+    AnyOf(Vec<Node>),
+    OneOf(Vec<Node>),
+    Not(Box<Node>),
+    Dependencies(Vec<(String, Dependency)>),
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Numbers of any type, as a double.
+fn number_value(v: &Bson) -> Option<f64> {
+    match *v {
+        Int32(n) => Some(n as f64),
+        Int64(n) => Some(n as f64),
+        super::Float(f) => Some(f),
+        Decimal128(ref bytes) => Some(decimal::to_f64(bytes.as_slice())),
+        _ => None,
+    }
+}
+
+/// Equality with numbers compared by value, so `1` matches `1.0`.
+fn equal(a: &Bson, b: &Bson) -> bool {
+    match (number_value(a), number_value(b)) {
+        (Some(x), Some(y)) => x == y,
+        _ => a == b,
+    }
+}
+
+fn object<'a>(spec: &'a Bson, here: &str) -> SchemaResult<&'a Object> {
+    match *spec {
+        super::Object(ref obj) => Ok(obj),
+        _ => fail(here, "expected a document".to_string()),
+    }
+}
+
+fn number(spec: &Bson, here: &str) -> SchemaResult<f64> {
+    match number_value(spec) {
+        Some(n) => Ok(n),
+        None => fail(here, "expected a number".to_string()),
+    }
+}
+
+fn count(spec: &Bson, here: &str) -> SchemaResult<uint> {
+    match number_value(spec) {
+        Some(n) if n >= 0.0 && n == n.trunc() => Ok(n as uint),
+        _ => fail(here, "expected a non-negative integer".to_string()),
+    }
+}
+
+/// A non-empty array of distinct strings.
+fn strings(spec: &Bson, here: &str) -> SchemaResult<Vec<String>> {
+    let list = match *spec {
+        super::List(ref list) if !list.is_empty() => list,
+        _ => return fail(here, "expected a non-empty array of strings".to_string()),
+    };
+    let mut out = Vec::new();
+    for v in list.iter() {
+        match *v {
+            String(ref s) if !out.contains(s) => out.push(s.clone()),
+            String(ref s) => return fail(here, format!("`{}` appears twice", s)),
+            _ => return fail(here, "expected a non-empty array of strings".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+fn regex(pattern: &str, here: &str) -> SchemaResult<Regex> {
+    Regex::new(pattern).map_err(|e| SchemaError { path: here.to_string(), msg: format!("bad regular expression: {}", e) })
+}
+
+/// `bsonType`, or `type` when `json` is set, as a single name or an array.
+fn type_names(spec: &Bson, here: &str, json: bool) -> SchemaResult<Vec<&'static str>> {
+    let names = match *spec {
+        String(ref s) => vec!(s.clone()),
+        _ => try!(strings(spec, here)),
+    };
+    let mut types = Vec::new();
+    for name in names.iter() {
+        let name = name.as_slice();
+        if name == "number" {
+            types.push_all(NUMBER_TYPES);
+            continue;
+        }
+        let found = if json {
+            match name {
+                "object" => Some("object"),
+                "array" => Some("array"),
+                "boolean" => Some("bool"),
+                "string" => Some("string"),
+                "null" => Some("null"),
+                _ => None,
+            }
+        } else {
+            BSON_TYPES.iter().find(|t| **t == name).map(|t| *t)
+        };
+        match found {
+            Some(t) => types.push(t),
+            None => return fail(here, format!("unknown type `{}`", name)),
+        }
+    }
+    Ok(types)
+}
+
+fn schemas(spec: &Bson, here: &str) -> SchemaResult<Vec<Node>> {
+    let list = match *spec {
+        super::List(ref list) if !list.is_empty() => list,
+        _ => return fail(here, "expected a non-empty array of schemas".to_string()),
+    };
+    let mut nodes = Vec::new();
+    for (i, v) in list.iter().enumerate() {
+        let at = join(here, i.to_string().as_slice());
+        nodes.push(try!(compile(try!(object(v, at.as_slice())), at.as_slice())));
+    }
+    Ok(nodes)
+}
+
+fn subschema(spec: &Bson, here: &str) -> SchemaResult<Box<Node>> {
+    Ok(box try!(compile(try!(object(spec, here)), here)))
+}
+
+fn flag(schema: &Object, key: &str) -> bool {
+    match schema.find(&key.to_string()) {
+        Some(&Boolean(b)) => b,
+        _ => false,
+    }
+}
+
+fn compile(schema: &Object, path: &str) -> SchemaResult<Node> {
+    let mut rules = Vec::new();
+    for (key, spec) in schema.iter() {
+        let here = join(path, key.as_slice());
+        let here = here.as_slice();
+        let mut specified = TreeMap::new();
+        specified.insert(key.clone(), spec.clone());
+        let kind = match key.as_slice() {
+            "bsonType" => Types(try!(type_names(spec, here, false))),
+            "type" => Types(try!(type_names(spec, here, true))),
+            "required" => Required(try!(strings(spec, here))),
+            "properties" => {
+                let mut props = Vec::new();
+                for (name, sub) in try!(object(spec, here)).iter() {
+                    let at = join(here, name.as_slice());
+                    props.push((name.clone(), try!(compile(try!(object(sub, at.as_slice())), at.as_slice()))));
+                }
+                Properties(props)
+            },
+            "patternProperties" => {
+                let mut props = Vec::new();
+                for (pattern, sub) in try!(object(spec, here)).iter() {
+                    let at = join(here, pattern.as_slice());
+                    let re = try!(regex(pattern.as_slice(), at.as_slice()));
+                    props.push((pattern.clone(), re, try!(compile(try!(object(sub, at.as_slice())), at.as_slice()))));
+                }
+                PatternProperties(props)
+            },
+            "additionalProperties" => {
+                let known = match schema.find(&"properties".to_string()) {
+                    Some(&super::Object(ref props)) => props.keys().map(|k| k.clone()).collect(),
+                    _ => Vec::new(),
+                };
+                // Bad patterns are reported under patternProperties.
+                let patterns = match schema.find(&"patternProperties".to_string()) {
+                    Some(&super::Object(ref props)) => {
+                        props.keys().filter_map(|k| Regex::new(k.as_slice()).ok()).collect()
+                    },
+                    _ => Vec::new(),
+                };
+                match *spec {
+                    Boolean(true) => continue,
+                    Boolean(false) => AdditionalProperties(None, known, patterns),
+                    _ => AdditionalProperties(Some(try!(subschema(spec, here))), known, patterns),
+                }
+            },
+            "items" => match *spec {
+                super::List(_) => TupleItems(try!(schemas(spec, here))),
+                _ => Items(try!(subschema(spec, here))),
+            },
+            "additionalItems" => {
+                // Only means something after an array of items.
+                let tuple = match schema.find(&"items".to_string()) {
+                    Some(&super::List(ref items)) => items.len(),
+                    _ => continue,
+                };
+                match *spec {
+                    Boolean(true) => continue,
+                    Boolean(false) => AdditionalItems(None, tuple),
+                    _ => AdditionalItems(Some(try!(subschema(spec, here))), tuple),
+                }
+            },
+            "minimum" | "maximum" => {
+                let exclusive_key = if key.as_slice() == "minimum" { "exclusiveMinimum" } else { "exclusiveMaximum" };
+                let exclusive = flag(schema, exclusive_key);
+                if exclusive {
+                    specified.insert(exclusive_key.to_string(), Boolean(true));
+                }
+                let bound = try!(number(spec, here));
+                if key.as_slice() == "minimum" { Minimum(bound, exclusive) } else { Maximum(bound, exclusive) }
+            },
+            "exclusiveMinimum" | "exclusiveMaximum" => {
+                let base = if key.as_slice() == "exclusiveMinimum" { "minimum" } else { "maximum" };
+                match *spec {
+                    Boolean(_) => (),
+                    _ => return fail(here, "expected a boolean".to_string()),
+                }
+                if !schema.contains_key(&base.to_string()) {
+                    return fail(here, format!("needs `{}` alongside", base));
+                }
+                continue;
+            },
+            "multipleOf" => match try!(number(spec, here)) {
+                n if n > 0.0 => MultipleOf(n),
+                _ => return fail(here, "expected a positive number".to_string()),
+            },
+            "minLength" => MinLength(try!(count(spec, here))),
+            "maxLength" => MaxLength(try!(count(spec, here))),
+            "pattern" => match *spec {
+                String(ref p) => Pattern(try!(regex(p.as_slice(), here))),
+                _ => return fail(here, "expected a string".to_string()),
+            },
+            "minItems" => MinItems(try!(count(spec, here))),
+            "maxItems" => MaxItems(try!(count(spec, here))),
+            "uniqueItems" => match *spec {
+                Boolean(true) => UniqueItems,
+                Boolean(false) => continue,
+                _ => return fail(here, "expected a boolean".to_string()),
+            },
+            "minProperties" => MinProperties(try!(count(spec, here))),
+            "maxProperties" => MaxProperties(try!(count(spec, here))),
+            "enum" => match *spec {
+                super::List(ref values) if !values.is_empty() => Enum(values.clone()),
+                _ => return fail(here, "expected a non-empty array".to_string()),
+            },
+            "allOf" => AllOf(try!(schemas(spec, here))),
+            "anyOf" => AnyOf(try!(schemas(spec, here))),
+            "oneOf" => OneOf(try!(schemas(spec, here))),
+            "not" => Not(try!(subschema(spec, here))),
+            "dependencies" => {
+                let mut deps = Vec::new();
+                for (name, dep) in try!(object(spec, here)).iter() {
+                    let at = join(here, name.as_slice());
+                    let dep = match *dep {
+                        super::List(_) => PropertyDependency(try!(strings(dep, at.as_slice()))),
+                        _ => SchemaDependency(try!(compile(try!(object(dep, at.as_slice())), at.as_slice()))),
+                    };
+                    deps.push((name.clone(), dep));
+                }
+                Dependencies(deps)
+            },
+            "title" | "description" => match *spec {
+                String(_) => continue,
+                _ => return fail(here, "expected a string".to_string()),
+            },
+            _ => return fail(here, format!("unsupported keyword `{}`", key)),
+        };
+        let leaf = match kind {
+            Properties(_) | PatternProperties(_) | Items(_) | TupleItems(_) | AllOf(_) | AnyOf(_)
+                | OneOf(_) | Not(_) | Dependencies(_) => false,
+            AdditionalProperties(ref sub, _, _) | AdditionalItems(ref sub, _) => sub.is_none(),
+            _ => true,
+        };
+        rules.push(Rule {
+            keyword: key.clone(),
+            specified: if leaf { Some(specified) } else { None },
+            kind: kind,
+        });
+    }
+    Ok(Node { rules: rules })
+}
+
+fn text(s: &str) -> Bson {
+    String(s.to_string())
+}
+
+fn doc(fields: Vec<(&str, Bson)>) -> Bson {
+    let mut obj = TreeMap::new();
+    for (k, v) in fields.into_iter() {
+        obj.insert(k.to_string(), v);
+    }
+    super::Object(obj)
+}
+
+/// Every rule of `node` that `v` breaks, each as the server reports it.
+fn check(node: &Node, v: &Bson) -> Vec<Bson> {
+    node.rules.iter().filter_map(|rule| rule.check(v)).collect()
+}
+
+/// The first item from `start` on that breaks `node`, reported as `items`
+/// and `additionalItems` do.
+fn first_bad_item(node: &Node, items: &[Bson], start: uint) -> Option<(uint, Vec<Bson>)> {
+    for (i, item) in items.iter().enumerate().skip(start) {
+        let details = check(node, item);
+        if !details.is_empty() {
+            return Some((i, details));
+        }
+    }
+    None
+}
+
+/// `{ index, details }` for each of `nodes` that `v` breaks.
+fn not_satisfied(nodes: &[Node], v: &Bson) -> Vec<Bson> {
+    nodes.iter().enumerate().filter_map(|(i, node)| {
+        let details = check(node, v);
+        if details.is_empty() {
+            None
+        } else {
+            Some(doc(vec!(("index", Int32(i as i32)), ("details", super::List(details)))))
+        }
+    }).collect()
+}
+
+impl Rule {
+    fn failure(&self, extra: Vec<(&str, Bson)>) -> Option<Bson> {
+        let mut obj = TreeMap::new();
+        obj.insert("operatorName".to_string(), text(self.keyword.as_slice()));
+        match self.specified {
+            Some(ref specified) => {
+                obj.insert("specifiedAs".to_string(), super::Object(specified.clone()));
+            },
+            None => (),
+        }
+        for (k, v) in extra.into_iter() {
+            obj.insert(k.to_string(), v);
+        }
+        Some(super::Object(obj))
+    }
+
+    fn check(&self, v: &Bson) -> Option<Bson> {
+        match (&self.kind, v) {
+            (&Types(ref types), _) => {
+                if types.iter().any(|t| *t == v.type_name()) {
+                    return None;
+                }
+                self.failure(vec!(("reason", text("type did not match")), ("consideredValue", v.clone()),
+                                  ("consideredType", text(v.type_name()))))
+            },
+            (&Required(ref names), &super::Object(ref obj)) => {
+                let missing: Vec<Bson> = names.iter().filter(|n| !obj.contains_key(*n)).map(|n| text(n.as_slice())).collect();
+                if missing.is_empty() {
+                    return None;
+                }
+                self.failure(vec!(("missingProperties", super::List(missing))))
+            },
+            (&Properties(ref props), &super::Object(ref obj)) => {
+                let failed: Vec<Bson> = props.iter().filter_map(|&(ref name, ref node)| {
+                    let details = match obj.find(name) {
+                        Some(value) => check(node, value),
+                        None => return None,
+                    };
+                    if details.is_empty() {
+                        None
+                    } else {
+                        Some(doc(vec!(("propertyName", text(name.as_slice())), ("details", super::List(details)))))
+                    }
+                }).collect();
+                if failed.is_empty() {
+                    return None;
+                }
+                self.failure(vec!(("propertiesNotSatisfied", super::List(failed))))
+            },
+            (&PatternProperties(ref props), &super::Object(ref obj)) => {
+                let mut failed = Vec::new();
+                for (key, value) in obj.iter() {
+                    for &(ref pattern, ref re, ref node) in props.iter() {
+                        if !re.is_match(key.as_slice()) {
+                            continue;
+                        }
+                        let details = check(node, value);
+                        if !details.is_empty() {
+                            failed.push(doc(vec!(("propertyName", text(key.as_slice())),
+                                                 ("regexMatched", text(pattern.as_slice())),
+                                                 ("details", super::List(details)))));
+                        }
+                    }
+                }
+                if failed.is_empty() {
+                    return None;
+                }
+                self.failure(vec!(("details", super::List(failed))))
+            },
+            (&AdditionalProperties(ref sub, ref known, ref patterns), &super::Object(ref obj)) => {
+                let extra: Vec<&String> = obj.keys()
+                    .filter(|k| !known.contains(*k) && !patterns.iter().any(|re| re.is_match(k.as_slice())))
+                    .collect();
+                match *sub {
+                    None if extra.is_empty() => None,
+                    None => {
+                        let names = extra.iter().map(|k| text(k.as_slice())).collect();
+                        self.failure(vec!(("additionalProperties", super::List(names))))
+                    },
+                    Some(ref node) => {
+                        for k in extra.iter() {
+                            let details = check(&**node, obj.find(*k).unwrap());
+                            if !details.is_empty() {
+                                return self.failure(vec!(
+                                    ("reason", text("at least one additional property did not match the subschema")),
+                                    ("failingProperty", text(k.as_slice())),
+                                    ("details", super::List(details))));
+                            }
+                        }
+                        None
+                    },
+                }
+            },
+            (&Items(ref node), &super::List(ref items)) => first_bad_item(&**node, items.as_slice(), 0).and_then(|(i, details)| {
+                self.failure(vec!(("reason", text("At least one item did not match the sub-schema")),
+                                  ("itemIndex", Int32(i as i32)), ("details", super::List(details))))
+            }),
+            (&TupleItems(ref nodes), &super::List(ref items)) => {
+                for (i, (node, item)) in nodes.iter().zip(items.iter()).enumerate() {
+                    let details = check(node, item);
+                    if !details.is_empty() {
+                        return self.failure(vec!(("reason", text("At least one item did not match the sub-schema")),
+                                                 ("itemIndex", Int32(i as i32)), ("details", super::List(details))));
+                    }
+                }
+                None
+            },
+            (&AdditionalItems(ref sub, tuple), &super::List(ref items)) => match *sub {
+                _ if items.len() <= tuple => None,
+                None => self.failure(vec!(("reason", text("found additional items")),
+                                          ("additionalItems", super::List(items.slice_from(tuple).to_vec())))),
+                Some(ref node) => first_bad_item(&**node, items.as_slice(), tuple).and_then(|(i, details)| {
+                    self.failure(vec!(("reason", text("At least one additional item did not match the sub-schema")),
+                                      ("itemIndex", Int32(i as i32)), ("details", super::List(details))))
+                }),
+            },
+            (&Minimum(bound, exclusive), _) | (&Maximum(bound, exclusive), _) => {
+                let x = match number_value(v) {
+                    Some(x) => x,
+                    None => return None,
+                };
+                let ok = match self.kind {
+                    Minimum(..) => x > bound || (!exclusive && x == bound),
+                    _ => x < bound || (!exclusive && x == bound),
+                };
+                if ok {
+                    return None;
+                }
+                self.failure(vec!(("reason", text("comparison failed")), ("consideredValue", v.clone())))
+            },
+            (&MultipleOf(m), _) => match number_value(v) {
+                Some(x) if x % m != 0.0 => {
+                    self.failure(vec!(("reason", text("considered value is not a multiple of the specified value")), ("consideredValue", v.clone())))
+                },
+                _ => None,
+            },
+            (&MinLength(n), &String(ref s)) | (&MaxLength(n), &String(ref s)) => {
+                let len = s.as_slice().char_len();
+                let ok = match self.kind {
+                    MinLength(_) => len >= n,
+                    _ => len <= n,
+                };
+                if ok {
+                    return None;
+                }
+                self.failure(vec!(("reason", text("specified string length was not satisfied")), ("consideredValue", v.clone())))
+            },
+            (&Pattern(ref re), &String(ref s)) => {
+                if re.is_match(s.as_slice()) {
+                    return None;
+                }
+                self.failure(vec!(("reason", text("regular expression did not match")), ("consideredValue", v.clone())))
+            },
+            (&MinItems(n), &super::List(ref items)) | (&MaxItems(n), &super::List(ref items)) => {
+                let ok = match self.kind {
+                    MinItems(_) => items.len() >= n,
+                    _ => items.len() <= n,
+                };
+                if ok {
+                    return None;
+                }
+                self.failure(vec!(("reason", text("array did not match specified length")), ("consideredValue", v.clone())))
+            },
+            (&UniqueItems, &super::List(ref items)) => {
+                for (i, a) in items.iter().enumerate() {
+                    if items.slice_from(i + 1).iter().any(|b| equal(a, b)) {
+                        return self.failure(vec!(("reason", text("found a duplicate item")), ("consideredValue", v.clone()),
+                                                 ("duplicatedValue", a.clone())));
+                    }
+                }
+                None
+            },
+            (&MinProperties(n), &super::Object(ref obj)) | (&MaxProperties(n), &super::Object(ref obj)) => {
+                let ok = match self.kind {
+                    MinProperties(_) => obj.len() >= n,
+                    _ => obj.len() <= n,
+                };
+                if ok {
+                    return None;
+                }
+                self.failure(vec!(("reason", text("specified number of properties was not satisfied")),
+                                  ("numberOfProperties", Int32(obj.len() as i32))))
+            },
+            (&Enum(ref values), _) => {
+                if values.iter().any(|e| equal(e, v)) {
+                    return None;
+                }
+                self.failure(vec!(("reason", text("value was not found in enum")), ("consideredValue", v.clone())))
+            },
+            (&AllOf(ref nodes), _) => {
+                let failed = not_satisfied(nodes.as_slice(), v);
+                if failed.is_empty() {
+                    return None;
+                }
+                self.failure(vec!(("schemasNotSatisfied", super::List(failed))))
+            },
+            (&AnyOf(ref nodes), _) => {
+                let failed = not_satisfied(nodes.as_slice(), v);
+                if failed.len() < nodes.len() {
+                    return None;
+                }
+                self.failure(vec!(("schemasNotSatisfied", super::List(failed))))
+            },
+            (&OneOf(ref nodes), _) => {
+                let failed = not_satisfied(nodes.as_slice(), v);
+                match nodes.len() - failed.len() {
+                    1 => None,
+                    0 => self.failure(vec!(("schemasNotSatisfied", super::List(failed)))),
+                    _ => {
+                        let matching = nodes.iter().enumerate()
+                            .filter(|&(_, node)| check(node, v).is_empty())
+                            .map(|(i, _)| Int32(i as i32))
+                            .collect();
+                        self.failure(vec!(("reason", text("more than one subschema matched")),
+                                          ("matchingSchemaIndexes", super::List(matching))))
+                    },
+                }
+            },
+            (&Not(ref node), _) => {
+                if !check(&**node, v).is_empty() {
+                    return None;
+                }
+                self.failure(vec!(("reason", text("child expression matched"))))
+            },
+            (&Dependencies(ref deps), &super::Object(ref obj)) => {
+                let mut failed = Vec::new();
+                for &(ref name, ref dep) in deps.iter() {
+                    if !obj.contains_key(name) {
+                        continue;
+                    }
+                    match *dep {
+                        PropertyDependency(ref names) => {
+                            let missing: Vec<Bson> = names.iter().filter(|n| !obj.contains_key(*n)).map(|n| text(n.as_slice())).collect();
+                            if !missing.is_empty() {
+                                failed.push(doc(vec!(("conditionalProperty", text(name.as_slice())),
+                                                     ("missingProperties", super::List(missing)))));
+                            }
+                        },
+                        SchemaDependency(ref node) => {
+                            let details = check(node, v);
+                            if !details.is_empty() {
+                                failed.push(doc(vec!(("conditionalProperty", text(name.as_slice())),
+                                                     ("details", super::List(details)))));
+                            }
+                        },
+                    }
+                }
+                if failed.is_empty() {
+                    return None;
+                }
+                self.failure(vec!(("failingDependencies", super::List(failed))))
+            },
+            // Keywords for one type don't constrain values of another.
+            _ => None,
+        }
+    }
+}
+
+/// A compiled `$jsonSchema`, checking documents the way a collection
+/// validator would.
+pub struct Validator {
+    root: Node,
+}
+
+impl Validator {
+    /// Compiles a schema, given either as the `$jsonSchema` document itself
+    /// or as a validator `{ $jsonSchema: ... }` holding one.
+    pub fn new(schema: &Bson) -> SchemaResult<Validator> {
+        let obj = try!(object(schema, "$jsonSchema"));
+        let key = "$jsonSchema".to_string();
+        let root = match obj.find(&key) {
+            Some(inner) if obj.len() == 1 => try!(compile(try!(object(inner, "$jsonSchema")), "")),
+            _ => try!(compile(obj, "")),
+        };
+        Ok(Validator { root: root })
+    }
+
+    pub fn is_valid(&self, document: &Bson) -> bool {
+        check(&self.root, document).is_empty()
+    }
+
+    /// Checks `doc`, describing what's wrong with it the way the server's
+    /// `errInfo` does: `{ failingDocumentId, details: { operatorName:
+    /// "$jsonSchema", schemaRulesNotSatisfied: [...] } }`, each rule with
+    /// its `operatorName` and, nested under the keyword that led there,
+    /// the properties, items or subschemas that failed.
+    pub fn validate(&self, document: &Bson) -> Result<(), Object> {
+        let failures = check(&self.root, document);
+        if failures.is_empty() {
+            return Ok(());
+        }
+        let mut info = TreeMap::new();
+        match *document {
+            super::Object(ref obj) => match obj.find(&"_id".to_string()) {
+                Some(id) => {
+                    info.insert("failingDocumentId".to_string(), id.clone());
+                },
+                None => (),
+            },
+            _ => (),
+        }
+        info.insert("details".to_string(), doc(vec!(("operatorName", text("$jsonSchema")),
+                                                    ("schemaRulesNotSatisfied", super::List(failures)))));
+        Err(info)
+    }
+}
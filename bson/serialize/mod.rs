@@ -1,6 +1,29 @@
 
 use std::collections::TreeMap;
 
+/// How an enum is laid out when it is encoded, and expected to be laid out
+/// when it is decoded. Unit variants are encoded as the variant name (or a
+/// tag-only document), tuple variants carry their arguments as an array, and
+/// struct variants carry their fields as a document.
+#[deriving(Clone, PartialEq, Show)]
+pub enum EnumRepr {
+    /// `{ "Variant": <content> }`, with unit variants as plain `"Variant"`.
+    ExternallyTagged,
+    /// `{ <tag>: "Variant", <fields>... }`. Only unit and struct variants
+    /// can be represented this way.
+    InternallyTagged(&'static str),
+    /// `{ <tag>: "Variant", <content key>: <content> }`.
+    AdjacentlyTagged(&'static str, &'static str),
+    /// Just `<content>`; unit variants are null. Decoding tries each variant
+    /// in order and takes the first one that fits.
+    Untagged,
+    /// Plain `"Variant"`. Only enums without variant data can use this.
+    UnitAsString,
+}
+
+/// The tag field used by `InternallyTagged` enums unless told otherwise.
+pub static DEFAULT_TAG: &'static str = "type";
+
 pub trait Encoder<E> {
     fn build_nil(&mut self) -> Result< Vec<u8> , E>;
     fn build_bool(&mut self, v: bool) -> Result< Vec<u8>, E>;
@@ -13,6 +36,12 @@ pub trait Encoder<E> {
     fn build_map(&mut self, len: uint, f: |&mut Self| -> Result< Vec<u8>, E>) -> Result< Vec<u8>, E>;
     fn build_map_item(&mut self, idx: uint, key: |&mut Self| -> Result< Vec<u8>, E>, val: |&mut Self| -> Result< Vec<u8>, E>) -> Result<Vec<u8>, E>;
 
+    fn build_enum(&mut self, name: &str, repr: EnumRepr, f: |&mut Self| -> Result<Vec<u8>,E>) -> Result<Vec<u8>,E>;
+    fn build_enum_variant(&mut self, v_name: &str, v_id: uint, len: uint, f: |&mut Self| -> Result<Vec<u8>,E>) -> Result<Vec<u8>,E>;
+    fn build_enum_variant_arg(&mut self, a_idx: uint, f: |&mut Self| -> Result<Vec<u8>,E>) -> Result<Vec<u8>,E>;
+    fn build_enum_struct_variant(&mut self, v_name: &str, v_id: uint, len: uint, f: |&mut Self| -> Result<Vec<u8>,E>) -> Result<Vec<u8>,E>;
+    fn build_enum_struct_variant_field(&mut self, f_name: &str, f_idx: uint, f: |&mut Self| -> Result<Vec<u8>,E>) -> Result<Vec<u8>,E>;
+
     fn build_custom(&mut self, f: |&mut Self| -> Result< Vec<u8>, E>) -> Result<Vec<u8>, E> {
         f(self)
     }
@@ -72,11 +101,43 @@ impl<E, S:Encoder<E>, K:Encodable<S,E>+PartialEq+Ord, V:Encodable<S,E>+PartialEq
 }
 
 pub trait Decoder<E> {
+    fn read_nil(&mut self) -> Result<(), E>;
     fn read_bool(&mut self) -> Result< bool, E>;
     fn read_f64(&mut self) -> Result< f64, E>;
+    fn read_f32(&mut self) -> Result<f32, E>;
     fn read_str(&mut self) -> Result<String, E>;
+    fn read_char(&mut self) -> Result<char, E>;
+
+    fn read_i8(&mut self) -> Result<i8, E>;
+    fn read_i16(&mut self) -> Result<i16, E>;
+    fn read_i32(&mut self) -> Result<i32, E>;
+    fn read_i64(&mut self) -> Result<i64, E>;
+    fn read_int(&mut self) -> Result<int, E>;
+    fn read_u8(&mut self) -> Result<u8, E>;
+    fn read_u16(&mut self) -> Result<u16, E>;
+    fn read_u32(&mut self) -> Result<u32, E>;
+    fn read_u64(&mut self) -> Result<u64, E>;
+    fn read_uint(&mut self) -> Result<uint, E>;
+
+    fn read_option<T>(&mut self, f: |&mut Self, bool| -> Result<T, E>) -> Result<T, E>;
+
+    fn read_seq<T>(&mut self, f: |&mut Self, uint| -> Result<T, E>) -> Result<T, E>;
+    fn read_seq_elt<T>(&mut self, idx: uint, f: |&mut Self| -> Result<T, E>) -> Result<T, E>;
+
+    fn read_struct<T>(&mut self, s_name: &str, len: uint, f: |&mut Self| -> Result<T, E>) -> Result<T, E>;
+    fn read_struct_field<T>(&mut self, f_name: &str, f_idx: uint, f: |&mut Self| -> Result<T, E>) -> Result<T, E>;
+
     fn read_map<T>(&mut self, f: |&mut Self, uint| -> Result<T, E>) -> Result<T, E>;
     fn read_map_item<T1,T2>(&mut self, idx: uint, key: |&mut Self| -> Result<T1, E>, val: |&mut Self| -> Result<T2, E>) -> Result<(T1,T2), E>;
+
+    fn read_enum<T>(&mut self, name: &str, repr: EnumRepr, f: |&mut Self| -> Result<T, E>) -> Result<T, E>;
+    fn read_enum_variant<T>(&mut self, names: &[&str], f: |&mut Self, uint| -> Result<T, E>) -> Result<T, E>;
+    fn read_enum_variant_arg<T>(&mut self, a_idx: uint, f: |&mut Self| -> Result<T, E>) -> Result<T, E>;
+    fn read_enum_struct_variant<T>(&mut self, names: &[&str], f: |&mut Self, uint| -> Result<T, E>) -> Result<T, E>;
+    fn read_enum_struct_variant_field<T>(&mut self, f_name: &str, f_idx: uint, f: |&mut Self| -> Result<T, E>) -> Result<T, E>;
+
+    /// Builds an error for a `Decodable` that found something it can't use.
+    fn error(&mut self, msg: &str) -> E;
 }
 
 pub trait Decodable<D: Decoder<E>, E> {
@@ -95,12 +156,114 @@ impl<E, D: Decoder<E>> Decodable<D, E> for f64 {
     }
 }
 
+impl<E, D: Decoder<E>> Decodable<D, E> for i8 {
+    fn decode(d: &mut D) -> Result<i8, E> {
+        d.read_i8()
+    }
+}
+
+impl<E, D: Decoder<E>> Decodable<D, E> for i16 {
+    fn decode(d: &mut D) -> Result<i16, E> {
+        d.read_i16()
+    }
+}
+
+impl<E, D: Decoder<E>> Decodable<D, E> for i32 {
+    fn decode(d: &mut D) -> Result<i32, E> {
+        d.read_i32()
+    }
+}
+
+impl<E, D: Decoder<E>> Decodable<D, E> for i64 {
+    fn decode(d: &mut D) -> Result<i64, E> {
+        d.read_i64()
+    }
+}
+
+impl<E, D: Decoder<E>> Decodable<D, E> for int {
+    fn decode(d: &mut D) -> Result<int, E> {
+        d.read_int()
+    }
+}
+
+impl<E, D: Decoder<E>> Decodable<D, E> for u8 {
+    fn decode(d: &mut D) -> Result<u8, E> {
+        d.read_u8()
+    }
+}
+
+impl<E, D: Decoder<E>> Decodable<D, E> for u16 {
+    fn decode(d: &mut D) -> Result<u16, E> {
+        d.read_u16()
+    }
+}
+
+impl<E, D: Decoder<E>> Decodable<D, E> for u32 {
+    fn decode(d: &mut D) -> Result<u32, E> {
+        d.read_u32()
+    }
+}
+
+impl<E, D: Decoder<E>> Decodable<D, E> for u64 {
+    fn decode(d: &mut D) -> Result<u64, E> {
+        d.read_u64()
+    }
+}
+
+impl<E, D: Decoder<E>> Decodable<D, E> for uint {
+    fn decode(d: &mut D) -> Result<uint, E> {
+        d.read_uint()
+    }
+}
+
+impl<E, D: Decoder<E>> Decodable<D, E> for f32 {
+    fn decode(d: &mut D) -> Result<f32, E> {
+        d.read_f32()
+    }
+}
+
+impl<E, D: Decoder<E>> Decodable<D, E> for char {
+    fn decode(d: &mut D) -> Result<char, E> {
+        d.read_char()
+    }
+}
+
+impl<E, D: Decoder<E>> Decodable<D, E> for () {
+    fn decode(d: &mut D) -> Result<(), E> {
+        d.read_nil()
+    }
+}
+
 impl<E, D: Decoder<E>> Decodable<D, E> for String {
     fn decode(d: &mut D) -> Result<String, E> {
         d.read_str()
     }
 }
 
+impl<E, D: Decoder<E>, T: Decodable<D, E>> Decodable<D, E> for Option<T> {
+    fn decode(d: &mut D) -> Result<Option<T>, E> {
+        d.read_option(|d, present| {
+            if present {
+                Ok(Some(try!(Decodable::decode(d))))
+            } else {
+                Ok(None)
+            }
+        })
+    }
+}
+
+impl<E, D: Decoder<E>, T: Decodable<D, E>> Decodable<D, E> for Vec<T> {
+    fn decode(d: &mut D) -> Result<Vec<T>, E> {
+        d.read_seq(|d, len| {
+            let mut v = Vec::with_capacity(len);
+            for i in range(0u, len) {
+                v.push(try!(d.read_seq_elt(i, |d| Decodable::decode(d))));
+            }
+            Ok(v)
+        })
+    }
+}
+
 impl<E, D:Decoder<E>, K:Decodable<D,E>+PartialEq+Ord, V:Decodable<D,E>+PartialEq> Decodable<D,E> for TreeMap<K,V> {
     fn decode(d: &mut D) -> Result<TreeMap<K,V>, E> {
         d.read_map(|d, len| {
@@ -0,0 +1,582 @@
+use std::{fmt,char,f64,i32};
+use std::collections::TreeMap;
+use std::num::Float;
+
+use super::{Bson,Object,ObjectId,String,Boolean,List,Null,Binary,Undefined,UtcDatetime,RegExp};
+use super::{DbPointer,JavaScript,Symbol,JavaScriptWithScope,Int32,Timestamp,Int64,Decimal128};
+use super::{MinKey,MaxKey,RawString};
+use super::{decimal,datetime,base64,to_hex,from_hex,format_double,MAX_DEPTH};
+use super::extjson::quote;
+
+/// How `to_shell` lays out documents.
+#[deriving(PartialEq, Clone, Show)]
+pub struct ShellOptions {
+    /// Spaces per level of nesting.
+    pub indent: uint,
+    /// Documents and arrays that fit in this many columns stay on one line.
+    pub width: uint,
+}
+impl ShellOptions {
+    pub fn new() -> ShellOptions {
+        ShellOptions { indent: 2, width: 80 }
+    }
+}
+
+/// Prints a value the way the mongo shell would, as something that can be
+/// pasted back into it.
+pub fn to_shell(v: &Bson, options: &ShellOptions) -> String {
+    render(v, options, 0, 0)
+}
+
+// Integers past this can't be written as a plain shell number.
+static SAFE_INTEGER: i64 = 1 << 53;
+
+fn key(k: &str) -> String {
+    let bare = !k.is_empty() && k.chars().enumerate().all(|(i, c)| {
+        c == '_' || c == '$' || (c.is_alphabetic() && (c as u32) < 0x80) || (i > 0 && c >= '0' && c <= '9')
+    });
+    if bare { k.to_string() } else { quote(k) }
+}
+
+/// A `/pattern/flags` literal where `parse_regex` would read the same
+/// pattern back, and `RegExp("pattern", "flags")` where it wouldn't: an
+/// empty pattern or one starting with `*` (which would open a comment), an
+/// escaped slash (which the literal can't tell from a plain one), a
+/// trailing backslash, a newline or a class left open.
+fn regex(pattern: &str, options: &str) -> String {
+    let mut literal = String::new();
+    let mut in_class = false;
+    let mut chars = pattern.chars();
+    let mut fits = !pattern.is_empty() && !pattern.starts_with("*") && options.chars().all(|c| is_ident(c));
+    while fits {
+        match chars.next() {
+            None => break,
+            Some('\\') => match chars.next() {
+                Some(c) if c != '/' && c != '\n' => {
+                    literal.push('\\');
+                    literal.push(c);
+                },
+                _ => fits = false,
+            },
+            Some('\n') => fits = false,
+            Some('/') => literal.push_str("\\/"),
+            Some(c) => {
+                if c == '[' {
+                    in_class = true;
+                } else if c == ']' {
+                    in_class = false;
+                }
+                literal.push(c);
+            },
+        }
+    }
+    if fits && !in_class {
+        format!("/{}/{}", literal, options)
+    } else {
+        format!("RegExp({}, {})", quote(pattern), quote(options))
+    }
+}
+
+fn double(v: f64) -> String {
+    if v.is_nan() {
+        "NaN".to_string()
+    } else if v.is_infinite() {
+        if v > 0f64 { "Infinity".to_string() } else { "-Infinity".to_string() }
+    } else {
+        format_double(v)
+    }
+}
+
+/// Everything on one line.
+fn compact(v: &Bson) -> String {
+    match *v {
+        ObjectId(ref id) => format!("ObjectId({})", quote(to_hex(id.as_slice()).as_slice())),
+        super::Float(f) => double(f),
+        String(ref s) => quote(s.as_slice()),
+        Boolean(b) => b.to_string(),
+        List(ref items) if items.is_empty() => "[]".to_string(),
+        List(ref items) => {
+            let items: Vec<String> = items.iter().map(|item| compact(item)).collect();
+            format!("[ {} ]", items.connect(", "))
+        },
+        Object(ref obj) => compact_document(obj),
+        Null => "null".to_string(),
+        Binary(subtype, ref data) => format!("BinData({}, {})", subtype, quote(base64::encode(data.as_slice()).as_slice())),
+        Undefined => "undefined".to_string(),
+        UtcDatetime(ms) if ms >= 0 && ms <= datetime::MAX_ISO_DATE => {
+            format!("ISODate({})", quote(datetime::to_iso8601(ms).as_slice()))
+        },
+        UtcDatetime(ms) => format!("new Date({})", ms),
+        RegExp(ref pattern, ref options) => regex(pattern.as_slice(), options.as_slice()),
+        DbPointer(ref ns, ref id) => {
+            format!("DBPointer({}, ObjectId({}))", quote(ns.as_slice()), quote(to_hex(id.as_slice()).as_slice()))
+        },
+        JavaScript(ref code) => format!("Code({})", quote(code.as_slice())),
+        Symbol(ref s) => format!("Symbol({})", quote(s.as_slice())),
+        JavaScriptWithScope(ref code, ref scope) => {
+            format!("Code({}, {})", quote(code.as_slice()), compact_document(scope))
+        },
+        Int32(i) => i.to_string(),
+        Timestamp(time, increment) => format!("Timestamp({}, {})", time, increment),
+        Int64(i) if i > -SAFE_INTEGER && i < SAFE_INTEGER => format!("NumberLong({})", i),
+        Int64(i) => format!("NumberLong(\"{}\")", i),
+        Decimal128(ref bytes) => format!("NumberDecimal({})", quote(decimal::to_string(bytes.as_slice()).as_slice())),
+        MinKey => "MinKey".to_string(),
+        MaxKey => "MaxKey".to_string(),
+        RawString(ref bytes) => quote(String::from_utf8_lossy(bytes.as_slice()).as_slice()),
+    }
+}
+
+fn compact_document(obj: &Object) -> String {
+    if obj.is_empty() {
+        return "{}".to_string();
+    }
+    let fields: Vec<String> = obj.iter().map(|(k, v)| format!("{}: {}", key(k.as_slice()), compact(v))).collect();
+    format!("{{ {} }}", fields.connect(", "))
+}
+
+/// Lays out `v` at nesting `depth`, with `used` columns of its first line
+/// already taken by the key in front of it.
+fn render(v: &Bson, options: &ShellOptions, depth: uint, used: uint) -> String {
+    let flat = compact(v);
+    if depth * options.indent + used + flat.as_slice().char_len() <= options.width {
+        return flat;
+    }
+    let inner = " ".repeat((depth + 1) * options.indent);
+    let outer = " ".repeat(depth * options.indent);
+    match *v {
+        Object(ref obj) if !obj.is_empty() => {
+            let fields: Vec<String> = obj.iter().map(|(k, v)| {
+                let k = key(k.as_slice());
+                // The key, the colon and its space, and a trailing comma.
+                let used = k.as_slice().char_len() + 3;
+                format!("{}{}: {}", inner, k, render(v, options, depth + 1, used))
+            }).collect();
+            format!("{{\n{}\n{}}}", fields.connect(",\n"), outer)
+        },
+        List(ref items) if !items.is_empty() => {
+            let items: Vec<String> = items.iter().map(|item| {
+                format!("{}{}", inner, render(item, options, depth + 1, 1))
+            }).collect();
+            format!("[\n{}\n{}]", items.connect(",\n"), outer)
+        },
+        _ => flat,
+    }
+}
+
+/// Where shell input went wrong. Lines and columns count from one.
+#[deriving(PartialEq, Clone)]
+pub struct ShellError {
+    pub line: uint,
+    pub column: uint,
+    pub msg: String,
+}
+
+impl fmt::Show for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.msg, self.line, self.column)
+    }
+}
+
+pub type ShellResult<T> = Result<T, ShellError>;
+
+/// Parses a shell literal: JSON plus unquoted keys, single-quoted strings,
+/// `/regex/flags` and the helpers `to_shell` writes, such as `ObjectId()`,
+/// `ISODate()`, `NumberLong()`, `NumberInt()`, `NumberDecimal()`,
+/// `BinData()` and `Timestamp()`.
+pub fn from_shell(s: &str) -> ShellResult<Bson> {
+    let mut parser = ShellParser { chars: s.chars().collect(), pos: 0, line: 1, column: 1, depth: 0 };
+    let v = try!(parser.parse_value());
+    parser.skip_whitespace();
+    if parser.peek().is_some() {
+        return parser.error("trailing characters");
+    }
+    Ok(v)
+}
+
+struct ShellParser {
+    chars: Vec<char>,
+    pos: uint,
+    line: uint,
+    column: uint,
+    depth: uint,
+}
+
+fn is_ident_start(c: char) -> bool {
+    c == '_' || c == '$' || c.is_alphabetic()
+}
+
+fn is_ident(c: char) -> bool {
+    is_ident_start(c) || (c >= '0' && c <= '9')
+}
+
+impl ShellParser {
+    fn error<T>(&self, msg: &str) -> ShellResult<T> {
+        Err(ShellError { line: self.line, column: self.column, msg: msg.to_string() })
+    }
+
+    fn error_at<T>(&self, (line, column): (uint, uint), msg: String) -> ShellResult<T> {
+        Err(ShellError { line: line, column: column, msg: msg })
+    }
+
+    fn peek(&self) -> Option<char> {
+        if self.pos < self.chars.len() { Some(self.chars[self.pos]) } else { None }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        match c {
+            Some('\n') => {
+                self.line += 1;
+                self.column = 1;
+            },
+            Some(_) => self.column += 1,
+            None => (),
+        }
+        self.pos += 1;
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map_or(false, |c| c.is_whitespace()) {
+            self.bump();
+        }
+    }
+
+    /// Skips whitespace and takes `c` if it comes next.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> ShellResult<()> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            self.error(format!("expected `{}`", c).as_slice())
+        }
+    }
+
+    fn parse_value(&mut self) -> ShellResult<Bson> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => Ok(Object(try!(self.parse_object()))),
+            Some('[') => self.parse_array(),
+            Some('"') | Some('\'') => Ok(String(try!(self.parse_string()))),
+            Some('/') => self.parse_regex(),
+            Some(c) if c == '-' || c == '+' || c == '.' || (c >= '0' && c <= '9') => self.parse_number(),
+            Some(c) if is_ident_start(c) => self.parse_call(),
+            Some(_) => self.error("expected a value"),
+            None => self.error("unexpected end of input"),
+        }
+    }
+
+    fn enter(&mut self) -> ShellResult<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return self.error("nested too deeply");
+        }
+        self.bump();
+        Ok(())
+    }
+
+    /// Comma separated items up to `close`, which may have a trailing comma.
+    fn parse_list(&mut self, close: char, item: |&mut ShellParser| -> ShellResult<()>) -> ShellResult<()> {
+        loop {
+            if self.eat(close) {
+                return Ok(());
+            }
+            try!(item(self));
+            if !self.eat(',') {
+                return self.expect(close);
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> ShellResult<Bson> {
+        try!(self.enter());
+        let mut items = Vec::new();
+        try!(self.parse_list(']', |p| {
+            items.push(try!(p.parse_value()));
+            Ok(())
+        }));
+        self.depth -= 1;
+        Ok(List(items))
+    }
+
+    fn parse_object(&mut self) -> ShellResult<Object> {
+        try!(self.enter());
+        let mut obj = TreeMap::new();
+        try!(self.parse_list('}', |p| {
+            p.skip_whitespace();
+            let at = (p.line, p.column);
+            let k = match p.peek() {
+                Some('"') | Some('\'') => try!(p.parse_string()),
+                Some(c) if is_ident(c) => p.parse_ident(),
+                _ => return p.error("expected a key"),
+            };
+            try!(p.expect(':'));
+            let v = try!(p.parse_value());
+            if obj.contains_key(&k) {
+                return p.error_at(at, format!("duplicate key `{}`", k));
+            }
+            obj.insert(k, v);
+            Ok(())
+        }));
+        self.depth -= 1;
+        Ok(obj)
+    }
+
+    fn parse_ident(&mut self) -> String {
+        let mut s = String::new();
+        while self.peek().map_or(false, |c| is_ident(c)) {
+            s.push(self.bump().unwrap());
+        }
+        s
+    }
+
+    fn parse_hex4(&mut self) -> ShellResult<u32> {
+        let mut n = 0u32;
+        for _ in range(0u, 4) {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(d) => n = n * 16 + d as u32,
+                None => return self.error("expected four hex digits"),
+            }
+            self.bump();
+        }
+        Ok(n)
+    }
+
+    /// A single- or double-quoted string with JavaScript escapes.
+    fn parse_string(&mut self) -> ShellResult<String> {
+        let quote = self.bump().unwrap();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => return self.error("unterminated string"),
+                Some(c) if c == quote => return Ok(s),
+                Some('\\') => {
+                    let c = match self.bump() {
+                        Some('b') => '\x08',
+                        Some('f') => '\x0c',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('v') => '\x0b',
+                        Some('0') => '\0',
+                        Some('u') => {
+                            let mut n = try!(self.parse_hex4());
+                            if n >= 0xD800 && n < 0xDC00 && self.peek() == Some('\\') {
+                                self.bump();
+                                if self.bump() != Some('u') {
+                                    return self.error("expected the second half of a surrogate pair");
+                                }
+                                let low = try!(self.parse_hex4());
+                                if low < 0xDC00 || low >= 0xE000 {
+                                    return self.error("expected the second half of a surrogate pair");
+                                }
+                                n = 0x10000 + ((n - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            match char::from_u32(n) {
+                                Some(c) => c,
+                                None => return self.error("invalid unicode escape"),
+                            }
+                        },
+                        // Anything else, quotes included, stands for itself.
+                        Some(c) => c,
+                        None => return self.error("unterminated string"),
+                    };
+                    s.push(c);
+                },
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    /// `/pattern/flags`. Escapes are kept as they are, other than `\/`.
+    fn parse_regex(&mut self) -> ShellResult<Bson> {
+        self.bump();
+        let mut pattern = String::new();
+        let mut in_class = false;
+        loop {
+            match self.bump() {
+                None | Some('\n') => return self.error("unterminated regular expression"),
+                Some('/') if !in_class => break,
+                Some('\\') => match self.bump() {
+                    Some('/') => pattern.push('/'),
+                    Some(c) => {
+                        pattern.push('\\');
+                        pattern.push(c);
+                    },
+                    None => return self.error("unterminated regular expression"),
+                },
+                Some(c) => {
+                    if c == '[' {
+                        in_class = true;
+                    } else if c == ']' {
+                        in_class = false;
+                    }
+                    pattern.push(c);
+                },
+            }
+        }
+        let options = self.parse_ident();
+        Ok(RegExp(pattern, sort_options(options.as_slice())))
+    }
+
+    fn parse_number(&mut self) -> ShellResult<Bson> {
+        let at = (self.line, self.column);
+        let start = self.pos;
+        if self.peek() == Some('-') || self.peek() == Some('+') {
+            self.bump();
+            if self.peek().map_or(false, |c| is_ident_start(c)) {
+                let sign = if self.chars[start] == '-' { -1f64 } else { 1f64 };
+                return match self.parse_ident().as_slice() {
+                    "Infinity" => Ok(super::Float(sign * f64::INFINITY)),
+                    "NaN" => Ok(super::Float(f64::NAN)),
+                    other => self.error_at(at, format!("expected a number, found `{}`", other)),
+                };
+            }
+        }
+        while self.peek().map_or(false, |c| (c >= '0' && c <= '9') || c == '.' || c == 'e' || c == 'E') {
+            let c = self.bump().unwrap();
+            if (c == 'e' || c == 'E') && (self.peek() == Some('-') || self.peek() == Some('+')) {
+                self.bump();
+            }
+        }
+        let text = String::from_chars(self.chars.slice(start, self.pos));
+        let text = text.as_slice().trim_left_chars('+');
+        if !text.contains_char('.') && !text.contains_char('e') && !text.contains_char('E') {
+            match from_str::<i64>(text) {
+                Some(v) if v >= i32::MIN as i64 && v <= i32::MAX as i64 => return Ok(Int32(v as i32)),
+                _ => (),
+            }
+        }
+        match from_str::<f64>(text.replace("e+", "e").replace("E+", "E").as_slice()) {
+            Some(f) => Ok(super::Float(f)),
+            None => self.error_at(at, format!("invalid number `{}`", text)),
+        }
+    }
+
+    fn parse_args(&mut self) -> ShellResult<Vec<Bson>> {
+        let mut args = Vec::new();
+        if self.eat('(') {
+            try!(self.parse_list(')', |p| {
+                args.push(try!(p.parse_value()));
+                Ok(())
+            }));
+        }
+        Ok(args)
+    }
+
+    /// A bare word: a literal like `true`, or one of the shell's helpers with
+    /// its arguments.
+    fn parse_call(&mut self) -> ShellResult<Bson> {
+        let at = (self.line, self.column);
+        let mut name = self.parse_ident();
+        if name.as_slice() == "new" {
+            self.skip_whitespace();
+            name = self.parse_ident();
+        }
+        match name.as_slice() {
+            "true" => return Ok(Boolean(true)),
+            "false" => return Ok(Boolean(false)),
+            "null" => return Ok(Null),
+            "undefined" => return Ok(Undefined),
+            "NaN" => return Ok(super::Float(f64::NAN)),
+            "Infinity" => return Ok(super::Float(f64::INFINITY)),
+            _ => (),
+        }
+        let args = try!(self.parse_args());
+        let (line, column) = at;
+        let bad = |what: &str| -> ShellResult<Bson> {
+            Err(ShellError { line: line, column: column, msg: format!("`{}` expects {}", name, what) })
+        };
+        let v = match (name.as_slice(), args.as_slice()) {
+            ("MinKey", []) => MinKey,
+            ("MaxKey", []) => MaxKey,
+            ("ObjectId", [String(ref hex)]) => match from_hex(hex.as_slice()) {
+                Some(ref id) if id.len() == 12 => ObjectId(id.clone()),
+                _ => return bad("24 hex digits"),
+            },
+            ("ISODate", [String(ref s)]) | ("Date", [String(ref s)]) => match iso_date(s.as_slice()) {
+                Some(ms) => UtcDatetime(ms),
+                None => return bad("an ISO-8601 date"),
+            },
+            ("Date", [ref n]) => match integer(n) {
+                Some(ms) => UtcDatetime(ms),
+                None => return bad("milliseconds since the epoch"),
+            },
+            ("NumberInt", [ref n]) => match integer(n) {
+                Some(i) if i >= i32::MIN as i64 && i <= i32::MAX as i64 => Int32(i as i32),
+                _ => return bad("a 32-bit integer"),
+            },
+            ("NumberLong", [ref n]) => match integer(n) {
+                Some(i) => Int64(i),
+                None => return bad("a 64-bit integer"),
+            },
+            ("NumberDecimal", [ref n]) => match decimal_arg(n) {
+                Some(bytes) => Decimal128(bytes),
+                None => return bad("a decimal string"),
+            },
+            ("BinData", [ref subtype, String(ref data)]) => match (integer(subtype), base64::decode(data.as_slice())) {
+                (Some(t), Some(bytes)) if t >= 0 && t <= 0xFF => Binary(t as u8, bytes),
+                _ => return bad("a subtype and base64 data"),
+            },
+            ("Timestamp", [ref t, ref i]) => match (integer(t), integer(i)) {
+                (Some(t), Some(i)) if t >= 0 && i >= 0 && t <= 0xFFFFFFFF && i <= 0xFFFFFFFF => Timestamp(t as u32, i as u32),
+                _ => return bad("two 32-bit unsigned integers"),
+            },
+            ("Code", [String(ref code)]) => JavaScript(code.clone()),
+            ("Code", [String(ref code), Object(ref scope)]) => JavaScriptWithScope(code.clone(), scope.clone()),
+            ("RegExp", [String(ref pattern)]) => RegExp(pattern.clone(), String::new()),
+            ("RegExp", [String(ref pattern), String(ref options)]) => {
+                RegExp(pattern.clone(), sort_options(options.as_slice()))
+            },
+            ("Symbol", [String(ref s)]) => Symbol(s.clone()),
+            ("DBPointer", [String(ref ns), ObjectId(ref id)]) => DbPointer(ns.clone(), id.clone()),
+            _ => return self.error_at(at, format!("unknown shell value `{}` with {} arguments", name, args.len())),
+        };
+        Ok(v)
+    }
+}
+
+/// Regex options in alphabetical order, as the decoder and `from_extjson`
+/// give them, so a regex compares the same wherever it was read from.
+fn sort_options(options: &str) -> String {
+    let mut options: Vec<char> = options.chars().collect();
+    options.sort();
+    options.into_iter().collect()
+}
+
+fn iso_date(s: &str) -> Option<i64> {
+    // The shell also takes a bare date for midnight UTC.
+    if s.len() == 10 {
+        datetime::from_iso8601(format!("{}T00:00:00Z", s).as_slice())
+    } else {
+        datetime::from_iso8601(s)
+    }
+}
+
+/// A whole number given as a number or as a string of digits.
+fn integer(v: &Bson) -> Option<i64> {
+    match *v {
+        Int32(i) => Some(i as i64),
+        super::Float(f) if f == f.trunc() && f.abs() < SAFE_INTEGER as f64 => Some(f as i64),
+        String(ref s) => from_str::<i64>(s.as_slice()),
+        _ => None,
+    }
+}
+
+fn decimal_arg(v: &Bson) -> Option<Vec<u8>> {
+    match *v {
+        String(ref s) => decimal::parse(s.as_slice()),
+        Int32(i) => decimal::parse(i.to_string().as_slice()),
+        _ => None,
+    }
+}
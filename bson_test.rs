@@ -1,11 +1,31 @@
 
-use std::io::File;
+#![feature(struct_variant, phase)]
+
+extern crate serialize;
+extern crate regex;
+extern crate serde;
+#[phase(plugin)]
+extern crate serde_macros;
+extern crate test;
+
+use std::os;
+use std::io::{File,IoError,MemReader,MemWriter};
+use std::io::process::{Command,ProcessOutput,ExitStatus};
+use std::io::fs;
+use std::io::fs::PathExtensions;
+use std::rand::{Rng,SeedableRng,XorShiftRng};
 use std::collections::TreeMap;
 
 use bson::Bson;
-use bson::serialize::Decodable;
+use bson::serialize::{Decodable,Encodable,Decoder,Encoder};
+use bson::serialize::{EnumRepr,ExternallyTagged,InternallyTagged,UnitAsString};
+
+use serialize::json;
+use test::Bencher;
 
 mod bson;
+#[path = "fuzz/targets.rs"]
+mod fuzz_targets;
 
 fn show_bson(v: &Vec<u8>) {
     print!("[");
@@ -34,16 +54,12 @@ fn serialize_empty() {
     show_bson(&serialized);
     assert_eq!(serialized, vec!(0x05,0x00,0x00,0x00,0x00));
 }
-/*
 #[test]
 fn deserialize_empty() {
-    /*
     let empty: TreeMap<String, bson::Bson> = TreeMap::new();
-    let built = bson::decode(vec!(0x05,0x00,0x00,0x00,0x00)).unwrap();
+    let built = bson::from_vec(vec!(0x05,0x00,0x00,0x00,0x00)).unwrap();
     assert_eq!(bson::Object(empty), built);
-    */
 }
-*/
 
 #[test]
 fn serialize_f64() {
@@ -60,10 +76,11 @@ fn serialize_f64() {
 struct FloatStruct {
     f: f64
 }
-impl bson::serialize::Decodable<bson::Decoder, bson::DecoderError> for FloatStruct {
-    fn decode(d: &mut Decoder) -> Result<FloatStruct, io::IoError> {
-        let map = try!(d.read_map());
-        // TODO - FloatStruct { f: map.get("f") }
+impl Decodable<bson::Decoder, bson::DecoderError> for FloatStruct {
+    fn decode(d: &mut bson::Decoder) -> Result<FloatStruct, bson::DecoderError> {
+        d.read_struct("FloatStruct", 1, |d| {
+            Ok(FloatStruct { f: try!(d.read_struct_field("f", 0, |d| Decodable::decode(d))) })
+        })
     }
 }
 #[test]
@@ -74,20 +91,140 @@ fn deserialize_f64() {
                  0x00,0x00,0x00,0x00,0x00,0x00,0x24,0x40,
                  0x00);
     let obj: FloatStruct = bson::decode(v).unwrap();
+    assert_eq!(obj.f, 10f64);
+}
+
+#[deriving(PartialEq, Show)]
+struct Order {
+    id: i64,
+    qty: u8,
+    tags: Vec<String>,
+    note: Option<String>,
+}
+impl Decodable<bson::Decoder, bson::DecoderError> for Order {
+    fn decode(d: &mut bson::Decoder) -> Result<Order, bson::DecoderError> {
+        d.read_struct("Order", 4, |d| {
+            Ok(Order {
+                id: try!(d.read_struct_field("id", 0, |d| Decodable::decode(d))),
+                qty: try!(d.read_struct_field("qty", 1, |d| Decodable::decode(d))),
+                tags: try!(d.read_struct_field("tags", 2, |d| Decodable::decode(d))),
+                note: try!(d.read_struct_field("note", 3, |d| Decodable::decode(d))),
+            })
+        })
+    }
+}
+fn order(qty: Bson) -> Bson {
+    let mut doc = TreeMap::new();
+    doc.insert("id".to_string(), bson::Int64(7));
+    doc.insert("qty".to_string(), qty);
+    doc.insert("tags".to_string(), bson::List(vec!(bson::String("a".to_string()),
+                                                   bson::String("b".to_string()))));
+    bson::Object(doc)
+}
+
+#[test]
+fn deserialize_struct() {
+    let mut d = bson::Decoder::new(order(bson::Int32(3)));
+    let decoded: Order = Decodable::decode(&mut d).unwrap();
+    assert_eq!(decoded, Order { id: 7, qty: 3, tags: vec!("a".to_string(), "b".to_string()), note: None });
+}
+
+#[test]
+fn deserialize_integer_out_of_range() {
+    let mut d = bson::Decoder::new(bson::List(vec!(order(bson::Int32(3)), order(bson::Int32(300)))));
+    let res: Result<Vec<Order>, bson::DecoderError> = Decodable::decode(&mut d);
+    match res.err().unwrap() {
+        bson::OutOfRange { path, value, target } => {
+            assert_eq!(path, "[1].qty".to_string());
+            assert_eq!(value, "300".to_string());
+            assert_eq!(target, "u8".to_string());
+        },
+        other => fail!("unexpected error: {}", other),
+    }
+}
+
+#[test]
+fn deserialize_unsigned_and_f32() {
+    let mut d = bson::Decoder::new(bson::Int32(7));
+    let small: u32 = Decodable::decode(&mut d).unwrap();
+    assert_eq!(small, 7);
+    let mut d = bson::Decoder::new(bson::Int32(-1));
+    let negative: Result<u32, bson::DecoderError> = Decodable::decode(&mut d);
+    assert!(negative.is_err());
+
+    let mut d = bson::Decoder::new(bson::Float(1.5f64));
+    let f: f32 = Decodable::decode(&mut d).unwrap();
+    assert_eq!(f, 1.5f32);
+    let mut d = bson::Decoder::new(bson::Float(1e300f64));
+    let res: Result<f32, bson::DecoderError> = Decodable::decode(&mut d);
+    match res.err().unwrap() {
+        bson::OutOfRange { target, .. } => assert_eq!(target, "f32".to_string()),
+        other => fail!("unexpected error: {}", other),
+    }
+}
+
+#[test]
+fn deserialize_error_path() {
+    let mut orders = TreeMap::new();
+    let mut bad = order(bson::Int32(3));
+    match bad {
+        bson::Object(ref mut doc) => {
+            doc.insert("tags".to_string(), bson::List(vec!(bson::String("a".to_string()), bson::Int32(1))));
+        },
+        _ => unreachable!(),
+    }
+    orders.insert("orders".to_string(), bson::List(vec!(order(bson::Int32(1)), bad)));
+    let mut d = bson::Decoder::new(bson::Object(orders));
+    let res: Result<TreeMap<String, Vec<Order>>, bson::DecoderError> = Decodable::decode(&mut d);
+    match res.err().unwrap() {
+        bson::ExpectedType { path, expected, found } => {
+            assert_eq!(path, "orders[1].tags[1]".to_string());
+            assert_eq!(expected, "string".to_string());
+            assert_eq!(found, "int".to_string());
+        },
+        other => fail!("unexpected error: {}", other),
+    }
+}
+
+#[test]
+fn deserialize_unknown_field() {
+    let mut doc = TreeMap::new();
+    doc.insert("f".to_string(), bson::Float(1f64));
+    doc.insert("g".to_string(), bson::Float(2f64));
+    let mut d = bson::Decoder::new(bson::Object(doc.clone()));
+    let ok: Result<FloatStruct, bson::DecoderError> = Decodable::decode(&mut d);
+    assert!(ok.is_ok());
+
+    let mut d = bson::Decoder::new(bson::Object(doc));
+    d.set_deny_unknown_fields(true);
+    let res: Result<FloatStruct, bson::DecoderError> = Decodable::decode(&mut d);
+    match res.err().unwrap() {
+        bson::UnknownField { path } => assert_eq!(path, "g".to_string()),
+        other => fail!("unexpected error: {}", other),
+    }
+}
+
+#[test]
+fn deserialize_missing_field() {
+    let mut d = bson::Decoder::new(bson::Object(TreeMap::new()));
+    let res: Result<FloatStruct, bson::DecoderError> = Decodable::decode(&mut d);
+    match res.err().unwrap() {
+        bson::MissingField { path } => assert_eq!(path, "f".to_string()),
+        other => fail!("unexpected error: {}", other),
+    }
 }
-/*
+
 #[test]
 fn deserialize_int32() {
-    let mut correct = BSON::new();
-    correct.insert("int32".to_string(), BSONInt(10));
-    let built = BSON::deserialize(vec!(0x10,0x00,0x00,0x00,
-                                       0x10,
-                                       0x69,0x6e,0x74,0x33,0x32,0x00,
-                                       0x0A,0x00,0x00,0x00,
-                                       0x00));
-    assert_eq!(correct, built);
+    let mut correct = TreeMap::new();
+    correct.insert("int32".to_string(), bson::Int32(10));
+    let built = bson::from_vec(vec!(0x10,0x00,0x00,0x00,
+                                    0x10,
+                                    0x69,0x6e,0x74,0x33,0x32,0x00,
+                                    0x0A,0x00,0x00,0x00,
+                                    0x00)).unwrap();
+    assert_eq!(bson::Object(correct), built);
 }
-*/
 
 #[test]
 fn serialize_string() {
@@ -102,20 +239,18 @@ fn serialize_string() {
                                 0x73,0x61,0x6d,0x70,0x6c,0x65,0x00,
                                 0x00));
 }
-/*
 #[test]
 fn deserialize_string() {
-    let mut correct = BSON::new();
-    correct.insert("string".to_string(), BSONString("sample".to_string()));
-    let built = BSON::deserialize(vec!(0x18,0x00,0x00,0x00,
-                                       0x02,
-                                       0x73,0x74,0x72,0x69,0x6e,0x67,0x00,
-                                       0x07,0x00,0x00,0x00,
-                                       0x73,0x61,0x6d,0x70,0x6c,0x65,0x00,
-                                       0x00));
-    assert_eq!(correct, built);
+    let mut correct = TreeMap::new();
+    correct.insert("string".to_string(), bson::String("sample".to_string()));
+    let built = bson::from_vec(vec!(0x18,0x00,0x00,0x00,
+                                    0x02,
+                                    0x73,0x74,0x72,0x69,0x6e,0x67,0x00,
+                                    0x07,0x00,0x00,0x00,
+                                    0x73,0x61,0x6d,0x70,0x6c,0x65,0x00,
+                                    0x00)).unwrap();
+    assert_eq!(bson::Object(correct), built);
 }
-*/
 
 #[test]
 fn serialize_basic_mongo_document() {
@@ -209,67 +344,1953 @@ fn serialize_embedded() {
                           0x00));
 }
 
-/*
 #[test]
 fn deserialize_embedded() {
-    let mut embed = BSON::new();
-    embed.insert("this".to_string(), BSONString("is embedded".to_string()));
-    embed.insert("negative".to_string(), BSONInt(-5));
-    let mut correct = BSON::new();
-    correct.insert("hello".to_string(), BSONString("world".to_string()));
-    correct.insert("another".to_string(), BSONString("thing".to_string()));
-    correct.insert("number".to_string(), BSONInt(10));
-    correct.insert("embedded".to_string(), BSONObject(embed));
-    let built = BSON::deserialize(vec!(0x68,0x00,0x00,0x00,
-                                       0x02,
-                                       0x61,0x6e,0x6f,0x74,0x68,0x65,0x72,0x00,
-                                       0x06,0x00,0x00,0x00,
-                                       0x74,0x68,0x69,0x6e,0x67,0x00,
-                                       0x03,
-                                       0x65,0x6d,0x62,0x65,0x64,0x64,0x65,0x64,0x00,
-                                       0x29,0x00,0x00,0x00,
-                                       0x10,
-                                       0x6e,0x65,0x67,0x61,0x74,0x69,0x76,0x65,0x00,
-                                       0xFB,0xFF,0xFF,0xFF,
-                                       0x02,
-                                       0x74,0x68,0x69,0x73,0x00,
-                                       0x0c,0x00,0x00,0x00,
-                                       0x69,0x73,0x20,0x65,0x6d,0x62,0x65,0x64,0x64,0x65,0x64,0x00,
-                                       0x00,
-                                       0x02,
-                                       0x68,0x65,0x6c,0x6c,0x6f,0x00,
-                                       0x06,0x00,0x00,0x00,
-                                       0x77,0x6f,0x72,0x6c,0x64,0x00,
-                                       0x10,
-                                       0x6e,0x75,0x6d,0x62,0x65,0x72,0x00,
-                                       0x0A,0x00,0x00,0x00,
-                                       0x00));
-    assert_eq!(correct, built);
+    let mut embed = TreeMap::new();
+    embed.insert("this".to_string(), bson::String("is embedded".to_string()));
+    embed.insert("negative".to_string(), bson::Int32(-5));
+    let mut correct = TreeMap::new();
+    correct.insert("hello".to_string(), bson::String("world".to_string()));
+    correct.insert("another".to_string(), bson::String("thing".to_string()));
+    correct.insert("number".to_string(), bson::Int32(10));
+    correct.insert("embedded".to_string(), bson::Object(embed));
+    let built = bson::from_vec(vec!(0x68,0x00,0x00,0x00,
+                                    0x02,
+                                    0x61,0x6e,0x6f,0x74,0x68,0x65,0x72,0x00,
+                                    0x06,0x00,0x00,0x00,
+                                    0x74,0x68,0x69,0x6e,0x67,0x00,
+                                    0x03,
+                                    0x65,0x6d,0x62,0x65,0x64,0x64,0x65,0x64,0x00,
+                                    0x29,0x00,0x00,0x00,
+                                    0x10,
+                                    0x6e,0x65,0x67,0x61,0x74,0x69,0x76,0x65,0x00,
+                                    0xFB,0xFF,0xFF,0xFF,
+                                    0x02,
+                                    0x74,0x68,0x69,0x73,0x00,
+                                    0x0c,0x00,0x00,0x00,
+                                    0x69,0x73,0x20,0x65,0x6d,0x62,0x65,0x64,0x64,0x65,0x64,0x00,
+                                    0x00,
+                                    0x02,
+                                    0x68,0x65,0x6c,0x6c,0x6f,0x00,
+                                    0x06,0x00,0x00,0x00,
+                                    0x77,0x6f,0x72,0x6c,0x64,0x00,
+                                    0x10,
+                                    0x6e,0x75,0x6d,0x62,0x65,0x72,0x00,
+                                    0x0A,0x00,0x00,0x00,
+                                    0x00)).unwrap();
+    assert_eq!(bson::Object(correct), built);
 }
 
 #[test]
 fn serialize_id() {
-    let mut bson = BSON::new();
-    bson.insert("_id".to_string(), BSONId(vec!(0x00,0x01,0x02,0x03,0x04,0x05,0x06,0x07,0x08,0x09,0x0a,0x0b)));
-    let serialized = bson.serialize();
+    let mut doc = TreeMap::new();
+    doc.insert("_id".to_string(), bson::ObjectId(vec!(0x00,0x01,0x02,0x03,0x04,0x05,0x06,0x07,0x08,0x09,0x0a,0x0b)));
+    let serialized = bson::encode(&bson::Object(doc));
     show_bson(&serialized);
     assert_eq!(serialized, vec!(0x16,0x00,0x00,0x00,
-                          0x07,
-                          0x5f,0x69,0x64,0x00,
-                          0x00,0x01,0x02,0x03,0x04,0x05,0x06,0x07,0x08,0x09,0x0a,0x0b,
-                          0x00));
+                                0x07,
+                                0x5f,0x69,0x64,0x00,
+                                0x00,0x01,0x02,0x03,0x04,0x05,0x06,0x07,0x08,0x09,0x0a,0x0b,
+                                0x00));
 }
 
 #[test]
 fn deserialize_id() {
-    let mut correct = BSON::new();
-    correct.insert("_id".to_string(), BSONId(vec!(0x00,0x01,0x02,0x03,0x04,0x05,0x06,0x07,0x08,0x09,0x0a,0x0b)));
-    // TODO - this works even with an incorrect length specified
-    let built = BSON::deserialize(vec!(0x16,0x00,0x00,0x00,
-                                       0x07,
-                                       0x5f,0x69,0x64,0x00,
-                                       0x00,0x01,0x02,0x03,0x04,0x05,0x06,0x07,0x08,0x09,0x0a,0x0b,
-                                       0x00));
-    assert_eq!(correct, built);
-}
-*/
+    let mut correct = TreeMap::new();
+    correct.insert("_id".to_string(), bson::ObjectId(vec!(0x00,0x01,0x02,0x03,0x04,0x05,0x06,0x07,0x08,0x09,0x0a,0x0b)));
+    let built = bson::from_vec(vec!(0x16,0x00,0x00,0x00,
+                                    0x07,
+                                    0x5f,0x69,0x64,0x00,
+                                    0x00,0x01,0x02,0x03,0x04,0x05,0x06,0x07,0x08,0x09,0x0a,0x0b,
+                                    0x00)).unwrap();
+    assert_eq!(bson::Object(correct), built);
+}
+
+fn parse_error(v: Vec<u8>) -> (bson::ErrorCode, uint, String) {
+    match bson::from_vec(v) {
+        Err(bson::SyntaxError(code, offset, path)) => (code, offset, path),
+        other => fail!("expected a syntax error, got {}", other),
+    }
+}
+
+#[test]
+fn deserialize_id_wrong_length() {
+    let (code, offset, _) = parse_error(vec!(0x17,0x00,0x00,0x00,
+                                             0x07,
+                                             0x5f,0x69,0x64,0x00,
+                                             0x00,0x01,0x02,0x03,0x04,0x05,0x06,0x07,0x08,0x09,0x0a,0x0b,
+                                             0x00));
+    assert_eq!(code, bson::UnexpectedEndOfInput);
+    assert_eq!(offset, 0);
+}
+
+#[test]
+fn deserialize_truncated_element() {
+    // The declared length is right, but the document ends partway through
+    // the id, so the terminator check catches it.
+    let (code, offset, _) = parse_error(vec!(0x0E,0x00,0x00,0x00,
+                                             0x07,
+                                             0x5f,0x69,0x64,0x00,
+                                             0x00,0x01,0x02,0x03,0x04));
+    assert_eq!(code, bson::MissingTerminator);
+    assert_eq!(offset, 13);
+}
+
+#[test]
+fn deserialize_bad_string_length() {
+    let (code, offset, path) = parse_error(vec!(0x18,0x00,0x00,0x00,
+                                                0x03,
+                                                0x6f,0x00,
+                                                0x10,0x00,0x00,0x00,
+                                                0x02,
+                                                0x73,0x00,
+                                                0x00,0x00,0x00,0x00,
+                                                0x74,0x00,
+                                                0x00,0x00,0x00,
+                                                0x00));
+    assert_eq!(code, bson::BadLength);
+    assert_eq!(offset, 14);
+    assert_eq!(path, "o.s".to_string());
+}
+
+#[test]
+fn deserialize_invalid_utf8() {
+    let (code, offset, path) = parse_error(vec!(0x0E,0x00,0x00,0x00,
+                                                0x02,
+                                                0x73,0x00,
+                                                0x02,0x00,0x00,0x00,
+                                                0xff,0x00,
+                                                0x00));
+    assert_eq!(code, bson::InvalidUtf8);
+    assert_eq!(offset, 11);
+    assert_eq!(path, "s".to_string());
+}
+
+#[test]
+fn deserialize_unknown_element_type() {
+    let (code, offset, path) = parse_error(vec!(0x08,0x00,0x00,0x00,
+                                                0x20,
+                                                0x73,0x00,
+                                                0x00));
+    assert_eq!(code, bson::UnknownElementType);
+    assert_eq!(offset, 4);
+    assert_eq!(path, "s".to_string());
+}
+
+#[test]
+fn deserialize_duplicate_key() {
+    let (code, offset, path) = parse_error(vec!(0x10,0x00,0x00,0x00,
+                                                0x0A,
+                                                0x61,0x00,
+                                                0x03,
+                                                0x61,0x00,
+                                                0x05,0x00,0x00,0x00,
+                                                0x00,
+                                                0x00));
+    // The second 'a' is the one reported, at its type byte.
+    assert_eq!(code, bson::DuplicateKey);
+    assert_eq!(offset, 7);
+    assert_eq!(path, "a".to_string());
+}
+
+fn nested(depth: uint) -> Vec<u8> {
+    let mut doc = bson::Object(TreeMap::new());
+    for _ in range(0, depth) {
+        let mut outer = TreeMap::new();
+        outer.insert("a".to_string(), doc);
+        doc = bson::Object(outer);
+    }
+    bson::encode(&doc)
+}
+
+#[test]
+fn deserialize_depth_limit() {
+    assert!(bson::from_vec(nested(bson::MAX_DEPTH - 1)).is_ok());
+    let (code, _, path) = parse_error(nested(bson::MAX_DEPTH));
+    assert_eq!(code, bson::DepthExceeded);
+    assert_eq!(path.as_slice().split('.').count(), bson::MAX_DEPTH);
+}
+
+#[deriving(PartialEq, Show)]
+enum Shape {
+    Circle(f64),
+    Rect { w: f64, h: f64 },
+    Empty,
+}
+static SHAPES: &'static [&'static str] = &["Circle", "Rect", "Empty"];
+
+struct Tagged(EnumRepr, Shape);
+impl<'a> Encodable<bson::Encoder<'a>, IoError> for Tagged {
+    fn encode(&self, e: &mut bson::Encoder<'a>) -> Result<Vec<u8>, IoError> {
+        let Tagged(repr, ref shape) = *self;
+        e.build_enum("Shape", repr, |e| match *shape {
+            Circle(r) => e.build_enum_variant("Circle", 0, 1, |e| {
+                e.build_enum_variant_arg(0, |e| r.encode(e))
+            }),
+            Rect { w, h } => e.build_enum_struct_variant("Rect", 1, 2, |e| {
+                let mut buf = try!(e.build_enum_struct_variant_field("w", 0, |e| w.encode(e)));
+                buf.push_all(try!(e.build_enum_struct_variant_field("h", 1, |e| h.encode(e))).as_slice());
+                Ok(buf)
+            }),
+            Empty => e.build_enum_variant("Empty", 2, 0, |_| Ok(Vec::new())),
+        })
+    }
+}
+impl Decodable<bson::Decoder, bson::DecoderError> for Shape {
+    fn decode(d: &mut bson::Decoder) -> Result<Shape, bson::DecoderError> {
+        d.read_enum("Shape", ExternallyTagged, |d| {
+            d.read_enum_variant(SHAPES, |d, idx| match idx {
+                0 => Ok(Circle(try!(d.read_enum_variant_arg(0, |d| Decodable::decode(d))))),
+                1 => Ok(Rect {
+                    w: try!(d.read_enum_struct_variant_field("w", 0, |d| Decodable::decode(d))),
+                    h: try!(d.read_enum_struct_variant_field("h", 1, |d| Decodable::decode(d))),
+                }),
+                _ => Ok(Empty),
+            })
+        })
+    }
+}
+
+#[test]
+fn serialize_enum_externally_tagged() {
+    let mut map = TreeMap::new();
+    map.insert("s".to_string(), Tagged(ExternallyTagged, Circle(1f64)));
+    let serialized = bson::encode(&map);
+    show_bson(&serialized);
+    assert_eq!(serialized, vec!(0x25,0x00,0x00,0x00,
+                                0x03,
+                                0x73,0x00,
+                                0x1d,0x00,0x00,0x00,
+                                0x04,
+                                0x43,0x69,0x72,0x63,0x6c,0x65,0x00,
+                                0x10,0x00,0x00,0x00,
+                                0x01,
+                                0x30,0x00,
+                                0x00,0x00,0x00,0x00,0x00,0x00,0xf0,0x3f,
+                                0x00,
+                                0x00,
+                                0x00));
+}
+
+#[test]
+fn serialize_enum_internally_tagged() {
+    let mut map = TreeMap::new();
+    map.insert("s".to_string(), Tagged(InternallyTagged("type"), Rect { w: 1f64, h: 2f64 }));
+    let serialized = bson::encode(&map);
+    show_bson(&serialized);
+    assert_eq!(serialized, vec!(0x32,0x00,0x00,0x00,
+                                0x03,
+                                0x73,0x00,
+                                0x2a,0x00,0x00,0x00,
+                                0x02,
+                                0x74,0x79,0x70,0x65,0x00,
+                                0x05,0x00,0x00,0x00,
+                                0x52,0x65,0x63,0x74,0x00,
+                                0x01,
+                                0x77,0x00,
+                                0x00,0x00,0x00,0x00,0x00,0x00,0xf0,0x3f,
+                                0x01,
+                                0x68,0x00,
+                                0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x40,
+                                0x00,
+                                0x00));
+}
+
+#[test]
+fn serialize_enum_unit_as_string() {
+    let mut map = TreeMap::new();
+    map.insert("s".to_string(), Tagged(UnitAsString, Empty));
+    let serialized = bson::encode(&map);
+    assert_eq!(serialized, vec!(0x12,0x00,0x00,0x00,
+                                0x02,
+                                0x73,0x00,
+                                0x06,0x00,0x00,0x00,
+                                0x45,0x6d,0x70,0x74,0x79,0x00,
+                                0x00));
+}
+
+#[test]
+#[should_fail]
+fn serialize_enum_unit_as_string_with_data() {
+    let mut map = TreeMap::new();
+    map.insert("s".to_string(), Tagged(UnitAsString, Circle(1f64)));
+    bson::encode(&map);
+}
+
+#[test]
+fn deserialize_enum_struct_variant() {
+    let mut fields = TreeMap::new();
+    fields.insert("w".to_string(), bson::Float(1f64));
+    fields.insert("h".to_string(), bson::Float(2f64));
+    let mut tagged = TreeMap::new();
+    tagged.insert("Rect".to_string(), bson::Object(fields));
+    let mut d = bson::Decoder::new(bson::Object(tagged));
+    let shape: Shape = Decodable::decode(&mut d).unwrap();
+    assert_eq!(shape, Rect { w: 1f64, h: 2f64 });
+}
+
+#[test]
+fn deserialize_enum_unit_variant() {
+    let mut d = bson::Decoder::new(bson::String("Empty".to_string()));
+    let shape: Shape = Decodable::decode(&mut d).unwrap();
+    assert_eq!(shape, Empty);
+}
+
+#[test]
+fn deserialize_enum_unknown_variant() {
+    let mut d = bson::Decoder::new(bson::String("Hexagon".to_string()));
+    let res: Result<Shape, bson::DecoderError> = Decodable::decode(&mut d);
+    match res.err().unwrap() {
+        bson::UnknownVariant { variant, expected, .. } => {
+            assert_eq!(variant, "Hexagon".to_string());
+            assert_eq!(expected, vec!("Circle".to_string(), "Rect".to_string(), "Empty".to_string()));
+        },
+        other => fail!("unexpected error: {}", other),
+    }
+}
+
+fn two_documents() -> Vec<u8> {
+    let mut v = vec!(0x05,0x00,0x00,0x00,0x00);
+    v.push_all([0x10,0x00,0x00,0x00,
+                0x10,
+                0x69,0x6e,0x74,0x33,0x32,0x00,
+                0x0A,0x00,0x00,0x00,
+                0x00]);
+    v
+}
+
+#[test]
+fn read_document_stream() {
+    let mut reader = bson::DocumentReader::new(MemReader::new(two_documents()));
+    assert_eq!(reader.next().unwrap().unwrap(), bson::Object(TreeMap::new()));
+    let mut second = TreeMap::new();
+    second.insert("int32".to_string(), bson::Int32(10));
+    assert_eq!(reader.next().unwrap().unwrap(), bson::Object(second));
+    assert_eq!(reader.offset(), 5);
+    assert!(reader.next().is_none());
+}
+
+#[test]
+fn read_document_stream_truncated() {
+    let mut v = two_documents();
+    v.push_all([0x10,0x00,0x00,0x00,0x10]);
+    let docs: Vec<Result<Bson, bson::ParserError>> = bson::DocumentReader::new(MemReader::new(v)).collect();
+    assert_eq!(docs.len(), 3);
+    assert!(docs[0].is_ok() && docs[1].is_ok());
+    match docs[2] {
+        Err(bson::SyntaxError(bson::TruncatedDocument, offset, _)) => assert_eq!(offset, 21),
+        ref other => fail!("expected a truncated document, got {}", other),
+    }
+}
+
+// {"s": "a\xff", "k": 1, "k": 2}
+fn legacy_document() -> Vec<u8> {
+    vec!(0x1D,0x00,0x00,0x00,
+         0x02,
+         0x73,0x00,
+         0x03,0x00,0x00,0x00,
+         0x61,0xff,0x00,
+         0x10,
+         0x6b,0x00,
+         0x01,0x00,0x00,0x00,
+         0x10,
+         0x6b,0x00,
+         0x02,0x00,0x00,0x00,
+         0x00)
+}
+
+fn field(doc: &Bson, key: &str) -> Bson {
+    match *doc {
+        bson::Object(ref o) => o.find(&key.to_string()).unwrap().clone(),
+        _ => fail!("not a document"),
+    }
+}
+
+#[test]
+fn deserialize_strict_by_default() {
+    let (code, offset, path) = parse_error(legacy_document());
+    assert_eq!(code, bson::InvalidUtf8);
+    assert_eq!(offset, 11);
+    assert_eq!(path, "s".to_string());
+}
+
+#[test]
+fn deserialize_lenient() {
+    let mut options = bson::DecoderOptions::new();
+    options.utf8 = bson::Utf8Lossy;
+    options.duplicate_keys = bson::KeepFirst;
+    let doc = bson::from_vec_with_options(legacy_document(), options.clone()).unwrap();
+    assert_eq!(field(&doc, "s"), bson::String("a\ufffd".to_string()));
+    assert_eq!(field(&doc, "k"), bson::Int32(1));
+
+    options.utf8 = bson::Utf8Raw;
+    options.duplicate_keys = bson::KeepLast;
+    let doc = bson::from_vec_with_options(legacy_document(), options).unwrap();
+    assert_eq!(field(&doc, "s"), bson::RawString(vec!(0x61, 0xff)));
+    assert_eq!(field(&doc, "k"), bson::Int32(2));
+
+    // Raw strings go back out exactly as they came in.
+    let mut single = TreeMap::new();
+    single.insert("s".to_string(), field(&doc, "s"));
+    assert_eq!(bson::encode(&bson::Object(single)), vec!(0x0F,0x00,0x00,0x00,
+                                                         0x02,
+                                                         0x73,0x00,
+                                                         0x03,0x00,0x00,0x00,
+                                                         0x61,0xff,0x00,
+                                                         0x00));
+}
+
+#[test]
+fn deserialize_size_limit() {
+    let mut options = bson::DecoderOptions::new();
+    options.max_document_size = 16;
+    assert!(bson::from_vec_with_options(legacy_document(), options).is_err());
+}
+
+fn decode_coerced<T: Decodable<bson::Decoder, bson::DecoderError>>(v: Bson, coercion: bson::Coercion) -> Result<T, bson::DecoderError> {
+    let mut options = bson::DecoderOptions::new();
+    options.coercion = coercion;
+    let mut d = bson::Decoder::with_options(v, options);
+    Decodable::decode(&mut d)
+}
+
+fn numeric() -> bson::Coercion {
+    let mut coercion = bson::Coercion::strict();
+    coercion.numeric = true;
+    coercion
+}
+
+#[test]
+fn coerce_numbers() {
+    let strict: Result<i64, bson::DecoderError> = decode_coerced(bson::Int32(5), bson::Coercion::strict());
+    assert!(strict.is_err());
+    let widened: i64 = decode_coerced(bson::Int32(5), numeric()).unwrap();
+    assert_eq!(widened, 5);
+    let exact: i32 = decode_coerced(bson::Float(3f64), numeric()).unwrap();
+    assert_eq!(exact, 3);
+    let fraction: Result<i32, bson::DecoderError> = decode_coerced(bson::Float(3.5f64), numeric());
+    assert!(fraction.is_err());
+    let double: f64 = decode_coerced(bson::Int64(1 << 53), numeric()).unwrap();
+    assert_eq!(double, 9007199254740992f64);
+    let inexact: Result<f64, bson::DecoderError> = decode_coerced(bson::Int64((1 << 53) + 1), numeric());
+    match inexact.err().unwrap() {
+        bson::OutOfRange { value, target, .. } => {
+            assert_eq!(value, "9007199254740993".to_string());
+            assert_eq!(target, "f64".to_string());
+        },
+        other => fail!("unexpected error: {}", other),
+    }
+}
+
+// 1.5 as a Decimal128
+fn one_and_a_half() -> Bson {
+    bson::Decimal128(vec!(0x0F,0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+                          0x00,0x00,0x00,0x00,0x00,0x00,0x3E,0x30))
+}
+
+#[test]
+fn coerce_decimal_and_object_id() {
+    let strict: Result<f64, bson::DecoderError> = decode_coerced(one_and_a_half(), bson::Coercion::strict());
+    assert!(strict.is_err());
+
+    let mut coercion = bson::Coercion::strict();
+    coercion.decimal_to_f64 = true;
+    coercion.to_string = true;
+    let f: f64 = decode_coerced(one_and_a_half(), coercion.clone()).unwrap();
+    assert_eq!(f, 1.5f64);
+    let s: String = decode_coerced(one_and_a_half(), coercion.clone()).unwrap();
+    assert_eq!(s, "1.5".to_string());
+    let id: String = decode_coerced(bson::ObjectId(vec!(0x00,0x01,0x02,0x03,0x04,0x05,0x06,0x07,0x08,0x09,0x0a,0x0b)), coercion).unwrap();
+    assert_eq!(id, "000102030405060708090a0b".to_string());
+}
+
+#[test]
+fn coerce_undefined_to_none() {
+    let strict: Result<Option<f64>, bson::DecoderError> = decode_coerced(bson::Undefined, bson::Coercion::strict());
+    assert!(strict.is_err());
+    let mut coercion = bson::Coercion::strict();
+    coercion.undefined_as_none = true;
+    let none: Option<f64> = decode_coerced(bson::Undefined, coercion).unwrap();
+    assert_eq!(none, None);
+}
+
+// {"a": {"b": 1, "c": "x"}, "d": [{"b": 1, "e": 2}, 3], "f": "\xff"}
+fn projected_document() -> Vec<u8> {
+    vec!(0x4b,0x00,0x00,0x00,
+         0x03,0x61,0x00,0x15,0x00,0x00,0x00,
+           0x10,0x62,0x00,0x01,0x00,0x00,0x00,
+           0x02,0x63,0x00,0x02,0x00,0x00,0x00,0x78,0x00,
+           0x00,
+         0x04,0x64,0x00,0x22,0x00,0x00,0x00,
+           0x03,0x30,0x00,0x13,0x00,0x00,0x00,
+             0x10,0x62,0x00,0x01,0x00,0x00,0x00,
+             0x10,0x65,0x00,0x02,0x00,0x00,0x00,
+             0x00,
+           0x10,0x31,0x00,0x03,0x00,0x00,0x00,
+           0x00,
+         0x02,0x66,0x00,0x02,0x00,0x00,0x00,0xff,0x00,
+         0x00)
+}
+
+#[test]
+fn deserialize_projection() {
+    let mut options = bson::DecoderOptions::new();
+    options.projection = Some(bson::Projection::new(&["a.b", "d.e"]));
+    // "f" isn't valid UTF-8, but it's never looked at.
+    let doc = bson::from_vec_with_options(projected_document(), options.clone()).unwrap();
+    let mut a = TreeMap::new();
+    a.insert("b".to_string(), bson::Int32(1));
+    let mut d = TreeMap::new();
+    d.insert("e".to_string(), bson::Int32(2));
+    let mut expected = TreeMap::new();
+    expected.insert("a".to_string(), bson::Object(a));
+    expected.insert("d".to_string(), bson::List(vec!(bson::Object(d))));
+    assert_eq!(doc, bson::Object(expected));
+
+    // Skipped elements still have to fit inside the buffer.
+    let mut v = projected_document();
+    *v.get_mut(68) = 0x20;
+    match bson::from_vec_with_options(v, options) {
+        Err(bson::SyntaxError(code, _, path)) => {
+            assert_eq!(code, bson::UnexpectedEndOfInput);
+            assert_eq!(path, "f".to_string());
+        },
+        other => fail!("expected a syntax error, got {}", other),
+    }
+}
+
+fn wide_document() -> Vec<u8> {
+    let mut doc = TreeMap::new();
+    for i in range(0u, 1000) {
+        let mut inner = TreeMap::new();
+        inner.insert("x".to_string(), bson::Int32(i as i32));
+        inner.insert("s".to_string(), bson::String("a string long enough to be worth skipping".to_string()));
+        doc.insert(format!("f{}", i), bson::Object(inner));
+    }
+    bson::encode(&bson::Object(doc))
+}
+
+#[bench]
+fn bench_decode_wide(b: &mut Bencher) {
+    let v = wide_document();
+    b.iter(|| bson::from_slice(v.as_slice()).unwrap());
+}
+
+#[bench]
+fn bench_decode_wide_projected(b: &mut Bencher) {
+    let v = wide_document();
+    let mut options = bson::DecoderOptions::new();
+    options.projection = Some(bson::Projection::new(&["f500.x"]));
+    b.iter(|| bson::from_slice_with_options(v.as_slice(), options.clone()).unwrap());
+}
+
+static KEY_CHARS: &'static [char] = &['a', 'b', 'z', 'A', '0', '9', '_', '$', '.', ' ', 'é', '☃'];
+// Values may also hold a NUL, since they carry their own length.
+static VALUE_CHARS: &'static [char] = &['a', 'z', '0', ' ', '"', '\\', '\n', 'é', '☃', '\0'];
+
+fn arbitrary_text<R: Rng>(rng: &mut R, chars: &[char]) -> String {
+    let len = rng.gen_range(0u, 10);
+    range(0, len).map(|_| chars[rng.gen_range(0, chars.len())]).collect()
+}
+
+fn arbitrary_bytes<R: Rng>(rng: &mut R, len: uint) -> Vec<u8> {
+    range(0, len).map(|_| rng.gen::<u8>()).collect()
+}
+
+fn arbitrary_object<R: Rng>(rng: &mut R, depth: uint) -> TreeMap<String, Bson> {
+    let mut obj = TreeMap::new();
+    for _ in range(0, rng.gen_range(0u, 6)) {
+        let key = arbitrary_text(rng, KEY_CHARS);
+        let value = arbitrary_bson(rng, depth);
+        obj.insert(key, value);
+    }
+    obj
+}
+
+/// A Decimal128 in the form the spec's string conversion gives back, so
+/// it survives `$numberDecimal`: a positive NaN with no payload, or a
+/// coefficient small enough to need none of the `11` combination bits.
+fn arbitrary_decimal<R: Rng>(rng: &mut R) -> Bson {
+    let sign = if rng.gen() { 1u64 << 63 } else { 0 };
+    let (high, low) = match rng.gen_range(0u, 8) {
+        0 => (0x7C00000000000000u64, 0u64),
+        1 => (sign | 0x7800000000000000, 0),
+        // A biased exponent up to 12287, the most the `00` form allows.
+        _ => (sign | (rng.gen_range(0u64, 12288) << 49), rng.gen::<u64>()),
+    };
+    let mut bytes = Vec::with_capacity(16);
+    for i in range(0u, 8) {
+        bytes.push((low >> (8 * i)) as u8);
+    }
+    for i in range(0u, 8) {
+        bytes.push((high >> (8 * i)) as u8);
+    }
+    bson::Decimal128(bytes)
+}
+
+/// Any value the codec can carry, other than NaN (which never compares
+/// equal) and raw strings (which only come back under `Utf8Raw`).
+fn arbitrary_bson<R: Rng>(rng: &mut R, depth: uint) -> Bson {
+    let kinds = if depth == 0 { 19 } else { 22 };
+    match rng.gen_range(0u, kinds) {
+        0 => bson::ObjectId(arbitrary_bytes(rng, 12)),
+        1 => bson::Float(rng.gen_range(-1e12f64, 1e12f64)),
+        2 => bson::String(arbitrary_text(rng, VALUE_CHARS)),
+        3 => bson::Boolean(rng.gen()),
+        4 => bson::Null,
+        5 => {
+            let subtype = [0x00u8, 0x02, 0x04, 0x80][rng.gen_range(0u, 4)];
+            let len = rng.gen_range(0u, 20);
+            bson::Binary(subtype, arbitrary_bytes(rng, len))
+        },
+        6 => bson::Undefined,
+        7 => bson::UtcDatetime(rng.gen()),
+        8 => {
+            // Options come back sorted, so only generate them that way.
+            let options = ['i', 'm', 's', 'x'].iter().filter(|_| rng.gen()).map(|&c| c).collect();
+            bson::RegExp(arbitrary_text(rng, KEY_CHARS), options)
+        },
+        9 => bson::DbPointer(arbitrary_text(rng, VALUE_CHARS), arbitrary_bytes(rng, 12)),
+        10 => bson::JavaScript(arbitrary_text(rng, VALUE_CHARS)),
+        11 => bson::Symbol(arbitrary_text(rng, VALUE_CHARS)),
+        12 => bson::Int32(rng.gen()),
+        13 => bson::Timestamp(rng.gen(), rng.gen()),
+        14 => bson::Int64(rng.gen()),
+        15 => arbitrary_decimal(rng),
+        16 => bson::MinKey,
+        17 => bson::MaxKey,
+        18 => bson::Float([-0f64, 1f64 / 0f64, -1f64 / 0f64, 5e-324f64][rng.gen_range(0u, 4)]),
+        19 => {
+            let len = rng.gen_range(0u, 6);
+            bson::List(range(0, len).map(|_| arbitrary_bson(rng, depth - 1)).collect())
+        },
+        20 => bson::Object(arbitrary_object(rng, depth - 1)),
+        _ => bson::JavaScriptWithScope(arbitrary_text(rng, VALUE_CHARS), arbitrary_object(rng, depth - 1)),
+    }
+}
+
+fn seeded() -> XorShiftRng {
+    SeedableRng::from_seed([0x193a6754u32, 0xa8a7d469, 0x97830e05, 0x113ba7bb])
+}
+
+#[test]
+fn roundtrip_arbitrary() {
+    let mut rng = seeded();
+    for _ in range(0u, 500) {
+        let doc = bson::Object(arbitrary_object(&mut rng, 3));
+        let bytes = bson::encode(&doc);
+        let decoded = match bson::from_slice(bytes.as_slice()) {
+            Ok(d) => d,
+            Err(e) => fail!("{} failed to decode: {}", doc, e),
+        };
+        assert_eq!(decoded, doc);
+        assert_eq!(bson::encode(&decoded), bytes);
+    }
+}
+
+/// Damages a buffer in one of the ways that tends to find parser bugs.
+fn mutate<R: Rng>(rng: &mut R, v: &mut Vec<u8>) {
+    if v.is_empty() {
+        return;
+    }
+    let i = rng.gen_range(0, v.len());
+    match rng.gen_range(0u, 4) {
+        0 => *v.get_mut(i) = rng.gen(),
+        1 => v.truncate(i),
+        2 => v.insert(i, rng.gen()),
+        _ => {
+            // The largest or a negative length.
+            let top = if rng.gen() { 0x7f } else { 0xff };
+            for (j, &b) in [0xffu8, 0xff, 0xff, top].iter().enumerate() {
+                if i + j < v.len() {
+                    *v.get_mut(i + j) = b;
+                }
+            }
+        },
+    }
+}
+
+// Whatever the bytes, parsing has to come back with a result rather than
+// panic.
+#[test]
+fn fuzz_from_vec() {
+    let mut rng = seeded();
+    for _ in range(0u, 5000) {
+        let mut v = bson::encode(&bson::Object(arbitrary_object(&mut rng, 3)));
+        for _ in range(0, rng.gen_range(1u, 4)) {
+            mutate(&mut rng, &mut v);
+        }
+        let _ = bson::from_vec(v.clone());
+        let mut options = bson::DecoderOptions::new();
+        options.projection = Some(bson::Projection::new(&["a", "b.z"]));
+        let _ = bson::from_vec_with_options(v, options);
+    }
+    for _ in range(0u, 1000) {
+        let len = rng.gen_range(0u, 64);
+        let _ = bson::from_vec(arbitrary_bytes(&mut rng, len));
+    }
+}
+
+// Inputs that have tripped up BSON parsers, each of which has to be an error.
+#[test]
+fn fuzz_regressions() {
+    let cases = vec!(
+        vec!(),
+        vec!(0x05,0x00,0x00,0x00),
+        // The largest possible length on a five byte buffer.
+        vec!(0xff,0xff,0xff,0x7f,0x00),
+        // A string with a length of -1.
+        vec!(0x0C,0x00,0x00,0x00,0x02,0x73,0x00,0xff,0xff,0xff,0xff,0x00),
+        // Binary data claiming to be 2GB long.
+        vec!(0x0D,0x00,0x00,0x00,0x05,0x62,0x00,0xff,0xff,0xff,0x7f,0x00,0x00),
+        // Old binary with too little data to hold its inner length.
+        vec!(0x0F,0x00,0x00,0x00,0x05,0x62,0x00,0x02,0x00,0x00,0x00,0x02,0xaa,0xbb,0x00),
+        // Code with scope whose contents run past its own length.
+        vec!(0x17,0x00,0x00,0x00,0x0F,0x63,0x00,0x0E,0x00,0x00,0x00,
+             0x02,0x00,0x00,0x00,0x78,0x00,
+             0x05,0x00,0x00,0x00,0x00,
+             0x00),
+        // An element type that doesn't exist.
+        vec!(0x08,0x00,0x00,0x00,0x14,0x61,0x00,0x00));
+    for v in cases.into_iter() {
+        assert!(bson::from_vec(v.clone()).is_err(), "{} was accepted", bson::to_hex(v.as_slice()));
+    }
+
+    // The stream reader checks the length before reading a byte of the body.
+    let mut docs = bson::DocumentReader::new(MemReader::new(vec!(0xff,0xff,0xff,0x7f)));
+    match docs.next() {
+        Some(Err(bson::SyntaxError(code, 0, _))) => assert_eq!(code, bson::DocumentTooLarge),
+        other => fail!("expected DocumentTooLarge, got {}", other),
+    }
+}
+
+// Every input kept for the fuzz targets, crashes they found included, goes
+// back through the target's checks.
+#[test]
+fn fuzz_corpus() {
+    for target in fuzz_targets::TARGETS.iter() {
+        let dir = Path::new("fuzz/corpus").join(*target);
+        let mut inputs = match fs::readdir(&dir) {
+            Ok(paths) => paths,
+            Err(e) => fail!("can't read {}: {}", dir.display(), e),
+        };
+        inputs.sort();
+        assert!(!inputs.is_empty(), "no inputs in {}", dir.display());
+        for path in inputs.iter() {
+            let data = File::open(path).read_to_end().unwrap();
+            fuzz_targets::run(*target, data.as_slice());
+        }
+    }
+}
+
+/// Corpus cases known to fail, by file and description, with the reason.
+/// They still run: one that passes, or that isn't in the corpus, fails the
+/// test, so nothing stays on this list longer than it has to.
+static CORPUS_SKIPS: &'static [(&'static str, &'static str, &'static str)] = &[
+    // Documents are TreeMaps, so these come back out in key order.
+    ("dbref.json", "DBRef", "fields out of key order"),
+    ("dbref.json", "DBRef with database", "fields out of key order"),
+    ("dbref.json", "DBRef with database and additional fields", "fields out of key order"),
+    ("dbref.json", "DBRef with additional fields", "fields out of key order"),
+    ("dbref.json", "Document with key names similar to those of a DBRef", "fields out of key order"),
+    ("multi-type.json", "All BSON types", "fields out of key order"),
+    ("regex.json", "Regular expression as value of $regex query operator with $options", "fields out of key order"),
+    // `from_extjson` takes a bare number as the v1 `$date`, in milliseconds.
+    ("top.json", "Bad $date (number, not string or hash)", "v1 $date is accepted"),
+];
+
+struct CorpusReport {
+    passed: uint,
+    failed: Vec<String>,
+    /// The `CORPUS_SKIPS` entries seen so far, by index.
+    skipped: Vec<uint>,
+}
+
+impl CorpusReport {
+    /// Records one case's failures, if any, unless it's on `CORPUS_SKIPS`.
+    fn record(&mut self, file: &str, description: &str, failures: Vec<String>) {
+        let name = format!("{} / {}", file, description);
+        match CORPUS_SKIPS.iter().position(|&(f, d, _)| f == file && d == description) {
+            Some(i) => {
+                self.skipped.push(i);
+                if failures.is_empty() {
+                    self.failed.push(format!("{}: passes, so it should come off CORPUS_SKIPS", name));
+                }
+            },
+            None if failures.is_empty() => self.passed += 1,
+            None => for f in failures.into_iter() {
+                self.failed.push(format!("{}: {}", name, f));
+            },
+        }
+    }
+}
+
+fn corpus_str<'a>(case: &'a json::Json, key: &str) -> Option<&'a str> {
+    case.find(&key.to_string()).and_then(|v| v.as_string())
+}
+
+fn corpus_cases<'a>(file: &'a json::Json, key: &str) -> Vec<&'a json::Json> {
+    match file.find(&key.to_string()).and_then(|v| v.as_list()) {
+        Some(cases) => cases.iter().collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Compares JSON texts as values, so spacing and key order don't matter.
+fn same_json(actual: &str, expected: &str) -> bool {
+    json::from_str(actual).ok() == Some(json::from_str(expected).unwrap())
+}
+
+fn run_valid_case(case: &json::Json, lossy: bool) -> Vec<String> {
+    let mut failures = Vec::new();
+    let canonical = bson::from_hex(corpus_str(case, "canonical_bson").unwrap()).unwrap();
+    let encoded = |v: &Bson| bson::to_hex(bson::encode(v).as_slice());
+    let expected_hex = bson::to_hex(canonical.as_slice());
+    let native = match bson::from_slice(canonical.as_slice()) {
+        Ok(d) => d,
+        Err(e) => return vec!(format!("canonical_bson failed to decode: {}", e)),
+    };
+    if encoded(&native) != expected_hex {
+        failures.push(format!("canonical_bson encoded back as {}", encoded(&native)));
+    }
+    match corpus_str(case, "degenerate_bson") {
+        Some(hex) => match bson::from_vec(bson::from_hex(hex).unwrap()) {
+            Ok(ref d) if encoded(d) == expected_hex => (),
+            Ok(ref d) => failures.push(format!("degenerate_bson encoded as {}", encoded(d))),
+            Err(e) => failures.push(format!("degenerate_bson failed to decode: {}", e)),
+        },
+        None => (),
+    }
+
+    let canonical_json = corpus_str(case, "canonical_extjson").unwrap();
+    let written = bson::to_canonical_extjson(&native);
+    if !same_json(written.as_slice(), canonical_json) {
+        failures.push(format!("canonical_bson wrote canonical_extjson {}", written));
+    }
+    match bson::from_extjson(canonical_json) {
+        Ok(v) => {
+            if !lossy && encoded(&v) != expected_hex {
+                failures.push(format!("canonical_extjson encoded as {}", encoded(&v)));
+            }
+            let written = bson::to_canonical_extjson(&v);
+            if !same_json(written.as_slice(), canonical_json) {
+                failures.push(format!("canonical_extjson wrote back as {}", written));
+            }
+        },
+        Err(e) => failures.push(format!("canonical_extjson failed to parse: {}", e)),
+    }
+    match corpus_str(case, "relaxed_extjson") {
+        Some(relaxed_json) => {
+            let written = bson::to_relaxed_extjson(&native);
+            if !same_json(written.as_slice(), relaxed_json) {
+                failures.push(format!("canonical_bson wrote relaxed_extjson {}", written));
+            }
+            match bson::from_extjson(relaxed_json) {
+                Ok(v) => {
+                    let written = bson::to_relaxed_extjson(&v);
+                    if !same_json(written.as_slice(), relaxed_json) {
+                        failures.push(format!("relaxed_extjson wrote back as {}", written));
+                    }
+                },
+                Err(e) => failures.push(format!("relaxed_extjson failed to parse: {}", e)),
+            }
+        },
+        None => (),
+    }
+    match corpus_str(case, "degenerate_extjson") {
+        Some(degenerate_json) => match bson::from_extjson(degenerate_json) {
+            Ok(v) => {
+                if !lossy && encoded(&v) != expected_hex {
+                    failures.push(format!("degenerate_extjson encoded as {}", encoded(&v)));
+                }
+                let written = bson::to_canonical_extjson(&v);
+                if !same_json(written.as_slice(), canonical_json) {
+                    failures.push(format!("degenerate_extjson wrote canonical_extjson {}", written));
+                }
+            },
+            Err(e) => failures.push(format!("degenerate_extjson failed to parse: {}", e)),
+        },
+        None => (),
+    }
+    // converted_bson and converted_extjson are for codecs without symbols
+    // or undefined, which this one has.
+    failures
+}
+
+fn run_corpus_file(path: &Path, report: &mut CorpusReport) {
+    let text = File::open(path).read_to_string().unwrap();
+    let file = match json::from_str(text.as_slice()) {
+        Ok(f) => f,
+        Err(e) => fail!("{} isn't valid JSON: {}", path.display(), e),
+    };
+    let file_name = path.filename_display().to_string();
+    let description = |case: &json::Json| corpus_str(case, "description").unwrap_or("").to_string();
+    for case in corpus_cases(&file, "valid").into_iter() {
+        let lossy = case.find(&"lossy".to_string()).and_then(|v| v.as_boolean()) == Some(true);
+        report.record(file_name.as_slice(), description(case).as_slice(), run_valid_case(case, lossy));
+    }
+    for case in corpus_cases(&file, "decodeErrors").into_iter() {
+        let failures = match bson::from_vec(bson::from_hex(corpus_str(case, "bson").unwrap()).unwrap()) {
+            Ok(d) => vec!(format!("decoded to {}", d)),
+            Err(_) => Vec::new(),
+        };
+        report.record(file_name.as_slice(), description(case).as_slice(), failures);
+    }
+    // Decimal128 parse errors are bare strings; the rest are Extended JSON.
+    let decimal = corpus_str(&file, "bson_type") == Some("0x13");
+    for case in corpus_cases(&file, "parseErrors").into_iter() {
+        let s = corpus_str(case, "string").unwrap();
+        let rejected = if decimal {
+            bson::decimal::parse(s).is_none()
+        } else {
+            bson::from_extjson(s).is_err()
+        };
+        let failures = if rejected { Vec::new() } else { vec!(format!("parsed {}", s)) };
+        report.record(file_name.as_slice(), description(case).as_slice(), failures);
+    }
+}
+
+#[test]
+fn bson_corpus() {
+    let mut report = CorpusReport { passed: 0, failed: Vec::new(), skipped: Vec::new() };
+    let mut files: Vec<Path> = match fs::readdir(&Path::new("bson_corpus")) {
+        Ok(paths) => paths.into_iter().filter(|p| p.extension_str() == Some("json")).collect(),
+        Err(e) => fail!("can't read bson_corpus: {}", e),
+    };
+    files.sort();
+    assert!(!files.is_empty(), "no corpus files in bson_corpus, see bson_corpus/README.md");
+    for path in files.iter() {
+        run_corpus_file(path, &mut report);
+    }
+    for (i, &(file, description, _)) in CORPUS_SKIPS.iter().enumerate() {
+        if !report.skipped.contains(&i) {
+            report.failed.push(format!("{} / {}: on CORPUS_SKIPS but not in the corpus", file, description));
+        }
+    }
+    let skipped: Vec<String> = CORPUS_SKIPS.iter()
+        .map(|&(file, description, why)| format!("{} / {} ({})", file, description, why))
+        .collect();
+    assert!(report.failed.is_empty(), "{} corpus cases passed, {} failed:\n{}\nskipped:\n{}",
+            report.passed, report.failed.len(), report.failed.connect("\n"), skipped.connect("\n"));
+}
+
+fn extjson_document() -> Bson {
+    let mut doc = TreeMap::new();
+    doc.insert("_id".to_string(), bson::ObjectId(vec!(0x57,0xe1,0x93,0xd7,0xa9,0xcc,0x81,0xb4,0x02,0x74,0x98,0xb5)));
+    doc.insert("bin".to_string(), bson::Binary(0x04, vec!(0x00,0x01,0x02,0x03)));
+    doc.insert("d".to_string(), bson::Float(1.5f64));
+    doc.insert("date".to_string(), bson::UtcDatetime(1356351330501));
+    doc.insert("dec".to_string(), one_and_a_half());
+    doc.insert("i".to_string(), bson::Int32(-5));
+    doc.insert("inf".to_string(), bson::Float(-1f64 / 0f64));
+    doc.insert("l".to_string(), bson::Int64(42));
+    doc.insert("max".to_string(), bson::MaxKey);
+    doc.insert("re".to_string(), bson::RegExp("^a\"b".to_string(), "i".to_string()));
+    doc.insert("s".to_string(), bson::String("tab\there".to_string()));
+    doc.insert("ts".to_string(), bson::Timestamp(123, 4));
+    doc.insert("z".to_string(), bson::Float(-0f64));
+    bson::Object(doc)
+}
+
+#[test]
+fn extended_json() {
+    let doc = extjson_document();
+    assert_eq!(bson::to_canonical_extjson(&doc),
+               concat!(r#"{"_id":{"$oid":"57e193d7a9cc81b4027498b5"},"#,
+                       r#""bin":{"$binary":{"base64":"AAECAw==","subType":"04"}},"#,
+                       r#""d":{"$numberDouble":"1.5"},"#,
+                       r#""date":{"$date":{"$numberLong":"1356351330501"}},"#,
+                       r#""dec":{"$numberDecimal":"1.5"},"#,
+                       r#""i":{"$numberInt":"-5"},"#,
+                       r#""inf":{"$numberDouble":"-Infinity"},"#,
+                       r#""l":{"$numberLong":"42"},"#,
+                       r#""max":{"$maxKey":1},"#,
+                       r#""re":{"$regularExpression":{"pattern":"^a\"b","options":"i"}},"#,
+                       r#""s":"tab\there","#,
+                       r#""ts":{"$timestamp":{"t":123,"i":4}},"#,
+                       r#""z":{"$numberDouble":"-0.0"}}"#).to_string());
+    assert_eq!(bson::to_relaxed_extjson(&doc),
+               concat!(r#"{"_id":{"$oid":"57e193d7a9cc81b4027498b5"},"#,
+                       r#""bin":{"$binary":{"base64":"AAECAw==","subType":"04"}},"#,
+                       r#""d":1.5,"#,
+                       r#""date":{"$date":"2012-12-24T12:15:30.501Z"},"#,
+                       r#""dec":{"$numberDecimal":"1.5"},"#,
+                       r#""i":-5,"#,
+                       r#""inf":{"$numberDouble":"-Infinity"},"#,
+                       r#""l":42,"#,
+                       r#""max":{"$maxKey":1},"#,
+                       r#""re":{"$regularExpression":{"pattern":"^a\"b","options":"i"}},"#,
+                       r#""s":"tab\there","#,
+                       r#""ts":{"$timestamp":{"t":123,"i":4}},"#,
+                       r#""z":-0.0}"#).to_string());
+    assert_eq!(bson::to_relaxed_extjson(&bson::UtcDatetime(-1)), r#"{"$date":{"$numberLong":"-1"}}"#.to_string());
+    assert_eq!(bson::to_relaxed_extjson(&bson::Float(1e300f64)), "1.0E+300".to_string());
+}
+
+#[test]
+fn extended_json_roundtrip() {
+    let doc = extjson_document();
+    assert_eq!(bson::from_extjson(bson::to_canonical_extjson(&doc).as_slice()).unwrap(), doc);
+    let relaxed = bson::from_extjson(bson::to_relaxed_extjson(&doc).as_slice()).unwrap();
+    assert_eq!(field(&relaxed, "date"), bson::UtcDatetime(1356351330501));
+    assert_eq!(field(&relaxed, "d"), bson::Float(1.5f64));
+    assert_eq!(field(&relaxed, "l"), bson::Int32(42));
+
+    let mut rng = seeded();
+    for _ in range(0u, 300) {
+        let doc = bson::Object(arbitrary_object(&mut rng, 3));
+        let json = bson::to_canonical_extjson(&doc);
+        match bson::from_extjson(json.as_slice()) {
+            Ok(d) => assert_eq!(d, doc),
+            Err(e) => fail!("{} failed to parse: {}", json, e),
+        }
+    }
+}
+
+#[test]
+fn extended_json_legacy() {
+    let doc = bson::from_extjson(r#"{"d": {"$date": 1356351330501},
+                                     "b": {"$binary": "AAECAw==", "$type": "4"},
+                                     "r": {"$regex": "^a", "$options": "mi"},
+                                     "u": {"$uuid": "73ffd264-44b3-4c69-90e8-e7d1dfc035d4"},
+                                     "q": {"$gt": {"$date": "2012-12-24T13:15:30.501+01:00"}}}"#).unwrap();
+    assert_eq!(field(&doc, "d"), bson::UtcDatetime(1356351330501));
+    assert_eq!(field(&doc, "b"), bson::Binary(0x04, vec!(0x00,0x01,0x02,0x03)));
+    assert_eq!(field(&doc, "r"), bson::RegExp("^a".to_string(), "im".to_string()));
+    assert_eq!(field(&doc, "u"), bson::Binary(0x04, bson::from_hex("73ffd26444b34c6990e8e7d1dfc035d4").unwrap()));
+    assert_eq!(field(&field(&doc, "q"), "$gt"), bson::UtcDatetime(1356351330501));
+}
+
+#[test]
+fn extended_json_errors() {
+    let err = bson::from_extjson("{\"a\": 1,\n \"b\": {\"$oid\": \"abc\"}}").err().unwrap();
+    assert_eq!((err.line, err.column), (2, 16));
+    let err = bson::from_extjson(r#"{"$oid": "57e193d7a9cc81b4027498b5", "x": 1}"#).err().unwrap();
+    assert_eq!((err.line, err.column), (1, 38));
+    assert_eq!(err.msg, "unexpected key `x` in `$oid`".to_string());
+    assert!(bson::from_extjson(r#"{"$numberLong": 5}"#).is_err());
+    assert!(bson::from_extjson(r#"{"$numberDecimal": "32.4E+-21"}"#).is_err());
+    assert!(bson::from_extjson(r#"{"$uuid": "73ff-d26444b-34c6-990e8e-7d1dfc035d4"}"#).is_err());
+    assert!(bson::from_extjson(r#"{"a\u0000": 1}"#).is_err());
+    assert!(bson::from_extjson(r#"{"$regularExpression": {"pattern": "b\u0000", "options": ""}}"#).is_err());
+    assert!(bson::from_extjson(r#"{"$date": {"$numberLong": "1"}, "$oid": "57e193d7a9cc81b4027498b5"}"#).is_err());
+    assert!(bson::from_extjson("[1, 2").is_err());
+}
+
+#[test]
+fn shell_print() {
+    let mut inner = TreeMap::new();
+    inner.insert("n".to_string(), bson::Int64(5));
+    inner.insert("re".to_string(), bson::RegExp("a/b".to_string(), "i".to_string()));
+    let mut doc = TreeMap::new();
+    doc.insert("_id".to_string(), bson::ObjectId(vec!(0x57,0xe1,0x93,0xd7,0xa9,0xcc,0x81,0xb4,0x02,0x74,0x98,0xb5)));
+    doc.insert("created at".to_string(), bson::UtcDatetime(1356351330501));
+    doc.insert("inner".to_string(), bson::Object(inner));
+    doc.insert("price".to_string(), one_and_a_half());
+    let doc = bson::Object(doc);
+
+    let options = bson::shell::ShellOptions::new();
+    assert_eq!(bson::shell::to_shell(&doc, &options),
+               concat!("{\n",
+                       "  _id: ObjectId(\"57e193d7a9cc81b4027498b5\"),\n",
+                       "  \"created at\": ISODate(\"2012-12-24T12:15:30.501Z\"),\n",
+                       "  inner: { n: NumberLong(5), re: /a\\/b/i },\n",
+                       "  price: NumberDecimal(\"1.5\")\n",
+                       "}").to_string());
+    let wide = bson::shell::ShellOptions { indent: 4, width: 200 };
+    assert_eq!(bson::shell::to_shell(&field(&doc, "inner"), &wide), "{ n: NumberLong(5), re: /a\\/b/i }".to_string());
+    assert_eq!(bson::shell::from_shell(bson::shell::to_shell(&doc, &options).as_slice()).unwrap(), doc);
+}
+
+#[test]
+fn shell_parse() {
+    let doc = bson::shell::from_shell(r#"{
+        _id: ObjectId('57e193d7a9cc81b4027498b5'),
+        when: ISODate("2012-12-24T12:15:30.501Z"),
+        n: NumberLong(5), big: NumberLong("9007199254740993"),
+        d: NumberDecimal("1.5"), f: 2.5, i: 3,
+        name: /^jo\/hn/i,
+        'quoted key': 'it\'s',
+        list: [1, 'two', null,],
+    }"#).unwrap();
+    assert_eq!(field(&doc, "_id"), bson::ObjectId(vec!(0x57,0xe1,0x93,0xd7,0xa9,0xcc,0x81,0xb4,0x02,0x74,0x98,0xb5)));
+    assert_eq!(field(&doc, "when"), bson::UtcDatetime(1356351330501));
+    assert_eq!(field(&doc, "n"), bson::Int64(5));
+    assert_eq!(field(&doc, "big"), bson::Int64(9007199254740993));
+    assert_eq!(field(&doc, "d"), one_and_a_half());
+    assert_eq!(field(&doc, "f"), bson::Float(2.5f64));
+    assert_eq!(field(&doc, "i"), bson::Int32(3));
+    assert_eq!(field(&doc, "name"), bson::RegExp("^jo/hn".to_string(), "i".to_string()));
+    assert_eq!(field(&doc, "quoted key"), bson::String("it's".to_string()));
+    assert_eq!(field(&doc, "list"), bson::List(vec!(bson::Int32(1), bson::String("two".to_string()), bson::Null)));
+
+    let err = bson::shell::from_shell("{ a: 1,\n  b: ObjectId('xyz') }").err().unwrap();
+    assert_eq!((err.line, err.column), (2, 6));
+}
+
+#[test]
+fn shell_regex_roundtrip() {
+    let options = bson::shell::ShellOptions::new();
+    let print = |pattern: &str| bson::shell::to_shell(&bson::RegExp(pattern.to_string(), "i".to_string()), &options);
+    assert_eq!(print("a/b[/]"), "/a\\/b[\\/]/i".to_string());
+    assert_eq!(print("a\\/b"), "RegExp(\"a\\\\/b\", \"i\")".to_string());
+    for pattern in ["a/b", "a\\/b", "a\\\\/b", "ends\\", "line\nbreak", "", "*a", "[/]", "a[b", "\\[/", "\\d+\\.\\d*"].iter() {
+        let re = bson::RegExp(pattern.to_string(), "i".to_string());
+        let text = bson::shell::to_shell(&re, &options);
+        match bson::shell::from_shell(text.as_slice()) {
+            Ok(v) => assert!(v == re, "{} read back as {}", text, v),
+            Err(e) => fail!("{} didn't parse: {}", text, e),
+        }
+    }
+
+    // Options are sorted, as they are coming from BSON or Extended JSON.
+    let re = bson::RegExp("a".to_string(), "im".to_string());
+    assert_eq!(bson::shell::from_shell("/a/mi").unwrap(), re);
+    assert_eq!(bson::shell::from_shell(r#"RegExp("a", "mi")"#).unwrap(), re);
+    assert_eq!(bson::from_extjson(r#"{"$regularExpression": {"pattern": "a", "options": "mi"}}"#).unwrap(), re);
+
+    assert_eq!(bson::shell::from_shell(r"'\ud83d\ude00'").unwrap(), bson::String("\U0001F600".to_string()));
+    assert!(bson::shell::from_shell(r"'\ud83d\u0041'").is_err());
+    assert!(bson::shell::from_shell(r"'\ud83d'").is_err());
+}
+
+#[test]
+fn dump_annotated() {
+    let (listing, error) = bson::dump::annotate(legacy_document().as_slice());
+    assert!(error.is_none());
+    let lines: Vec<&str> = listing.as_slice().lines().collect();
+    assert_eq!(lines.len(), 8);
+    assert!(lines[0].starts_with("00000000  1d 00 00 00 "));
+    assert!(lines[0].ends_with("  document length 29"));
+    assert!(lines[1].starts_with("00000004  02 73 00 "));
+    assert!(lines[1].ends_with("  string \"s\""));
+    assert!(lines[2].ends_with("    length 3 \"a�\""));
+    assert!(lines[4].starts_with("00000011  01 00 00 00 "));
+    assert!(lines[4].ends_with("    1"));
+    assert!(lines[7].starts_with("0000001c  00"));
+    assert!(lines[7].ends_with("end of document"));
+
+    // A string length running off the end stops the listing where the
+    // string should have been.
+    let mut v = legacy_document();
+    *v.get_mut(7) = 0x40;
+    let (listing, error) = bson::dump::annotate(v.as_slice());
+    let last = listing.as_slice().lines().last().unwrap();
+    assert!(last.starts_with("0000000b  61 ff 00 10 "));
+    assert!(last.contains("error: "));
+    assert!(error.is_some());
+}
+
+#[test]
+fn dump_diff() {
+    let left = legacy_document();
+    let mut right = legacy_document();
+    *right.get_mut(24) = 0x03;
+    let diff = bson::dump::diff(left.as_slice(), right.as_slice());
+    let lines: Vec<&str> = diff.as_slice().lines().collect();
+    assert_eq!(lines[0], "first difference at `k`: left 0x18, right 0x18");
+    let marked: Vec<&&str> = lines.iter().filter(|l| l.starts_with(">>")).collect();
+    assert_eq!(marked.len(), 1);
+    assert!(marked[0].contains("    2") && marked[0].contains("    3"));
+    assert_eq!(bson::dump::diff(left.as_slice(), left.as_slice()).as_slice().lines().next(), Some("identical"));
+}
+
+/// Runs the bsondump binary, which has to be built next to this test
+/// binary, over `input`.
+fn run_bsondump(args: &[&str], input: &[u8]) -> ProcessOutput {
+    let exe = os::self_exe_path().unwrap().join("bsondump");
+    assert!(exe.exists(), "{} is missing; build bsondump.rs alongside the tests", exe.display());
+    let mut process = match Command::new(&exe).args(args).spawn() {
+        Ok(p) => p,
+        Err(e) => fail!("couldn't run {}: {}", exe.display(), e),
+    };
+    // Dropping stdin closes it, so bsondump sees the end of the stream.
+    process.stdin.take().unwrap().write(input).unwrap();
+    process.wait_with_output().unwrap()
+}
+
+#[test]
+fn bsondump_validate() {
+    let mut stream = bson::encode(&numbered(1));
+    stream.push_all(bson::encode(&numbered(2)).as_slice());
+    let good = run_bsondump(&["--validate"], stream.as_slice());
+    assert!(good.status.success());
+    assert_eq!(String::from_utf8(good.error).unwrap(), "2 documents\n".to_string());
+
+    // A third document cut off after its length and type byte.
+    let offset = stream.len();
+    let third = bson::encode(&numbered(3));
+    stream.push_all(third.slice_to(5));
+    let bad = run_bsondump(&["--validate"], stream.as_slice());
+    assert_eq!(bad.status, ExitStatus(1));
+    let error = String::from_utf8(bad.error).unwrap();
+    assert!(error.as_slice().starts_with(format!("corrupt document at offset {}: ", offset).as_slice()), "{}", error);
+    assert!(bad.output.is_empty());
+}
+
+#[test]
+fn bsondump_debug() {
+    let good = run_bsondump(&["--debug"], legacy_document().as_slice());
+    assert!(good.status.success());
+
+    // Framed properly, but with a string length running off the end: the
+    // listing comes out as far as it goes, and the run still fails.
+    let mut v = legacy_document();
+    *v.get_mut(7) = 0x40;
+    let bad = run_bsondump(&["--debug"], v.as_slice());
+    assert_eq!(bad.status, ExitStatus(1));
+    let output = String::from_utf8(bad.output).unwrap();
+    assert!(output.as_slice().starts_with("document at offset 0\n"), "{}", output);
+    assert!(output.as_slice().contains("error: "));
+    let error = String::from_utf8(bad.error).unwrap();
+    assert!(error.as_slice().starts_with("corrupt document at offset 0: "), "{}", error);
+}
+
+fn numbered_fields(n: i32) -> bson::Object {
+    let mut obj = TreeMap::new();
+    obj.insert("n".to_string(), bson::Int32(n));
+    obj
+}
+
+fn numbered(n: i32) -> Bson {
+    bson::Object(numbered_fields(n))
+}
+
+#[test]
+fn archive_roundtrip() {
+    use bson::archive::{ArchiveWriter,ArchiveReader,ArchiveHeader,CollectionMetadata,Prelude};
+    use bson::archive::{Document,EndOfCollection};
+
+    let collection = |name: &str| CollectionMetadata {
+        db: "test".to_string(),
+        collection: name.to_string(),
+        metadata: r#"{"indexes":[]}"#.to_string(),
+        size: 0,
+    };
+    let prelude = Prelude {
+        header: ArchiveHeader {
+            concurrent_collections: 2,
+            version: "0.1".to_string(),
+            server_version: "4.4.0".to_string(),
+            tool_version: "100.0.0".to_string(),
+        },
+        collections: vec!(collection("a"), collection("b")),
+    };
+    let mut writer = ArchiveWriter::new(MemWriter::new(), &prelude).unwrap();
+    writer.write_documents("test", "a", vec!(numbered_fields(1), numbered_fields(2)).into_iter()).unwrap();
+    writer.write_documents("test", "b", vec!(numbered_fields(10)).into_iter()).unwrap();
+    writer.write_documents("test", "a", vec!(numbered_fields(3)).into_iter()).unwrap();
+    writer.end_collection("test", "b").unwrap();
+    writer.end_collection("test", "a").unwrap();
+    let bytes = writer.unwrap().unwrap();
+
+    let mut reader = ArchiveReader::new(MemReader::new(bytes.clone())).unwrap();
+    assert_eq!(reader.prelude(), &prelude);
+    let entries: Vec<bson::archive::ArchiveEntry> = reader.by_ref().map(|e| e.unwrap()).collect();
+    assert_eq!(entries, vec!(
+        Document("test.a".to_string(), numbered(1)),
+        Document("test.a".to_string(), numbered(2)),
+        Document("test.b".to_string(), numbered(10)),
+        Document("test.a".to_string(), numbered(3)),
+        EndOfCollection("test.b".to_string()),
+        EndOfCollection("test.a".to_string())));
+
+    let (read, collections) = bson::archive::read_archive(MemReader::new(bytes.clone())).unwrap();
+    assert_eq!(read, prelude);
+    assert_eq!(collections.find(&"test.a".to_string()), Some(&vec!(numbered(1), numbered(2), numbered(3))));
+    assert_eq!(collections.find(&"test.b".to_string()), Some(&vec!(numbered(10))));
+}
+
+#[test]
+fn archive_errors() {
+    use bson::archive::{ArchiveReader,BadMagic,Corrupt};
+
+    match ArchiveReader::new(MemReader::new(vec!(1u8, 2, 3, 4))) {
+        Err(BadMagic(0x04030201)) => (),
+        other => fail!("expected a bad magic number, got {}", other.err()),
+    }
+    // The magic number and nothing else.
+    match ArchiveReader::new(MemReader::new(vec!(0x6d, 0xe2, 0x99, 0x81))) {
+        Err(Corrupt(4, _)) => (),
+        other => fail!("expected a truncated archive, got {}", other.err()),
+    }
+}
+
+fn people() -> Vec<Bson> {
+    let first = bson::shell::from_shell(r#"{
+        name: 'Ann, B', address: { city: 'Oslo', zip: '0150' }, tags: ['a', 'b'],
+    }"#).unwrap();
+    let second = bson::shell::from_shell(r#"{
+        name: 'Bo', address: { city: 'Rome' }, tags: ['c'], score: 2.5,
+    }"#).unwrap();
+    vec!(first, second)
+}
+
+#[test]
+fn csv_export() {
+    use bson::csv::{CsvOptions,IndexedColumns};
+
+    let fields = ["name", "address", "tags", "score"];
+    let csv = bson::csv::export(people().as_slice(), &fields, &CsvOptions::new());
+    assert_eq!(csv.as_slice(), "name,address.city,address.zip,tags,score\n\
+                                \"Ann, B\",Oslo,0150,\"[\"\"a\"\",\"\"b\"\"]\",\n\
+                                Bo,Rome,,\"[\"\"c\"\"]\",2.5\n");
+
+    let options = CsvOptions { arrays: IndexedColumns, ..CsvOptions::tsv() };
+    let tsv = bson::csv::export(people().as_slice(), &fields, &options);
+    assert_eq!(tsv.as_slice(), "name\taddress.city\taddress.zip\ttags.0\ttags.1\tscore\n\
+                                Ann, B\tOslo\t0150\ta\tb\t\n\
+                                Bo\tRome\t\tc\t\t2.5\n");
+
+    // Fields nobody has still get a column.
+    let csv = bson::csv::export(people().as_slice(), &["name", "missing"], &CsvOptions::new());
+    assert_eq!(csv.as_slice().lines().next(), Some("name,missing"));
+}
+
+#[test]
+fn csv_import() {
+    use bson::csv::CsvOptions;
+
+    let text = "name.string(),age.int32(),created.date(2006-01-02),address.zip.string(),score,id\n\
+                \"Smith, J\",42,2012-12-24,0150,2.5,ObjectId(57e193d7a9cc81b4027498b5)\r\n\
+                \n\
+                Bo,7,1970-01-02,,3,x,extra\n";
+    let docs = bson::csv::import(text, &CsvOptions::new()).unwrap();
+    assert_eq!(docs.len(), 2);
+    assert_eq!(field(&docs[0], "name"), bson::String("Smith, J".to_string()));
+    assert_eq!(field(&docs[0], "age"), bson::Int32(42));
+    assert_eq!(field(&docs[0], "created"), bson::UtcDatetime(1356307200000));
+    assert_eq!(field(&field(&docs[0], "address"), "zip"), bson::String("0150".to_string()));
+    assert_eq!(field(&docs[0], "score"), bson::Float(2.5f64));
+    assert_eq!(field(&docs[0], "id"), bson::ObjectId(vec!(0x57,0xe1,0x93,0xd7,0xa9,0xcc,0x81,0xb4,0x02,0x74,0x98,0xb5)));
+    assert_eq!(field(&docs[1], "created"), bson::UtcDatetime(86400000));
+    assert_eq!(field(&field(&docs[1], "address"), "zip"), bson::String(String::new()));
+    assert_eq!(field(&docs[1], "score"), bson::Int32(3));
+    assert_eq!(field(&docs[1], "id"), bson::String("x".to_string()));
+    assert_eq!(field(&docs[1], "field6"), bson::String("extra".to_string()));
+
+    let options = CsvOptions { ignore_blanks: true, ..CsvOptions::tsv() };
+    let docs = bson::csv::import("a.b\tc\n\t1\n", &options).unwrap();
+    assert_eq!(bson::csv::export(docs.as_slice(), &["a", "c"], &CsvOptions::new()).as_slice(), "a,c\n,1\n");
+
+    let dates = "ms.date_ms(yyyy-MM-dd HH:mm:ss.fff),go.date(Jan _2 2006 3:04PM),iso.date(2006-01-02T15:04:05Z07:00)\n\
+                 2012-12-24 12:15:30.501,Dec 24 2012 12:15PM,2012-12-24T13:15:30.501+01:00\n";
+    let docs = bson::csv::import(dates, &CsvOptions::new()).unwrap();
+    assert_eq!(field(&docs[0], "ms"), bson::UtcDatetime(1356351330501));
+    assert_eq!(field(&docs[0], "go"), bson::UtcDatetime(1356351300000));
+    assert_eq!(field(&docs[0], "iso"), bson::UtcDatetime(1356351330501));
+}
+
+#[test]
+fn csv_import_errors() {
+    use bson::csv::CsvOptions;
+
+    let err = |text: &str| {
+        let e = bson::csv::import(text, &CsvOptions::new()).err().unwrap();
+        (e.line, e.column)
+    };
+    assert_eq!(err("a.int32(),b\n1,2\nx,3\n"), (3, 1));
+    assert_eq!(err("a,b.nope()\n"), (1, 2));
+    assert_eq!(err("a,a\n"), (1, 2));
+    assert_eq!(err("a,b\n1,\"2\n3\n"), (2, 2));
+    assert_eq!(err("a,a.b\n1,2\n"), (2, 2));
+    assert_eq!(err("d.date(2006-01-02)\n2012-02-30\n"), (2, 1));
+}
+
+// Types deriving the standard library's traits and serde's, kept apart
+// from the `bson::serialize` imports above.
+mod bridged {
+    use std::collections::TreeMap;
+    use bson::bridge::{ObjectId,DateTime,Decimal128};
+
+    #[deriving(Encodable, Decodable, PartialEq, Clone, Show)]
+    #[deriving_serializable]
+    #[deriving_deserializable]
+    pub enum Kind {
+        Personal,
+        Joint(String),
+    }
+
+    #[deriving(Encodable, Decodable, PartialEq, Clone, Show)]
+    #[deriving_serializable]
+    #[deriving_deserializable]
+    pub struct Account {
+        pub _id: ObjectId,
+        pub owner: String,
+        pub opened: DateTime,
+        pub balance: Decimal128,
+        pub limit: Option<i32>,
+        pub kind: Kind,
+        pub tags: Vec<String>,
+        pub rates: TreeMap<String, f64>,
+    }
+
+    pub fn account() -> Account {
+        let mut rates = TreeMap::new();
+        rates.insert("savings".to_string(), 0.5f64);
+        Account {
+            _id: ObjectId(vec!(0x57,0xe1,0x93,0xd7,0xa9,0xcc,0x81,0xb4,0x02,0x74,0x98,0xb5)),
+            owner: "Ann".to_string(),
+            opened: DateTime(1356351330501),
+            balance: Decimal128(vec!(0x0F,0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+                                     0x00,0x00,0x00,0x00,0x00,0x00,0x3E,0x30)),
+            limit: None,
+            kind: Joint("Bo".to_string()),
+            tags: vec!("new".to_string()),
+            rates: rates,
+        }
+    }
+}
+
+#[test]
+fn bridge_roundtrip() {
+    let account = bridged::account();
+    let doc = bson::Object(bson::bridge::to_document(&account).unwrap());
+    assert_eq!(field(&doc, "_id"), bson::ObjectId(vec!(0x57,0xe1,0x93,0xd7,0xa9,0xcc,0x81,0xb4,0x02,0x74,0x98,0xb5)));
+    assert_eq!(field(&doc, "opened"), bson::UtcDatetime(1356351330501));
+    assert_eq!(field(&doc, "balance"), one_and_a_half());
+    assert_eq!(field(&doc, "limit"), bson::Null);
+    assert_eq!(field(&field(&doc, "kind"), "Joint"), bson::List(vec!(bson::String("Bo".to_string()))));
+    assert_eq!(field(&field(&doc, "rates"), "savings"), bson::Float(0.5f64));
+
+    let bytes = bson::bridge::to_vec(&account).unwrap();
+    let back: bridged::Account = bson::bridge::from_slice(bytes.as_slice()).unwrap();
+    assert_eq!(back, account);
+
+    let mut other = account.clone();
+    other.limit = Some(100);
+    other.kind = bridged::Personal;
+    let back: bridged::Account = bson::bridge::from_bson(bson::bridge::to_bson(&other).unwrap()).unwrap();
+    assert_eq!(back, other);
+
+    // The same types still work with JSON, with the markers as Extended JSON.
+    let text = json::encode(&account);
+    assert!(text.as_slice().contains(r#""_id":{"$oid":"57e193d7a9cc81b4027498b5"}"#));
+    assert!(text.as_slice().contains(r#""balance":{"$numberDecimal":"1.5"}"#));
+    let back: bridged::Account = json::decode(text.as_slice()).unwrap();
+    assert_eq!(back, account);
+}
+
+#[test]
+fn bridge_errors() {
+    let mut doc = bson::bridge::to_document(&bridged::account()).unwrap();
+    doc.insert("opened".to_string(), bson::String("yesterday".to_string()));
+    let res: bson::DecodeResult<bridged::Account> = bson::bridge::from_document(doc.clone());
+    match res.err().unwrap() {
+        bson::ExpectedType { path, expected, .. } => {
+            assert_eq!(path, "opened".to_string());
+            assert_eq!(expected, "object".to_string());
+        },
+        other => fail!("unexpected error: {}", other),
+    }
+
+    doc.remove(&"opened".to_string());
+    let res: bson::DecodeResult<bridged::Account> = bson::bridge::from_document(doc.clone());
+    match res.err().unwrap() {
+        bson::MissingField { path } => assert_eq!(path, "opened".to_string()),
+        other => fail!("unexpected error: {}", other),
+    }
+
+    doc.insert("opened".to_string(), bson::UtcDatetime(0));
+    doc.insert("tags".to_string(), bson::List(vec!(bson::Int32(1))));
+    let res: bson::DecodeResult<bridged::Account> = bson::bridge::from_document(doc);
+    match res.err().unwrap() {
+        bson::ExpectedType { path, .. } => assert_eq!(path, "tags[0]".to_string()),
+        other => fail!("unexpected error: {}", other),
+    }
+
+    assert!(bson::bridge::to_document(&vec!(1i32)).is_err());
+
+    // Doubles are read as strictly as `bson::Decoder` reads them.
+    let mut doc = bson::bridge::to_document(&bridged::account()).unwrap();
+    let mut rates = TreeMap::new();
+    rates.insert("savings".to_string(), bson::Int32(1));
+    doc.insert("rates".to_string(), bson::Object(rates));
+    let res: bson::DecodeResult<bridged::Account> = bson::bridge::from_document(doc);
+    match res.err().unwrap() {
+        bson::ExpectedType { path, expected, .. } => {
+            assert_eq!(path, "rates.savings".to_string());
+            assert_eq!(expected, "double".to_string());
+        },
+        other => fail!("unexpected error: {}", other),
+    }
+    let res: bson::DecodeResult<f32> = bson::bridge::from_bson(bson::Float(1e300));
+    match res.err().unwrap() {
+        bson::OutOfRange { target, .. } => assert_eq!(target, "f32".to_string()),
+        other => fail!("unexpected error: {}", other),
+    }
+    let res: bson::DecodeResult<f32> = bson::bridge::from_bson(bson::Float(1.5));
+    assert_eq!(res.unwrap(), 1.5f32);
+}
+
+#[test]
+fn serde_roundtrip() {
+    let account = bridged::account();
+    let doc = bson::bridge::serde::to_document(&account).unwrap();
+    assert_eq!(doc, bson::bridge::to_document(&account).unwrap());
+
+    // Written straight to bytes, in field order, and read straight back.
+    let bytes = bson::bridge::serde::to_vec(&account).unwrap();
+    assert_eq!(bson::from_slice(bytes.as_slice()).unwrap(), doc);
+    let back: bridged::Account = bson::bridge::serde::from_slice(bytes.as_slice()).unwrap();
+    assert_eq!(back, account);
+
+    let mut other = account.clone();
+    other.limit = Some(100);
+    other.kind = bridged::Personal;
+    let back: bridged::Account = bson::bridge::serde::from_bson(bson::bridge::serde::to_bson(&other).unwrap()).unwrap();
+    assert_eq!(back, other);
+    let bytes = bson::bridge::serde::to_vec(&other).unwrap();
+    let back: bridged::Account = bson::bridge::serde::from_slice(bytes.as_slice()).unwrap();
+    assert_eq!(back, other);
+
+    // What one bridge writes, the other reads.
+    let back: bridged::Account = bson::bridge::serde::from_document(bson::bridge::to_document(&account).unwrap()).unwrap();
+    assert_eq!(back, account);
+}
+
+#[test]
+fn serde_errors() {
+    let mut doc = bson::bridge::serde::to_document(&bridged::account()).unwrap();
+    doc.insert("opened".to_string(), bson::String("yesterday".to_string()));
+    let res: bson::DecodeResult<bridged::Account> = bson::bridge::serde::from_document(doc.clone());
+    match res.err().unwrap() {
+        bson::ExpectedType { path, expected, .. } => {
+            assert_eq!(path, "opened".to_string());
+            assert_eq!(expected, "object".to_string());
+        },
+        other => fail!("unexpected error: {}", other),
+    }
+
+    doc.remove(&"opened".to_string());
+    let res: bson::DecodeResult<bridged::Account> = bson::bridge::serde::from_document(doc);
+    match res.err().unwrap() {
+        bson::MissingField { path } => assert_eq!(path, "opened".to_string()),
+        other => fail!("unexpected error: {}", other),
+    }
+
+    assert!(bson::bridge::serde::to_document(&vec!(1i32)).is_err());
+    assert!(bson::bridge::serde::to_vec(&vec!(1i32)).is_err());
+
+    let bytes = bson::bridge::serde::to_vec(&bridged::account()).unwrap();
+    let res: bson::DecodeResult<bridged::Account> = bson::bridge::serde::from_slice(bytes.slice_to(bytes.len() - 1));
+    match res.err().unwrap() {
+        bson::ParseError(_) => (),
+        other => fail!("unexpected error: {}", other),
+    }
+}
+
+fn account_schema() -> bson::schema::Validator {
+    let schema = bson::shell::from_shell(r#"{ '$jsonSchema': {
+        bsonType: 'object',
+        required: ['name', 'age'],
+        properties: {
+            _id: {},
+            name: { bsonType: 'string', minLength: 2 },
+            age: { bsonType: ['int', 'long'], minimum: 0 },
+            tags: { bsonType: 'array', items: { enum: ['a', 'b'] } },
+        },
+        additionalProperties: false,
+    } }"#).unwrap();
+    bson::schema::Validator::new(&schema).unwrap()
+}
+
+#[test]
+fn schema_validate() {
+    let validator = account_schema();
+    let good = bson::shell::from_shell("{ _id: 1, name: 'Ann', age: 30, tags: ['a'] }").unwrap();
+    assert!(validator.is_valid(&good));
+    assert!(validator.validate(&good).is_ok());
+
+    let bad = bson::shell::from_shell("{ _id: 2, name: 'A', tags: ['a', 'c'], extra: true }").unwrap();
+    let info = bson::Object(validator.validate(&bad).err().unwrap());
+    let expected = bson::shell::from_shell(r#"{
+        failingDocumentId: 2,
+        details: { operatorName: '$jsonSchema', schemaRulesNotSatisfied: [
+            { operatorName: 'additionalProperties', specifiedAs: { additionalProperties: false },
+              additionalProperties: ['extra'] },
+            { operatorName: 'properties', propertiesNotSatisfied: [
+                { propertyName: 'name', details: [
+                    { operatorName: 'minLength', specifiedAs: { minLength: 2 },
+                      reason: 'specified string length was not satisfied', consideredValue: 'A' },
+                ] },
+                { propertyName: 'tags', details: [
+                    { operatorName: 'items', reason: 'At least one item did not match the sub-schema',
+                      itemIndex: 1, details: [
+                        { operatorName: 'enum', specifiedAs: { enum: ['a', 'b'] },
+                          reason: 'value was not found in enum', consideredValue: 'c' },
+                    ] },
+                ] },
+            ] },
+            { operatorName: 'required', specifiedAs: { required: ['name', 'age'] },
+              missingProperties: ['age'] },
+        ] },
+    }"#).unwrap();
+    assert_eq!(info, expected);
+
+    // Keywords for one type let values of other types through.
+    let schema = bson::shell::from_shell("{ minimum: 5, pattern: '^a', oneOf: [{ bsonType: 'string' }, { enum: [1.0] }] }").unwrap();
+    let validator = bson::schema::Validator::new(&schema).unwrap();
+    assert!(validator.is_valid(&bson::String("abc".to_string())));
+    assert!(!validator.is_valid(&bson::String("bc".to_string())));
+    assert!(!validator.is_valid(&bson::Int32(1)));
+    assert!(!validator.is_valid(&bson::Null));
+}
+
+#[test]
+fn schema_errors() {
+    let err = |text: &str| {
+        let schema = bson::shell::from_shell(text).unwrap();
+        bson::schema::Validator::new(&schema).err().unwrap().path
+    };
+    assert_eq!(err("{ properties: { a: { bsonType: 'integer' } } }"), "properties.a.bsonType".to_string());
+    assert_eq!(err("{ '$jsonSchema': { format: 'email' } }"), "format".to_string());
+    assert_eq!(err("{ anyOf: [{}, { pattern: '(' }] }"), "anyOf.1.pattern".to_string());
+    assert_eq!(err("{ exclusiveMinimum: true }"), "exclusiveMinimum".to_string());
+    assert_eq!(err("{ required: [] }"), "required".to_string());
+}
+
+#[test]
+fn infer_schema() {
+    let docs = vec!(
+        "{ name: 'Ann', age: 30, tags: ['a', 'b'], address: { city: 'Oslo' } }",
+        "{ name: 'Bo', age: 2.5, tags: [] }",
+        "{ name: 'Cy', tags: ['c'], address: { city: 'Rome', zip: '00100' } }",
+    ).into_iter().map(|s| bson::shell::from_shell(s).unwrap()).collect::<Vec<Bson>>();
+    let inference = bson::infer::infer(docs.clone().into_iter());
+    assert_eq!(inference.documents, 3);
+
+    let age = inference.fields.find(&"age".to_string()).unwrap();
+    assert_eq!((age.count("int"), age.count("double"), age.count("string")), (1, 1, 0));
+    assert_eq!(age.min, Some(bson::Float(2.5f64)));
+    assert_eq!(age.max, Some(bson::Int32(30)));
+    assert_eq!(inference.presence("age"), 2.0 / 3.0);
+    let name = inference.fields.find(&"name".to_string()).unwrap();
+    assert_eq!(name.string_lengths, Some((2, 3)));
+    let tags = inference.fields.find(&"tags".to_string()).unwrap();
+    assert_eq!(tags.array_lengths.iter().map(|(len, n)| (*len, *n)).collect::<Vec<(uint, uint)>>(),
+               vec!((0, 1), (1, 1), (2, 1)));
+    let elements = inference.fields.find(&"tags.$[]".to_string()).unwrap();
+    assert_eq!((elements.values, elements.documents), (3, 2));
+    assert_eq!(inference.presence("address.zip"), 1.0 / 3.0);
+    assert_eq!(inference.presence("missing"), 0.0);
+
+    let schema = inference.to_schema();
+    let expected = bson::shell::from_shell(r#"{
+        bsonType: 'object',
+        required: ['name', 'tags'],
+        properties: {
+            address: {
+                bsonType: 'object',
+                required: ['city'],
+                properties: {
+                    city: { bsonType: 'string', minLength: 4, maxLength: 4 },
+                    zip: { bsonType: 'string', minLength: 5, maxLength: 5 },
+                },
+            },
+            age: { bsonType: ['double', 'int'], minimum: 2.5, maximum: 30 },
+            name: { bsonType: 'string', minLength: 2, maxLength: 3 },
+            tags: {
+                bsonType: 'array', minItems: 0, maxItems: 2,
+                items: { bsonType: 'string', minLength: 1, maxLength: 1 },
+            },
+        },
+    }"#).unwrap();
+    assert_eq!(schema, expected);
+
+    // The draft accepts everything it was drawn from.
+    let validator = bson::schema::Validator::new(&schema).unwrap();
+    assert!(docs.iter().all(|doc| validator.is_valid(doc)));
+
+    // No bound holds for NaN, so a field that had one gets none.
+    let docs = vec!("{ x: 1 }", "{ x: NaN }", "{ x: 3.5 }").into_iter()
+        .map(|s| bson::shell::from_shell(s).unwrap()).collect::<Vec<Bson>>();
+    let schema = bson::infer::infer(docs.clone().into_iter()).to_schema();
+    let x = field(&field(&schema, "properties"), "x");
+    assert_eq!(x, bson::shell::from_shell("{ bsonType: ['double', 'int'] }").unwrap());
+    let validator = bson::schema::Validator::new(&schema).unwrap();
+    assert!(docs.iter().all(|doc| validator.is_valid(doc)));
+}
+
+#[test]
+fn redact_paths() {
+    use bson::redact::{redact,RedactOptions,KeyedHash};
+
+    let doc = bson::shell::from_shell(r#"{
+        user: { name: 'ann', ssn: '123-45-6789' },
+        logins: [{ at: 1, password: 'x' }, { at: 2, password: 'x' }],
+        db: { password: 'z', nested: { password: 'deeper' } },
+        ssn: 'top level',
+    }"#).unwrap();
+    let redacted = redact(&doc, &RedactOptions::new(&["user.ssn", "*.password", "logins.1.at"]));
+    let expected = bson::shell::from_shell(r#"{
+        user: { name: 'ann', ssn: '<redacted>' },
+        logins: [{ at: 1, password: '<redacted>' }, { at: '<redacted>', password: '<redacted>' }],
+        db: { password: '<redacted>', nested: { password: 'deeper' } },
+        ssn: 'top level',
+    }"#).unwrap();
+    assert_eq!(redacted, expected);
+
+    // Equal values hash alike under the same key, and differently under another.
+    let options = RedactOptions { mask: KeyedHash(1, 2), ..RedactOptions::new(&["**.pass*"]) };
+    let hashed = redact(&doc, &options);
+    let logins = field(&hashed, "logins");
+    let first = match logins {
+        bson::List(ref items) => {
+            assert_eq!(field(&items[0], "password"), field(&items[1], "password"));
+            field(&items[0], "password")
+        },
+        other => fail!("expected an array, got {}", other),
+    };
+    assert!(first != bson::String("x".to_string()));
+    assert!(field(&field(&field(&hashed, "db"), "nested"), "password") != bson::String("deeper".to_string()));
+    let rekeyed = redact(&doc, &RedactOptions { mask: KeyedHash(3, 4), ..options.clone() });
+    assert!(field(&rekeyed, "logins") != logins);
+}
+
+#[test]
+fn redact_commands() {
+    use bson::redact::{redact,redact_reply,RedactOptions,KeyedHash};
+
+    let options = RedactOptions::new(&[]);
+    let redacted = |text: &str| redact(&bson::shell::from_shell(text).unwrap(), &options);
+    assert_eq!(redacted("{ saslStart: 1, mechanism: 'PLAIN', payload: 'secret' }"),
+               bson::shell::from_shell("{ saslStart: '<redacted>' }").unwrap());
+    assert_eq!(redacted("{ createUser: 'ann', pwd: 'secret', roles: [] }"),
+               bson::shell::from_shell("{ createUser: '<redacted>' }").unwrap());
+    assert_eq!(redacted("{ authenticate: 1, user: 'ann', mechanism: 'MONGODB-X509' }"),
+               bson::shell::from_shell("{ authenticate: '<redacted>' }").unwrap());
+    assert_eq!(redacted("{ hello: 1, speculativeAuthenticate: { saslStart: 1 } }"),
+               bson::shell::from_shell("{ hello: '<redacted>' }").unwrap());
+    let find = "{ find: 'users', filter: { name: 'ann' } }";
+    assert_eq!(redacted(find), bson::shell::from_shell(find).unwrap());
+
+    // A hash would fingerprint the password, so commands never get one.
+    let hashing = RedactOptions { mask: KeyedHash(1, 2), ..RedactOptions::new(&["filter.name"]) };
+    assert_eq!(redact(&bson::shell::from_shell("{ createUser: 'ann', pwd: 'secret' }").unwrap(), &hashing),
+               bson::shell::from_shell("{ createUser: '<redacted>' }").unwrap());
+
+    let reply = |text: &str, command: &str| redact_reply(&bson::shell::from_shell(text).unwrap(), command, &hashing);
+    let empty = bson::Object(TreeMap::new());
+    assert_eq!(reply("{ conversationId: 1, done: false, payload: BinData(0, 'c2VjcmV0'), ok: 1 }", "saslStart"), empty);
+    assert_eq!(reply("{ conversationId: 1, done: true, payload: BinData(0, ''), ok: 1 }", "saslContinue"), empty);
+    assert_eq!(reply("{ isWritablePrimary: true, speculativeAuthenticate: { payload: 'x' }, ok: 1 }", "hello"), empty);
+    let hello = "{ isWritablePrimary: true, maxWireVersion: 17, ok: 1 }";
+    assert_eq!(reply(hello, "hello"), bson::shell::from_shell(hello).unwrap());
+    let found = reply("{ filter: { name: 'ann' }, ok: 1 }", "find");
+    assert!(field(&field(&found, "filter"), "name") != bson::String("ann".to_string()));
+}
+
+#[test]
+fn cbor_roundtrip() {
+    let id = vec!(0x57,0xe1,0x93,0xd7,0xa9,0xcc,0x81,0xb4,0x02,0x74,0x98,0xb5);
+    let mut scope = TreeMap::new();
+    scope.insert("a".to_string(), bson::Int32(1));
+    let mut doc = match extjson_document() {
+        bson::Object(obj) => obj,
+        _ => unreachable!(),
+    };
+    doc.insert("code".to_string(), bson::JavaScriptWithScope("a + 1".to_string(), scope));
+    doc.insert("js".to_string(), bson::JavaScript("f()".to_string()));
+    doc.insert("list".to_string(), bson::List(vec!(bson::Boolean(true), bson::Int64(1 << 40), bson::Binary(0, vec!(1, 2)))));
+    doc.insert("min".to_string(), bson::MinKey);
+    doc.insert("n".to_string(), bson::Null);
+    doc.insert("plain".to_string(), bson::RegExp("x+".to_string(), String::new()));
+    doc.insert("ptr".to_string(), bson::DbPointer("db.coll".to_string(), id));
+    doc.insert("secs".to_string(), bson::UtcDatetime(1356351330000));
+    doc.insert("sym".to_string(), bson::Symbol("s".to_string()));
+    doc.insert("u".to_string(), bson::Undefined);
+    doc.insert("uuid".to_string(), bson::Binary(4, Vec::from_elem(16, 0xab)));
+    let doc = bson::Object(doc);
+
+    let (bytes, losses) = bson::cbor::to_cbor(&doc);
+    assert!(losses.is_empty());
+    let (back, losses) = bson::cbor::from_cbor(bytes.as_slice()).unwrap();
+    assert!(losses.is_empty());
+    assert_eq!(back, doc);
+
+    // Plain values take their shortest form, but longs stay eight bytes wide.
+    let encoded = |v: bson::Bson| {
+        let (bytes, _) = bson::cbor::to_cbor(&v);
+        bytes
+    };
+    assert_eq!(encoded(bson::Int32(500)), vec!(0x19, 0x01, 0xf4));
+    assert_eq!(encoded(bson::Int32(-1)), vec!(0x20));
+    assert_eq!(encoded(bson::Int64(1)), vec!(0x1b, 0, 0, 0, 0, 0, 0, 0, 1));
+    assert_eq!(encoded(bson::UtcDatetime(1363896240000)), vec!(0xc1, 0x1a, 0x51, 0x4b, 0x67, 0xb0));
+    assert_eq!(encoded(bson::String("a".to_string())), vec!(0x61, 0x61));
+
+    let (bytes, losses) = bson::cbor::to_cbor(&bson::RawString(vec!(0x61, 0xff)));
+    assert_eq!(bytes, vec!(0x42, 0x61, 0xff));
+    assert_eq!(losses.len(), 1);
+
+    // Inline flags in the pattern itself come back as options.
+    let (bytes, losses) = bson::cbor::to_cbor(&bson::RegExp("(?i)abc".to_string(), String::new()));
+    assert_eq!(losses.len(), 1);
+    let (back, _) = bson::cbor::from_cbor(bytes.as_slice()).unwrap();
+    assert_eq!(back, bson::RegExp("abc".to_string(), "i".to_string()));
+    let (_, losses) = bson::cbor::to_cbor(&bson::RegExp("(?x)abc".to_string(), "i".to_string()));
+    assert!(losses.is_empty());
+    let (_, losses) = bson::cbor::to_cbor(&bson::UtcDatetime(std::i64::MAX));
+    assert_eq!(losses.len(), 1);
+}
+
+#[test]
+fn cbor_from_foreign() {
+    let read = |bytes: &[u8]| {
+        let (v, losses) = bson::cbor::from_cbor(bytes).unwrap();
+        (v, losses.iter().map(|l| l.to_string()).collect::<Vec<String>>())
+    };
+    // Examples from RFC 8949, appendix A.
+    assert_eq!(read(&[0xf9, 0x3c, 0x00]), (bson::Float(1.0), vec!()));
+    assert_eq!(read(&[0xfa, 0x47, 0xc3, 0x50, 0x00]), (bson::Float(100000.0), vec!()));
+    assert_eq!(read(&[0x9f, 0x01, 0x82, 0x02, 0x03, 0xff]),
+               (bson::List(vec!(bson::Int32(1), bson::List(vec!(bson::Int32(2), bson::Int32(3))))), vec!()));
+    assert_eq!(read(b"\x7f\x65strea\x64ming\xff"), (bson::String("streaming".to_string()), vec!()));
+    assert_eq!(read(b"\xc0\x742013-03-21T20:04:00Z"), (bson::UtcDatetime(1363896240000), vec!()));
+
+    let (v, losses) = read(&[0xa1, 0x01, 0xa1, 0x61, 0x62, 0xf0]);
+    assert_eq!(v, bson::shell::from_shell("{ '1': { b: null } }").unwrap());
+    assert_eq!(losses, vec!("map key 1 isn't text, so it became a string".to_string(),
+                            "1.b: simple value 16 has no BSON counterpart, so it was read as null".to_string()));
+    let (v, losses) = read(b"\xd8\x20\x76http://www.example.com");
+    assert_eq!(v, bson::String("http://www.example.com".to_string()));
+    assert_eq!(losses, vec!("tag 32 has no BSON counterpart, so it was dropped".to_string()));
+    let (v, losses) = read(&[0x1b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(v, bson::Float(18446744073709551615.0));
+    assert_eq!(losses.len(), 1);
+
+    let offset = |bytes: &[u8]| bson::cbor::from_cbor(bytes).err().unwrap().offset;
+    assert_eq!(offset(&[0x18]), 1);
+    assert_eq!(offset(&[0x01, 0x02]), 1);
+    assert_eq!(offset(&[0x82, 0x01, 0x1c]), 2);
+    assert_eq!(offset(&[0xff]), 0);
+    assert_eq!(offset(&[0x62, 0xff, 0xfe]), 0);
+    assert_eq!(offset(&[0x5a, 0xff, 0xff, 0xff, 0xff]), 0);
+
+    // Tags nest as deeply as arrays can, and no deeper.
+    let tags = Vec::from_elem(1_000_000, 0xc6u8);
+    let err = bson::cbor::from_cbor(tags.as_slice()).err().unwrap();
+    assert_eq!((err.offset, err.msg), (100, "nested too deeply".to_string()));
+    let mixed: Vec<u8> = range(0u, 1000).flat_map(|_| vec!(0x81u8, 0xc6).into_iter()).collect();
+    assert_eq!(offset(mixed.as_slice()), 100);
+}
+
+#[test]
+fn jsonpath_query() {
+    let doc = bson::shell::from_shell(r#"{
+        store: 'main',
+        orders: [
+            { id: 1, total: 50, items: [{ sku: 'a' }] },
+            { id: 2, total: 150.5, items: [{ sku: 'b' }, { sku: 'c' }] },
+            { id: 3, total: NumberLong(200), status: 'void' },
+            { id: 4, total: 'n/a' },
+        ],
+    }"#).unwrap();
+    let ids = |expr: &str| {
+        bson::jsonpath::query(expr, &doc).unwrap().into_iter().map(|v| match *v {
+            bson::Int32(n) => n,
+            ref other => fail!("expected an int, got {}", other),
+        }).collect::<Vec<i32>>()
+    };
+    // Numbers compare across widths, but not with strings.
+    assert_eq!(ids("$.orders[?(@.total > 100)].id"), vec!(2, 3));
+    assert_eq!(ids("$.orders[?(@.total != 50)].id"), vec!(2, 3, 4));
+    assert_eq!(ids("$.orders[?(@.status)].id"), vec!(3));
+    assert_eq!(ids("$.orders[? !@.status && (@.total >= 150.5 || @.id == 1)].id"), vec!(1, 2));
+    assert_eq!(ids("$.orders[?(@.total == $.orders[0].total)].id"), vec!(1));
+    assert_eq!(ids("$.orders[*].id"), vec!(1, 2, 3, 4));
+    assert_eq!(ids("$.orders[-1:].id"), vec!(4));
+    assert_eq!(ids("$.orders[::-2].id"), vec!(4, 2));
+    assert_eq!(ids("$.orders[1:3]['id']"), vec!(2, 3));
+    assert_eq!(ids("$.orders[0, 2].id"), vec!(1, 3));
+    assert_eq!(ids("$.orders[9].id"), vec!());
+    assert_eq!(ids("$.orders[1::9223372036854775807].id"), vec!(2));
+    assert_eq!(ids("$.orders[-9223372036854775808::9223372036854775807].id"), vec!(1));
+    assert_eq!(ids("$.orders[3::-9223372036854775808].id"), vec!(4));
+
+    let skus: Vec<bson::Bson> = bson::jsonpath::query("$..sku", &doc).unwrap().into_iter().map(|v| v.clone()).collect();
+    assert_eq!(skus, vec!(bson::String("a".to_string()), bson::String("b".to_string()), bson::String("c".to_string())));
+    assert_eq!(bson::jsonpath::query("$", &doc).unwrap(), vec!(&doc));
+    assert_eq!(bson::jsonpath::query("$.store", &doc).unwrap(), vec!(&bson::String("main".to_string())));
+
+    let pos = |expr: &str| bson::jsonpath::JsonPath::parse(expr).err().unwrap().pos;
+    assert_eq!(pos("orders"), 0);
+    assert_eq!(pos("$.orders["), 9);
+    assert_eq!(pos("$.orders[?(@.total ==)]"), 21);
+    assert_eq!(pos("$.a b"), 3);
+}
+
+#[test]
+fn json_patch() {
+    use bson::patch::{parse_patch,apply_patch};
+
+    let shell = |text: &str| bson::shell::from_shell(text).unwrap();
+    let doc = shell(r#"{
+        _id: ObjectId('57e193d7a9cc81b4027498b5'), name: 'ann', tags: ['a', 'b'],
+        seen: ISODate('2012-12-24T12:15:30.501Z'), 'a/b': { 'c~d': 1 },
+    }"#);
+    let patch = parse_patch(&shell(r#"[
+        { op: 'add', path: '/tags/1', value: 'x' },
+        { op: 'add', path: '/tags/-', value: 'z' },
+        { op: 'remove', path: '/tags/0' },
+        { op: 'replace', path: '/name', value: 'bo' },
+        { op: 'move', from: '/a~1b/c~0d', path: '/count' },
+        { op: 'copy', from: '/seen', path: '/last' },
+        { op: 'test', path: '/count', value: 1.0 },
+        { op: 'test', path: '/_id', value: ObjectId('57e193d7a9cc81b4027498b5') },
+    ]"#)).unwrap();
+    assert_eq!(apply_patch(&doc, patch.as_slice()).unwrap(), shell(r#"{
+        _id: ObjectId('57e193d7a9cc81b4027498b5'), name: 'bo', tags: ['x', 'b', 'z'],
+        seen: ISODate('2012-12-24T12:15:30.501Z'), last: ISODate('2012-12-24T12:15:30.501Z'),
+        'a/b': {}, count: 1,
+    }"#));
+    let replaced = parse_patch(&shell("[{ op: 'replace', path: '', value: NumberDecimal('1.5') }]")).unwrap();
+    assert_eq!(apply_patch(&doc, replaced.as_slice()).unwrap(), one_and_a_half());
+
+    let failure = |text: &str| {
+        let ops = parse_patch(&shell(text)).unwrap();
+        let e = apply_patch(&doc, ops.as_slice()).err().unwrap();
+        (e.index, e.msg)
+    };
+    assert_eq!(failure("[{ op: 'remove', path: '/nope' }]"), (0, "no field `nope`".to_string()));
+    assert_eq!(failure("[{ op: 'add', path: '/tags/3', value: 1 }]"), (0, "index 3 is out of bounds".to_string()));
+    assert_eq!(failure("[{ op: 'add', path: '/tags/01', value: 1 }]"), (0, "`01` isn't an array index".to_string()));
+    assert_eq!(failure("[{ op: 'move', from: '/a~1b', path: '/a~1b/inner' }]"),
+               (0, "can't move a value inside itself".to_string()));
+    assert_eq!(failure("[{ op: 'replace', path: '/name', value: 'x' }, { op: 'test', path: '/name', value: 'y' }]"),
+               (1, r#"test failed: found "x""#.to_string()));
+
+    let parse_failure = |text: &str| {
+        let e = parse_patch(&shell(text)).err().unwrap();
+        (e.index, e.msg)
+    };
+    assert_eq!(parse_failure("[{ op: 'remove', path: '/a' }, { op: 'add', path: '/x' }]"), (1, "missing `value`".to_string()));
+    assert_eq!(parse_failure("[{ op: 'frob', path: '' }]"), (0, "unknown operation `frob`".to_string()));
+}
+
+#[test]
+fn json_merge_patch() {
+    use bson::patch::merge_patch;
+
+    let shell = |text: &str| bson::shell::from_shell(text).unwrap();
+    // The example from RFC 7396.
+    let target = shell(r#"{
+        title: 'Goodbye!', author: { givenName: 'John', familyName: 'Doe' },
+        tags: ['example', 'sample'], content: 'This will be unchanged',
+    }"#);
+    let patch = shell(r#"{
+        title: 'Hello!', phoneNumber: '+01-123-456-7890', author: { familyName: null }, tags: ['example'],
+    }"#);
+    assert_eq!(merge_patch(&target, &patch), shell(r#"{
+        title: 'Hello!', author: { givenName: 'John' }, tags: ['example'],
+        content: 'This will be unchanged', phoneNumber: '+01-123-456-7890',
+    }"#));
+
+    let target = shell("{ _id: ObjectId('57e193d7a9cc81b4027498b5'), at: Timestamp(1, 2), n: 1 }");
+    let patch = shell("{ n: NumberDecimal('1.5'), at: { t: 1 } }");
+    assert_eq!(merge_patch(&target, &patch), shell(r#"{
+        _id: ObjectId('57e193d7a9cc81b4027498b5'), at: { t: 1 }, n: NumberDecimal('1.5'),
+    }"#));
+    assert_eq!(merge_patch(&target, &bson::MaxKey), bson::MaxKey);
+}
@@ -0,0 +1,157 @@
+#![feature(struct_variant)]
+
+//! Reads a stream of BSON documents, like a `.bson` file from mongodump,
+//! and writes them out one per line.
+//!
+//!     bsondump [--format=canonical|relaxed|shell] [--debug | --validate] [FILE]
+//!
+//! With no file, or `-`, documents are read from stdin.
+
+extern crate serialize;
+extern crate regex;
+extern crate serde;
+
+use std::{io,os};
+use std::io::{File,BufferedReader};
+use bson::{Bson,DocumentReader};
+use bson::shell::{to_shell,ShellOptions};
+
+mod bson;
+
+static USAGE: &'static str = "usage: bsondump [--format=canonical|relaxed|shell] [--debug | --validate] [FILE]";
+
+enum Format {
+    Canonical,
+    Relaxed,
+    Shell,
+}
+
+enum Mode {
+    /// One document per line in the chosen format.
+    Print(Format),
+    /// The element-by-element layout of each document.
+    Debug,
+    /// Nothing but a count, or where the first bad document starts.
+    Validate,
+}
+
+fn format(doc: &Bson, format: &Format) -> String {
+    match *format {
+        Canonical => bson::to_canonical_extjson(doc),
+        Relaxed => bson::to_relaxed_extjson(doc),
+        // Shell output on one line, so it's still one document per line.
+        Shell => to_shell(doc, &ShellOptions { indent: 0, width: std::uint::MAX }),
+    }
+}
+
+fn fail(msg: &str) -> bool {
+    let _ = io::stderr().write_line(msg);
+    false
+}
+
+/// Runs `mode` over everything in `input`, returning whether it was all
+/// good.
+fn run<R: Reader>(input: R, mode: Mode) -> bool {
+    let mut docs = DocumentReader::new(BufferedReader::new(input));
+    let mut out = io::stdout();
+    let mut count = 0u;
+    let mut ok = true;
+    loop {
+        let res = match mode {
+            Debug => {
+                let annotated = match docs.next_raw() {
+                    None => break,
+                    Some(Ok(raw)) => Ok(bson::dump::annotate(raw)),
+                    Some(Err(e)) => Err(e),
+                };
+                let heading = format!("document at offset {}", docs.offset());
+                match annotated {
+                    Ok((listing, None)) => Ok(format!("{}\n{}", heading, listing)),
+                    // The listing still shows how far the document got, so
+                    // it goes out before the error is reported.
+                    Ok((listing, Some(e))) => {
+                        if out.write_line(format!("{}\n{}", heading, listing).as_slice()).is_err() {
+                            return false;
+                        }
+                        Err(e)
+                    },
+                    Err(e) => Err(e),
+                }
+            },
+            Print(ref f) => match docs.next() {
+                None => break,
+                Some(Ok(doc)) => Ok(format(&doc, f)),
+                Some(Err(e)) => Err(e),
+            },
+            Validate => match docs.next() {
+                None => break,
+                Some(Ok(_)) => Ok(String::new()),
+                Some(Err(e)) => Err(e),
+            },
+        };
+        match res {
+            Ok(text) => {
+                count += 1;
+                match mode {
+                    Validate => (),
+                    _ => if out.write_line(text.as_slice()).is_err() {
+                        return false;
+                    },
+                }
+            },
+            Err(e) => {
+                ok = fail(format!("corrupt document at offset {}: {}", docs.offset(), e).as_slice());
+                match mode {
+                    Validate => return false,
+                    _ => (),
+                }
+            },
+        }
+    }
+    let _ = out.flush();
+    let _ = io::stderr().write_line(format!("{} documents", count).as_slice());
+    ok
+}
+
+fn main() {
+    let args = os::args();
+    let mut format = Relaxed;
+    let mut debug = false;
+    let mut validate = false;
+    let mut path = None;
+    for arg in args.iter().skip(1) {
+        match arg.as_slice() {
+            "--format=canonical" => format = Canonical,
+            "--format=relaxed" => format = Relaxed,
+            "--format=shell" => format = Shell,
+            "--debug" => debug = true,
+            "--validate" => validate = true,
+            a if path.is_none() && (a == "-" || !a.starts_with("-")) => path = Some(a.to_string()),
+            _ => {
+                fail(USAGE);
+                os::set_exit_status(2);
+                return;
+            },
+        }
+    }
+    let mode = match (debug, validate) {
+        (true, true) => {
+            fail(USAGE);
+            os::set_exit_status(2);
+            return;
+        },
+        (true, false) => Debug,
+        (false, true) => Validate,
+        (false, false) => Print(format),
+    };
+    let ok = match path {
+        Some(ref p) if p.as_slice() != "-" => match File::open(&Path::new(p.as_slice())) {
+            Ok(file) => run(file, mode),
+            Err(e) => fail(format!("can't open {}: {}", p, e).as_slice()),
+        },
+        _ => run(io::stdin(), mode),
+    };
+    if !ok {
+        os::set_exit_status(1);
+    }
+}
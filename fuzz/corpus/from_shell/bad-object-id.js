@@ -0,0 +1,2 @@
+{ a: 1,
+  b: ObjectId('xyz') }
\ No newline at end of file
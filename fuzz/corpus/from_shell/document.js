@@ -0,0 +1,9 @@
+{
+    _id: ObjectId('57e193d7a9cc81b4027498b5'),
+    when: ISODate("2012-12-24T12:15:30.501Z"),
+    n: NumberLong(5), big: NumberLong("9007199254740993"),
+    d: NumberDecimal("1.5"), f: 2.5, i: 3,
+    name: /^jo\/hn/i,
+    'quoted key': 'it\'s',
+    list: [1, 'two', null,],
+}
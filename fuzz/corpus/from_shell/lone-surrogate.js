@@ -0,0 +1 @@
+'\ud83d'
\ No newline at end of file
@@ -0,0 +1 @@
+RegExp("a\\/b", "i")
\ No newline at end of file
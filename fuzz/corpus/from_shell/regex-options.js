@@ -0,0 +1 @@
+/a/mi
\ No newline at end of file
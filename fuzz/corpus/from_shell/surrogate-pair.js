@@ -0,0 +1 @@
+'\ud83d\ude00'
\ No newline at end of file
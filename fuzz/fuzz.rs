@@ -0,0 +1,55 @@
+#![feature(struct_variant)]
+
+//! Fuzz targets for the decoder, the Extended JSON reader and the shell
+//! parser, for afl-fuzz or any other fuzzer that runs a program per input.
+//!
+//!     fuzz from_vec|from_extjson|from_shell [FILE...]
+//!
+//! Each file is one input; with none, stdin is. A failed check aborts, so
+//! the fuzzer sees a crash rather than an exit status.
+
+extern crate serialize;
+extern crate regex;
+extern crate serde;
+extern crate libc;
+
+use std::{io,os,task};
+use std::io::File;
+
+#[path = "../bson/mod.rs"]
+mod bson;
+mod targets;
+
+static USAGE: &'static str = "usage: fuzz from_vec|from_extjson|from_shell [FILE...]";
+
+fn check(target: &str, data: Vec<u8>) {
+    let target = target.to_string();
+    if task::try(proc() targets::run(target.as_slice(), data.as_slice())).is_err() {
+        unsafe { libc::abort() }
+    }
+}
+
+fn error(msg: &str) {
+    let _ = io::stderr().write_line(msg);
+    os::set_exit_status(2);
+}
+
+fn main() {
+    let args = os::args();
+    let target = match args.as_slice().get(1) {
+        Some(t) if targets::TARGETS.contains(&t.as_slice()) => t.clone(),
+        _ => return error(USAGE),
+    };
+    if args.len() == 2 {
+        match io::stdin().read_to_end() {
+            Ok(data) => check(target.as_slice(), data),
+            Err(e) => error(format!("can't read stdin: {}", e).as_slice()),
+        }
+    }
+    for path in args.iter().skip(2) {
+        match File::open(&Path::new(path.as_slice())).read_to_end() {
+            Ok(data) => check(target.as_slice(), data),
+            Err(e) => error(format!("can't read {}: {}", path, e).as_slice()),
+        }
+    }
+}
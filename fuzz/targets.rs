@@ -0,0 +1,77 @@
+//! The checks behind each fuzz target, shared by `fuzz.rs` and the test
+//! that replays `fuzz/corpus`. Any input is fair game: a check only fails
+//! when the code under test panics or accepts something it can't give back.
+
+use std::str;
+
+use bson;
+use bson::shell::{from_shell,to_shell,ShellOptions};
+
+/// The targets, named as their corpus directories are.
+pub static TARGETS: &'static [&'static str] = &["from_vec", "from_extjson", "from_shell"];
+
+pub fn run(target: &str, data: &[u8]) {
+    match target {
+        "from_vec" => decode(data),
+        "from_extjson" => extjson(data),
+        "from_shell" => shell(data),
+        _ => fail!("no fuzz target `{}`", target),
+    }
+}
+
+/// Decoding returns rather than panics, with or without a projection, and
+/// a document it accepts encodes to bytes that decode and encode the same.
+fn decode(data: &[u8]) {
+    let mut options = bson::DecoderOptions::new();
+    options.projection = Some(bson::Projection::new(&["a", "b.z"]));
+    let _ = bson::from_slice_with_options(data, options);
+    let doc = match bson::from_slice(data) {
+        Ok(doc) => doc,
+        Err(_) => return,
+    };
+    let bytes = bson::encode(&doc);
+    match bson::from_slice(bytes.as_slice()) {
+        Ok(ref again) if bson::encode(again) == bytes => (),
+        Ok(again) => fail!("{} encoded back as {}", bson::to_hex(bytes.as_slice()), bson::to_hex(bson::encode(&again).as_slice())),
+        Err(e) => fail!("{} was accepted, but its encoding {} wasn't: {}", bson::to_hex(data), bson::to_hex(bytes.as_slice()), e),
+    }
+}
+
+/// Whatever `from_extjson` accepts writes canonical Extended JSON that
+/// parses and writes back the same.
+fn extjson(data: &[u8]) {
+    let text = match str::from_utf8(data) {
+        Some(text) => text,
+        None => return,
+    };
+    let v = match bson::from_extjson(text) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    let written = bson::to_canonical_extjson(&v);
+    match bson::from_extjson(written.as_slice()) {
+        Ok(ref again) if bson::to_canonical_extjson(again) == written => (),
+        Ok(again) => fail!("{} wrote back as {}", written, bson::to_canonical_extjson(&again)),
+        Err(e) => fail!("{} was read, but what it wrote wasn't: {}: {}", text, written, e),
+    }
+}
+
+/// Whatever `from_shell` accepts prints as shell syntax that parses and
+/// prints back the same.
+fn shell(data: &[u8]) {
+    let text = match str::from_utf8(data) {
+        Some(text) => text,
+        None => return,
+    };
+    let v = match from_shell(text) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    let options = ShellOptions::new();
+    let printed = to_shell(&v, &options);
+    match from_shell(printed.as_slice()) {
+        Ok(ref again) if to_shell(again, &options) == printed => (),
+        Ok(again) => fail!("{} printed back as {}", printed, to_shell(&again, &options)),
+        Err(e) => fail!("{} was read, but what it printed wasn't: {}: {}", text, printed, e),
+    }
+}
@@ -1,7 +1,14 @@
+#![feature(struct_variant)]
 
+extern crate serialize;
+extern crate regex;
+extern crate serde;
+
+use std::io;
 use std::io::{IoResult,IoError};
 use std::io::net::tcp::TcpStream;
-use bson::{BSON,BSONString};
+use std::collections::TreeMap;
+use bson::Bson;
 
 mod bson;
 
@@ -23,7 +30,7 @@ struct Reply {
 	cursorID: i64,
 	startingFrom: i32,
 	numberReturned: i32,
-	documents: Vec<BSON>
+	documents: Vec<Bson>
 }
 
 struct Query {
@@ -37,7 +44,7 @@ struct Query {
 }
 
 struct DBResult {
-	docs: Vec<BSON>
+	docs: Vec<Bson>
 }
 
 struct MongoClient {
@@ -47,7 +54,7 @@ impl MongoClient {
 	pub fn default_connect() -> MongoClient {
 		MongoClient { socket: TcpStream::connect("127.0.0.1",27017).unwrap() }
 	}
-	pub fn query(&mut self, q: BSON) -> IoResult<DBResult> {
+	pub fn query(&mut self, q: Bson) -> IoResult<DBResult> {
 		println!("Doing query");
         let mut q = Query {
             header: MsgHeader {messageLength: 0, requestID: 1, responseTo: None, opCode: OpQuery as i32},
@@ -55,7 +62,7 @@ impl MongoClient {
             fullCollectionName: "test.coll".to_string(),
             numberToSkip: 0,
             numberToReturn: 0,
-            query: q.serialize(),
+            query: bson::encode(&q),
             returnFieldsSelector: None
         };
         q.header.messageLength = 16 + 4 + q.fullCollectionName.len() + 1 + 4 + 4 + q.query.len();
@@ -110,7 +117,14 @@ impl MongoClient {
             println!("Trying to get: {:x}", len);
             doc.push_all(try!(self.socket.read_exact(len-4)).as_slice());
             i += len;
-            r.documents.push(BSON::deserialize(doc));
+            match bson::from_vec(doc) {
+                Ok(d) => r.documents.push(d),
+                Err(e) => return Err(IoError {
+                    kind: io::InvalidInput,
+                    desc: "malformed document in reply",
+                    detail: Some(format!("{}", e)),
+                }),
+            }
         }
         println!("Docs: {}", r.documents);
 		Ok(DBResult { docs: Vec::new() })
@@ -119,6 +133,6 @@ impl MongoClient {
 
 fn main() {
     let mut client = MongoClient::default_connect();
-    let mut q = BSON::new();
+    let mut q = bson::Object(TreeMap::new());
     client.query(q);
 }